@@ -0,0 +1,124 @@
+use napi::{bindgen_prelude::Buffer, Error, Result};
+use napi_derive::napi;
+use transactions::{
+    client::{Client, ClientStore},
+    error::TransactionError,
+    handle_transactions_with_registry,
+    transaction::TransactionRegistry,
+    ClientID,
+};
+
+/// Convert an error of the engine to a JavaScript `Error`
+fn js_error(err: TransactionError) -> Error {
+    Error::from_reason(err.to_string())
+}
+
+/// Transaction submitted from JavaScript
+#[napi(object)]
+pub struct TransactionInput {
+    /// Type name of the transaction, e.g. `deposit`
+    #[napi(js_name = "type")]
+    pub t_type: String,
+    /// Client to execute the transaction on
+    pub client: i64,
+    /// Transaction identifier
+    pub tx: u32,
+    /// Amount of the transaction, absent for disputes, resolves and chargebacks
+    pub amount: Option<f64>,
+}
+
+/// State of a client
+#[napi(object)]
+pub struct ClientState {
+    pub client: i64,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl From<&Client> for ClientState {
+    fn from(client: &Client) -> Self {
+        Self {
+            client: client.id as i64,
+            available: client.available,
+            held: client.held,
+            total: client.total(),
+            locked: client.locked,
+        }
+    }
+}
+
+/// Report of processing a buffer
+#[napi(object)]
+pub struct ProcessingReport {
+    /// Number of rows read from the buffer
+    pub rows_read: i64,
+    /// Number of rows whose transaction was applied
+    pub applied: i64,
+    /// Number of rows which could not be parsed or whose transaction was rejected
+    pub rejected: i64,
+}
+
+/// Transaction engine holding the state of clients between calls
+#[napi]
+pub struct Engine {
+    store: ClientStore,
+    registry: TransactionRegistry,
+}
+
+#[napi]
+impl Engine {
+    /// Create a new Engine with no clients
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self {
+            store: ClientStore::new(),
+            registry: TransactionRegistry::new(),
+        }
+    }
+
+    /// Submit a single transaction, throwing if it is rejected.
+    #[napi]
+    pub fn submit(&mut self, transaction: TransactionInput) -> Result<()> {
+        let client = ClientID::try_from(transaction.client)
+            .map_err(|_| Error::from_reason(format!("Invalid client {}", transaction.client)))?;
+        let amount = transaction.amount.unwrap_or(0.0);
+        if !amount.is_finite() {
+            return Err(Error::from_reason("Amount must be a finite number"));
+        }
+        let transaction = self
+            .registry
+            .create_named(&transaction.t_type, client, transaction.tx, amount)
+            .map_err(js_error)?;
+        self.store.execute(transaction.as_ref()).map_err(js_error)
+    }
+
+    /// Handle a buffer of CSV transactions.
+    ///
+    /// Rows which can not be handled are logged to stderr and counted as rejected.
+    #[napi]
+    pub fn process_buffer(&mut self, input: Buffer) -> ProcessingReport {
+        let report =
+            handle_transactions_with_registry(input.as_ref(), &self.registry, &mut self.store);
+        ProcessingReport {
+            rows_read: report.rows_read as i64,
+            applied: report.applied as i64,
+            rejected: report.rejected as i64,
+        }
+    }
+
+    /// Get the current state of all clients, sorted by their client
+    #[napi]
+    pub fn state(&self) -> Vec<ClientState> {
+        let mut state: Vec<ClientState> = self.store.clients.values().map(Into::into).collect();
+        state.sort_by_key(|client| client.client);
+        state
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::hash_map::Entry;
+
+use crate::{
+    client::{Client, DisputeState},
+    observer::TransactionObserver,
+    transaction::{Transaction, TransactionKind},
+    Amount, ClientID, IdMap, IdSet, TransactionID,
+};
+
+/// Threshold rule evaluated against a client after every transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertRule {
+    /// Funds available to the client are below the amount
+    BalanceBelow(Amount),
+    /// Funds held by disputes are above the amount
+    HeldAbove(Amount),
+    /// The client has more than the number of open disputes
+    OpenDisputesAbove(usize),
+}
+
+impl AlertRule {
+    /// Get the value the rule checks and whether it is over the threshold.
+    fn check(&self, client: &Client, open_disputes: usize) -> (Amount, bool) {
+        match *self {
+            Self::BalanceBelow(amount) => (client.available, client.available < amount),
+            Self::HeldAbove(amount) => (client.held, client.held > amount),
+            Self::OpenDisputesAbove(count) => (open_disputes as Amount, open_disputes > count),
+        }
+    }
+}
+
+/// Alert raised when a rule starts to hold for a client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    /// Rule which holds
+    pub rule: AlertRule,
+    /// Client the rule holds for
+    pub client: ClientID,
+    /// Transaction after which the rule holds
+    pub tx: TransactionID,
+    /// Kind of the transaction
+    pub kind: TransactionKind,
+    /// Value over the threshold, funds or the number of open disputes
+    pub value: Amount,
+}
+
+/// Receiver of alerts, e.g. a pager or email integration
+///
+/// Implemented for closures taking an Alert.
+pub trait Alerter {
+    /// Handle an alert.
+    fn alert(&mut self, alert: &Alert);
+}
+
+impl<F> Alerter for F
+where
+    F: FnMut(&Alert),
+{
+    fn alert(&mut self, alert: &Alert) {
+        self(alert)
+    }
+}
+
+/// Observer evaluating threshold rules after every applied transaction
+///
+/// An alert is raised once when a rule starts to hold for a client, and
+/// again only after the rule stopped holding in between, so a client staying
+/// over a threshold does not page on every transaction. Hosts get alerts
+/// without polling the state of the store.
+pub struct AlertRules<A>
+where
+    A: Alerter,
+{
+    rules: Vec<AlertRule>,
+    alerter: A,
+    /// Rules by their index which currently hold for every client
+    firing: IdSet<(ClientID, usize)>,
+    /// Number of open disputes of every client seen
+    open_disputes: IdMap<ClientID, usize>,
+}
+
+impl<A> AlertRules<A>
+where
+    A: Alerter,
+{
+    /// Create new AlertRules raising alerts of the rules to the alerter
+    pub fn new(rules: Vec<AlertRule>, alerter: A) -> Self {
+        Self {
+            rules,
+            alerter,
+            firing: IdSet::default(),
+            open_disputes: IdMap::default(),
+        }
+    }
+
+    /// Update the number of open disputes of the client after the transaction.
+    fn open_disputes(&mut self, kind: TransactionKind, client: &Client) -> usize {
+        match self.open_disputes.entry(client.id) {
+            // Clients seen first, e.g. loaded from a saved state, are counted once
+            Entry::Vacant(entry) => *entry.insert(
+                client
+                    .client_history
+                    .values()
+                    .filter(|history| history.state == DisputeState::Disputed)
+                    .count(),
+            ),
+            Entry::Occupied(mut entry) => {
+                let open = entry.get_mut();
+                match kind {
+                    TransactionKind::Dispute => *open += 1,
+                    TransactionKind::Resolve | TransactionKind::Chargeback => {
+                        *open = open.saturating_sub(1)
+                    }
+                    _ => {}
+                }
+                *open
+            }
+        }
+    }
+}
+
+impl<A> TransactionObserver for AlertRules<A>
+where
+    A: Alerter,
+{
+    fn on_applied(&mut self, transaction: &dyn Transaction, client: &Client) {
+        let kind = transaction.kind();
+        let open_disputes = self.open_disputes(kind, client);
+        for (index, rule) in self.rules.iter().enumerate() {
+            let (value, holds) = rule.check(client, open_disputes);
+            if !holds {
+                let _ = self.firing.remove(&(client.id, index));
+            } else if self.firing.insert((client.id, index)) {
+                self.alerter.alert(&Alert {
+                    rule: *rule,
+                    client: client.id,
+                    tx: transaction.transaction_id(),
+                    kind,
+                    value,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::client::ClientStore;
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn alerts_raised_when_rules_start_to_hold() {
+        let alerts = Rc::new(RefCell::new(Vec::new()));
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        client_store.add_observer(AlertRules::new(
+            vec![
+                AlertRule::BalanceBelow(0.0),
+                AlertRule::HeldAbove(4.0),
+                AlertRule::OpenDisputesAbove(1),
+            ],
+            {
+                let alerts = Rc::clone(&alerts);
+                move |alert: &Alert| {
+                    alerts
+                        .borrow_mut()
+                        .push((alert.rule, alert.tx, alert.value))
+                }
+            },
+        ));
+        for (name, tx, amount) in [
+            ("deposit", 1, 3.0),
+            ("deposit", 2, 2.0),
+            ("withdrawal", 3, 4.0),
+            ("dispute", 1, 0.0),
+            ("dispute", 2, 0.0),
+            ("deposit", 4, 1.0),
+            ("resolve", 2, 0.0),
+            ("dispute", 2, 0.0),
+        ] {
+            let transaction = registry.create_named(name, 1, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        assert_eq!(
+            *alerts.borrow(),
+            vec![
+                (AlertRule::BalanceBelow(0.0), 1, -2.0),
+                (AlertRule::HeldAbove(4.0), 2, 5.0),
+                (AlertRule::OpenDisputesAbove(1), 2, 2.0),
+                (AlertRule::HeldAbove(4.0), 2, 5.0),
+                (AlertRule::OpenDisputesAbove(1), 2, 2.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+#[cfg(feature = "csv")]
+use std::io::Read;
+
+#[cfg(feature = "csv")]
+use serde::Deserialize;
+
+use crate::{error::TransactionError, ClientID};
+
+/// Table mapping external client ids of partners to internal clients
+///
+/// Partner files identifying the same clients differently are processed
+/// into one consistent store by mapping their ids while parsing. Ids without
+/// an alias are parsed as internal clients, so inputs using internal ids can
+/// be mixed in. Aliases take precedence over internal ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientAliases {
+    clients: HashMap<String, ClientID>,
+}
+
+/// Row of a mapping file
+#[cfg(feature = "csv")]
+#[derive(Debug, Deserialize)]
+struct AliasRow {
+    external: String,
+    client: ClientID,
+}
+
+impl ClientAliases {
+    /// Create a new empty ClientAliases
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the aliases from a CSV mapping file with `external,client` headers.
+    ///
+    /// Returns an error if an external id is mapped twice.
+    #[cfg(feature = "csv")]
+    pub fn from_csv<R>(reader: R) -> Result<Self, TransactionError>
+    where
+        R: Read,
+    {
+        let mut aliases = Self::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for row in reader.deserialize() {
+            let AliasRow { external, client } = row?;
+            if aliases.clients.contains_key(&external) {
+                return Err(format!("Client alias {} is mapped twice", external).into());
+            }
+            aliases.insert(external, client);
+        }
+        Ok(aliases)
+    }
+
+    /// Map the external id to the client
+    pub fn insert(&mut self, external: String, client: ClientID) {
+        let _ = self.clients.insert(external, client);
+    }
+
+    /// Get the client of an id of the input, an alias or an internal id
+    pub fn resolve(&self, id: &str) -> Result<ClientID, TransactionError> {
+        match self.clients.get(id) {
+            Some(&client) => Ok(client),
+            None => id
+                .parse()
+                .map_err(|err| format!("Invalid client {}: {}", id, err).into()),
+        }
+    }
+
+    /// Get the number of aliases
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Check if there are no aliases
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "csv"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_read_from_mapping_file() {
+        let aliases =
+            ClientAliases::from_csv("external,client\nACME-17,3\nglobex:17, 4\n".as_bytes())
+                .unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases.resolve("ACME-17").unwrap(), 3);
+        assert_eq!(aliases.resolve("globex:17").unwrap(), 4);
+        // Ids without an alias are internal clients
+        assert_eq!(aliases.resolve("17").unwrap(), 17);
+        aliases.resolve("ACME-18").unwrap_err();
+
+        ClientAliases::from_csv("external,client\nACME-17,3\nACME-17,4\n".as_bytes()).unwrap_err();
+    }
+}
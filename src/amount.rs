@@ -0,0 +1,289 @@
+use serde::Deserialize;
+
+use crate::{error::TransactionError, Amount};
+
+/// Rounding error of sums of amounts relative to their magnitude
+///
+/// Amounts are floats, so e.g. `0.1 + 0.2` is not exactly `0.3`.
+const TOLERANCE: Amount = 1e-12;
+
+/// Rounding mode for amounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Round half away from zero
+    #[default]
+    HalfUp,
+    /// Round half to the nearest even digit, also known as bankers rounding
+    HalfEven,
+    /// Truncate extra decimal places
+    Truncate,
+}
+
+/// Handling of amounts with more than the maximum number of decimal places
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrecisionPolicy {
+    /// The row is rejected.
+    #[default]
+    Reject,
+    /// The amount is rounded to the maximum number of decimal places.
+    Round(RoundingMode),
+}
+
+/// Format of amounts in the input
+///
+/// Default format accepts plain decimal and scientific notation amounts,
+/// like the standard float parser, but rejects NaN and infinite amounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountFormat {
+    /// Maximum number of decimal places an amount may have
+    ///
+    /// Unlimited if None.
+    pub max_decimal_places: Option<u32>,
+    /// Handling of amounts with more than the maximum number of decimal places
+    pub precision_policy: PrecisionPolicy,
+    /// Allow amounts in scientific notation, e.g. `1.5e3`
+    pub allow_scientific: bool,
+    /// Currency symbols stripped from the start or end of an amount, e.g. `$`
+    pub currency_symbols: Vec<String>,
+    /// Thousands separator stripped from an amount, e.g. `,` in `1,000.00`
+    pub thousands_separator: Option<char>,
+    /// Decimal separator of an amount, e.g. `,` in `1.234,56`
+    pub decimal_separator: char,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            max_decimal_places: None,
+            precision_policy: PrecisionPolicy::default(),
+            allow_scientific: true,
+            currency_symbols: Vec::new(),
+            thousands_separator: None,
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl AmountFormat {
+    /// Create a format for comma decimal and dot thousands amounts, e.g. `1.234,56`
+    pub fn comma_decimal() -> Self {
+        Self {
+            thousands_separator: Some('.'),
+            decimal_separator: ',',
+            ..Default::default()
+        }
+    }
+
+    /// Parse an amount from the input.
+    ///
+    /// Surrounding currency symbols and thousands separators are stripped and
+    /// the decimal separator is normalized before parsing. Returns an error if the amount is not a finite number
+    /// or does not follow the format.
+    pub fn parse(&self, raw: &str) -> Result<Amount, TransactionError> {
+        self.parse_rounded(raw).map(|(amount, _rounded)| amount)
+    }
+
+    /// Parse an amount from the input, reporting if it was rounded.
+    ///
+    /// Same as `parse`, but also returns true if the amount had too many
+    /// decimal places and was rounded by the precision policy.
+    pub fn parse_rounded(&self, raw: &str) -> Result<(Amount, bool), TransactionError> {
+        let mut amount = raw.trim();
+        for symbol in self.currency_symbols.iter() {
+            amount = amount
+                .strip_prefix(symbol.as_str())
+                .or_else(|| amount.strip_suffix(symbol.as_str()))
+                .unwrap_or(amount)
+                .trim();
+        }
+        let normalized;
+        if self.thousands_separator.is_some() || self.decimal_separator != '.' {
+            normalized = amount
+                .chars()
+                .filter(|c| Some(*c) != self.thousands_separator)
+                .map(|c| if c == self.decimal_separator { '.' } else { c })
+                .collect::<String>();
+            amount = &normalized;
+        }
+
+        let (mantissa, exponent) = match amount.find(['e', 'E']) {
+            Some(index) => (&amount[..index], Some(&amount[index + 1..])),
+            None => (amount, None),
+        };
+        if !mantissa
+            .trim_start_matches(['+', '-'])
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || byte == b'.')
+        {
+            return Err(format!("Invalid amount {}", raw).into());
+        }
+        if exponent.is_some() && !self.allow_scientific {
+            return Err(format!("Amount {} is in scientific notation", raw).into());
+        }
+
+        let value: Amount = amount
+            .parse()
+            .map_err(|err| format!("Invalid amount {}: {}", raw, err))?;
+        if !value.is_finite() {
+            return Err(format!("Amount {} is not finite", raw).into());
+        }
+
+        if let Some(max_decimal_places) = self.max_decimal_places {
+            if decimal_places(mantissa, exponent) > max_decimal_places as i64 {
+                return match self.precision_policy {
+                    PrecisionPolicy::Reject => Err(format!(
+                        "Amount {} has more than {} decimal places",
+                        raw, max_decimal_places
+                    )
+                    .into()),
+                    PrecisionPolicy::Round(mode) => {
+                        Ok((round(value, max_decimal_places, mode), true))
+                    }
+                };
+            }
+        }
+        Ok((value, false))
+    }
+}
+
+/// Round an amount to the number of decimal places.
+pub fn round(amount: Amount, decimal_places: u32, mode: RoundingMode) -> Amount {
+    let scale = 10f64.powi(decimal_places as i32);
+    // Scaling is inexact, e.g. 1.00005 is 10000.499999.. when scaled.
+    // Rounding the scaled amount to a few less digits than an f64 holds
+    // first removes the error so ties are detected.
+    let scaled = amount * scale;
+    let magnitude = scaled.abs().log10().ceil().max(0.0) as i32;
+    let cleanup = 10f64.powi((15 - magnitude).max(0));
+    let scaled = (scaled * cleanup).round() / cleanup;
+    let rounded = match mode {
+        RoundingMode::HalfUp => scaled.round(),
+        RoundingMode::HalfEven => scaled.round_ties_even(),
+        RoundingMode::Truncate => scaled.trunc(),
+    };
+    rounded / scale
+}
+
+/// Check if the amount exceeds the limit by more than the rounding error.
+pub(crate) fn exceeds(amount: Amount, limit: Amount) -> bool {
+    amount - limit > TOLERANCE * amount.abs().max(limit.abs()).max(1.0)
+}
+
+/// Get the amount, or zero if it is zero within the rounding error of the scale.
+///
+/// Used for funds which are exactly zero when summed exactly, e.g. held
+/// funds once the last dispute is settled.
+pub(crate) fn settle(amount: Amount, scale: Amount) -> Amount {
+    if amount.abs() <= TOLERANCE * scale.abs().max(1.0) {
+        0.0
+    } else {
+        amount
+    }
+}
+
+/// Number of decimal places of an amount split into mantissa and exponent.
+fn decimal_places(mantissa: &str, exponent: Option<&str>) -> i64 {
+    let fraction = mantissa
+        .split_once('.')
+        .map_or("", |(_whole, fraction)| fraction)
+        .trim_end_matches('0');
+    let exponent: i64 = exponent.and_then(|e| e.parse().ok()).unwrap_or(0);
+    (fraction.len() as i64 - exponent).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding_errors_tolerated() {
+        assert!(!exceeds(0.1 + 0.2, 0.3));
+        assert!(!exceeds(0.1, 0.7 + 0.1 - 0.7));
+        assert!(exceeds(0.3001, 0.3));
+        assert!(exceeds(1e9 + 0.01, 1e9));
+        assert_eq!(settle(0.7 + 0.1 - 0.7 - 0.1, 0.1), 0.0);
+        assert_eq!(settle(0.0001, 0.1), 0.0001);
+    }
+
+    #[test]
+    fn parse_plain_amount() {
+        let format = AmountFormat::default();
+        assert_eq!(format.parse("1.5").unwrap(), 1.5);
+        assert_eq!(format.parse("15").unwrap(), 15.0);
+        assert_eq!(format.parse("1.5e2").unwrap(), 150.0);
+    }
+
+    #[test]
+    fn parse_non_finite_errors() {
+        let format = AmountFormat::default();
+        format.parse("NaN").unwrap_err();
+        format.parse("inf").unwrap_err();
+        format.parse("-infinity").unwrap_err();
+        format.parse("1e400").unwrap_err();
+    }
+
+    #[test]
+    fn parse_too_precise_errors() {
+        let format = AmountFormat {
+            max_decimal_places: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(format.parse("1.2345").unwrap(), 1.2345);
+        assert_eq!(format.parse("1.234500").unwrap(), 1.2345);
+        assert_eq!(format.parse("1.2345e1").unwrap(), 12.345);
+        format.parse("1.23456").unwrap_err();
+        format.parse("1.2345e-1").unwrap_err();
+    }
+
+    #[test]
+    fn parse_scientific_rejected() {
+        let format = AmountFormat {
+            allow_scientific: false,
+            ..Default::default()
+        };
+        format.parse("1.5e2").unwrap_err();
+    }
+
+    #[test]
+    fn parse_currency_and_thousands() {
+        let format = AmountFormat {
+            currency_symbols: vec!["$".to_owned(), "USD".to_owned()],
+            thousands_separator: Some(','),
+            ..Default::default()
+        };
+        assert_eq!(format.parse("$1,234.5").unwrap(), 1234.5);
+        assert_eq!(format.parse("1,234.5 USD").unwrap(), 1234.5);
+        AmountFormat::default().parse("$1,234.5").unwrap_err();
+    }
+
+    #[test]
+    fn parse_comma_decimal() {
+        let format = AmountFormat::comma_decimal();
+        assert_eq!(format.parse("1.234,56").unwrap(), 1234.56);
+        assert_eq!(format.parse("0,5").unwrap(), 0.5);
+        assert_eq!(format.parse("12").unwrap(), 12.0);
+        // Dots are thousands separators, so this is 1234
+        assert_eq!(format.parse("1.234").unwrap(), 1234.0);
+    }
+
+    #[test]
+    fn parse_too_precise_rounded() {
+        let format = AmountFormat {
+            max_decimal_places: Some(4),
+            precision_policy: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            ..Default::default()
+        };
+        assert_eq!(format.parse_rounded("1.2345").unwrap(), (1.2345, false));
+        assert_eq!(format.parse_rounded("1.23456").unwrap(), (1.2346, true));
+    }
+
+    #[test]
+    fn rounding_modes() {
+        assert_eq!(round(1.00005, 4, RoundingMode::HalfUp), 1.0001);
+        assert_eq!(round(1.00005, 4, RoundingMode::HalfEven), 1.0);
+        assert_eq!(round(1.00015, 4, RoundingMode::HalfEven), 1.0002);
+        assert_eq!(round(1.00009, 4, RoundingMode::Truncate), 1.0);
+        assert_eq!(round(-1.00005, 4, RoundingMode::HalfUp), -1.0001);
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    fs,
+    hash::{BuildHasher, Hasher},
+    path::Path,
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{error::TransactionError, ClientID};
+
+/// Number of bytes of the keyed hash kept in a pseudonym
+const PSEUDONYM_SIZE: usize = 8;
+
+/// Replaces clients with pseudonyms, so outputs can be shared without revealing accounts
+///
+/// A pseudonym is the HMAC-SHA256 of the client keyed with a secret, as 16
+/// hexadecimal digits. The same client gets the same pseudonym with the same
+/// key, so outputs of runs with a shared key can be joined, while clients
+/// can't be recovered or guessed without the key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    /// Create a new Pseudonymizer with a secret key
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: key.as_ref().to_vec(),
+        }
+    }
+
+    /// Create a new Pseudonymizer with a random key, so pseudonyms are only
+    /// stable within one run
+    pub fn random() -> Self {
+        // Every RandomState is seeded with random keys of the process, so
+        // it's hashes are unpredictable without pulling in a random generator
+        let key: Vec<u8> = (0..4u8)
+            .flat_map(|part| {
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_u8(part);
+                hasher.finish().to_le_bytes()
+            })
+            .collect();
+        Self::new(key)
+    }
+
+    /// Read the secret key from a file, ignoring surrounding whitespace
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TransactionError> {
+        let key = fs::read_to_string(path)?;
+        if key.trim().is_empty() {
+            return Err("Anonymization key must not be empty".into());
+        }
+        Ok(Self::new(key.trim()))
+    }
+
+    /// Get the pseudonym of a client
+    ///
+    /// Clients are hashed as 64 bit integers, so pseudonyms don't depend on
+    /// the width of ClientID.
+    pub fn pseudonym(&self, client: ClientID) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&(u128::from(client) as u64).to_le_bytes());
+        mac.finalize().into_bytes()[..PSEUDONYM_SIZE]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Keys are not printed, so they do not leak into logs
+impl Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Pseudonymizer(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudonyms_stable_per_key() {
+        let pseudonymizer = Pseudonymizer::new("secret");
+        let pseudonym = pseudonymizer.pseudonym(1);
+        assert_eq!(pseudonym.len(), 2 * PSEUDONYM_SIZE);
+        assert_eq!(pseudonym, Pseudonymizer::new("secret").pseudonym(1));
+        assert_ne!(pseudonym, pseudonymizer.pseudonym(2));
+        assert_ne!(pseudonym, Pseudonymizer::new("other").pseudonym(1));
+        assert_ne!(pseudonym, Pseudonymizer::random().pseudonym(1));
+        assert_ne!(Pseudonymizer::random(), Pseudonymizer::random());
+        assert!(!format!("{:?}", pseudonymizer).contains("secret"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use tempfile::{Builder, NamedTempFile};
+
+use crate::error::TransactionError;
+
+/// Suffix of the temporary files of AtomicFiles
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// File which replaces it's path only once it is completely written
+///
+/// Everything is written to a temporary file next to the path, which is
+/// fsynced and atomically renamed to the path by `commit`. If the run is
+/// interrupted before, the previous file at the path is left untouched.
+/// Temporary files left by interrupted runs are removed with `remove_stale`.
+pub struct AtomicFile {
+    path: PathBuf,
+    file: BufWriter<NamedTempFile>,
+}
+
+impl AtomicFile {
+    /// Create a new AtomicFile replacing the path once committed
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, TransactionError> {
+        let path = path.as_ref().to_path_buf();
+        let prefix = temp_prefix(&path);
+        let mut builder = Builder::new();
+        let _ = builder.prefix(&prefix).suffix(TEMP_SUFFIX);
+        // Temporary files are private by default, the replaced path is not
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        let file = builder.tempfile_in(directory(&path))?;
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+        })
+    }
+
+    /// Path the file replaces once committed
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flush and fsync the file, then atomically replace the path with it.
+    pub fn commit(self) -> Result<(), TransactionError> {
+        let file = self.file.into_inner().map_err(|err| err.into_error())?;
+        file.as_file().sync_all()?;
+        let _ = file.persist(&self.path).map_err(|err| err.error)?;
+        // The rename is only durable once the directory is synced
+        #[cfg(unix)]
+        fs::File::open(directory(&self.path))?.sync_all()?;
+        Ok(())
+    }
+
+    /// Remove the temporary files of the path left by interrupted runs.
+    ///
+    /// Returns the number of files removed. Must not be called while
+    /// another AtomicFile of the path is being written.
+    pub fn remove_stale(path: impl AsRef<Path>) -> Result<usize, TransactionError> {
+        let path = path.as_ref();
+        let prefix = temp_prefix(path);
+        let mut removed = 0;
+        for entry in fs::read_dir(directory(path))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with(&prefix) && name.ends_with(TEMP_SUFFIX) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Directory of a path, the current directory for a bare file name
+fn directory(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Prefix of the temporary files of a path, e.g. `.state.csv.`
+fn temp_prefix(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!(".{}.", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_only_once_committed() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state.csv");
+        fs::write(&path, "previous").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"interrupted").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"current").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous");
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "current");
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn stale_files_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("state.csv");
+        for name in [
+            ".state.csv.a1b2c3.tmp",
+            ".other.csv.a1b2c3.tmp",
+            "state.csv",
+        ] {
+            fs::write(directory.path().join(name), "").unwrap();
+        }
+
+        assert_eq!(AtomicFile::remove_stale(&path).unwrap(), 1);
+        let mut names: Vec<String> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![".other.csv.a1b2c3.tmp", "state.csv"]);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+#[cfg(feature = "csv")]
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    error::{ErrorKind, TransactionError},
+    transaction::{Transaction, TransactionKind},
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Stable code of the reason a transaction was rejected
+///
+/// Codes are part of the audit's format, unlike the error messages, so
+/// consumers can match on them across releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReasonCode {
+    /// The client is locked
+    AccountLocked,
+    /// The client's available funds don't cover the transaction
+    InsufficientFunds,
+    /// The referenced transaction or hold does not exist
+    TxNotFound,
+    /// The transaction or hold was already applied
+    DuplicateTx,
+    /// The referenced transaction belongs to a different client
+    WrongClient,
+    /// The referenced transaction can not move to the dispute state
+    InvalidDisputeState,
+    /// A dispute would leave the available funds negative
+    NegativeAvailable,
+    /// The transaction would leave the held funds negative
+    NegativeHeld,
+    /// The referenced transaction was pruned from the history
+    HistoryPruned,
+    /// A withdrawal would exceed a velocity rule
+    VelocityExceeded,
+    /// The transaction would exceed a limit of the client's tier
+    TierLimitExceeded,
+    /// The amount is below the minimum transaction amount
+    AmountBelowMinimum,
+    /// The amount is above the maximum transaction amount
+    AmountAboveMaximum,
+    /// The transaction would exceed a daily cumulative limit
+    DailyLimitExceeded,
+    /// The transaction was denied by the risk scorer
+    RiskDenied,
+    /// The transaction is restricted for clients which are not verified
+    Unverified,
+    /// The client is frozen
+    Frozen,
+    /// The input could not be read or decoded
+    CorruptInput,
+    /// The storage backend failed temporarily, also after retries
+    Transient,
+    /// Any other reason, e.g. a veto of a middleware
+    Other,
+}
+
+impl ReasonCode {
+    /// Get the code as it is written to the audit
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AccountLocked => "ACCOUNT_LOCKED",
+            Self::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            Self::TxNotFound => "TX_NOT_FOUND",
+            Self::DuplicateTx => "DUPLICATE_TX",
+            Self::WrongClient => "WRONG_CLIENT",
+            Self::InvalidDisputeState => "INVALID_DISPUTE_STATE",
+            Self::NegativeAvailable => "NEGATIVE_AVAILABLE",
+            Self::NegativeHeld => "NEGATIVE_HELD",
+            Self::HistoryPruned => "HISTORY_PRUNED",
+            Self::VelocityExceeded => "VELOCITY_EXCEEDED",
+            Self::TierLimitExceeded => "TIER_LIMIT_EXCEEDED",
+            Self::AmountBelowMinimum => "AMOUNT_BELOW_MINIMUM",
+            Self::AmountAboveMaximum => "AMOUNT_ABOVE_MAXIMUM",
+            Self::DailyLimitExceeded => "DAILY_LIMIT_EXCEEDED",
+            Self::RiskDenied => "RISK_DENIED",
+            Self::Unverified => "UNVERIFIED",
+            Self::Frozen => "FROZEN",
+            Self::CorruptInput => "CORRUPT_INPUT",
+            Self::Transient => "TRANSIENT",
+            Self::Other => "OTHER",
+        }
+    }
+}
+
+impl From<ErrorKind> for ReasonCode {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::AccountLocked => Self::AccountLocked,
+            ErrorKind::InsufficientFunds => Self::InsufficientFunds,
+            ErrorKind::TransactionNotFound => Self::TxNotFound,
+            ErrorKind::DuplicateTransaction => Self::DuplicateTx,
+            ErrorKind::WrongClient => Self::WrongClient,
+            ErrorKind::InvalidDisputeState => Self::InvalidDisputeState,
+            ErrorKind::NegativeAvailable => Self::NegativeAvailable,
+            ErrorKind::NegativeHeld => Self::NegativeHeld,
+            ErrorKind::HistoryPruned => Self::HistoryPruned,
+            ErrorKind::VelocityExceeded => Self::VelocityExceeded,
+            ErrorKind::TierLimitExceeded => Self::TierLimitExceeded,
+            ErrorKind::AmountBelowMinimum => Self::AmountBelowMinimum,
+            ErrorKind::AmountAboveMaximum => Self::AmountAboveMaximum,
+            ErrorKind::DailyLimitExceeded => Self::DailyLimitExceeded,
+            ErrorKind::RiskDenied => Self::RiskDenied,
+            ErrorKind::Unverified => Self::Unverified,
+            ErrorKind::Frozen => Self::Frozen,
+            ErrorKind::CorruptInput => Self::CorruptInput,
+            ErrorKind::Transient => Self::Transient,
+            ErrorKind::Other => Self::Other,
+        }
+    }
+}
+
+impl Display for ReasonCode {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Rejected transaction recorded by the audit
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejection {
+    /// Reason the transaction was rejected
+    pub code: ReasonCode,
+    /// Client of the transaction
+    pub client: ClientID,
+    /// Transaction which was rejected
+    pub tx: TransactionID,
+    /// Kind of the transaction
+    #[serde(rename = "type")]
+    pub kind: TransactionKind,
+    /// Amount of the transaction, if it has one
+    pub amount: Option<Amount>,
+    /// Time of the transaction, if known
+    pub timestamp: Option<Timestamp>,
+    /// Message of the error
+    pub message: String,
+}
+
+/// Audit trail of every transaction rejected by a ClientStore
+///
+/// Enabled with `ClientStore::enable_audit` and queried after the run with
+/// `ClientStore::audit`. Rejections are kept in the order they occurred.
+#[derive(Debug, Clone, Default)]
+pub struct RejectionAudit {
+    rejections: Vec<Rejection>,
+}
+
+impl RejectionAudit {
+    /// Create a new empty RejectionAudit
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the transaction rejected with the error.
+    pub fn record(
+        &mut self,
+        transaction: &dyn Transaction,
+        err: &TransactionError,
+        timestamp: Option<Timestamp>,
+    ) {
+        self.rejections.push(Rejection {
+            code: err.kind().into(),
+            client: transaction.requested_client_id(),
+            tx: transaction.transaction_id(),
+            kind: transaction.kind(),
+            amount: transaction.amount(),
+            timestamp,
+            message: err.to_string(),
+        });
+    }
+
+    /// Iterate over the rejections in the order they occurred
+    pub fn iter(&self) -> impl Iterator<Item = &Rejection> + '_ {
+        self.rejections.iter()
+    }
+
+    /// Iterate over the rejections with the code
+    pub fn with_code(&self, code: ReasonCode) -> impl Iterator<Item = &Rejection> + '_ {
+        self.rejections
+            .iter()
+            .filter(move |rejection| rejection.code == code)
+    }
+
+    /// Iterate over the rejections of the client
+    pub fn of_client(&self, client: ClientID) -> impl Iterator<Item = &Rejection> + '_ {
+        self.rejections
+            .iter()
+            .filter(move |rejection| rejection.client == client)
+    }
+
+    /// Get the number of rejections of every code
+    pub fn counts(&self) -> BTreeMap<ReasonCode, u64> {
+        let mut counts = BTreeMap::new();
+        for rejection in &self.rejections {
+            *counts.entry(rejection.code).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Get the number of rejections
+    pub fn len(&self) -> usize {
+        self.rejections.len()
+    }
+
+    /// Check if no transaction was rejected
+    pub fn is_empty(&self) -> bool {
+        self.rejections.is_empty()
+    }
+
+    /// Write the rejections as CSV with a header.
+    ///
+    /// Columns are `code,client,tx,type,amount,timestamp,message`, amounts
+    /// and times which are unknown are empty.
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W>(&self, writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        let mut writer = csv::Writer::from_writer(writer);
+        // The header is only written with the first record otherwise
+        if self.rejections.is_empty() {
+            writer.write_record([
+                "code",
+                "client",
+                "tx",
+                "type",
+                "amount",
+                "timestamp",
+                "message",
+            ])?;
+        }
+        for rejection in &self.rejections {
+            writer.serialize(rejection)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientStore;
+    use crate::config::{DuplicatePolicy, StoreConfig};
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn rejections_recorded_by_code() {
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Default::default()
+        });
+        client_store.enable_audit();
+        for (name, client, tx, amount) in [
+            ("deposit", 1, 1, 5.0),
+            ("withdrawal", 1, 2, 9.0),
+            ("dispute", 1, 7, 0.0),
+            ("deposit", 1, 1, 6.0),
+            ("dispute", 1, 1, 0.0),
+            ("chargeback", 1, 1, 0.0),
+            ("deposit", 1, 3, 1.0),
+            ("withdrawal", 2, 4, 1.0),
+        ] {
+            let transaction = registry.create_named(name, client, tx, amount).unwrap();
+            let _ = client_store.execute(transaction.as_ref());
+        }
+
+        let audit = client_store.audit().unwrap();
+        let codes: Vec<ReasonCode> = audit.iter().map(|rejection| rejection.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                ReasonCode::InsufficientFunds,
+                ReasonCode::TxNotFound,
+                ReasonCode::DuplicateTx,
+                ReasonCode::AccountLocked,
+                ReasonCode::InsufficientFunds,
+            ]
+        );
+        assert_eq!(audit.counts()[&ReasonCode::InsufficientFunds], 2);
+        assert_eq!(audit.of_client(2).count(), 1);
+        assert_eq!(
+            audit
+                .with_code(ReasonCode::AccountLocked)
+                .next()
+                .unwrap()
+                .tx,
+            3
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn rejections_exported_as_csv() {
+        let registry = TransactionRegistry::new();
+        let mut audit = RejectionAudit::new();
+        let withdrawal = registry.create_named("withdrawal", 1, 2, 1.5).unwrap();
+        let dispute = registry.create_named("dispute", 1, 7, 0.0).unwrap();
+        audit.record(
+            withdrawal.as_ref(),
+            &TransactionError::new(ErrorKind::InsufficientFunds, "Insufficent funds!".into()),
+            Some(1_700_000_000),
+        );
+        audit.record(
+            dispute.as_ref(),
+            &TransactionError::new(ErrorKind::TransactionNotFound, "No transaction, 7".into()),
+            None,
+        );
+
+        let mut csv = Vec::new();
+        audit.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "code,client,tx,type,amount,timestamp,message\n\
+             INSUFFICIENT_FUNDS,1,2,withdrawal,1.5,1700000000,Insufficent funds!\n\
+             TX_NOT_FOUND,1,7,dispute,,,\"No transaction, 7\"\n"
+        );
+    }
+}
@@ -1,31 +1,1405 @@
-use std::{env, fs::File, io::BufReader};
-
-use transactions::client::ClientStore;
-use transactions::handle_transactions_from_reader;
-
-/// Execute transactions and output the final state of all clients. 
-/// 
-/// expects a single command line arguement be a path to a csv file which contains
-/// the transactions to execute. 
-/// 
-/// When all transactions are complete, the final state is printed to stdout.
-/// If errors occur while handling transactions, theses errors are printed to 
-/// stderr.
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, IsTerminal, Read, StdoutLock, Write},
+    path::{Path, PathBuf},
+    process,
+    time::Instant,
+};
+
+use transactions::amount::RoundingMode;
+use transactions::atomic::AtomicFile;
+use transactions::client::{ClientStore, MemoryFootprint};
+use transactions::clock::{Clock, SystemClock};
+#[cfg(not(target_arch = "wasm32"))]
+use transactions::diagnostics::{DiagnosticsReport, KindLatency};
+#[cfg(feature = "encryption")]
+use transactions::encryption::{EncryptedReader, EncryptedWriter, EncryptionKey};
+use transactions::error::{Severity, TransactionError};
+#[cfg(not(target_arch = "wasm32"))]
+use transactions::generator::{Generator, GeneratorOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use transactions::loadtest::{run_load_test, LoadTestOptions};
+#[cfg(feature = "toml")]
+use transactions::output::TomlFormat;
+#[cfg(feature = "yaml")]
+use transactions::output::YamlFormat;
+use transactions::output::{
+    shard_path, CompressedWriter, Compression, CsvFormat, OutputFormat, OutputOptions, ShardBy,
+    SignatureMode, TableFormat,
+};
+use transactions::report::ProcessingReport;
+use transactions::settings::Settings;
+#[cfg(feature = "signing")]
+use transactions::signing::{ReportSigner, SigningWriter};
+use transactions::statement::{handle_transactions_from_statement, StatementFormat};
+use transactions::transaction::TransactionRegistry;
+use transactions::{
+    handle_transactions_from_directory, handle_transactions_from_directory_with_rejects,
+    handle_transactions_with_rejects,
+};
+
+const USAGE: &str = "Usage: transations [--config <PATH>] [--format csv|tsv|table|yaml|toml] \
+                     [--changed-only] [--mark-changed] [--status] [--unsorted] \
+                     [--anonymize] [--anonymize-key-file <PATH>] [--decimal-places <0-6>] \
+                     [--rounding half_up|half_even|truncate] [--reject-file <PATH>] \
+                     [--audit-file <PATH>] [--dormancy-report <PATH>] [--log-level warning|error|fatal] \
+                     [--count-level warning|error|fatal] [--fail-on warning|error|fatal] [--verify] \
+                     [--load-state <PATH>] [--save-state <PATH>] [--full-state-every <COUNT>] \
+                     [--state-key-file <PATH>] [--output <PATH>] \
+                     [--output-compress none|gzip|zstd] [--shards <COUNT>] \
+                     [--shard-by range|hash] [--signing-key-file <PATH>] \
+                     [--signature detached|trailer] [--export-postgres <URL> [--export-history]] \
+                     [--client-aliases <PATH>] [--sql-query <QUERY> --sql-sequence <COLUMN>] \
+                     [--statement-client <CLIENT>] \
+                     [--retries <COUNT>] [--diagnostics <COUNT>] <FILE, DIRECTORY OR URL>";
+
+#[cfg(not(target_arch = "wasm32"))]
+const BENCH_USAGE: &str =
+    "Usage: transactions bench [--rows <COUNT>] [--clients <COUNT>] [--seed <SEED>]";
+
+#[cfg(not(target_arch = "wasm32"))]
+const LOADTEST_USAGE: &str = "Usage: transactions loadtest [--duration <SECONDS>] \
+                              [--concurrency <COUNT>] [--clients <COUNT>] \
+                              [--mix <KIND>:<WEIGHT>,...] [--seed <SEED>]";
+
+/// Execute transactions and output the final state of all clients.
+///
+/// expects a command line arguement be a path to a csv file which contains
+/// the transactions to execute, or to a directory whose csv files are executed
+/// in lexicographic order as a single input. With the `http` feature, the path
+/// may also be an `http://` or `https://` URL, and with the `object-store`
+/// feature an `s3://` or `gs://` URL, which is streamed. Failed requests are
+/// retried `--retries` times, resuming where the input was cut off. The output format can be selected with
+/// `--format`, table by default when stdout is a terminal and csv otherwise.
+/// With `--changed-only`, only clients changed by the transactions are printed.
+/// With `--mark-changed`, every client is printed with a `changed` column.
+/// With `--status`, a column with the status of every client, `active`, `frozen`
+/// or `locked`, is printed. Clients are printed sorted by their client, so
+/// repeated runs print identical output, unless `--unsorted` is given.
+/// With the `anonymize` feature, `--anonymize` replaces clients with pseudonyms,
+/// keyed hashes which are only stable within the run, or across runs sharing
+/// the secret key of `--anonymize-key-file`, so the output can be shared
+/// without revealing accounts. Anonymized clients are sorted by pseudonym.
+/// With `--decimal-places`, amounts are printed with exactly that many decimal
+/// places, rounded with the `--rounding` mode, half up by default, which also
+/// rounds accrued interest.
+/// With `--output-compress`, the output is compressed with gzip or zstd, which
+/// require the `gzip` and `zstd` features. With `--shards`, the output is
+/// partitioned into that many files named after `--output`, e.g. `state-00.csv`
+/// to `state-15.csv`, by client range or, with `--shard-by hash`, by a hash of
+/// the client.
+/// With `--reject-file`, rows which could not be parsed or were rejected are
+/// written to the file as read, with the error's code and reason in extra columns.
+/// With `--audit-file`, every rejected transaction is written to the file with
+/// a stable reason code, e.g. `INSUFFICIENT_FUNDS`, after the run.
+/// With a `dormancy_period` in the `[store]` settings, clients without activity
+/// for the period are reviewed at the end of the run, and frozen with
+/// `freeze_dormant`. With `--dormancy-report`, the dormant clients are written
+/// to the file.
+/// Problems are warnings, like duplicates, errors, like rejected rows, or fatal,
+/// like corrupt input. Only problems of `--log-level` and above are logged and
+/// of `--count-level` and above counted, all by default. With `--fail-on`, the
+/// run exits with status 2 if problems of the level and above were counted,
+/// without writing the output or saving the state.
+/// With `--verify`, the global invariants are checked with a trial balance
+/// after the run: the clients' totals add up to the deposits minus
+/// withdrawals minus chargebacks, every total is available plus held, and no
+/// held funds are negative. Broken invariants are printed and the run exits
+/// with status 3, without writing the output or saving the state.
+///
+/// With `--client-aliases`, client ids of the input are mapped to internal
+/// clients by a CSV file with `external,client` columns, so partner files
+/// identifying clients differently update the same clients.
+///
+/// With the `sql` feature and `--sql-query`, the input is the URL of a Postgres
+/// or MySQL database, e.g. `postgres://user@host/ledger`, and transactions are
+/// read by the query in the order of the `--sql-sequence` column.
+///
+/// With `--export-postgres`, the final state is also upserted into the
+/// `clients` table of a Postgres database, and with `--export-history` every
+/// history entry into the `history` table, before the state is saved.
+///
+/// Inputs with an `.ofx`, `.qfx` or `.qif` extension are bank statements,
+/// whose credits are deposited to and debits withdrawn from the
+/// `--statement-client`. With the `camt` feature, so are ISO 20022 camt.053
+/// statements with an `.xml` or `.camt` extension.
+///
+/// With `--load-state`, the run starts from the state saved by a previous run
+/// with `--save-state`, so batches can build on each other. The saved state is
+/// only replaced once the run and it's output succeeded. With
+/// `--full-state-every`, a run saving to the path it loaded from only saves
+/// the changed clients, as a delta next to the state, e.g. `state.bin.delta-000001`,
+/// and saves the state in full again after that many deltas. Loading a state
+/// applies the deltas saved on top of it. With the `encryption` feature,
+/// `--state-key-file` or `TRANSACTIONS_STATE_KEY` give a key of 64 hexadecimal
+/// digits the state and it's deltas are encrypted with, using AES-256-GCM.
+/// Spilled history entries are always encrypted with the feature.
+///
+/// Every file is written to a temporary file which atomically replaces it once
+/// complete, so an interrupted run never corrupts a previous good file.
+/// Temporary files left by interrupted runs are removed at startup.
+///
+/// With `--diagnostics`, the slowest transactions, the busiest clients and the
+/// largest histories are printed to stderr, to profile pathological inputs.
+///
+/// With the `toml` feature, `--config` reads the settings of the run, including
+/// reader options and store policies which have no flag, from a TOML file.
+/// With the `webhooks` feature, the webhook URLs of the settings are posted a
+/// JSON payload when an account gets locked, a chargeback is applied or a
+/// balance goes negative.
+/// Every setting can also be given by a `TRANSACTIONS_*` environment variable,
+/// e.g. `TRANSACTIONS_OUTPUT_FORMAT`, which the file and flags override.
+/// Flags given on the command line override the file.
+///
+/// When all transactions are complete, the final state is printed to stdout,
+/// or to the file given with `--output`.
+/// With the `signing` feature, `--signing-key-file` signs the output with an
+/// Ed25519 key, so consumers can verify it came from the engine unaltered. The
+/// signature covers the bytes of the sorted output and is written to a file
+/// named after the output with a `.sig` extension, or with `--signature trailer`
+/// appended as a `# ed25519 <SIGNATURE>` line.
+/// If errors occur while handling transactions, theses errors are printed to
+/// stderr, followed by a summary of the run once the output is written,
+/// with the time spent parsing, executing and writing the output.
+///
+/// `bench` generates transactions in memory instead, processes them and
+/// prints the throughput, peak memory and latency percentiles of every kind.
+/// `loadtest` executes random transactions of a `--mix` of kinds, e.g.
+/// `deposit:60,withdrawal:30,dispute:10`, on `--concurrency` workers for
+/// `--duration` seconds and prints the sustained throughput and error rates.
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    #[cfg(not(target_arch = "wasm32"))]
+    match env::args().nth(1).as_deref() {
+        Some("bench") => {
+            bench(env::args().skip(2));
+            return;
+        }
+        Some("loadtest") => {
+            loadtest(env::args().skip(2));
+            return;
+        }
+        _ => {}
+    }
+
+    let mut flags = Settings::default();
+    #[cfg(feature = "toml")]
+    let mut config_path = None;
+    let mut file_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            #[cfg(feature = "toml")]
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(path),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--format" => match args.next() {
+                Some(name) => flags.output.format = Some(name),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--changed-only" => flags.output.changed_only = Some(true),
+            "--mark-changed" => flags.output.mark_changed = Some(true),
+            "--status" => flags.output.status = Some(true),
+            "--verify" => flags.log.verify = Some(true),
+            "--unsorted" => flags.output.sort = Some(false),
+            "--anonymize" => flags.output.anonymize = Some(true),
+            "--anonymize-key-file" => match args.next() {
+                Some(path) => flags.output.anonymize_key_file = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--decimal-places" => match args.next().and_then(|places| places.parse().ok()) {
+                Some(places) => flags.output.decimal_places = Some(places),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--rounding" => match args.next().as_deref() {
+                Some("half_up") => flags.output.rounding = Some(RoundingMode::HalfUp),
+                Some("half_even") => flags.output.rounding = Some(RoundingMode::HalfEven),
+                Some("truncate") => flags.output.rounding = Some(RoundingMode::Truncate),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--reject-file" => match args.next() {
+                Some(path) => flags.log.reject_file = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--audit-file" => match args.next() {
+                Some(path) => flags.log.audit_file = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--dormancy-report" => match args.next() {
+                Some(path) => flags.log.dormancy_report = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--log-level" => match severity(args.next()) {
+                Some(level) => flags.log.level = Some(level),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--count-level" => match severity(args.next()) {
+                Some(level) => flags.log.count_level = Some(level),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--fail-on" => match severity(args.next()) {
+                Some(level) => flags.log.fail_level = Some(level),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--load-state" => match args.next() {
+                Some(path) => flags.state.load = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--save-state" => match args.next() {
+                Some(path) => flags.state.save = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--full-state-every" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => flags.state.full_every = Some(count),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--state-key-file" => match args.next() {
+                Some(path) => flags.state.key_file = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--client-aliases" => match args.next() {
+                Some(path) => flags.input.client_aliases = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--sql-query" => match args.next() {
+                Some(query) => flags.input.sql_query = Some(query),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--sql-sequence" => match args.next() {
+                Some(column) => flags.input.sql_sequence = Some(column),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--export-postgres" => match args.next() {
+                Some(url) => flags.output.postgres = Some(url),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--export-history" => flags.output.postgres_history = Some(true),
+            "--statement-client" => match args.next().and_then(|client| client.parse().ok()) {
+                Some(client) => flags.input.statement_client = Some(client),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--retries" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => flags.input.retries = Some(count),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--diagnostics" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => flags.log.diagnostics = Some(count),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--output" => match args.next() {
+                Some(path) => flags.output.path = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--output-compress" => match args.next().as_deref() {
+                Some("none") => flags.output.compress = Some(Compression::None),
+                #[cfg(feature = "gzip")]
+                Some("gzip") => flags.output.compress = Some(Compression::Gzip),
+                #[cfg(feature = "zstd")]
+                Some("zstd") => flags.output.compress = Some(Compression::Zstd),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--signing-key-file" => match args.next() {
+                Some(path) => flags.output.signing_key_file = Some(path.into()),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--signature" => match args.next().as_deref() {
+                Some("detached") => flags.output.signature = Some(SignatureMode::Detached),
+                Some("trailer") => flags.output.signature = Some(SignatureMode::Trailer),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--shards" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => flags.output.shards = Some(count),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--shard-by" => match args.next().as_deref() {
+                Some("range") => flags.output.shard_by = Some(ShardBy::Range),
+                Some("hash") => flags.output.shard_by = Some(ShardBy::Hash),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            _ if file_path.is_none() => file_path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return;
+            }
+        }
+    }
+    let file_path = match file_path {
+        Some(file_path) => file_path,
+        None => {
+            eprintln!("{}", USAGE);
+            return;
+        }
+    };
+
+    let environment = match Settings::from_env() {
+        Ok(environment) => environment,
+        Err(err) => {
+            eprintln!("Invalid environment: {}", err);
+            process::exit(1);
+        }
+    };
+    #[cfg(feature = "toml")]
+    let environment = match config_path {
+        Some(config_path) => match std::fs::read_to_string(&config_path)
+            .map_err(TransactionError::from)
+            .and_then(|config| Settings::from_toml(&config))
+        {
+            Ok(config) => environment.merge(config),
+            Err(err) => {
+                eprintln!("Couldn't load config from {}: {}", config_path, err);
+                process::exit(1);
+            }
+        },
+        None => environment,
+    };
+    let settings = environment.merge(flags);
+    let parse_options = match settings.parse_options() {
+        Ok(parse_options) => parse_options,
+        Err(err) => {
+            eprintln!("Invalid input settings: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let options = match settings.output_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Invalid output settings: {}", err);
+            process::exit(1);
+        }
+    };
+    let signer = match report_signer(&settings) {
+        Ok(signer) => signer,
+        Err(err) => {
+            eprintln!("Invalid output settings: {}", err);
+            process::exit(1);
+        }
+    };
+    let signature_mode = settings.output.signature.unwrap_or_default();
+    if signer.is_some() {
+        // Unsorted clients are in no particular order, which consumers can not reproduce
+        let invalid = if !options.sort {
+            Some("a signed output must be sorted")
+        } else if signature_mode == SignatureMode::Trailer
+            && settings.output.compress.unwrap_or_default() != Compression::None
+        {
+            Some("a signature trailer needs uncompressed output")
+        } else if signature_mode == SignatureMode::Detached && settings.output.path.is_none() {
+            Some("a detached signature needs --output")
+        } else {
+            None
+        };
+        if let Some(invalid) = invalid {
+            eprintln!("Invalid output settings: {}", invalid);
+            process::exit(1);
+        }
+    }
+    #[cfg(feature = "anonymize")]
+    if options.pseudonymizer.is_some() && settings.output.shards.is_some() {
+        // Shards are partitioned by the real clients
+        eprintln!("Invalid output settings: an anonymized output can not be sharded");
+        process::exit(1);
+    }
+    if settings.log.dormancy_report.is_some() && settings.store.dormancy_period.is_none() {
+        eprintln!("Invalid store settings: a dormancy report needs a dormancy period");
+        process::exit(1);
+    }
+    #[cfg(not(feature = "sql"))]
+    if settings.output.postgres.is_some() {
+        eprintln!("Invalid output settings: exporting to Postgres needs the sql feature");
+        process::exit(1);
+    }
+    let output_paths: Vec<PathBuf> = match (&settings.output.path, settings.output.shards) {
+        (Some(path), Some(shards)) => (0..shards)
+            .map(|shard| shard_path(path, shard, shards))
+            .collect(),
+        (path, _) => path.iter().cloned().collect(),
+    };
+    let signature_paths: Vec<PathBuf> = match (&signer, signature_mode) {
+        (Some(_), SignatureMode::Detached) => output_paths
+            .iter()
+            .map(|path| signature_path(path))
+            .collect(),
+        _ => Vec::new(),
+    };
+    for path in output_paths
+        .iter()
+        .chain(&signature_paths)
+        .chain(&settings.log.reject_file)
+        .chain(&settings.log.audit_file)
+        .chain(&settings.log.dormancy_report)
+        .chain(&settings.state.save)
+    {
+        if let Err(err) = AtomicFile::remove_stale(path) {
+            eprintln!(
+                "Couldn't remove stale temporary files of {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    let (outputs, terminal): (Vec<Output>, bool) =
+        match (&settings.output.path, settings.output.shards) {
+            (_, Some(0)) | (None, Some(_)) => {
+                eprintln!(
+                    "Invalid output settings: --shards needs at least one shard and --output"
+                );
+                process::exit(1);
+            }
+            (Some(_), _) => (
+                output_paths
+                    .iter()
+                    .map(|path| Output::File(create_file(path)))
+                    .collect(),
+                false,
+            ),
+            (None, None) => {
+                let stdout = io::stdout();
+                let terminal = stdout.is_terminal();
+                (vec![Output::Stdout(stdout.lock())], terminal)
+            }
+        };
+    #[cfg(feature = "signing")]
+    let outputs: Vec<Output> = match &signer {
+        Some(signer) => outputs
+            .into_iter()
+            .map(|output| {
+                let signed = Box::new(SigningWriter::new(output));
+                Output::Signed(signed, signer.clone(), signature_mode)
+            })
+            .collect(),
+        None => outputs,
+    };
+    let compression = settings.output.compress.unwrap_or_default();
+    // Compressed output is never meant to be read on a terminal
+    let terminal = terminal && compression == Compression::None;
+    let mut outputs = match outputs
+        .into_iter()
+        .map(|output| CompressedWriter::new(output, compression))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(outputs) => outputs,
+        Err(err) => {
+            eprintln!("Couldn't compress output: {}", err);
+            process::exit(1);
+        }
+    };
+    let format_name = settings
+        .output
+        .format
+        .clone()
+        .unwrap_or_else(|| if terminal { "table" } else { "csv" }.to_owned());
+    let mut formats = match outputs
+        .iter_mut()
+        .map(|output| new_format(&format_name, output, &options, terminal))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(formats) => formats,
+        None => {
+            eprintln!("Unknown format {}\n{}", format_name, USAGE);
+            return;
+        }
+    };
+
+    let state_key = match state_key(&settings) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Invalid state settings: {}", err);
+            process::exit(1);
+        }
+    };
+    let mut client_store = ClientStore::with_config(settings.store_config());
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(top) = settings.log.diagnostics {
+        client_store.enable_diagnostics(top);
+    }
+    if let Some(load_path) = &settings.state.load {
+        let deltas = settings.state.full_every.is_some();
+        let loaded = load_state(&mut client_store, load_path, deltas, state_key.as_ref());
+        if let Err(err) = loaded {
+            eprintln!("Couldn't load state from {}: {}", load_path.display(), err);
+            process::exit(1);
+        }
+    }
+
+    if settings.log.audit_file.is_some() {
+        client_store.enable_audit();
+    }
+    for (client, tier) in settings.tier_assignments() {
+        client_store.set_tier(client, tier);
+    }
+    for client in settings.verified_clients() {
+        client_store.set_verified(*client, true);
+    }
+    if let Some(urls) = settings
+        .webhooks
+        .urls
+        .clone()
+        .filter(|urls| !urls.is_empty())
+    {
+        #[cfg(feature = "webhooks")]
+        client_store.add_observer(transactions::webhook::WebhookNotifier::new(
+            urls,
+            settings.webhooks.retries.unwrap_or_default(),
+        ));
+        #[cfg(not(feature = "webhooks"))]
+        {
+            eprintln!(
+                "Invalid webhook settings: {} URLs need the webhooks feature",
+                urls.len()
+            );
+            process::exit(1);
+        }
+    }
+
+    let registry = TransactionRegistry::new();
+    let mut rejects = settings.log.reject_file.as_ref().map(create_file);
+    let mut report = if let Some(query) = &settings.input.sql_query {
+        if rejects.is_some() {
+            eprintln!("Invalid input settings: SQL input has no rows to write to a reject file");
+            process::exit(1);
+        }
+        let Some(sequence) = &settings.input.sql_sequence else {
+            eprintln!("Invalid input settings: a SQL query needs a sequence column");
+            process::exit(1);
+        };
+        #[cfg(feature = "sql")]
+        match transactions::sql::handle_transactions_from_sql(
+            &transactions::sql::SqlSource::new(&file_path, query, sequence),
+            &registry,
+            &parse_options,
+            &mut client_store,
+        ) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("Couldn't handle transactions of {}: {}", file_path, err);
+                process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "sql"))]
+        {
+            let _ = (query, sequence);
+            eprintln!("Invalid input settings: SQL input needs the sql feature");
+            process::exit(1);
+        }
+    } else if let Some(format) = StatementFormat::from_path(Path::new(&file_path)) {
+        if rejects.is_some() {
+            eprintln!("Invalid input settings: statements have no rows to write to a reject file");
+            process::exit(1);
+        }
+        let Some(client) = settings.input.statement_client else {
+            eprintln!("Invalid input settings: a statement needs a client");
+            process::exit(1);
+        };
+        let result =
+            open_input(&file_path, settings.input.retries.unwrap_or_default()).and_then(|reader| {
+                handle_transactions_from_statement(
+                    reader,
+                    format,
+                    client,
+                    &registry,
+                    &parse_options,
+                    &mut client_store,
+                )
+            });
+        match result {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("Couldn't handle statement {}: {}", file_path, err);
+                process::exit(1);
+            }
+        }
+    } else if Path::new(&file_path).is_dir() {
+        let report = match rejects.as_mut() {
+            Some(rejects) => handle_transactions_from_directory_with_rejects(
+                &file_path,
+                &registry,
+                &parse_options,
+                &mut client_store,
+                rejects,
+            ),
+            None => handle_transactions_from_directory(
+                &file_path,
+                &registry,
+                &parse_options,
+                &mut client_store,
+            ),
+        };
+        match report {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("Couldn't handle transactions in {}: {}", file_path, err);
+                process::exit(1);
+            }
+        }
+    } else {
+        let reader = match open_input(&file_path, settings.input.retries.unwrap_or_default()) {
+            Ok(reader) => reader,
+            Err(err) => {
+                eprintln!("Couldn't open {}: {}", file_path, err);
+                process::exit(1);
+            }
+        };
+        match rejects.as_mut() {
+            Some(rejects) => handle_transactions_with_rejects(
+                reader,
+                &registry,
+                &parse_options,
+                &mut client_store,
+                rejects,
+            ),
+            // Rows are parsed on a dedicated thread while they are applied
+            #[cfg(not(target_arch = "wasm32"))]
+            None => transactions::parallel::handle_transactions_pipelined(
+                reader,
+                &registry,
+                &parse_options,
+                &mut client_store,
+            ),
+            #[cfg(target_arch = "wasm32")]
+            None => transactions::handle_transactions_with_parse_options(
+                reader,
+                &registry,
+                &parse_options,
+                &mut client_store,
+            ),
+        }
+    };
+    if let Some(rejects) = rejects {
+        commit(rejects);
+    }
+    // Reviewed before the output, so clients frozen for dormancy are output frozen
+    if settings.store.dormancy_period.is_some() {
+        let report = client_store.review_dormancy(SystemClock.now());
+        if let Some(path) = &settings.log.dormancy_report {
+            let mut file = create_file(path);
+            if let Err(err) = report.write_csv(&mut file) {
+                eprintln!("Couldn't write {}: {}", path.display(), err);
+                process::exit(1);
+            }
+            commit(file);
+        }
+    }
+    if let (Some(path), Some(audit)) = (&settings.log.audit_file, client_store.audit()) {
+        let mut file = create_file(path);
+        if let Err(err) = audit.write_csv(&mut file) {
+            eprintln!("Couldn't write {}: {}", path.display(), err);
+            process::exit(1);
+        }
+        commit(file);
+    }
+
+    // Checked before the output and state, so a failed run commits neither
+    if settings.log.verify.unwrap_or_default() {
+        let balance = client_store.trial_balance();
+        for violation in &balance.violations {
+            eprintln!("Trial balance failed: {}", violation);
+        }
+        if !balance.is_balanced() {
+            process::exit(3);
+        }
+    }
+    if let Some(level) = settings.log.fail_level {
+        if report.rejected_at_least(level) > 0 {
+            process::exit(2);
+        }
+    }
 
-    if args.len() != 2 {
-        eprintln!("Usage: transations <FILE PATH>");
-        return;
+    let output_start = Instant::now();
+    let shard_by = settings.output.shard_by.unwrap_or_default();
+    let written = if let [format] = formats.as_mut_slice() {
+        client_store.write_state_with_options(format.as_mut(), &options)
+    } else if options.changed_only {
+        client_store.write_changed_sharded_state(&mut formats, shard_by, options.sort)
+    } else {
+        client_store.write_sharded_state(&mut formats, shard_by, options.sort)
+    };
+    if let Err(err) = written {
+        eprintln!("Couldn't write output: {}", err);
+        process::exit(1);
     }
+    drop(formats);
+    for output in outputs {
+        match output.finish() {
+            Ok(output) => finish_output(output),
+            Err(err) => {
+                eprintln!("Couldn't finish output: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+    report.timings.output = output_start.elapsed();
+    if settings.log.summary.unwrap_or(true) {
+        print_summary(
+            &report,
+            client_store.changed_clients().count(),
+            client_store.memory_footprint(),
+        );
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(diagnostics) = client_store.diagnostics() {
+        print_diagnostics(&diagnostics);
+    }
+
+    #[cfg(feature = "sql")]
+    if let Some(url) = &settings.output.postgres {
+        let mut exporter = transactions::sql::PgExporter::new(url);
+        if settings.output.postgres_history.unwrap_or_default() {
+            exporter = exporter.history_table("history");
+        }
+        if let Err(err) = exporter.export(&client_store) {
+            eprintln!("Couldn't export state to Postgres: {}", err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(save_path) = &settings.state.save {
+        // Deltas only continue the state they were loaded on top of
+        let deltas = settings
+            .state
+            .full_every
+            .filter(|_| settings.state.load.as_ref() == Some(save_path));
+        if let Err(err) = save_state(&mut client_store, save_path, deltas, state_key.as_ref()) {
+            eprintln!("Couldn't save state to {}: {}", save_path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parse the severity level of a flag.
+fn severity(level: Option<String>) -> Option<Severity> {
+    match level.as_deref() {
+        Some("warning") => Some(Severity::Warning),
+        Some("error") => Some(Severity::Error),
+        Some("fatal") => Some(Severity::Fatal),
+        _ => None,
+    }
+}
+
+/// Destination of the final state
+enum Output<'a> {
+    /// Standard output of the process
+    Stdout(StdoutLock<'a>),
+    /// File replaced once the output is complete
+    File(AtomicFile),
+    /// Output signed with the signer once complete
+    #[cfg(feature = "signing")]
+    Signed(Box<SigningWriter<Output<'a>>>, ReportSigner, SignatureMode),
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::File(file) => file.write(buf),
+            #[cfg(feature = "signing")]
+            Self::Signed(signed, ..) => signed.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::File(file) => file.flush(),
+            #[cfg(feature = "signing")]
+            Self::Signed(signed, ..) => signed.flush(),
+        }
+    }
+}
+
+/// Complete an output, signing it if signed, exiting if it can't be completed.
+fn finish_output(output: Output) {
+    match output {
+        Output::File(file) => commit(file),
+        Output::Stdout(_) => {}
+        #[cfg(feature = "signing")]
+        Output::Signed(signed, signer, SignatureMode::Trailer) => {
+            match signed.finish_with_trailer(&signer) {
+                Ok(output) => finish_output(output),
+                Err(err) => {
+                    eprintln!("Couldn't sign output: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        #[cfg(feature = "signing")]
+        Output::Signed(signed, signer, SignatureMode::Detached) => {
+            let (output, signature) = signed.finish(&signer);
+            let path = match &output {
+                Output::File(file) => Some(signature_path(file.path())),
+                _ => None,
+            };
+            finish_output(output);
+            // The signature is only replaced once the output it signs is
+            if let Some(path) = path {
+                let mut file = create_file(&path);
+                if let Err(err) = writeln!(file, "{}", signature) {
+                    eprintln!("Couldn't write {}: {}", path.display(), err);
+                    process::exit(1);
+                }
+                commit(file);
+            }
+        }
+    }
+}
+
+/// Get the path of the detached signature of an output, e.g. `state.csv.sig`
+fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sig");
+    name.into()
+}
+
+/// Stand-in for the signer of outputs, which needs the `signing` feature
+#[cfg(not(feature = "signing"))]
+type ReportSigner = std::convert::Infallible;
+
+/// Get the signer of the output, if any.
+#[cfg(feature = "signing")]
+fn report_signer(settings: &Settings) -> Result<Option<ReportSigner>, TransactionError> {
+    match (
+        &settings.output.signing_key,
+        &settings.output.signing_key_file,
+    ) {
+        (Some(key), _) => ReportSigner::from_hex(key).map(Some),
+        (None, Some(path)) => ReportSigner::from_file(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Get the signer of the output, if any.
+#[cfg(not(feature = "signing"))]
+fn report_signer(settings: &Settings) -> Result<Option<ReportSigner>, TransactionError> {
+    if settings.output.signing_key.is_some() || settings.output.signing_key_file.is_some() {
+        return Err("signing the output needs the signing feature".into());
+    }
+    Ok(None)
+}
+
+/// Create an output file, exiting if it can't be created.
+fn create_file<P>(path: P) -> AtomicFile
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match AtomicFile::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Couldn't create {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Replace the path of an output file with it, exiting if it can't be replaced.
+fn commit(file: AtomicFile) {
+    let path = file.path().to_path_buf();
+    if let Err(err) = file.commit() {
+        eprintln!("Couldn't write {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+/// Create the output format of the name writing to the writer.
+///
+/// Returns None if the format is unknown.
+fn new_format<'w, W>(
+    name: &str,
+    writer: W,
+    options: &OutputOptions,
+    terminal: bool,
+) -> Option<Box<dyn OutputFormat + 'w>>
+where
+    W: Write + 'w,
+{
+    Some(match name {
+        "csv" => Box::new(CsvFormat::new(writer, options)),
+        "tsv" => Box::new(CsvFormat::tsv(writer, options)),
+        "table" => Box::new(TableFormat::new(writer, options, terminal)),
+        #[cfg(feature = "yaml")]
+        "yaml" => Box::new(YamlFormat::new(writer, options)),
+        #[cfg(feature = "toml")]
+        "toml" => Box::new(TomlFormat::new(writer, options)),
+        _ => return None,
+    })
+}
+
+/// Open the input, a local file or, with the `http` feature, a URL.
+///
+/// Failed requests of a URL are retried up to the number of retries.
+fn open_input(path: &str, retries: u32) -> Result<Box<dyn Read + Send>, TransactionError> {
+    #[cfg(feature = "http")]
+    if transactions::remote::is_url(path) {
+        let reader = transactions::remote::open_url(path, retries)?;
+        return Ok(Box::new(BufReader::new(reader)));
+    }
+    #[cfg(not(feature = "http"))]
+    let _ = retries;
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+}
+
+/// Stand-in for the key of encrypted states, which need the `encryption` feature
+#[cfg(not(feature = "encryption"))]
+type EncryptionKey = std::convert::Infallible;
+
+/// Get the key the state is encrypted with, if any.
+#[cfg(feature = "encryption")]
+fn state_key(settings: &Settings) -> Result<Option<EncryptionKey>, TransactionError> {
+    match (&settings.state.key, &settings.state.key_file) {
+        (Some(key), _) => EncryptionKey::from_hex(key).map(Some),
+        (None, Some(path)) => EncryptionKey::from_file(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Get the key the state is encrypted with, if any.
+#[cfg(not(feature = "encryption"))]
+fn state_key(settings: &Settings) -> Result<Option<EncryptionKey>, TransactionError> {
+    if settings.state.key.is_some() || settings.state.key_file.is_some() {
+        return Err("an encryption key needs the encryption feature".into());
+    }
+    Ok(None)
+}
+
+/// Open a state file, decrypting it with the key if any.
+fn open_state(path: &Path, key: Option<&EncryptionKey>) -> Result<Box<dyn Read>, TransactionError> {
+    let file = BufReader::new(File::open(path)?);
+    match key {
+        #[cfg(feature = "encryption")]
+        Some(key) => Ok(Box::new(EncryptedReader::new(file, key)?)),
+        #[cfg(not(feature = "encryption"))]
+        Some(never) => match *never {},
+        None => Ok(Box::new(file)),
+    }
+}
+
+/// Atomically replace a state file with what is written, encrypted with the key if any.
+fn write_state_file<F>(
+    path: &Path,
+    key: Option<&EncryptionKey>,
+    write: F,
+) -> Result<(), TransactionError>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), TransactionError>,
+{
+    let mut file = AtomicFile::create(path)?;
+    match key {
+        #[cfg(feature = "encryption")]
+        Some(key) => {
+            let mut encrypted = EncryptedWriter::new(&mut file, key)?;
+            write(&mut encrypted)?;
+            let _ = encrypted.finish()?;
+        }
+        #[cfg(not(feature = "encryption"))]
+        Some(never) => match *never {},
+        None => write(&mut file)?,
+    }
+    file.commit()
+}
+
+/// Load the state saved at the path, with the deltas saved on top of it.
+///
+/// Changes of the store are tracked for the next delta if deltas are saved.
+fn load_state(
+    client_store: &mut ClientStore,
+    path: &Path,
+    deltas: bool,
+    key: Option<&EncryptionKey>,
+) -> Result<(), TransactionError> {
+    let state = open_state(path, key)?;
+    let paths = state_deltas(path)?;
+    if paths.is_empty() && !deltas {
+        return client_store.load_state(state);
+    }
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        files.push(open_state(&path, key)?);
+    }
+    client_store
+        .load_state_with_deltas(state, files)
+        .map(|_applied| ())
+}
+
+/// Save the state of the store, atomically replacing any previous state.
+///
+/// The state is written to a temporary file next to the path, which
+/// replaces the path only once it is completely written. With a number of
+/// deltas, only the changes since the state was loaded are saved, as the next
+/// delta next to the path, until that many deltas were saved. Deltas of a
+/// replaced state are removed.
+fn save_state(
+    client_store: &mut ClientStore,
+    path: &Path,
+    full_every: Option<u32>,
+    key: Option<&EncryptionKey>,
+) -> Result<(), TransactionError> {
+    if let (Some(full_every), Some(deltas)) = (full_every, client_store.checkpoint_deltas()) {
+        if deltas < full_every {
+            return write_state_file(&delta_path(path, deltas + 1), key, |writer| {
+                client_store.save_delta(writer).map(|_sequence| ())
+            });
+        }
+    }
+    write_state_file(path, key, |writer| client_store.save_state(writer))?;
+    for delta in state_deltas(path)? {
+        fs::remove_file(delta)?;
+    }
+    Ok(())
+}
+
+/// Get the path of a delta of the state at the path, by it's sequence number
+fn delta_path(path: &Path, sequence: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".delta-{:06}", sequence));
+    name.into()
+}
+
+/// Get the paths of the deltas saved next to the state at the path, in order
+fn state_deltas(path: &Path) -> Result<Vec<PathBuf>, TransactionError> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}.delta-", name);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut deltas = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let sequence = entry
+            .file_name()
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(&prefix)?.parse::<u32>().ok());
+        if let Some(sequence) = sequence {
+            deltas.push((sequence, entry.path()));
+        }
+    }
+    deltas.sort_unstable();
+    Ok(deltas.into_iter().map(|(_sequence, path)| path).collect())
+}
+
+/// Print a summary of the run to stderr.
+fn print_summary(report: &ProcessingReport, clients: usize, memory: MemoryFootprint) {
+    let reasons: Vec<String> = report
+        .rejected_by_kind
+        .iter()
+        .map(|(kind, count)| format!("{:?}: {}", kind, count))
+        .collect();
+    eprintln!(
+        "Read {} rows, applied {}, rejected {}{}",
+        report.rows_read,
+        report.applied,
+        report.rejected,
+        if reasons.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", reasons.join(", "))
+        }
+    );
+    for (path, file) in &report.files {
+        eprintln!(
+            "  {}: read {} rows, applied {}, rejected {}",
+            path.display(),
+            file.rows_read,
+            file.applied,
+            file.rejected
+        );
+    }
+    let timings = &report.timings;
+    eprintln!(
+        "Touched {} clients in {:.3}s ({:.0} rows/sec, {}/sec)",
+        clients,
+        timings.elapsed.as_secs_f64(),
+        report.rows_per_sec(),
+        human_bytes(report.bytes_per_sec() as usize)
+    );
+    eprintln!(
+        "Spent {:.3}s parsing, {:.3}s executing and {:.3}s writing the output",
+        timings.parse.as_secs_f64(),
+        timings.execute.as_secs_f64(),
+        timings.output.as_secs_f64()
+    );
+    eprintln!(
+        "Estimated memory {} (clients {}, histories {}, holds {}, tracking {})",
+        human_bytes(memory.total()),
+        human_bytes(memory.clients),
+        human_bytes(memory.histories),
+        human_bytes(memory.holds),
+        human_bytes(memory.tracking)
+    );
+}
+
+/// Format bytes in the largest binary unit they fill, e.g. `1.5 MiB`.
+fn human_bytes(bytes: usize) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return format!("{:.1} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
+/// Print the diagnostics of the run to stderr.
+#[cfg(not(target_arch = "wasm32"))]
+fn print_diagnostics(diagnostics: &DiagnosticsReport) {
+    eprintln!("Slowest transactions:");
+    for slow in &diagnostics.slowest {
+        eprintln!(
+            "  #{} {:?} tx {} of client {}: {:.3}ms",
+            slow.index,
+            slow.kind,
+            slow.transaction_id,
+            slow.client,
+            slow.elapsed.as_secs_f64() * 1000.0
+        );
+    }
+    eprintln!("Busiest clients:");
+    for (client, count) in &diagnostics.busiest {
+        eprintln!("  client {}: {} transactions", client, count);
+    }
+    eprintln!(
+        "Largest histories ({} entries in memory):",
+        diagnostics.history_entries
+    );
+    for (client, entries) in &diagnostics.largest_histories {
+        eprintln!("  client {}: {} entries", client, entries);
+    }
+    eprintln!("Latency by kind:");
+    for latency in &diagnostics.latencies {
+        eprintln!("  {}", format_latency(latency));
+    }
+}
+
+/// Format the wall time percentiles of a kind of transaction.
+#[cfg(not(target_arch = "wasm32"))]
+fn format_latency(latency: &KindLatency) -> String {
+    let millis = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+    format!(
+        "{}: {} transactions, p50 {:.4}ms, p90 {:.4}ms, p99 {:.4}ms, max {:.4}ms",
+        latency.kind.name(),
+        latency.count,
+        millis(latency.p50),
+        millis(latency.p90),
+        millis(latency.p99),
+        millis(latency.max)
+    )
+}
+
+/// Generate transactions in memory, process them and print the performance.
+///
+/// Prints the throughput in rows per second, the peak memory of the process
+/// and the latency percentiles of every kind of transaction, so performance
+/// regressions are easy to detect locally. The peak memory includes the
+/// generated input.
+#[cfg(not(target_arch = "wasm32"))]
+fn bench(mut args: impl Iterator<Item = String>) {
+    let mut options = GeneratorOptions {
+        clients: 1000,
+        rows: 1_000_000,
+        ..Default::default()
+    };
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let parsed = match arg.as_str() {
+            "--rows" => value
+                .and_then(|rows| rows.parse().ok())
+                .map(|rows| options.rows = rows),
+            "--clients" => value
+                .and_then(|clients| clients.parse().ok())
+                .map(|clients| options.clients = clients),
+            "--seed" => value
+                .and_then(|seed| seed.parse().ok())
+                .map(|seed| options.seed = seed),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", BENCH_USAGE);
+            return;
+        }
+    }
+
+    let start = Instant::now();
+    let mut input = Vec::new();
+    Generator::new(options.clone()).write(&mut input).unwrap();
+    println!(
+        "Generated {} rows for {} clients ({:.1} MB) in {:.3}s",
+        options.rows,
+        options.clients,
+        input.len() as f64 / 1_000_000.0,
+        start.elapsed().as_secs_f64()
+    );
 
-    let file_path = &args[1];
-    let f = File::open(file_path).unwrap();
-    let reader = BufReader::new(f);
     let mut client_store = ClientStore::new();
+    client_store.enable_diagnostics(0);
+    let start = Instant::now();
+    let report = transactions::handle_transactions_from_reader(input.as_slice(), &mut client_store);
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Processed {} rows in {:.3}s ({:.0} rows/sec), applied {}, rejected {}",
+        report.rows_read,
+        seconds,
+        report.rows_read as f64 / seconds.max(f64::EPSILON),
+        report.applied,
+        report.rejected
+    );
+    match peak_memory() {
+        Some(bytes) => println!("Peak memory: {:.1} MB", bytes as f64 / 1_000_000.0),
+        None => println!("Peak memory: unavailable"),
+    }
+    println!(
+        "Store memory: {:.1} MB",
+        client_store.memory_footprint().total() as f64 / 1_000_000.0
+    );
+    println!("Latency by kind:");
+    for latency in client_store
+        .diagnostics()
+        .map(|diagnostics| diagnostics.latencies)
+        .unwrap_or_default()
+    {
+        println!("  {}", format_latency(&latency));
+    }
+}
 
-    handle_transactions_from_reader(reader, &mut client_store);
+/// Execute random transactions for a duration and print the throughput and error rates.
+///
+/// Clients are partitioned across the workers, each with it's own store, so
+/// the throughput shows how the engine scales with sharded stores.
+#[cfg(not(target_arch = "wasm32"))]
+fn loadtest(mut args: impl Iterator<Item = String>) {
+    let mut options = LoadTestOptions::default();
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let parsed = match arg.as_str() {
+            "--duration" => value
+                .and_then(|seconds| seconds.parse().ok())
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                .map(|duration| options.duration = duration),
+            "--concurrency" => value
+                .and_then(|workers| workers.parse().ok())
+                .filter(|workers| *workers > 0)
+                .map(|workers| options.concurrency = workers),
+            "--clients" => value
+                .and_then(|clients| clients.parse().ok())
+                .map(|clients| options.clients = clients),
+            "--mix" => value
+                .and_then(|mix| mix.parse().ok())
+                .map(|mix| options.mix = mix),
+            "--seed" => value
+                .and_then(|seed| seed.parse().ok())
+                .map(|seed| options.seed = seed),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", LOADTEST_USAGE);
+            return;
+        }
+    }
+
+    println!(
+        "Load testing {} clients on {} workers for {:.1}s",
+        options.clients,
+        options.concurrency,
+        options.duration.as_secs_f64()
+    );
+    println!("{}", run_load_test(&options));
+}
 
-    let final_state = client_store.get_current_state(false).unwrap();
-    println!("{}", final_state);
+/// Peak resident memory of the process in bytes, where the platform reports it.
+#[cfg(not(target_arch = "wasm32"))]
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
 }
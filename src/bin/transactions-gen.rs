@@ -0,0 +1,65 @@
+use std::{
+    env,
+    io::{self, BufWriter},
+    str::FromStr,
+};
+
+use transactions::generator::{Generator, GeneratorOptions};
+
+const USAGE: &str = "Usage: transactions-gen [--clients N] [--rows N] [--dispute-ratio R] \
+[--resolve-ratio R] [--chargeback-ratio R] [--insufficient-funds-ratio R] [--seed N]";
+
+/// Parse the value of an option, printing the usage if it is missing or invalid.
+fn value<T>(name: &str, value: Option<String>) -> Option<T>
+where
+    T: FromStr,
+{
+    match value.map(|value| value.parse()) {
+        Some(Ok(value)) => Some(value),
+        _ => {
+            eprintln!("Invalid value for {}\n{}", name, USAGE);
+            None
+        }
+    }
+}
+
+/// Generate random transactions for benchmarks and load tests.
+///
+/// The generated CSV is printed to stdout. Client count, row count, the
+/// rates of disputes, their settlements and insufficient funds withdrawals
+/// can be set, as well as a seed to reproduce a previous file.
+fn main() {
+    let mut options = GeneratorOptions::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--clients" => value(&arg, args.next()).map(|value| options.clients = value),
+            "--rows" => value(&arg, args.next()).map(|value| options.rows = value),
+            "--dispute-ratio" => {
+                value(&arg, args.next()).map(|value| options.dispute_ratio = value)
+            }
+            "--resolve-ratio" => {
+                value(&arg, args.next()).map(|value| options.resolve_ratio = value)
+            }
+            "--chargeback-ratio" => {
+                value(&arg, args.next()).map(|value| options.chargeback_ratio = value)
+            }
+            "--insufficient-funds-ratio" => {
+                value(&arg, args.next()).map(|value| options.insufficient_funds_ratio = value)
+            }
+            "--seed" => value(&arg, args.next()).map(|value| options.seed = value),
+            _ => {
+                eprintln!("{}", USAGE);
+                None
+            }
+        };
+        if parsed.is_none() {
+            return;
+        }
+    }
+
+    let stdout = io::stdout();
+    Generator::new(options)
+        .write(BufWriter::new(stdout.lock()))
+        .unwrap();
+}
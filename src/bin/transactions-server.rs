@@ -0,0 +1,125 @@
+use std::{env, fs::File, io::BufReader, process};
+
+use tiny_http::{Header, Method, Request, Response, Server};
+use transactions::{
+    client::ClientStore, error::TransactionError, graphql::QueryApi,
+    handle_transactions_from_reader,
+};
+
+const USAGE: &str =
+    "Usage: transactions-server [--listen ADDR] [--load-state PATH] [--schema] [INPUT.csv ...]";
+
+/// Serve the GraphQL query API over the clients of the inputs.
+///
+/// The state saved at `--load-state` is loaded and the CSV inputs are
+/// processed before the server starts listening. Queries are POSTed as
+/// JSON to `/graphql` on `--listen`, `127.0.0.1:8080` by default.
+/// `--schema` prints the schema instead of serving it.
+fn main() {
+    let mut listen = "127.0.0.1:8080".to_owned();
+    let mut state_path = None;
+    let mut schema = false;
+    let mut inputs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => match args.next() {
+                Some(addr) => listen = addr,
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--load-state" => match args.next() {
+                Some(path) => state_path = Some(path),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--schema" => schema = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("{}", USAGE);
+                return;
+            }
+            _ => inputs.push(arg),
+        }
+    }
+
+    let mut client_store = ClientStore::new();
+    if let Some(path) = state_path {
+        let loaded = File::open(&path)
+            .map_err(TransactionError::from)
+            .and_then(|file| client_store.load_state(BufReader::new(file)));
+        if let Err(err) = loaded {
+            eprintln!("Couldn't load state from {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    for path in inputs {
+        match File::open(&path) {
+            Ok(file) => {
+                let report =
+                    handle_transactions_from_reader(BufReader::new(file), &mut client_store);
+                eprintln!(
+                    "Applied {} and rejected {} transactions of {}",
+                    report.applied, report.rejected, path
+                );
+            }
+            Err(err) => {
+                eprintln!("Couldn't open {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let api = match QueryApi::new(&client_store) {
+        Ok(api) => api,
+        Err(err) => {
+            eprintln!("Couldn't build the query API: {}", err);
+            process::exit(1);
+        }
+    };
+    if schema {
+        println!("{}", api.sdl());
+        return;
+    }
+
+    let server = match Server::http(&listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Couldn't listen on {}: {}", listen, err);
+            process::exit(1);
+        }
+    };
+    eprintln!("Serving GraphQL on http://{}/graphql", listen);
+    for request in server.incoming_requests() {
+        if let Err(err) = respond(&api, request) {
+            eprintln!("Couldn't respond: {}", err);
+        }
+    }
+}
+
+/// Answer a request, executing the GraphQL request POSTed to `/graphql`.
+fn respond(api: &QueryApi, mut request: Request) -> Result<(), TransactionError> {
+    let path = request.url().split('?').next().unwrap_or_default();
+    if path != "/graphql" {
+        return Ok(request.respond(Response::from_string("Not found").with_status_code(404))?);
+    }
+    if *request.method() != Method::Post {
+        return Ok(request.respond(
+            Response::from_string("Only POST is allowed")
+                .with_status_code(405)
+                .with_header(Header::from_bytes("Allow", "POST").unwrap()),
+        )?);
+    }
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    let response = match api.execute(&body) {
+        Ok(response) => Response::from_string(response)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()),
+        Err(err) => Response::from_string(err.to_string()).with_status_code(400),
+    };
+    Ok(request.respond(response)?)
+}
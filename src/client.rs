@@ -1,20 +1,118 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, BTreeSet, VecDeque};
+use std::mem::size_of;
 
-use csv::Writer;
-use itertools::Itertools as _;
-use serde::{ser::SerializeStruct as _, Serialize, Serializer};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Read, Write};
 
-use crate::error::TransactionError;
-use crate::transaction::Transaction;
-use crate::{Amount, ClientID, TransactionID};
+use serde::{ser::SerializeStruct as _, Deserialize, Serialize, Serializer};
+
+use crate::amount::{exceeds, round};
+use crate::audit::RejectionAudit;
+use crate::clock::Clock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use crate::config::{
+    CompactionPolicy, DayCount, DuplicatePolicy, HistoryRetention, NegativeAvailablePolicy,
+    StoreConfig, VelocityRule, DAY,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::diagnostics::{Diagnostics, DiagnosticsReport};
+use crate::error::{ErrorKind, TransactionError};
+use crate::idempotency::{IdempotencyKeys, Outcome};
+use crate::ledger::TransactionLedger;
+use crate::middleware::{MiddlewareAction, TransactionMiddleware};
+use crate::observer::{AccountEvent, Balances, TransactionObserver};
+#[cfg(feature = "csv")]
+use crate::output::CsvFormat;
+#[cfg(any(feature = "csv", feature = "json"))]
+use crate::output::{HistoryFormat, HistoryWriter};
+use crate::output::{OutputFormat, OutputOptions, ShardBy};
+use crate::reference::ReferenceMap;
+use crate::report::{DisputeAgingReport, DormancyReport, ProcessingReport};
+use crate::risk::{RiskDecision, RiskScorer};
+use crate::snapshot::{SnapshotReader, Snapshots};
+use crate::spill::{HistorySpill, ENTRY_SIZE};
+use crate::state::{StateReader, StateWriter};
+use crate::storage::Clients;
+use crate::transaction::{
+    Dispute, Freeze, Interest, Release, Timestamped, Transaction, TransactionKind,
+};
+use crate::trial::{Flows, TrialBalance};
+use crate::{Amount, ClientID, IdMap, IdSet, Timestamp, TransactionID};
+
+/// State of a transaction in the dispute lifecycle
+///
+/// Transactions start undisputed. A dispute is settled by a resolve or a
+/// chargeback, and a chargeback may be reversed. Resolved and reversed
+/// transactions can be disputed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    /// Transaction was never disputed
+    #[default]
+    Undisputed,
+    /// Transaction is being disputed and it's funds are held
+    Disputed,
+    /// Dispute was resolved and the funds released
+    Resolved,
+    /// Dispute was charged back and the funds removed
+    ChargedBack,
+    /// Chargeback was reversed and the funds restored
+    Reversed,
+}
+
+impl DisputeState {
+    /// Get the name of the state as it is exported, e.g. `charged_back`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Undisputed => "undisputed",
+            Self::Disputed => "disputed",
+            Self::Resolved => "resolved",
+            Self::ChargedBack => "charged_back",
+            Self::Reversed => "reversed",
+        }
+    }
+
+    /// Check if the state can move to another state
+    pub fn can_become(self, to: DisputeState) -> bool {
+        matches!(
+            (self, to),
+            (
+                Self::Undisputed | Self::Resolved | Self::Reversed,
+                Self::Disputed
+            ) | (Self::Disputed, Self::Resolved | Self::ChargedBack)
+                | (Self::ChargedBack, Self::Reversed)
+        )
+    }
+}
+
+impl Display for DisputeState {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Self::Undisputed => "undisputed",
+            Self::Disputed => "disputed",
+            Self::Resolved => "resolved",
+            Self::ChargedBack => "charged back",
+            Self::Reversed => "reversed",
+        })
+    }
+}
 
 /// History of a client's transactions
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct History {
     /// Amount of the transaction
     pub amount: Amount,
-    /// Boolean value if the transaction is being disputed.
-    pub dispute: bool,
+    /// State of the transaction in the dispute lifecycle
+    pub state: DisputeState,
+    /// Time the transaction was executed, if known
+    pub timestamp: Option<Timestamp>,
+    /// Kind of the transaction, if known
+    ///
+    /// Set by the ClientStore. Custom kinds are not kept by spilled entries and state snapshots.
+    pub kind: Option<TransactionKind>,
 }
 
 impl History {
@@ -22,12 +120,93 @@ impl History {
     pub fn new(amount: Amount) -> Self {
         Self {
             amount,
-            dispute: false,
+            state: DisputeState::Undisputed,
+            timestamp: None,
+            kind: None,
+        }
+    }
+
+    /// Check if the transaction is being disputed
+    pub fn is_disputed(&self) -> bool {
+        self.state == DisputeState::Disputed
+    }
+
+    /// Move the transaction to a state of the dispute lifecycle.
+    ///
+    /// Illegal transitions are rejected with an `InvalidDisputeState` error
+    /// and the state is left unchanged.
+    pub(crate) fn transition(
+        &mut self,
+        to: DisputeState,
+        transaction_id: TransactionID,
+        client_id: ClientID,
+    ) -> Result<(), TransactionError> {
+        if !self.state.can_become(to) {
+            return Err(TransactionError::new(
+                ErrorKind::InvalidDisputeState,
+                format!(
+                    "Transaction {} for client {} can not become {} while it is {}.",
+                    transaction_id, client_id, to, self.state
+                ),
+            ));
+        }
+        self.state = to;
+        Ok(())
+    }
+}
+
+/// Summary of compacted history entries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistorySummary {
+    /// Number of compacted entries
+    pub entries: u64,
+    /// Sum of the amounts of the compacted entries
+    pub amount: Amount,
+}
+
+/// Amounts a client deposited and withdrew on a calendar day
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DailyTotals {
+    /// Day counted in days since the Unix epoch, UTC
+    pub day: u64,
+    /// Sum of the deposits on the day
+    pub deposited: Amount,
+    /// Sum of the withdrawals on the day
+    pub withdrawn: Amount,
+}
+
+impl DailyTotals {
+    /// Get the totals of the day of the time, zero if they are of another day
+    pub fn on(&self, timestamp: Timestamp) -> Self {
+        let day = timestamp / DAY;
+        if day == self.day {
+            *self
+        } else {
+            Self {
+                day,
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Count a deposit or withdrawal at the time, resetting the totals on a new day.
+    ///
+    /// Transactions of days before the counted day are ignored.
+    fn count(&mut self, kind: TransactionKind, amount: Amount, timestamp: Timestamp) {
+        if timestamp / DAY < self.day {
+            return;
+        }
+        *self = self.on(timestamp);
+        match kind {
+            TransactionKind::Deposit => self.deposited += amount,
+            TransactionKind::Withdrawal => self.withdrawn += amount,
+            _ => {}
         }
     }
 }
 
 /// Representation of a client's account
+#[derive(Debug, Clone)]
 pub struct Client {
     /// Client's unique identifer
     pub id: ClientID,
@@ -38,7 +217,26 @@ pub struct Client {
     /// Client is locked status
     pub locked: bool,
     /// Collection of all transactions
-    pub client_history: HashMap<TransactionID, History>,
+    pub client_history: IdMap<TransactionID, History>,
+    /// Summary of settled transactions compacted out of the history
+    pub compacted_history: HistorySummary,
+    /// Amounts of open authorization holds by their transaction id
+    pub holds: IdMap<TransactionID, Amount>,
+    /// Account tier selecting the client's limits
+    pub tier: Tier,
+    /// Client passed identity verification (KYC)
+    pub verified: bool,
+    /// Client is paused by compliance
+    ///
+    /// Unlike `locked`, which is set by a chargeback and can not be undone,
+    /// a client is frozen and unfrozen by the `freeze` and `unfreeze` transactions.
+    pub frozen: bool,
+    /// Amounts deposited and withdrawn on the day of the latest timestamped transaction
+    pub daily: DailyTotals,
+    /// Time of the client's latest timestamped transaction
+    ///
+    /// Administrative transactions and interest are not activity of the client.
+    pub last_activity: Option<Timestamp>,
 }
 
 impl Client {
@@ -49,7 +247,27 @@ impl Client {
             available: 0.0,
             held: 0.0,
             locked: false,
-            client_history: HashMap::new(),
+            client_history: IdMap::default(),
+            compacted_history: HistorySummary::default(),
+            holds: IdMap::default(),
+            tier: Tier::default(),
+            verified: false,
+            frozen: false,
+            daily: DailyTotals::default(),
+            last_activity: None,
+        }
+    }
+
+    /// Get the client's status, `active`, `frozen` or `locked`
+    ///
+    /// Locked takes precedence, as the lock of a chargeback can not be undone.
+    pub fn status(&self) -> &'static str {
+        if self.locked {
+            "locked"
+        } else if self.frozen {
+            "frozen"
+        } else {
+            "active"
         }
     }
 
@@ -61,6 +279,19 @@ impl Client {
     }
 }
 
+/// Account tier of a client
+///
+/// Tiers select the limits in the `tier_limits` of the StoreConfig.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    /// Tier of clients which were not assigned one
+    #[default]
+    Basic,
+    /// Tier of clients with higher or no limits
+    Premium,
+}
+
 /// Custom serialize implementation to add new fields
 ///
 /// Adds the total field to the serialization so total
@@ -81,163 +312,2484 @@ impl Serialize for Client {
     }
 }
 
+/// Identifying fields of an applied transaction
+///
+/// Used to detect duplicate transactions.
+#[derive(Debug, PartialEq)]
+struct AppliedTransaction {
+    client: ClientID,
+    kind: TransactionKind,
+    amount: Option<Amount>,
+}
+
+impl AppliedTransaction {
+    fn new(transaction: &dyn Transaction) -> Self {
+        Self {
+            client: transaction.requested_client_id(),
+            kind: transaction.kind(),
+            amount: transaction.amount(),
+        }
+    }
+}
+
+/// Interest accrued by a client since it was last credited
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Accrual {
+    /// Time the balance was accrued up to
+    until: Timestamp,
+    /// Positive available funds times the years they were held for
+    balance_years: f64,
+}
+
+impl Accrual {
+    fn new(until: Timestamp) -> Self {
+        Self {
+            until,
+            balance_years: 0.0,
+        }
+    }
+
+    /// Accrue the available funds of the client up to a later time.
+    fn advance(&mut self, available: Amount, now: Timestamp, day_count: DayCount) {
+        if now > self.until {
+            self.balance_years += available.max(0.0) * day_count.year_fraction(self.until, now);
+            self.until = now;
+        }
+    }
+}
+
+/// Snapshot the state of the store was last saved or loaded as, with the
+/// changes since the last snapshot or delta
+#[derive(Debug)]
+struct Checkpoint {
+    /// Digest of the snapshot
+    base: u64,
+    /// Number of deltas saved on top of the snapshot
+    sequence: u32,
+    /// Changed clients with the history entries touched by the changes
+    changed: IdMap<ClientID, IdSet<TransactionID>>,
+}
+
+impl Checkpoint {
+    fn new(base: u64, sequence: u32) -> Self {
+        Self {
+            base,
+            sequence,
+            changed: IdMap::default(),
+        }
+    }
+
+    /// Track a change of the client, touching a history entry if any.
+    fn touch(&mut self, client_id: ClientID, transaction_id: Option<TransactionID>) {
+        let touched = self.changed.entry(client_id).or_default();
+        if let Some(transaction_id) = transaction_id {
+            let _ = touched.insert(transaction_id);
+        }
+    }
+}
+
+/// State read from a snapshot and it's deltas, before it replaces the state of a store
+struct SavedState {
+    clients: Clients,
+    interest_accrued: IdMap<ClientID, Accrual>,
+    references: ReferenceMap,
+    hold_expiries: BTreeSet<(Timestamp, ClientID, TransactionID)>,
+    reviewed_withdrawals: BTreeSet<TransactionID>,
+    idempotency_keys: IdempotencyKeys,
+    ledger: TransactionLedger,
+    flows: Flows,
+}
+
+impl SavedState {
+    /// Read the references, hold expiries, withdrawals in review, idempotency
+    /// keys, ledger and flows, which snapshots and deltas save whole.
+    fn read_tracking<R>(&mut self, reader: &mut StateReader<R>) -> Result<(), TransactionError>
+    where
+        R: Read,
+    {
+        self.references = reader.references()?;
+        self.hold_expiries.clear();
+        for _ in 0..reader.u64()? {
+            let expires = reader.u64()?;
+            let client_id = reader.client_id()?;
+            let _ = self
+                .hold_expiries
+                .insert((expires, client_id, reader.u32()?));
+        }
+        self.reviewed_withdrawals.clear();
+        for _ in 0..reader.u64()? {
+            let _ = self.reviewed_withdrawals.insert(reader.u32()?);
+        }
+        self.idempotency_keys = reader.idempotency_keys()?;
+        self.ledger = reader.ledger()?;
+        self.flows = reader.flows()?;
+        Ok(())
+    }
+
+    /// Read interest accrual times, adding them to the ones read before.
+    fn read_interest_accrued<R>(
+        &mut self,
+        reader: &mut StateReader<R>,
+    ) -> Result<(), TransactionError>
+    where
+        R: Read,
+    {
+        for _ in 0..reader.u64()? {
+            let client_id = reader.client_id()?;
+            let mut accrual = Accrual::new(reader.u64()?);
+            accrual.balance_years = reader.amount()?;
+            let _ = self.interest_accrued.insert(client_id, accrual);
+        }
+        Ok(())
+    }
+
+    /// Apply a delta saved by `ClientStore::save_delta`.
+    ///
+    /// Changed clients replace their fields and holds, the touched history
+    /// entries are replaced or removed.
+    fn apply_delta<R>(&mut self, reader: &mut StateReader<R>) -> Result<(), TransactionError>
+    where
+        R: Read,
+    {
+        for _ in 0..reader.u64()? {
+            let mut client = reader.client_fields()?;
+            if let Some(previous) = self.clients.get_mut(&client.id) {
+                client.client_history = std::mem::take(&mut previous.client_history);
+            }
+            for _ in 0..reader.u64()? {
+                let transaction_id = reader.u32()?;
+                match reader.u8()? {
+                    0 => {
+                        let _ = client.client_history.remove(&transaction_id);
+                    }
+                    _ => {
+                        let history = reader.history()?;
+                        let _ = client.client_history.insert(transaction_id, history);
+                    }
+                }
+            }
+            reader.holds(&mut client)?;
+            let _ = self.clients.insert(client.id, client)?;
+        }
+        self.read_interest_accrued(reader)?;
+        self.read_tracking(reader)
+    }
+}
+
+/// Metrics of history compaction
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompactionStats {
+    /// Number of entries dropped from client histories
+    pub dropped: u64,
+    /// Number of entries folded into client history summaries
+    pub summarized: u64,
+}
+
+/// Estimated memory used by a ClientStore in bytes
+///
+/// Estimated from the number of entries and their sizes, so the overhead of
+/// the maps holding them is not included. Spilled history entries are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// Clients, without their histories and holds
+    pub clients: usize,
+    /// History entries in memory
+    pub histories: usize,
+    /// Open authorization holds
+    pub holds: usize,
+    /// Transactions tracked by the store, i.e. their owners, applied
+    /// transactions, references and recent withdrawals
+    pub tracking: usize,
+}
+
+impl MemoryFootprint {
+    /// Total estimated bytes
+    pub fn total(&self) -> usize {
+        self.clients + self.histories + self.holds + self.tracking
+    }
+}
+
+/// Would-be effect of a transaction on a client
+#[derive(Debug, PartialEq)]
+pub struct StateDelta {
+    /// Client the transaction would run against
+    pub client: ClientID,
+    /// Change in available funds
+    pub available: Amount,
+    /// Change in held funds
+    pub held: Amount,
+    /// Client locked status before the transaction
+    pub was_locked: bool,
+    /// Client locked status after the transaction
+    pub locked: bool,
+}
+
+impl StateDelta {
+    /// Get the change in the client's total amount of funds
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+}
+
 /// Collection of all Clients.
 ///
 /// All Clients will have a unique Identifer.
 pub struct ClientStore {
-    /// Map of a client's unique identifer to a client.
-    pub clients: HashMap<ClientID, Client>,
+    /// Collection of a client's unique identifer to a client.
+    pub clients: Clients,
+    /// Observers notified of every executed transaction.
+    observers: Vec<Box<dyn TransactionObserver>>,
+    /// Middleware run before every transaction is executed.
+    middleware: Vec<Box<dyn TransactionMiddleware>>,
+    /// Configuration of the store
+    config: StoreConfig,
+    /// Applied transactions by their id.
+    ///
+    /// Only tracked if duplicates are not applied.
+    applied: IdMap<TransactionID, AppliedTransaction>,
+    /// Ids of transactions applied by this and earlier runs.
+    ///
+    /// Only tracked if duplicates are not applied.
+    ledger: TransactionLedger,
+    /// Funds moved by the transactions applied by this and earlier runs
+    flows: Flows,
+    /// Bounded memory layer for client histories, if configured.
+    spill: Option<HistorySpill>,
+    /// Number of transactions executed by the store
+    executed: u64,
+    /// Settled history entries waiting to be compacted, oldest first
+    settled: VecDeque<(u64, ClientID, TransactionID)>,
+    /// Metrics of history compaction
+    compaction_stats: CompactionStats,
+    /// Clients whose balances or locked status changed since the last `clear_changes`,
+    /// with their balances from before, None for clients created since
+    changed: IdMap<ClientID, Option<Balances>>,
+    /// Clients flagged for a dispute leaving their available funds negative
+    flagged: IdSet<ClientID>,
+    /// External transaction references and their transaction ids
+    references: ReferenceMap,
+    /// Outcomes of transactions executed with an idempotency key
+    idempotency_keys: IdempotencyKeys,
+    /// Clock timestamping transactions without a time of their own
+    clock: Option<Box<dyn Clock>>,
+    /// Recent withdrawals of every client checked by velocity rules, oldest first
+    withdrawals: IdMap<ClientID, VecDeque<(Timestamp, Amount)>>,
+    /// Fraud signal hook scoring deposits and withdrawals
+    risk_scorer: Option<Box<dyn RiskScorer>>,
+    /// Interest accrued by every client with timestamped transactions
+    interest_accrued: IdMap<ClientID, Accrual>,
+    /// Expiry of authorization holds, soonest first
+    hold_expiries: BTreeSet<(Timestamp, ClientID, TransactionID)>,
+    /// Withdrawals held for review by the risk scorer, withdrawn once resolved
+    reviewed_withdrawals: BTreeSet<TransactionID>,
+    /// Client owning every applied transaction with an amount
+    owners: IdMap<TransactionID, ClientID>,
+    /// History entries of every client and their time, oldest first
+    ///
+    /// Only tracked if the history retention is bounded.
+    retained: IdMap<ClientID, VecDeque<(TransactionID, Option<Timestamp>)>>,
+    /// Transactions pruned from histories by the retention
+    pruned: IdSet<TransactionID>,
+    /// Clients which do not exist yet, with the attributes assigned before
+    /// their first transaction
+    seeded: IdMap<ClientID, Client>,
+    /// Diagnostics of executed transactions, if enabled
+    #[cfg(not(target_arch = "wasm32"))]
+    diagnostics: Option<Diagnostics>,
+    /// Snapshots of the balances published to readers, if enabled
+    snapshots: Option<Snapshots>,
+    /// Audit trail of rejected transactions, if enabled
+    audit: Option<RejectionAudit>,
+    /// Snapshot changes are tracked against, if saved or loaded with deltas
+    checkpoint: Option<Checkpoint>,
 }
 
 impl ClientStore {
     /// Create a new ClientStore for storing all clients
     pub fn new() -> Self {
+        Self::with_config(StoreConfig::default())
+    }
+
+    /// Create a new ClientStore with the configuration
+    pub fn with_config(config: StoreConfig) -> Self {
         Self {
-            clients: HashMap::new(),
+            clients: Clients::new(config.client_storage),
+            observers: Vec::new(),
+            middleware: Vec::new(),
+            spill: config.history_memory_budget.map(HistorySpill::new),
+            config,
+            applied: IdMap::default(),
+            ledger: TransactionLedger::new(),
+            flows: Flows::default(),
+            executed: 0,
+            settled: VecDeque::new(),
+            compaction_stats: CompactionStats::default(),
+            changed: IdMap::default(),
+            flagged: IdSet::default(),
+            references: ReferenceMap::new(),
+            idempotency_keys: IdempotencyKeys::new(),
+            clock: None,
+            withdrawals: IdMap::default(),
+            risk_scorer: None,
+            interest_accrued: IdMap::default(),
+            hold_expiries: BTreeSet::new(),
+            reviewed_withdrawals: BTreeSet::new(),
+            owners: IdMap::default(),
+            retained: IdMap::default(),
+            pruned: IdSet::default(),
+            seeded: IdMap::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            diagnostics: None,
+            snapshots: None,
+            audit: None,
+            checkpoint: None,
         }
     }
 
+    /// Set the clock timestamping transactions without a time of their own.
+    ///
+    /// Without a clock, only transactions with a time of their own are timestamped.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Some(Box::new(clock));
+    }
+
+    /// Set the RiskScorer scoring deposits and withdrawals before they are applied.
+    pub fn set_risk_scorer<S>(&mut self, scorer: S)
+    where
+        S: RiskScorer + 'static,
+    {
+        self.risk_scorer = Some(Box::new(scorer));
+    }
+
+    /// Add middleware to run before transactions are executed.
+    ///
+    /// Middleware runs in the order it was added.
+    pub fn add_middleware<M>(&mut self, middleware: M)
+    where
+        M: TransactionMiddleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Add an observer to be notified of the lifecycle of executed transactions.
+    ///
+    /// Observers are notified in the order they were added.
+    pub fn add_observer<O>(&mut self, observer: O)
+    where
+        O: TransactionObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+    }
+
     /// Execute the transaction on the store.
     ///
     /// Get the client, or create the client if it is it's first transaction
     /// and execute the transaction on the client. What the transaction does
     /// is up to the transaction implementation.
-    pub fn execute<T>(&mut self, transaction: &T) -> Result<(), TransactionError>
-    where
-        T: Transaction + ?Sized,
-    {
-        match self.clients.get_mut(&transaction.requested_client_id()) {
-            Some(client) => transaction.execute(client),
-            None => {
-                let mut new_client = Client::new(transaction.requested_client_id());
-                transaction.execute(&mut new_client)?;
-                let _ = self
-                    .clients
-                    .insert(transaction.requested_client_id(), new_client);
-                Ok(())
+    ///
+    /// Middleware runs first and may rewrite or veto the transaction.
+    /// Observers are notified of the outcome. A client created for a rejected
+    /// transaction is not added to the store. Holds expired by the time of the
+    /// transaction are released first.
+    pub fn execute(&mut self, transaction: &dyn Transaction) -> Result<(), TransactionError> {
+        let now = transaction
+            .timestamp()
+            .or_else(|| self.clock.as_ref().map(|clock| clock.now()));
+        let client_id = transaction.requested_client_id();
+        if let Some(now) = now {
+            let _ = self.expire_holds(now);
+            self.accrue_balance(client_id, now);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = if self.diagnostics.is_some() {
+            let start = Instant::now();
+            let result = self.execute_transaction(transaction);
+            if let Some(diagnostics) = self.diagnostics.as_mut() {
+                diagnostics.record(transaction, start.elapsed());
             }
+            result
+        } else {
+            self.execute_transaction(transaction)
+        };
+        #[cfg(target_arch = "wasm32")]
+        let result = self.execute_transaction(transaction);
+        if let (Err(err), Some(audit)) = (&result, self.audit.as_mut()) {
+            audit.record(transaction, err, now);
+        }
+        // Interest accrues from the client's first timestamped transaction
+        if let (Ok(()), Some(now)) = (&result, now) {
+            let _ = self
+                .interest_accrued
+                .entry(client_id)
+                .or_insert_with(|| Accrual::new(now));
         }
+        if self.snapshots.as_mut().is_some_and(Snapshots::tick) {
+            self.refresh_snapshot();
+        }
+        result
     }
 
-    /// Get the current state of all the clients in the store.
-    ///
-    /// Returns a string representation of all the clients, their funds, and status in the store.
-    /// If a client state can not be converted to a string, all other clients are ignored
-    /// and an error is returned.
-    /// 
-    /// Clients in the final state can optionally be sorted by their client.
-    pub fn get_current_state(&self, sort: bool) -> Result<String, TransactionError> {
-        let mut state = Vec::new();
-        {
-            let mut writer = Writer::from_writer(&mut state);
-            if sort {
-                for (_id, client) in self.clients.iter().sorted_by_key(|kv| kv.0) {
-                    writer.serialize(client)?;
-                }
-            } else {
-                for client in self.clients.values() {
-                    writer.serialize(client)?;
-                }
-            };
-
-            writer.flush()?;
+    /// Accrue the available funds of the client up to a time, before they change.
+    fn accrue_balance(&mut self, client_id: ClientID, now: Timestamp) {
+        if let (Some(accrual), Some(client)) = (
+            self.interest_accrued.get_mut(&client_id),
+            self.clients.get(&client_id),
+        ) {
+            accrual.advance(client.available, now, self.config.day_count);
         }
-        Ok(String::from_utf8(state)?)
     }
-}
 
-impl Default for ClientStore {
-    fn default() -> Self {
-        Self::new()
+    /// Record every rejected transaction in a RejectionAudit, with it's reason code.
+    ///
+    /// Rejections recorded before are dropped.
+    pub fn enable_audit(&mut self) {
+        self.audit = Some(RejectionAudit::new());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Get the audit trail of rejected transactions.
+    ///
+    /// Returns None if the audit is not enabled.
+    pub fn audit(&self) -> Option<&RejectionAudit> {
+        self.audit.as_ref()
+    }
 
-    #[test]
-    fn new_client() {
-        let client = Client::new(157);
-        assert_eq!(client.id, 157, "New Client ID is not as expected!");
-        assert_eq!(
-            client.available, 0.0,
-            "New Client available balance is not as expected!"
-        );
-        assert_eq!(
-            client.locked, false,
-            "New Client is locked! Should be unlocked"
-        );
+    /// Publish a snapshot of the balances of all clients every number of transactions.
+    ///
+    /// Returns a reader of the latest snapshot, which can be cloned and read from
+    /// other threads without taking a lock. A first snapshot is published right
+    /// away. Readers of snapshots enabled before are no longer refreshed.
+    ///
+    /// Every snapshot copies the balances of all clients on the thread executing
+    /// the transactions, so every should be large compared to the number of clients.
+    pub fn enable_snapshots(&mut self, every: u64) -> SnapshotReader {
+        let snapshots = Snapshots::new(every);
+        let reader = snapshots.reader();
+        self.snapshots = Some(snapshots);
+        self.refresh_snapshot();
+        reader
     }
 
-    #[test]
-    fn client_total_greater_available_than_held() {
-        let mut client = Client::new(157);
-        client.available = 54.7345;
-        client.held = 3.5678;
-        assert_eq!(client.total(), 54.7345 + 3.5678)
+    /// Publish a snapshot of the balances of all clients now, e.g. at the end of an input.
+    ///
+    /// Does nothing if snapshots are not enabled.
+    pub fn refresh_snapshot(&self) {
+        if let Some(snapshots) = &self.snapshots {
+            snapshots.publish(self.clients.values());
+        }
     }
 
-    #[test]
-    fn client_total_greater_held_than_available() {
-        let mut client = Client::new(157);
-        client.available = 3.5678;
-        client.held = 54.7345;
-        assert_eq!(client.total(), 54.7345 + 3.5678)
+    /// Assign the client to a tier.
+    ///
+    /// Clients which do not exist yet are assigned the tier when they are created.
+    pub fn set_tier(&mut self, client_id: ClientID, tier: Tier) {
+        self.client_or_seed(client_id).tier = tier;
     }
 
-    #[test]
-    fn new_client_store() {
-        let client_store = ClientStore::new();
-        assert!(client_store.clients.is_empty())
+    /// Set whether the client is verified.
+    ///
+    /// Clients which do not exist yet are verified when they are created.
+    /// Clients can also be verified with the built in `verify` and `unverify`
+    /// transaction types.
+    pub fn set_verified(&mut self, client_id: ClientID, verified: bool) {
+        self.client_or_seed(client_id).verified = verified;
     }
 
-    struct TestTransaction {
-        id: ClientID,
-        amount: Amount,
+    /// Get the client, or the seed it will be created from if it does not exist yet
+    fn client_or_seed(&mut self, client_id: ClientID) -> &mut Client {
+        match self.clients.get_mut(&client_id) {
+            Some(client) => {
+                if let Some(checkpoint) = &mut self.checkpoint {
+                    checkpoint.touch(client_id, None);
+                }
+                client
+            }
+            None => self
+                .seeded
+                .entry(client_id)
+                .or_insert_with(|| Client::new(client_id)),
+        }
     }
 
-    impl Transaction for TestTransaction {
-        fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
-            client.available += self.amount;
-            Ok(())
+    /// Release the authorization holds expired by the time.
+    ///
+    /// Holds are released with a `release` transaction at the time they
+    /// expired, soonest first. Returns the number of released holds.
+    pub fn expire_holds(&mut self, now: Timestamp) -> u64 {
+        let mut released = 0;
+        while let Some(&(expires, client_id, tx)) = self.hold_expiries.first() {
+            if expires > now {
+                break;
+            }
+            let _ = self.hold_expiries.pop_first();
+            let open = self
+                .clients
+                .get(&client_id)
+                .is_some_and(|client| client.holds.contains_key(&tx));
+            let release = Timestamped::new(Box::new(Release::new(tx, client_id)), expires);
+            self.accrue_balance(client_id, expires);
+            if open && self.execute_transaction(&release).is_ok() {
+                released += 1;
+            }
         }
+        released
+    }
 
-        fn requested_client_id(&self) -> ClientID {
-            self.id
+    /// Execute the transaction on the store without releasing expired holds.
+    fn execute_transaction(
+        &mut self,
+        transaction: &dyn Transaction,
+    ) -> Result<(), TransactionError> {
+        let (replacement, rewritten) = self.run_middleware(transaction);
+        let transaction = replacement.as_deref().unwrap_or(transaction);
+        if let Err(err) = rewritten {
+            return self.reject(transaction, err);
         }
+        let Checks {
+            tracked,
+            timestamp,
+            velocity_checked,
+            decision,
+        } = match self.check(transaction) {
+            Ok(Some(checks)) => checks,
+            // Identical duplicates are ignored
+            Ok(None) => return Ok(()),
+            Err(err) => return self.reject(transaction, err),
+        };
 
-        fn amount(&self) -> Option<Amount> {
-            Some(self.amount)
+        // Make room for the referenced entry and a new entry
+        let retry = self.config.storage_retry;
+        if let Some(spill) = &mut self.spill {
+            if let Err(err) = retry.run(|| spill.make_room(&mut self.clients, 2)) {
+                return self.reject(transaction, err);
+            }
         }
-    }
 
-    #[test]
-    fn client_store_plus_1_transaction() {
-        let mut client_store = ClientStore::new();
-        client_store
-            .execute(&TestTransaction { id: 1, amount: 1.0 })
-            .unwrap();
-        assert_eq!(client_store.clients.get(&1).unwrap().available, 1.0);
-        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
-        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
-    }
+        let settles_review = self.settles_review(transaction);
+        let client_id = transaction.requested_client_id();
+        let created = !self.clients.contains_key(&client_id);
+        if let Err(err) = self.clients.check_id(client_id) {
+            return self.reject(transaction, err);
+        }
+        let mut new_client = None;
+        let client = match self.clients.get_mut(&client_id) {
+            Some(client) => client,
+            None => new_client.insert(
+                self.seeded
+                    .get(&client_id)
+                    .cloned()
+                    .unwrap_or_else(|| Client::new(client_id)),
+            ),
+        };
+        if let Some(spill) = &mut self.spill {
+            if let Err(err) = retry.run(|| spill.restore(client, transaction.transaction_id())) {
+                return self.reject(transaction, err);
+            }
+        }
+        let overdraws = match check_overdraw(transaction, client, self.config.negative_available) {
+            Ok(overdraws) => overdraws,
+            Err(err) => return self.reject(transaction, err),
+        };
+        let before = Balances::of(client);
+        let reviewed_withdrawal =
+            decision == RiskDecision::Review && transaction.kind() == TransactionKind::Withdrawal;
+        let result = execute_checked(transaction, client, decision, settles_review);
+        // Transactions with an amount add their own history entry
+        if let (Ok(()), Some(_)) = (&result, transaction.amount()) {
+            if let Some(history) = client.client_history.get_mut(&transaction.transaction_id()) {
+                history.timestamp = timestamp;
+                history.kind = Some(transaction.kind());
+            }
+        }
+        if result.is_ok()
+            && self.config.skip_withdrawal_history
+            && transaction.kind() == TransactionKind::Withdrawal
+            && decision != RiskDecision::Review
+        {
+            let _ = client.client_history.remove(&transaction.transaction_id());
+        }
+        if let (Ok(()), Some(spill)) = (&result, &mut self.spill) {
+            spill.touch(client, transaction.transaction_id());
+        }
 
-    #[test]
-    fn client_store_add_available_transaction_multiple() {
-        let mut client_store = ClientStore::new();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
+        if let (Ok(()), Some(timestamp), Some(amount)) = (&result, timestamp, transaction.amount())
+        {
+            client.daily.count(transaction.kind(), amount, timestamp);
+        }
+        let activity =
+            !transaction.kind().is_admin() && transaction.kind() != TransactionKind::Interest;
+        if let (Ok(()), true, Some(timestamp)) = (&result, activity, timestamp) {
+            client.last_activity = client.last_activity.max(Some(timestamp));
+        }
+        if let (Ok(()), true, Some(timestamp), Some(amount)) =
+            (&result, velocity_checked, timestamp, transaction.amount())
+        {
+            let recent = self.withdrawals.entry(client_id).or_default();
+            // Withdrawals older than the longest window are forgotten
+            let longest = self
+                .config
+                .velocity_rules
+                .iter()
+                .map(VelocityRule::window)
+                .max()
+                .unwrap_or_default();
+            while recent
+                .front()
+                .is_some_and(|(time, _amount)| time.saturating_add(longest) <= timestamp)
+            {
+                let _ = recent.pop_front();
+            }
+            recent.push_back((timestamp, amount));
+        }
+        if result.is_ok()
+            && transaction.kind() == TransactionKind::ChargebackReversal
+            && self.config.reversal_unlocks
+        {
+            client.locked = false;
+        }
+        if let (Ok(()), TransactionKind::Hold, Some(timestamp), Some(expiry)) = (
+            &result,
+            transaction.kind(),
+            timestamp,
+            self.config.hold_expiry,
+        ) {
+            let _ = self.hold_expiries.insert((
+                timestamp.saturating_add(expiry),
+                client_id,
+                transaction.transaction_id(),
+            ));
+        }
+        if result.is_ok() && (created || before != Balances::of(client)) {
+            let _ = self
+                .changed
+                .entry(client_id)
+                .or_insert((!created).then_some(before));
+        }
+        if result.is_ok() {
+            let delta = Balances::of(client).total() - before.total();
+            match (reviewed_withdrawal, settles_review, transaction.kind()) {
+                // Funds held for review are not withdrawn yet
+                (true, ..) => self.flows.count(TransactionKind::Withdrawal, None, delta),
+                (_, true, TransactionKind::Resolve) => {
+                    self.flows
+                        .count(TransactionKind::Withdrawal, Some(-delta), delta)
+                }
+                _ => self
+                    .flows
+                    .count(transaction.kind(), transaction.amount(), delta),
+            }
+        }
+        if result.is_ok() && reviewed_withdrawal {
+            let _ = self
+                .reviewed_withdrawals
+                .insert(transaction.transaction_id());
+        }
+        if result.is_ok() && settles_review {
+            let _ = self
+                .reviewed_withdrawals
+                .remove(&transaction.transaction_id());
+        }
+        if result.is_ok()
+            && overdraws
+            && self.config.negative_available == NegativeAvailablePolicy::Flag
+        {
+            let _ = self.flagged.insert(client_id);
+        }
+        let event = match (&result, self.observers.is_empty()) {
+            (Ok(()), false) => Some(AccountEvent {
+                client: client_id,
+                tx: transaction.transaction_id(),
+                kind: transaction.kind(),
+                before,
+                after: Balances::of(client),
+                timestamp,
+            }),
+            _ => None,
+        };
+        for observer in self.observers.iter_mut() {
+            match &result {
+                Ok(()) => {
+                    observer.on_applied(transaction, client);
+                    if let Some(event) = &event {
+                        observer.on_event(event);
+                    }
+                    match transaction.kind() {
+                        TransactionKind::Dispute => observer.on_dispute_opened(transaction, client),
+                        TransactionKind::Chargeback => observer.on_chargeback(transaction, client),
+                        _ => (),
+                    }
+                    if !before.locked && client.locked {
+                        observer.on_account_locked(transaction, client);
+                    }
+                }
+                Err(err) => observer.on_rejected(transaction, err),
+            }
+        }
+
+        if let (Ok(()), Some(new_client)) = (&result, new_client) {
+            // The id was checked before executing the transaction
+            let _ = self.clients.insert(client_id, new_client);
+        }
+        if let (Ok(()), Some(checkpoint)) = (&result, &mut self.checkpoint) {
+            checkpoint.touch(client_id, Some(transaction.transaction_id()));
+        }
+        if result.is_ok() && transaction.amount().is_some() {
+            let _ = self.owners.insert(transaction.transaction_id(), client_id);
+        }
+        if result.is_ok() && tracked {
+            let _ = self.applied.insert(
+                transaction.transaction_id(),
+                AppliedTransaction::new(transaction),
+            );
+            let _ = self.ledger.insert(transaction.transaction_id());
+        }
+        if result.is_ok() && self.config.compaction != CompactionPolicy::Keep {
+            self.executed += 1;
+            if matches!(
+                transaction.kind(),
+                TransactionKind::Resolve | TransactionKind::Chargeback
+            ) {
+                self.settled
+                    .push_back((self.executed, client_id, transaction.transaction_id()));
+            }
+            self.compact();
+        }
+        if result.is_ok() && self.config.history_retention.is_bounded() {
+            if transaction.amount().is_some() {
+                self.track_retained(client_id, transaction.transaction_id(), timestamp);
+            }
+            self.retain(client_id, timestamp);
+        }
+        result
+    }
+
+    /// Track a new history entry of the client for the retention.
+    ///
+    /// Transactions without an entry, e.g. skipped withdrawals, are not tracked.
+    fn track_retained(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        timestamp: Option<Timestamp>,
+    ) {
+        let recorded = self
+            .clients
+            .get(&client_id)
+            .is_some_and(|client| client.client_history.contains_key(&transaction_id));
+        if recorded {
+            let _ = self.pruned.remove(&transaction_id);
+            self.retained
+                .entry(client_id)
+                .or_default()
+                .push_back((transaction_id, timestamp));
+        }
+    }
+
+    /// Export every history entry of every client in the format.
+    ///
+    /// Entries are written with their client, tx, amount, kind, dispute state
+    /// and timestamp, ordered by client and tx, including spilled entries.
+    /// Entries compacted or pruned out of the histories are not exported.
+    #[cfg(any(feature = "csv", feature = "json"))]
+    pub fn export_all_history<W>(
+        &self,
+        writer: W,
+        format: HistoryFormat,
+    ) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        let mut writer = HistoryWriter::new(writer, format);
+        self.for_each_history(|client_id, tx, history| writer.write(client_id, tx, history))?;
+        writer.finish()
+    }
+
+    /// Call f with every history entry of every client, ordered by client and tx.
+    ///
+    /// Spilled entries are included.
+    #[cfg(any(feature = "csv", feature = "json"))]
+    pub(crate) fn for_each_history<F>(&self, mut f: F) -> Result<(), TransactionError>
+    where
+        F: FnMut(ClientID, TransactionID, &History) -> Result<(), TransactionError>,
+    {
+        let spilled = self.spilled_histories()?;
+
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        for client in clients {
+            let spilled = spilled.get(&client.id).map_or(&[][..], Vec::as_slice);
+            let mut entries: Vec<(TransactionID, &History)> = client
+                .client_history
+                .iter()
+                .map(|(tx, history)| (*tx, history))
+                .chain(spilled.iter().map(|(tx, history)| (*tx, history)))
+                .collect();
+            entries.sort_unstable_by_key(|(tx, _history)| *tx);
+            for (tx, history) in entries {
+                f(client.id, tx, history)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Report the open disputes of every client grouped by their age at a time.
+    ///
+    /// The age of a dispute is the age of the disputed transaction, from the
+    /// timestamp of it's history entry. Spilled entries are included.
+    pub fn dispute_aging(&self, now: Timestamp) -> Result<DisputeAgingReport, TransactionError> {
+        let mut report = DisputeAgingReport::new(now);
+        for history in self
+            .clients
+            .values()
+            .flat_map(|client| client.client_history.values())
+        {
+            if history.is_disputed() {
+                report.add(history.timestamp, history.amount);
+            }
+        }
+        if let Some(spill) = &self.spill {
+            for (client_id, transaction_id) in spill.spilled_entries() {
+                if let Some(history) = spill.read(client_id, transaction_id)? {
+                    if history.is_disputed() {
+                        report.add(history.timestamp, history.amount);
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Check the global invariants of the store with a trial balance.
+    ///
+    /// The changes of the clients' totals by every applied transaction, also
+    /// of earlier runs of a loaded state, must add up to the deposits minus
+    /// withdrawals minus chargebacks, plus other transactions like interest,
+    /// and to the sum of the clients' totals. Every client's total must be
+    /// it's available plus held funds, and no held funds may be negative.
+    pub fn trial_balance(&self) -> TrialBalance {
+        let mut balance = TrialBalance::new(self.flows);
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        for client in clients {
+            balance.add(client.id, client.available, client.held, client.total());
+        }
+        balance.finish()
+    }
+
+    /// Report the clients dormant at a time, freezing them if the policy does.
+    ///
+    /// Clients are dormant if their last activity is at least the dormancy
+    /// period of the config before the time. Dormant clients which are not
+    /// frozen yet are frozen with a `freeze` transaction at the time if the
+    /// policy freezes them. The report is empty without a dormancy period.
+    pub fn review_dormancy(&mut self, now: Timestamp) -> DormancyReport {
+        let mut report = DormancyReport::new(now);
+        let policy = self.config.dormancy;
+        let Some(period) = policy.period else {
+            return report;
+        };
+        let mut dormant: Vec<(ClientID, Timestamp, bool)> = self
+            .clients
+            .values()
+            .filter_map(|client| {
+                let last_activity = client.last_activity?;
+                (now.saturating_sub(last_activity) >= period).then_some((
+                    client.id,
+                    last_activity,
+                    client.frozen,
+                ))
+            })
+            .collect();
+        dormant.sort_unstable_by_key(|(client_id, ..)| *client_id);
+        for (client_id, last_activity, mut frozen) in dormant {
+            if policy.freeze && !frozen {
+                let freeze = Timestamped::new(Box::new(Freeze::new(0, client_id, true)), now);
+                frozen = self.execute(&freeze).is_ok();
+            }
+            report.add(client_id, last_activity, frozen);
+        }
+        report
+    }
+
+    /// Prune history entries of every client beyond the retention at a time.
+    ///
+    /// Entries are pruned as transactions are executed, only for the client
+    /// of the transaction, so entries of idle clients are only pruned by
+    /// their age with this. Returns the number of pruned entries.
+    pub fn prune_history(&mut self, now: Timestamp) -> u64 {
+        let client_ids: Vec<ClientID> = self.retained.keys().copied().collect();
+        client_ids
+            .into_iter()
+            .map(|client_id| self.retain(client_id, Some(now)))
+            .sum()
+    }
+
+    /// Prune history entries of the client beyond the retention at a time.
+    ///
+    /// Disputed entries are kept, entries already removed, e.g. by compaction,
+    /// are forgotten. Returns the number of pruned entries.
+    fn retain(&mut self, client_id: ClientID, now: Option<Timestamp>) -> u64 {
+        let HistoryRetention {
+            last_entries,
+            max_age,
+        } = self.config.history_retention;
+        let (Some(client), Some(entries)) = (
+            self.clients.get_mut(&client_id),
+            self.retained.get_mut(&client_id),
+        ) else {
+            return 0;
+        };
+        let oldest = max_age.zip(now).map(|(age, now)| now.saturating_sub(age));
+        let mut pruned = 0;
+        let mut index = 0;
+        while let Some(&(transaction_id, timestamp)) = entries.get(index) {
+            let over = last_entries.is_some_and(|last| entries.len() > last);
+            let expired =
+                matches!((oldest, timestamp), (Some(oldest), Some(time)) if time < oldest);
+            if !over && !expired {
+                break;
+            }
+            // A spilled entry is restored to check it's dispute status,
+            // it is kept if it can not be read.
+            if let Some(spill) = &mut self.spill {
+                if spill.restore(client, transaction_id).is_err() {
+                    index += 1;
+                    continue;
+                }
+            }
+            if let Entry::Occupied(entry) = client.client_history.entry(transaction_id) {
+                if entry.get().is_disputed() {
+                    index += 1;
+                    continue;
+                }
+                let _ = entry.remove();
+                if let Some(spill) = &mut self.spill {
+                    spill.forget(client_id, transaction_id);
+                }
+                if let Some(checkpoint) = &mut self.checkpoint {
+                    checkpoint.touch(client_id, Some(transaction_id));
+                }
+                let _ = self.pruned.insert(transaction_id);
+                pruned += 1;
+            }
+            let _ = entries.remove(index);
+        }
+        pruned
+    }
+
+    /// Iterate over the clients whose state changed since the last `clear_changes`
+    ///
+    /// A client changed if it was created since, or if it's balances or locked
+    /// status differ from before. Clients changed back to their previous state
+    /// are not changed.
+    pub fn changed_clients(&self) -> impl Iterator<Item = &Client> {
+        self.changed
+            .keys()
+            .filter_map(|id| self.clients.get(id))
+            .filter(|client| self.is_changed(client))
+    }
+
+    /// Check if the state of the client changed since the last `clear_changes`, see `changed_clients`.
+    pub fn is_changed(&self, client: &Client) -> bool {
+        match self.changed.get(&client.id) {
+            Some(Some(before)) => *before != Balances::of(client),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Forget which clients changed, starting a new run over the store.
+    ///
+    /// Used when the store is kept across runs so only clients changed by
+    /// the next run are output with `OutputOptions::changed_only`.
+    pub fn clear_changes(&mut self) {
+        self.changed.clear();
+    }
+
+    /// Iterate over the clients flagged by `NegativeAvailablePolicy::Flag`
+    pub fn flagged_clients(&self) -> impl Iterator<Item = &Client> {
+        self.flagged.iter().filter_map(|id| self.clients.get(id))
+    }
+
+    /// Get the external transaction references of the store
+    pub fn references(&self) -> &ReferenceMap {
+        &self.references
+    }
+
+    /// Get the external transaction references of the store to link or assign references
+    pub fn references_mut(&mut self) -> &mut ReferenceMap {
+        &mut self.references
+    }
+
+    /// Execute the transaction once for the idempotency key.
+    ///
+    /// If a transaction was executed with the key before, it's outcome is
+    /// returned and the transaction is not executed, even if it's tx differs.
+    /// Transient failures are not kept, so they can be retried.
+    pub fn execute_once(
+        &mut self,
+        key: &str,
+        transaction: &dyn Transaction,
+    ) -> Result<(), TransactionError> {
+        if let Some(outcome) = self.idempotency_keys.get(key) {
+            return outcome.result();
+        }
+        let result = self.execute(transaction);
+        self.idempotency_keys.record(key, &result);
+        result
+    }
+
+    /// Get the outcomes of transactions executed with an idempotency key
+    pub fn idempotency_keys(&self) -> &IdempotencyKeys {
+        &self.idempotency_keys
+    }
+
+    /// Get the ids of transactions applied by this and earlier runs
+    ///
+    /// Only tracked if duplicates are not applied.
+    pub fn ledger(&self) -> &TransactionLedger {
+        &self.ledger
+    }
+
+    /// Save the state of the store as a binary snapshot.
+    ///
+    /// The snapshot holds the clients with their histories, including spilled
+    /// entries, and open holds, the external references, idempotency keys, the
+    /// ledger of applied transaction ids, the flows of funds, hold expiries,
+    /// withdrawals in review and interest accrual times, so a later run can continue from it with `load_state`. Velocity windows and
+    /// the fields of applied transactions tracked for duplicates are not saved.
+    pub fn save_state<W>(&self, writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        self.write_snapshot(writer).map(|_digest| ())
+    }
+
+    /// Save the state of the store as a snapshot and track it's changes from then on.
+    ///
+    /// The snapshot is the same as one saved by `save_state`. The changes are
+    /// saved on top of it with `save_delta`, which is much smaller than a new
+    /// snapshot if few clients changed.
+    pub fn save_checkpoint<W>(&mut self, writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        let base = self.write_snapshot(writer)?;
+        self.checkpoint = Some(Checkpoint::new(base, 0));
+        Ok(())
+    }
+
+    /// Save the changes since the last snapshot or delta as a delta.
+    ///
+    /// The delta holds the changed clients with the history entries added,
+    /// changed or removed since, and their interest accrual times. The
+    /// references, hold expiries, idempotency keys, ledger and flows are saved
+    /// whole. Changes are tracked after `save_checkpoint` or
+    /// `load_state_with_deltas`, otherwise an error is returned. Returns the
+    /// sequence number of the delta on top of it's snapshot.
+    ///
+    /// Only the history entry of the transaction's own id is tracked, so custom
+    /// transactions changing other entries of the history need a new snapshot.
+    /// Changes made to `clients` directly are not tracked.
+    pub fn save_delta<W>(&mut self, writer: W) -> Result<u32, TransactionError>
+    where
+        W: Write,
+    {
+        let checkpoint = self
+            .checkpoint
+            .as_ref()
+            .ok_or("No checkpoint to save a delta of")?;
+        let sequence = checkpoint.sequence + 1;
+        let retry = self.config.storage_retry;
+
+        let mut writer = StateWriter::delta(writer, checkpoint.base, sequence)?;
+        let changed: Vec<_> = checkpoint
+            .changed
+            .iter()
+            .filter_map(|(client_id, touched)| Some((self.clients.get(client_id)?, touched)))
+            .collect();
+        writer.u64(changed.len() as u64)?;
+        for (client, touched) in &changed {
+            writer.client_fields(client)?;
+            writer.u64(touched.len() as u64)?;
+            for transaction_id in touched.iter() {
+                let history = match (client.client_history.get(transaction_id), &self.spill) {
+                    (Some(history), _) => Some(history.clone()),
+                    (None, Some(spill)) => retry.run(|| spill.read(client.id, *transaction_id))?,
+                    (None, None) => None,
+                };
+                writer.u32(*transaction_id)?;
+                match history {
+                    Some(history) => {
+                        writer.u8(1)?;
+                        writer.history(&history)?;
+                    }
+                    None => writer.u8(0)?,
+                }
+            }
+            writer.holds(client)?;
+        }
+        let accrued: Vec<_> = changed
+            .iter()
+            .filter_map(|(client, _touched)| {
+                Some((client.id, *self.interest_accrued.get(&client.id)?))
+            })
+            .collect();
+        writer.u64(accrued.len() as u64)?;
+        for (client_id, accrual) in accrued {
+            writer.client_id(client_id)?;
+            writer.u64(accrual.until)?;
+            writer.amount(accrual.balance_years)?;
+        }
+        self.write_tracking(&mut writer)?;
+        let _ = writer.finish()?;
+
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.sequence = sequence;
+            checkpoint.changed.clear();
+        }
+        Ok(sequence)
+    }
+
+    /// Get the number of deltas saved on top of the last snapshot
+    ///
+    /// Returns None if changes are not tracked, as no checkpoint was saved or loaded.
+    pub fn checkpoint_deltas(&self) -> Option<u32> {
+        self.checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.sequence)
+    }
+
+    /// Write a snapshot of the state, returning it's digest.
+    fn write_snapshot<W>(&self, writer: W) -> Result<u64, TransactionError>
+    where
+        W: Write,
+    {
+        let spilled = self.config.storage_retry.run(|| self.spilled_histories())?;
+
+        let mut writer = StateWriter::new(writer)?;
+        writer.u64(self.clients.len() as u64)?;
+        for client in self.clients.values() {
+            let spilled = spilled.get(&client.id).map_or(&[][..], Vec::as_slice);
+            writer.client(client, spilled)?;
+        }
+        writer.u64(self.interest_accrued.len() as u64)?;
+        for (client_id, accrual) in &self.interest_accrued {
+            writer.client_id(*client_id)?;
+            writer.u64(accrual.until)?;
+            writer.amount(accrual.balance_years)?;
+        }
+        self.write_tracking(&mut writer)?;
+        writer.finish()
+    }
+
+    /// Write the references, hold expiries, withdrawals in review, idempotency
+    /// keys, ledger and flows.
+    fn write_tracking<W>(&self, writer: &mut StateWriter<W>) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        writer.references(&self.references)?;
+        writer.u64(self.hold_expiries.len() as u64)?;
+        for (expires, client_id, transaction_id) in &self.hold_expiries {
+            writer.u64(*expires)?;
+            writer.client_id(*client_id)?;
+            writer.u32(*transaction_id)?;
+        }
+        writer.u64(self.reviewed_withdrawals.len() as u64)?;
+        for transaction_id in &self.reviewed_withdrawals {
+            writer.u32(*transaction_id)?;
+        }
+        writer.idempotency_keys(&self.idempotency_keys)?;
+        writer.ledger(&self.ledger)?;
+        writer.flows(&self.flows)
+    }
+
+    /// Read the spilled history entries of every client.
+    fn spilled_histories(
+        &self,
+    ) -> Result<IdMap<ClientID, Vec<(TransactionID, History)>>, TransactionError> {
+        let mut spilled: IdMap<ClientID, Vec<(TransactionID, History)>> = IdMap::default();
+        if let Some(spill) = &self.spill {
+            for (client_id, transaction_id) in spill.spilled_entries() {
+                if let Some(history) = spill.read(client_id, transaction_id)? {
+                    spilled
+                        .entry(client_id)
+                        .or_default()
+                        .push((transaction_id, history));
+                }
+            }
+        }
+        Ok(spilled)
+    }
+
+    /// Load the state of the store from a snapshot saved by `save_state`.
+    ///
+    /// Replaces the clients, references, idempotency keys, ledger, flows, hold
+    /// expiries and interest accrual times of the store. Configuration, observers and middleware are kept.
+    /// If the snapshot can not be read, an error is returned and the store
+    /// is unchanged.
+    pub fn load_state<R>(&mut self, reader: R) -> Result<(), TransactionError>
+    where
+        R: Read,
+    {
+        let (state, _digest) = self.read_state(reader)?;
+        self.install(state)
+    }
+
+    /// Load the state of the store from a snapshot with the deltas saved on top of it.
+    ///
+    /// Loads the snapshot like `load_state`, then applies the deltas, which
+    /// must be given in order of their sequence number. Deltas of another
+    /// snapshot, e.g. left behind by an earlier checkpoint, are skipped, while
+    /// a gap in the sequence is an error. Changes are tracked from then on, so
+    /// the next delta can be saved with `save_delta`. Returns the number of
+    /// applied deltas. If the snapshot or a delta can not be read, an error is
+    /// returned and the store is unchanged.
+    pub fn load_state_with_deltas<R, D, I>(
+        &mut self,
+        reader: R,
+        deltas: I,
+    ) -> Result<u32, TransactionError>
+    where
+        R: Read,
+        D: Read,
+        I: IntoIterator<Item = D>,
+    {
+        let (mut state, base) = self.read_state(reader)?;
+        let mut sequence = 0;
+        for delta in deltas {
+            let (mut reader, delta_base, delta_sequence) = StateReader::delta(delta)?;
+            if delta_base != base {
+                continue;
+            }
+            if delta_sequence != sequence + 1 {
+                return Err(format!(
+                    "State delta {} is missing before delta {}",
+                    sequence + 1,
+                    delta_sequence
+                )
+                .into());
+            }
+            state.apply_delta(&mut reader)?;
+            sequence = delta_sequence;
+        }
+        self.install(state)?;
+        self.checkpoint = Some(Checkpoint::new(base, sequence));
+        Ok(sequence)
+    }
+
+    /// Read a snapshot, returning it's state and digest.
+    fn read_state<R>(&self, reader: R) -> Result<(SavedState, u64), TransactionError>
+    where
+        R: Read,
+    {
+        let mut reader = StateReader::new(reader)?;
+        let mut clients = Clients::new(self.config.client_storage);
+        for _ in 0..reader.u64()? {
+            let client = reader.client()?;
+            let _ = clients.insert(client.id, client)?;
+        }
+        let mut state = SavedState {
+            clients,
+            interest_accrued: IdMap::default(),
+            references: ReferenceMap::new(),
+            hold_expiries: BTreeSet::new(),
+            reviewed_withdrawals: BTreeSet::new(),
+            idempotency_keys: IdempotencyKeys::new(),
+            ledger: TransactionLedger::new(),
+            flows: Flows::default(),
+        };
+        state.read_interest_accrued(&mut reader)?;
+        state.read_tracking(&mut reader)?;
+        Ok((state, reader.digest()))
+    }
+
+    /// Replace the state of the store with a saved state.
+    ///
+    /// Changes are no longer tracked against an earlier checkpoint.
+    fn install(&mut self, state: SavedState) -> Result<(), TransactionError> {
+        let SavedState {
+            mut clients,
+            interest_accrued,
+            references,
+            hold_expiries,
+            reviewed_withdrawals,
+            idempotency_keys,
+            ledger,
+            flows,
+        } = state;
+        // Owners are known from the history of every client
+        let mut owners = IdMap::default();
+        for client in clients.values() {
+            for transaction_id in client.client_history.keys() {
+                let _ = owners.insert(*transaction_id, client.id);
+            }
+        }
+        // The order entries were added in is not saved, so retained entries are ordered by time
+        let mut retained = IdMap::default();
+        if self.config.history_retention.is_bounded() {
+            for client in clients.values() {
+                let mut entries: Vec<_> = client
+                    .client_history
+                    .iter()
+                    .map(|(transaction_id, history)| (*transaction_id, history.timestamp))
+                    .collect();
+                entries.sort_by_key(|&(transaction_id, timestamp)| (timestamp, transaction_id));
+                let _ = retained.insert(client.id, entries.into());
+            }
+        }
+        if let Some(budget) = self.config.history_memory_budget {
+            let mut spill = HistorySpill::new(budget);
+            for client in clients.values() {
+                for transaction_id in client.client_history.keys() {
+                    spill.touch(client, *transaction_id);
+                }
+            }
+            spill.make_room(&mut clients, 0)?;
+            self.spill = Some(spill);
+        }
+        self.clients = clients;
+        self.references = references;
+        self.idempotency_keys = idempotency_keys;
+        self.ledger = ledger;
+        self.flows = flows;
+        self.interest_accrued = interest_accrued;
+        self.hold_expiries = hold_expiries;
+        self.reviewed_withdrawals = reviewed_withdrawals;
+        self.owners = owners;
+        self.retained = retained;
+        self.checkpoint = None;
+        self.refresh_snapshot();
+        Ok(())
+    }
+
+    /// Get the metrics of history compaction
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction_stats
+    }
+
+    /// Estimate the memory used by clients, their histories and the transactions tracked by the store.
+    ///
+    /// Counts are multiplied by the size of an entry, so the estimate is cheap
+    /// enough to call during a run.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let mut footprint = MemoryFootprint::default();
+        for client in self.clients.values() {
+            footprint.clients += size_of::<Client>();
+            footprint.histories += client.client_history.len() * ENTRY_SIZE;
+            footprint.holds +=
+                client.holds.len() * (size_of::<TransactionID>() + size_of::<Amount>());
+        }
+        let references: usize = self
+            .references
+            .iter()
+            .map(|(reference, _id)| reference.len() + size_of::<String>())
+            .sum();
+        let idempotency_keys: usize = self
+            .idempotency_keys
+            .iter()
+            .map(|(key, outcome)| {
+                let message = match outcome {
+                    Outcome::Applied => 0,
+                    Outcome::Rejected(_kind, message) => message.len(),
+                };
+                key.len() + message + size_of::<String>() + size_of::<Outcome>()
+            })
+            .sum();
+        let withdrawals: usize = self
+            .withdrawals
+            .values()
+            .map(|recent| recent.len() * size_of::<(Timestamp, Amount)>())
+            .sum();
+        let retained: usize = self
+            .retained
+            .values()
+            .map(|entries| entries.len() * size_of::<(TransactionID, Option<Timestamp>)>())
+            .sum();
+        footprint.tracking = self.owners.len()
+            * (size_of::<TransactionID>() + size_of::<ClientID>())
+            + self.applied.len() * (size_of::<TransactionID>() + size_of::<AppliedTransaction>())
+            + self.ledger.range_count() * 2 * size_of::<TransactionID>()
+            + references
+            + self.references.len() * size_of::<TransactionID>()
+            + idempotency_keys
+            + withdrawals
+            + retained
+            + self.pruned.len() * size_of::<TransactionID>()
+            + self.reviewed_withdrawals.len() * size_of::<TransactionID>()
+            + self.checkpoint.as_ref().map_or(0, |checkpoint| {
+                checkpoint
+                    .changed
+                    .values()
+                    .map(|touched| {
+                        size_of::<ClientID>() + touched.len() * size_of::<TransactionID>()
+                    })
+                    .sum()
+            });
+        footprint
+    }
+
+    /// Track the wall time of every transaction and the number of transactions of every client.
+    ///
+    /// The diagnostics report the top entries of each of their lists.
+    /// Diagnostics are not available on wasm, which has no clock to time transactions.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_diagnostics(&mut self, top: usize) {
+        self.diagnostics = Some(Diagnostics::new(top));
+    }
+
+    /// Report the slowest transactions, the busiest clients and the largest histories.
+    ///
+    /// Returns None if diagnostics are not enabled.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn diagnostics(&self) -> Option<DiagnosticsReport> {
+        self.diagnostics
+            .as_ref()
+            .map(|diagnostics| diagnostics.report(self.clients.values()))
+    }
+
+    /// Compact settled history entries which are past the dispute window.
+    ///
+    /// Entries disputed again since they were settled are skipped. They are
+    /// queued again when the new dispute is settled.
+    fn compact(&mut self) {
+        let (window, summarize) = match self.config.compaction {
+            CompactionPolicy::Keep => return,
+            CompactionPolicy::Drop { window } => (window, false),
+            CompactionPolicy::Summarize { window } => (window, true),
+        };
+        while let Some(&(settled_at, client_id, transaction_id)) = self.settled.front() {
+            if settled_at + window > self.executed {
+                break;
+            }
+            let _ = self.settled.pop_front();
+            let client = match self.clients.get_mut(&client_id) {
+                Some(client) => client,
+                None => continue,
+            };
+            // A spilled entry is restored to check it's dispute status,
+            // it is kept if it can not be read.
+            if let Some(spill) = &mut self.spill {
+                if spill.restore(client, transaction_id).is_err() {
+                    continue;
+                }
+            }
+            if let Entry::Occupied(entry) = client.client_history.entry(transaction_id) {
+                if entry.get().is_disputed() {
+                    continue;
+                }
+                let history = entry.remove();
+                if summarize {
+                    client.compacted_history.entries += 1;
+                    client.compacted_history.amount += history.amount;
+                    self.compaction_stats.summarized += 1;
+                } else {
+                    self.compaction_stats.dropped += 1;
+                }
+                if let Some(spill) = &mut self.spill {
+                    spill.forget(client_id, transaction_id);
+                }
+                if let Some(checkpoint) = &mut self.checkpoint {
+                    checkpoint.touch(client_id, Some(transaction_id));
+                }
+            }
+        }
+    }
+
+    /// Accrue interest at an annual rate up to a time and credit it to every client.
+    ///
+    /// Interest is accrued on the client's positive available funds over the
+    /// period since it was last credited, or since the client's first
+    /// timestamped transaction, weighted by the time every balance was held
+    /// for using the configured day count convention. Clients without
+    /// timestamped transactions do not accrue interest. Interest which can not
+    /// be credited is rejected in the report and kept accruing.
+    /// Interest is rounded to four decimal places with the configured rounding
+    /// mode and credited with an `interest` transaction, referenced by `interest-<client>-<as_of>`.
+    pub fn accrue_interest(&mut self, rate: f64, as_of: Timestamp) -> ProcessingReport {
+        let mut accruals = Vec::new();
+        for client in self.clients.values() {
+            let Some(mut accrual) = self.interest_accrued.get(&client.id).copied() else {
+                continue;
+            };
+            if accrual.until < as_of {
+                accrual.advance(client.available, as_of, self.config.day_count);
+                let interest = accrual.balance_years * rate;
+                accruals.push((client.id, round(interest, 4, self.config.rounding)));
+            }
+        }
+        accruals.sort_unstable_by_key(|(client, _interest)| *client);
+
+        let mut report = ProcessingReport::default();
+        for (client, interest) in accruals {
+            report.rows_read += 1;
+            let result = if interest > 0.0 {
+                let reference = format!("interest-{}-{}", client, as_of);
+                let tx = self.references.id(&reference);
+                let transaction =
+                    Timestamped::new(Box::new(Interest::new(tx, client, interest)), as_of);
+                self.execute(&transaction)
+            } else {
+                Ok(())
+            };
+            match result {
+                Ok(()) => {
+                    report.applied += 1;
+                    let _ = self.interest_accrued.insert(client, Accrual::new(as_of));
+                    if let Some(checkpoint) = &mut self.checkpoint {
+                        checkpoint.touch(client, None);
+                    }
+                }
+                Err(err) => report.reject(&err),
+            }
+        }
+        report
+    }
+
+    /// Check the transaction against the restrictions of unverified clients.
+    ///
+    /// Transactions restricted by the verification policy are rejected with
+    /// an `Unverified` error.
+    fn check_verification(&self, transaction: &dyn Transaction) -> Result<(), TransactionError> {
+        let policy = self.config.verification;
+        let client_id = transaction.requested_client_id();
+        let verified = self
+            .clients
+            .get(&client_id)
+            .or_else(|| self.seeded.get(&client_id))
+            .is_some_and(|client| client.verified);
+        let amount = transaction.amount().unwrap_or_default();
+        let restricted = !verified
+            && match transaction.kind() {
+                TransactionKind::Deposit => policy.block_deposits,
+                TransactionKind::Withdrawal => {
+                    policy.max_withdrawal.is_some_and(|max| amount > max)
+                }
+                _ => false,
+            };
+        if restricted {
+            return Err(TransactionError::new(
+                ErrorKind::Unverified,
+                format!(
+                    "Transaction {} is not allowed for unverified client {}.",
+                    transaction.transaction_id(),
+                    client_id
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check the amount of a deposit or withdrawal against the amount limits.
+    ///
+    /// Amounts below the minimum are rejected with an `AmountBelowMinimum`
+    /// error and above the maximum with an `AmountAboveMaximum` error.
+    fn check_amount_limits(&self, transaction: &dyn Transaction) -> Result<(), TransactionError> {
+        let limits = self.config.amount_limits;
+        let (TransactionKind::Deposit | TransactionKind::Withdrawal, Some(amount)) =
+            (transaction.kind(), transaction.amount())
+        else {
+            return Ok(());
+        };
+        let (kind, limit, bound) = match (limits.min, limits.max) {
+            (Some(min), _) if amount < min => (ErrorKind::AmountBelowMinimum, min, "minimum"),
+            (_, Some(max)) if amount > max => (ErrorKind::AmountAboveMaximum, max, "maximum"),
+            _ => return Ok(()),
+        };
+        Err(TransactionError::new(
+            kind,
+            format!(
+                "Amount {} of transaction {} is beyond the {} transaction amount of {}.",
+                amount,
+                transaction.transaction_id(),
+                bound,
+                limit
+            ),
+        ))
+    }
+
+    /// Check a deposit or withdrawal at the time against the daily limits.
+    ///
+    /// Transactions which would raise the client's total of the day over the
+    /// limit, or of a day before the day counted last, are rejected with a
+    /// `DailyLimitExceeded` error.
+    fn check_daily_limits(
+        &self,
+        transaction: &dyn Transaction,
+        timestamp: Timestamp,
+    ) -> Result<(), TransactionError> {
+        let limits = self.config.daily_limits;
+        let client_id = transaction.requested_client_id();
+        let counted = self
+            .clients
+            .get(&client_id)
+            .map(|client| client.daily)
+            .unwrap_or_default();
+        let totals = counted.on(timestamp);
+        let (limit, total) = match transaction.kind() {
+            TransactionKind::Deposit => (limits.max_deposited, totals.deposited),
+            TransactionKind::Withdrawal => (limits.max_withdrawn, totals.withdrawn),
+            _ => return Ok(()),
+        };
+        let amount = transaction.amount().unwrap_or_default();
+        match limit {
+            // Only the totals of the latest day are kept, so earlier days can't be checked
+            Some(_) if totals.day < counted.day => Err(TransactionError::new(
+                ErrorKind::DailyLimitExceeded,
+                format!(
+                    "Transaction {} is of a day before the latest day counted for the daily limit of client {}.",
+                    transaction.transaction_id(),
+                    client_id
+                ),
+            )),
+            Some(max) if exceeds(total + amount, max) => Err(TransactionError::new(
+                ErrorKind::DailyLimitExceeded,
+                format!(
+                    "Transaction {} would exceed the daily limit of {} of client {}.",
+                    transaction.transaction_id(),
+                    max,
+                    client_id
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the transaction against the limits of the client's tier.
+    ///
+    /// Deposits over the tier's maximum balance and withdrawals over it's
+    /// maximum withdrawal are rejected with a `TierLimitExceeded` error.
+    fn check_tier_limits(&self, transaction: &dyn Transaction) -> Result<(), TransactionError> {
+        let client_id = transaction.requested_client_id();
+        let client = self.clients.get(&client_id);
+        let tier = client
+            .or_else(|| self.seeded.get(&client_id))
+            .map_or_else(Tier::default, |client| client.tier);
+        let (Some(limits), Some(amount)) =
+            (self.config.tier_limits.get(&tier), transaction.amount())
+        else {
+            return Ok(());
+        };
+        let exceeded = match transaction.kind() {
+            TransactionKind::Deposit => limits
+                .max_balance
+                .filter(|max| client.map_or(0.0, Client::total) + amount > *max),
+            TransactionKind::Withdrawal => limits.max_withdrawal.filter(|max| amount > *max),
+            _ => None,
+        };
+        match exceeded {
+            Some(limit) => Err(TransactionError::new(
+                ErrorKind::TierLimitExceeded,
+                format!(
+                    "Transaction {} exceeds the limit of {} of the {:?} tier of client {}.",
+                    transaction.transaction_id(),
+                    limit,
+                    tier,
+                    client_id
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the middleware on the transaction.
+    ///
+    /// Returns the transaction as rewritten by the middleware, if it was, and
+    /// the error of middleware vetoing it.
+    fn run_middleware(
+        &self,
+        transaction: &dyn Transaction,
+    ) -> (Option<Box<dyn Transaction>>, Result<(), TransactionError>) {
+        let mut replacement: Option<Box<dyn Transaction>> = None;
+        for middleware in &self.middleware {
+            let current = replacement.as_deref().unwrap_or(transaction);
+            match middleware
+                .before_execute(current, self.clients.get(&current.requested_client_id()))
+            {
+                Ok(MiddlewareAction::Continue) => (),
+                Ok(MiddlewareAction::Replace(rewritten)) => replacement = Some(rewritten),
+                Err(err) => return (replacement, Err(err)),
+            }
+        }
+        (replacement, Ok(()))
+    }
+
+    /// Check if the store rejects the transaction before it is executed.
+    ///
+    /// Duplicates, the owner and pruning of referenced transactions, velocity
+    /// rules, the risk scorer, amount, daily and tier limits and verification
+    /// are checked without changing the state of the store. Returns None for a
+    /// duplicate which is ignored as identical.
+    fn check(&self, transaction: &dyn Transaction) -> Result<Option<Checks>, TransactionError> {
+        // Transactions with an amount introduce a new transaction id, others
+        // reference a previous transaction so they can not be duplicates.
+        let tracked = self.config.duplicate_policy != DuplicatePolicy::Apply
+            && transaction.amount().is_some();
+        if tracked {
+            if let Some(applied) = self.applied.get(&transaction.transaction_id()) {
+                let identical = applied == &AppliedTransaction::new(transaction);
+                if identical && self.config.duplicate_policy == DuplicatePolicy::Idempotent {
+                    return Ok(None);
+                }
+                return Err(TransactionError::new(
+                    ErrorKind::DuplicateTransaction,
+                    format!(
+                        "Transaction {} was already applied.",
+                        transaction.transaction_id()
+                    ),
+                ));
+            }
+            // The fields of transactions of earlier runs are not saved, so
+            // replays are ignored as identical if duplicates are idempotent
+            if self.ledger.contains(transaction.transaction_id()) {
+                if self.config.duplicate_policy == DuplicatePolicy::Idempotent {
+                    return Ok(None);
+                }
+                return Err(TransactionError::new(
+                    ErrorKind::DuplicateTransaction,
+                    format!(
+                        "Transaction {} was already applied by an earlier run.",
+                        transaction.transaction_id()
+                    ),
+                ));
+            }
+        }
+
+        // Transactions referencing a previous transaction must be for it's owner
+        if transaction.amount().is_none() && !transaction.kind().is_admin() {
+            match self.owners.get(&transaction.transaction_id()) {
+                Some(&owner) if owner != transaction.requested_client_id() => {
+                    return Err(TransactionError::new(
+                        ErrorKind::WrongClient,
+                        format!(
+                            "Transaction {} belongs to client {}, not client {}.",
+                            transaction.transaction_id(),
+                            owner,
+                            transaction.requested_client_id()
+                        ),
+                    ));
+                }
+                _ => (),
+            }
+            if self.pruned.contains(&transaction.transaction_id()) {
+                return Err(TransactionError::new(
+                    ErrorKind::HistoryPruned,
+                    format!(
+                        "Transaction {} was pruned from the history of client {}.",
+                        transaction.transaction_id(),
+                        transaction.requested_client_id()
+                    ),
+                ));
+            }
+        }
+
+        let timestamp = transaction
+            .timestamp()
+            .or_else(|| self.clock.as_ref().map(|clock| clock.now()));
+        let velocity_checked = transaction.kind() == TransactionKind::Withdrawal
+            && !self.config.velocity_rules.is_empty();
+        if let (true, Some(timestamp)) = (velocity_checked, timestamp) {
+            self.check_velocity(transaction, timestamp)?;
+        }
+
+        let decision = match (&self.risk_scorer, transaction.kind()) {
+            (Some(scorer), TransactionKind::Deposit | TransactionKind::Withdrawal) => scorer.score(
+                transaction,
+                self.clients.get(&transaction.requested_client_id()),
+            ),
+            _ => RiskDecision::Allow,
+        };
+        if decision == RiskDecision::Deny {
+            return Err(TransactionError::new(
+                ErrorKind::RiskDenied,
+                format!(
+                    "Transaction {} was denied by the risk scorer.",
+                    transaction.transaction_id()
+                ),
+            ));
+        }
+
+        self.check_amount_limits(transaction)?;
+        if let Some(timestamp) = timestamp {
+            self.check_daily_limits(transaction, timestamp)?;
+        }
+        self.check_tier_limits(transaction)?;
+        self.check_verification(transaction)?;
+        Ok(Some(Checks {
+            tracked,
+            timestamp,
+            velocity_checked,
+            decision,
+        }))
+    }
+
+    /// Check if the transaction resolves or charges back a withdrawal in review.
+    fn settles_review(&self, transaction: &dyn Transaction) -> bool {
+        matches!(
+            transaction.kind(),
+            TransactionKind::Resolve | TransactionKind::Chargeback
+        ) && self
+            .reviewed_withdrawals
+            .contains(&transaction.transaction_id())
+    }
+
+    /// Check a withdrawal at the time against the velocity rules.
+    fn check_velocity(
+        &self,
+        transaction: &dyn Transaction,
+        timestamp: Timestamp,
+    ) -> Result<(), TransactionError> {
+        let client_id = transaction.requested_client_id();
+        let recent = self.withdrawals.get(&client_id);
+        for rule in &self.config.velocity_rules {
+            let within = recent
+                .into_iter()
+                .flatten()
+                .filter(|(time, _amount)| time.saturating_add(rule.window()) > timestamp);
+            let exceeded = match *rule {
+                VelocityRule::MaxCount { count, .. } => within.count() as u64 + 1 > count,
+                VelocityRule::MaxAmount { amount, .. } => {
+                    within.map(|(_time, amount)| amount).sum::<Amount>()
+                        + transaction.amount().unwrap_or_default()
+                        > amount
+                }
+            };
+            if exceeded {
+                return Err(TransactionError::new(
+                    ErrorKind::VelocityExceeded,
+                    format!(
+                        "Withdrawal {} would exceed velocity rule {:?} of client {}.",
+                        transaction.transaction_id(),
+                        rule,
+                        client_id
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Notify observers of the rejected transaction and return the error.
+    fn reject(
+        &mut self,
+        transaction: &dyn Transaction,
+        err: TransactionError,
+    ) -> Result<(), TransactionError> {
+        for observer in self.observers.iter_mut() {
+            observer.on_rejected(transaction, &err);
+        }
+        Err(err)
+    }
+
+    /// Simulate the transaction on the store without modifying it.
+    ///
+    /// The transaction is run through the middleware and checked like by
+    /// `execute`, then executed on a copy of the client, or a new client if
+    /// it is it's first transaction, and the would-be change is returned.
+    /// Holds of the client expired by the time of the transaction are
+    /// released on the copy first. If the transaction would be rejected, the
+    /// error is returned. Observers are not notified.
+    pub fn simulate(&self, transaction: &dyn Transaction) -> Result<StateDelta, TransactionError> {
+        let (replacement, rewritten) = self.run_middleware(transaction);
+        rewritten?;
+        let transaction = replacement.as_deref().unwrap_or(transaction);
+        let checks = self.check(transaction)?;
+
+        let client_id = transaction.requested_client_id();
+        let mut client = match self.clients.get(&client_id) {
+            Some(client) => client.clone(),
+            None => self
+                .seeded
+                .get(&client_id)
+                .cloned()
+                .unwrap_or_else(|| Client::new(client_id)),
+        };
+        if let Some(spill) = &self.spill {
+            let transaction_id = transaction.transaction_id();
+            if let Entry::Vacant(entry) = client.client_history.entry(transaction_id) {
+                if let Some(history) = spill.read(client_id, transaction_id)? {
+                    let _ = entry.insert(history);
+                }
+            }
+        }
+        let (available, held, was_locked) = (client.available, client.held, client.locked);
+        let now = transaction
+            .timestamp()
+            .or_else(|| self.clock.as_ref().map(|clock| clock.now()));
+        if let Some(now) = now {
+            let expired = self
+                .hold_expiries
+                .iter()
+                .take_while(|(expires, _client, _tx)| *expires <= now)
+                .filter(|(_expires, expired_client, _tx)| *expired_client == client_id);
+            for &(_expires, _client, tx) in expired {
+                if client.holds.contains_key(&tx) {
+                    let _ = Release::new(tx, client_id).execute(&mut client);
+                }
+            }
+        }
+        // Identical duplicates are ignored, changing nothing
+        if let Some(checks) = checks {
+            let _ = check_overdraw(transaction, &client, self.config.negative_available)?;
+            execute_checked(
+                transaction,
+                &mut client,
+                checks.decision,
+                self.settles_review(transaction),
+            )?;
+        }
+        Ok(StateDelta {
+            client: client_id,
+            available: client.available - available,
+            held: client.held - held,
+            was_locked,
+            locked: client.locked,
+        })
+    }
+
+    /// Get the current state of all the clients in the store.
+    ///
+    /// Returns a string representation of all the clients, their funds, and status in the store.
+    /// If a client state can not be converted to a string, all other clients are ignored
+    /// and an error is returned.
+    ///
+    /// Clients are sorted by their client so the state is the same on every run,
+    /// unless sort is false, which keeps the order of the store.
+    #[cfg(feature = "csv")]
+    pub fn get_current_state(&self, sort: bool) -> Result<String, TransactionError> {
+        self.get_current_state_with_options(&OutputOptions {
+            sort,
+            ..Default::default()
+        })
+    }
+
+    /// Get the current state of all the clients in the store formatted with the options.
+    ///
+    /// Same as `get_current_state`, but sorting and amount formatting are set by the options.
+    /// Only changed clients are included if `changed_only` is set.
+    #[cfg(feature = "csv")]
+    pub fn get_current_state_with_options(
+        &self,
+        options: &OutputOptions,
+    ) -> Result<String, TransactionError> {
+        let mut state = Vec::new();
+        self.write_state_with_options(&mut CsvFormat::new(&mut state, options), options)?;
+        Ok(String::from_utf8(state)?)
+    }
+
+    /// Write the current state of the clients in the format, as set by the options.
+    ///
+    /// Only changed clients are written if `changed_only` is set. Sorted clients
+    /// are ordered by their pseudonyms if the options anonymize them, so the order
+    /// does not reveal them.
+    pub fn write_state_with_options(
+        &self,
+        format: &mut dyn OutputFormat,
+        options: &OutputOptions,
+    ) -> Result<(), TransactionError> {
+        let clients: Box<dyn Iterator<Item = &Client>> = if options.changed_only {
+            Box::new(self.changed_clients())
+        } else {
+            Box::new(self.clients.values())
+        };
+        if !options.sort {
+            return self.write_clients(format, clients, false);
+        }
+        // Without pseudonyms, clients are ordered by their client
+        let mut clients: Vec<(Option<String>, &Client)> = clients
+            .map(|client| (options.pseudonym(client.id), client))
+            .collect();
+        clients.sort_unstable_by(|(first, a), (second, b)| first.cmp(second).then(a.id.cmp(&b.id)));
+        self.write_clients(format, clients.into_iter().map(|(_, client)| client), false)
+    }
+
+    /// Write the current state of all the clients in the store in the format.
+    ///
+    /// Clients are sorted by their client if sort is true.
+    pub fn write_state(
+        &self,
+        format: &mut dyn OutputFormat,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        self.write_clients(format, self.clients.values(), sort)
+    }
+
+    /// Write the current state of the clients changed since the last `clear_changes` in the format.
+    ///
+    /// Clients are sorted by their client if sort is true.
+    pub fn write_changed_state(
+        &self,
+        format: &mut dyn OutputFormat,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        self.write_clients(format, self.changed_clients(), sort)
+    }
+
+    /// Write the current state of all clients partitioned into shards, one format per shard.
+    ///
+    /// Every shard gets a header, even if no client falls into it. Clients are
+    /// sorted by their client within every shard if sort is true.
+    pub fn write_sharded_state(
+        &self,
+        formats: &mut [Box<dyn OutputFormat + '_>],
+        shard_by: ShardBy,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        self.write_sharded_clients(formats, self.clients.values(), shard_by, sort)
+    }
+
+    /// Write the current state of the changed clients partitioned into shards, one format per shard.
+    ///
+    /// Clients are changed since the last `clear_changes`, as with `write_changed_state`.
+    pub fn write_changed_sharded_state(
+        &self,
+        formats: &mut [Box<dyn OutputFormat + '_>],
+        shard_by: ShardBy,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        self.write_sharded_clients(formats, self.changed_clients(), shard_by, sort)
+    }
+
+    fn write_sharded_clients<'a>(
+        &self,
+        formats: &mut [Box<dyn OutputFormat + '_>],
+        clients: impl Iterator<Item = &'a Client>,
+        shard_by: ShardBy,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        let shards = formats.len();
+        if shards == 0 {
+            return Ok(());
+        }
+        let mut partitions: Vec<Vec<&Client>> = vec![Vec::new(); shards];
+        for client in clients {
+            partitions[shard_by.shard(client.id, shards)].push(client);
+        }
+        for (format, clients) in formats.iter_mut().zip(partitions) {
+            self.write_clients(format.as_mut(), clients.into_iter(), sort)?;
+        }
+        Ok(())
+    }
+
+    /// Write the clients in the format, marked as changed or not.
+    fn write_clients<'a>(
+        &self,
+        format: &mut dyn OutputFormat,
+        clients: impl Iterator<Item = &'a Client>,
+        sort: bool,
+    ) -> Result<(), TransactionError> {
+        format.write_header()?;
+        if sort {
+            let mut clients: Vec<&Client> = clients.collect();
+            clients.sort_unstable_by_key(|client| client.id);
+            for client in clients {
+                format.write_marked_client(client, self.is_changed(client))?;
+            }
+        } else {
+            for client in clients {
+                format.write_marked_client(client, self.is_changed(client))?;
+            }
+        }
+        format.finish()
+    }
+}
+
+impl Default for ClientStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Outcome of the checks of a transaction before it is executed
+struct Checks {
+    /// Transaction is tracked for duplicates
+    tracked: bool,
+    /// Time of the transaction, from the clock if it has none of it's own
+    timestamp: Option<Timestamp>,
+    /// Withdrawal is counted by the velocity rules
+    velocity_checked: bool,
+    /// Decision of the risk scorer
+    decision: RiskDecision,
+}
+
+/// Check if a dispute would leave the client with negative available funds.
+///
+/// Returns an error if the policy rejects such disputes. Only disputes which
+/// would otherwise succeed are checked.
+fn check_overdraw(
+    transaction: &dyn Transaction,
+    client: &Client,
+    policy: NegativeAvailablePolicy,
+) -> Result<bool, TransactionError> {
+    let overdraws = transaction.kind() == TransactionKind::Dispute
+        && !client.locked
+        && client
+            .client_history
+            .get(&transaction.transaction_id())
+            .is_some_and(|history| {
+                history.state.can_become(DisputeState::Disputed)
+                    && client.available - history.amount < 0.0
+            });
+    if overdraws && policy == NegativeAvailablePolicy::Reject {
+        return Err(TransactionError::new(
+            ErrorKind::NegativeAvailable,
+            format!(
+                "Dispute of transaction {} would leave client {} with negative available funds.",
+                transaction.transaction_id(),
+                client.id
+            ),
+        ));
+    }
+    Ok(overdraws)
+}
+
+/// Execute a transaction which passed the checks of the store on the client.
+///
+/// Reviewed transactions are disputed once applied, so their funds are held.
+/// Resolves and chargebacks of withdrawals in review settle the review.
+fn execute_checked(
+    transaction: &dyn Transaction,
+    client: &mut Client,
+    decision: RiskDecision,
+    settles_review: bool,
+) -> Result<(), TransactionError> {
+    if decision == RiskDecision::Review {
+        // Hold the funds until the review is resolved, on a copy so the
+        // client is unchanged if either step fails.
+        let mut reviewed = client.clone();
+        transaction.execute(&mut reviewed)?;
+        // Withdrawals are only made once the review is resolved, so their
+        // funds are moved from available to held instead
+        if transaction.kind() == TransactionKind::Withdrawal {
+            reviewed.available += transaction.amount().unwrap_or_default();
+        }
+        Dispute::new(transaction.transaction_id(), client.id).execute(&mut reviewed)?;
+        *client = reviewed;
+        Ok(())
+    } else if settles_review {
+        settle_withdrawal_review(transaction, client)
+    } else {
+        transaction.execute(client)
+    }
+}
+
+/// Settle the review of a withdrawal with a resolve or chargeback.
+///
+/// Resolving approves the withdrawal, taking it's funds out of held. A
+/// chargeback rejects it, releasing the funds back to available and locking
+/// the client like any chargeback.
+fn settle_withdrawal_review(
+    transaction: &dyn Transaction,
+    client: &mut Client,
+) -> Result<(), TransactionError> {
+    let amount = client
+        .client_history
+        .get(&transaction.transaction_id())
+        .map_or(0.0, |history| history.amount);
+    // The lifecycle is checked by the transaction, which releases the held
+    // funds to available, or removes them, as for a deposit
+    transaction.execute(client)?;
+    match transaction.kind() {
+        TransactionKind::Resolve => client.available -= amount,
+        _ => client.available += amount,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use super::*;
+    use crate::amount::RoundingMode;
+    use crate::transaction::TransactionKind;
+
+    #[test]
+    fn new_client() {
+        let client = Client::new(157);
+        assert_eq!(client.id, 157, "New Client ID is not as expected!");
+        assert_eq!(
+            client.available, 0.0,
+            "New Client available balance is not as expected!"
+        );
+        assert_eq!(
+            client.locked, false,
+            "New Client is locked! Should be unlocked"
+        );
+    }
+
+    #[test]
+    fn client_total_greater_available_than_held() {
+        let mut client = Client::new(157);
+        client.available = 54.7345;
+        client.held = 3.5678;
+        assert_eq!(client.total(), 54.7345 + 3.5678)
+    }
+
+    #[test]
+    fn client_total_greater_held_than_available() {
+        let mut client = Client::new(157);
+        client.available = 3.5678;
+        client.held = 54.7345;
+        assert_eq!(client.total(), 54.7345 + 3.5678)
+    }
+
+    #[test]
+    fn new_client_store() {
+        let client_store = ClientStore::new();
+        assert!(client_store.clients.is_empty())
+    }
+
+    struct TestTransaction {
+        id: ClientID,
+        amount: Amount,
+    }
+
+    impl Transaction for TestTransaction {
+        fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+            client.available += self.amount;
+            Ok(())
+        }
+
+        fn requested_client_id(&self) -> ClientID {
+            self.id
+        }
+
+        fn amount(&self) -> Option<Amount> {
+            Some(self.amount)
+        }
+
+        fn transaction_id(&self) -> TransactionID {
+            0
+        }
+
+        fn kind(&self) -> TransactionKind {
+            TransactionKind::Deposit
+        }
+    }
+
+    #[test]
+    fn client_store_plus_1_transaction() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 1.0);
+        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
+        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+    }
+
+    #[test]
+    fn client_store_add_available_transaction_multiple() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        assert_eq!(
+            client_store.clients.get(&1).unwrap().available,
+            4.5689 + 4.5689 + 4.5689 + 4.5689
+        );
+        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
+        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+    }
+
+    #[test]
+    fn client_store_add_available_transaction_multiple_with_different_clients() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        assert_eq!(
+            client_store.clients.get(&1).unwrap().available,
+            4.5689 + 4.5689 + 4.5689 + 4.5689
+        );
+        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
+        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+        assert_eq!(
+            client_store.clients.get(&1).unwrap().available,
+            4.5689 + 4.5689 + 4.5689 + 4.5689
+        );
+        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
+        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn final_state_1_plus_1_transaction() {
+        // Use different transaction for testing
+        struct TestTransaction {}
+        impl Transaction for TestTransaction {
+            fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+                // Add one to client
+                client.available += 1.0;
+                client.held += 1.0;
+                Ok(())
+            }
+
+            fn requested_client_id(&self) -> ClientID {
+                1
+            }
+
+            fn amount(&self) -> Option<Amount> {
+                Some(1.0)
+            }
+
+            fn transaction_id(&self) -> TransactionID {
+                0
+            }
+
+            fn kind(&self) -> TransactionKind {
+                TransactionKind::Deposit
+            }
+        }
+        let mut client_store = ClientStore::new();
+        client_store.execute(&TestTransaction {}).unwrap();
+        assert_eq!(
+            &client_store.get_current_state(true).unwrap(),
+            "client,available,held,total,locked\n1,1.0,1.0,2.0,false\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn final_state_multiple_transactions() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        assert_eq!(
+            client_store.clients.get(&1).unwrap().available,
+            4.5689 + 4.5689 + 4.5689 + 4.5689
+        );
+        assert_eq!(
+            &client_store.get_current_state(true).unwrap(),
+            "client,available,held,total,locked\n1,18.2756,0.0,18.2756,false\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn final_state_multiple_transactions_multiple_clients() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction {
+                id: 1,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
             .execute(&TestTransaction {
                 id: 1,
                 amount: 4.5689,
             })
             .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
         client_store
             .execute(&TestTransaction {
                 id: 1,
@@ -250,195 +2802,1339 @@ mod tests {
                 amount: 4.5689,
             })
             .unwrap();
+        client_store
+            .execute(&TestTransaction {
+                id: 2,
+                amount: 4.5689,
+            })
+            .unwrap();
+        assert_eq!(&client_store.get_current_state(true).unwrap(), "client,available,held,total,locked\n1,18.2756,0.0,18.2756,false\n2,18.2756,0.0,18.2756,false\n");
+    }
+
+    #[test]
+    fn client_store_notifies_observers() {
+        use std::{cell::RefCell, rc::Rc};
+
+        struct TestObserver(Rc<RefCell<Vec<&'static str>>>);
+        impl TransactionObserver for TestObserver {
+            fn on_applied(&mut self, _transaction: &dyn Transaction, _client: &Client) {
+                self.0.borrow_mut().push("applied");
+            }
+
+            fn on_rejected(&mut self, _transaction: &dyn Transaction, _error: &TransactionError) {
+                self.0.borrow_mut().push("rejected");
+            }
+        }
+
+        struct RejectedTransaction {}
+        impl Transaction for RejectedTransaction {
+            fn execute(&self, _client: &mut Client) -> Result<(), TransactionError> {
+                Err("Rejected".into())
+            }
+
+            fn requested_client_id(&self) -> ClientID {
+                2
+            }
+
+            fn amount(&self) -> Option<Amount> {
+                None
+            }
+
+            fn transaction_id(&self) -> TransactionID {
+                0
+            }
+
+            fn kind(&self) -> TransactionKind {
+                TransactionKind::Withdrawal
+            }
+        }
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut client_store = ClientStore::new();
+        client_store.add_observer(TestObserver(Rc::clone(&events)));
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        client_store.execute(&RejectedTransaction {}).unwrap_err();
+        assert_eq!(*events.borrow(), vec!["applied", "rejected"]);
+        // Client of a rejected first transaction is not stored
+        assert!(!client_store.clients.contains_key(&2));
+    }
+
+    #[test]
+    fn client_store_middleware_rewrites_and_vetoes() {
+        // Doubles the amount of every transaction
+        struct Double {}
+        impl TransactionMiddleware for Double {
+            fn before_execute(
+                &self,
+                transaction: &dyn Transaction,
+                _client: Option<&Client>,
+            ) -> Result<MiddlewareAction, TransactionError> {
+                Ok(MiddlewareAction::Replace(Box::new(TestTransaction {
+                    id: transaction.requested_client_id(),
+                    amount: transaction.amount().unwrap() * 2.0,
+                })))
+            }
+        }
+
+        // Vetoes any transaction above a limit
+        struct Limit {}
+        impl TransactionMiddleware for Limit {
+            fn before_execute(
+                &self,
+                transaction: &dyn Transaction,
+                _client: Option<&Client>,
+            ) -> Result<MiddlewareAction, TransactionError> {
+                if transaction.amount().unwrap() > 5.0 {
+                    Err("Over limit".into())
+                } else {
+                    Ok(MiddlewareAction::Continue)
+                }
+            }
+        }
+
+        let mut client_store = ClientStore::new();
+        client_store.add_middleware(Double {});
+        client_store.add_middleware(Limit {});
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 2.0 })
+            .unwrap();
+        // Doubled to 6.0 which is over the limit
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 3.0 })
+            .unwrap_err();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 4.0);
+    }
+
+    #[test]
+    fn client_store_simulate_does_not_modify_store() {
+        let mut client_store = ClientStore::new();
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        let delta = client_store
+            .simulate(&TestTransaction { id: 1, amount: 2.5 })
+            .unwrap();
+        assert_eq!(
+            delta,
+            StateDelta {
+                client: 1,
+                available: 2.5,
+                held: 0.0,
+                was_locked: false,
+                locked: false,
+            }
+        );
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 1.0);
+
+        // Simulating a new client doesn't add it to the store
+        client_store
+            .simulate(&TestTransaction { id: 2, amount: 2.5 })
+            .unwrap();
+        assert!(!client_store.clients.contains_key(&2));
+    }
+
+    #[test]
+    fn client_store_simulate_releases_expired_holds() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            hold_expiry: Some(10),
+            ..Default::default()
+        });
+        let clock = ManualClock::new(0);
+        client_store.set_clock(clock.clone());
+        let deposit = registry.create_named("deposit", 1, 1, 5.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let hold = registry.create_named("hold", 1, 2, 4.0).unwrap();
+        client_store.execute(hold.as_ref()).unwrap();
+
+        // Only covered once the hold expired
+        clock.set(20);
+        let withdrawal = registry.create_named("withdrawal", 1, 3, 3.0).unwrap();
+        let delta = client_store.simulate(withdrawal.as_ref()).unwrap();
+        assert_eq!((delta.available, delta.held), (1.0, -4.0));
+        assert_eq!(client_store.clients.get(&1).unwrap().held, 4.0);
+        client_store.execute(withdrawal.as_ref()).unwrap();
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (2.0, 0.0));
+    }
+
+    #[test]
+    fn client_store_simulate_matches_execute() {
+        use crate::config::VerificationPolicy;
+        use crate::risk::RuleBasedScorer;
+        use crate::transaction::TransactionRegistry;
+
+        // Vetoes deposits of client 5
+        struct Veto {}
+        impl TransactionMiddleware for Veto {
+            fn before_execute(
+                &self,
+                transaction: &dyn Transaction,
+                _client: Option<&Client>,
+            ) -> Result<MiddlewareAction, TransactionError> {
+                if transaction.requested_client_id() == 5 {
+                    Err("Vetoed".into())
+                } else {
+                    Ok(MiddlewareAction::Continue)
+                }
+            }
+        }
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            duplicate_policy: DuplicatePolicy::Reject,
+            negative_available: NegativeAvailablePolicy::Reject,
+            verification: VerificationPolicy {
+                block_deposits: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        client_store.add_middleware(Veto {});
+        client_store.set_risk_scorer(RuleBasedScorer {
+            review_withdrawal_ratio: Some(0.5),
+            ..Default::default()
+        });
+        for client in [1, 2, 5] {
+            client_store.set_verified(client, true);
+        }
+        // Simulates the transaction, checks the store didn't change, then
+        // executes it and checks the outcome and change are the same
+        let mut check = |t_type, client, tx, amount| {
+            let transaction = registry.create_named(t_type, client, tx, amount).unwrap();
+            let balances = |client_store: &ClientStore| {
+                client_store
+                    .clients
+                    .get(&client)
+                    .map(|client| (client.available, client.held))
+            };
+            let before = balances(&client_store);
+            let simulated = client_store.simulate(transaction.as_ref());
+            assert_eq!(balances(&client_store), before);
+            let executed = client_store.execute(transaction.as_ref());
+            match (&simulated, &executed) {
+                (Ok(delta), Ok(())) => {
+                    let (available, held) = before.unwrap_or_default();
+                    let after = balances(&client_store).unwrap();
+                    assert_eq!(
+                        (delta.available, delta.held),
+                        (after.0 - available, after.1 - held)
+                    );
+                }
+                (Err(simulated), Err(executed)) => assert_eq!(simulated.kind(), executed.kind()),
+                _ => panic!("Simulated {:?}, executed {:?}", simulated, executed),
+            }
+            simulated.map_err(|err| err.kind())
+        };
+
+        check("deposit", 1, 1, 10.0).unwrap();
+        // Duplicates are rejected
+        assert_eq!(
+            check("deposit", 1, 1, 10.0).unwrap_err(),
+            ErrorKind::DuplicateTransaction
+        );
+        // Referenced transactions must be of the client
+        assert_eq!(
+            check("dispute", 2, 1, 0.0).unwrap_err(),
+            ErrorKind::WrongClient
+        );
+        // Reviewed withdrawals hold their funds
+        let delta = check("withdrawal", 1, 2, 8.0).unwrap();
+        assert_eq!((delta.available, delta.held), (-8.0, 8.0));
+        // Disputes may not leave negative available funds
+        assert_eq!(
+            check("dispute", 1, 1, 0.0).unwrap_err(),
+            ErrorKind::NegativeAvailable
+        );
+        // Seeded clients are verified, others are not
+        check("deposit", 2, 3, 1.0).unwrap();
+        assert_eq!(
+            check("deposit", 3, 4, 1.0).unwrap_err(),
+            ErrorKind::Unverified
+        );
+        // Middleware is run
+        assert_eq!(check("deposit", 5, 5, 1.0).unwrap_err(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn client_store_idempotent_duplicates() {
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            ..Default::default()
+        });
+        // Test transactions all share the same transaction id
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 2.0 })
+            .unwrap_err();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 1.0);
+    }
+
+    #[test]
+    fn client_store_reject_duplicates() {
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Default::default()
+        });
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap();
+        client_store
+            .execute(&TestTransaction { id: 1, amount: 1.0 })
+            .unwrap_err();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 1.0);
+    }
+
+    #[test]
+    fn client_store_rejects_duplicates_of_earlier_runs() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let deposit = |tx| registry.create_named("deposit", 1, tx, 1.0).unwrap();
+        let config = StoreConfig {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Default::default()
+        };
+        let mut client_store = ClientStore::with_config(config.clone());
+        for tx in [1, 2, 4] {
+            client_store.execute(deposit(tx).as_ref()).unwrap();
+        }
+        let mut state = Vec::new();
+        client_store.save_state(&mut state).unwrap();
+
+        let mut next_run = ClientStore::with_config(config);
+        next_run.load_state(state.as_slice()).unwrap();
+        assert_eq!(next_run.ledger().range_count(), 2);
+        let err = next_run.execute(deposit(2).as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DuplicateTransaction);
+        next_run.execute(deposit(3).as_ref()).unwrap();
+        assert_eq!(next_run.clients.get(&1).unwrap().available, 4.0);
+        assert_eq!(next_run.ledger().range_count(), 1);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn client_store_compacts_settled_history() {
+        use crate::transaction::TransactionRegistry;
+
+        fn execute_all(client_store: &mut ClientStore, data: &str) {
+            let registry = TransactionRegistry::new();
+            let mut reader = csv::ReaderBuilder::new().from_reader(data.as_bytes());
+            for result in reader.deserialize() {
+                let transaction = registry.create(result.unwrap()).unwrap();
+                let _ = client_store.execute(transaction.as_ref());
+            }
+        }
+
+        let data = "t_type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndispute,1,1,\nresolve,1,1,\ndispute,1,2,\n";
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            compaction: CompactionPolicy::Summarize { window: 1 },
+            ..Default::default()
+        });
+        execute_all(&mut client_store, data);
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!(client.client_history.get(&1), None);
+        assert_eq!(
+            client.compacted_history,
+            HistorySummary {
+                entries: 1,
+                amount: 1.0
+            }
+        );
+        assert_eq!(client_store.compaction_stats().summarized, 1);
+
+        // Within the window, the settled entry is kept
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            compaction: CompactionPolicy::Drop { window: 2 },
+            ..Default::default()
+        });
+        execute_all(&mut client_store, data);
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!(
+            client.client_history.get(&1).map(|history| history.state),
+            Some(DisputeState::Resolved)
+        );
+        assert_eq!(client_store.compaction_stats(), CompactionStats::default());
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn client_store_rejects_disputes_leaving_available_negative() {
+        use crate::transaction::TransactionRegistry;
+
+        let data = "t_type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,1.5\ndispute,1,1,\n";
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            negative_available: NegativeAvailablePolicy::Reject,
+            ..Default::default()
+        });
+        let mut reader = csv::ReaderBuilder::new().from_reader(data.as_bytes());
+        let results: Vec<_> = reader
+            .deserialize()
+            .map(|result| client_store.execute(registry.create(result.unwrap()).unwrap().as_ref()))
+            .collect();
+        assert_eq!(
+            results[2].as_ref().unwrap_err().kind(),
+            ErrorKind::NegativeAvailable
+        );
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (0.5, 0.0));
+        assert!(!client.client_history.get(&1).unwrap().is_disputed());
+    }
+
+    #[test]
+    fn client_store_rejects_withdrawals_over_velocity_rules() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let clock = ManualClock::new(0);
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            velocity_rules: vec![
+                VelocityRule::MaxCount {
+                    count: 2,
+                    window: 60,
+                },
+                VelocityRule::MaxAmount {
+                    amount: 10.0,
+                    window: 3600,
+                },
+            ],
+            ..Default::default()
+        });
+        client_store.set_clock(clock.clone());
+        let withdraw = |client_store: &mut ClientStore, tx, amount| {
+            let transaction = registry.create_named("withdrawal", 1, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref())
+        };
+        let deposit = registry.create_named("deposit", 1, 1, 100.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+
+        withdraw(&mut client_store, 2, 1.0).unwrap();
+        withdraw(&mut client_store, 3, 1.0).unwrap();
+        let err = withdraw(&mut client_store, 4, 1.0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::VelocityExceeded);
+        clock.advance(60);
+        withdraw(&mut client_store, 5, 7.0).unwrap();
+        let err = withdraw(&mut client_store, 6, 2.0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::VelocityExceeded);
+        clock.advance(3600);
+        withdraw(&mut client_store, 7, 2.0).unwrap();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 89.0);
+    }
+
+    #[test]
+    fn client_store_scores_risk() {
+        use crate::risk::RuleBasedScorer;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        client_store.set_risk_scorer(RuleBasedScorer {
+            deny_amount: Some(1000.0),
+            review_amount: Some(100.0),
+            ..Default::default()
+        });
+        let mut execute = |t_type, tx, amount| {
+            let transaction = registry.create_named(t_type, 1, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref())
+        };
+        execute("deposit", 1, 50.0).unwrap();
+        // Reviewed deposits are held until resolved
+        execute("deposit", 2, 200.0).unwrap();
+        let err = execute("deposit", 3, 5000.0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::RiskDenied);
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (50.0, 200.0));
+        assert!(!client.client_history.contains_key(&3));
+
+        let resolve = registry.create_named("resolve", 1, 2, 0.0).unwrap();
+        client_store.execute(resolve.as_ref()).unwrap();
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (250.0, 0.0));
+    }
+
+    #[test]
+    fn client_store_holds_reviewed_withdrawals() {
+        use crate::risk::RuleBasedScorer;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        client_store.set_risk_scorer(RuleBasedScorer {
+            review_withdrawal_ratio: Some(0.5),
+            ..Default::default()
+        });
+        let execute = |client_store: &mut ClientStore, t_type, client, tx, amount| {
+            let transaction = registry.create_named(t_type, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref())
+        };
+        for (client, tx) in [(1, 1), (2, 2)] {
+            execute(&mut client_store, "deposit", client, tx, 10.0).unwrap();
+            // Draining withdrawals are reviewed, their funds held but not withdrawn
+            execute(&mut client_store, "withdrawal", client, tx + 10, 10.0).unwrap();
+            let client = client_store.clients.get(&client).unwrap();
+            assert_eq!((client.available, client.held), (0.0, 10.0));
+        }
+        let err = execute(&mut client_store, "withdrawal", 1, 13, 1.0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InsufficientFunds);
+        assert!(client_store.trial_balance().is_balanced());
+
+        // The review is kept in the state
+        let mut snapshot = Vec::new();
+        client_store.save_state(&mut snapshot).unwrap();
+        let mut client_store = ClientStore::new();
+        client_store.load_state(snapshot.as_slice()).unwrap();
+
+        // Resolving makes the withdrawal, a chargeback releases it's funds
+        execute(&mut client_store, "resolve", 1, 11, 0.0).unwrap();
+        execute(&mut client_store, "chargeback", 2, 12, 0.0).unwrap();
+        let client = client_store.clients.get(&1).unwrap();
+        assert_eq!(
+            (client.available, client.held, client.locked),
+            (0.0, 0.0, false)
+        );
+        let client = client_store.clients.get(&2).unwrap();
+        assert_eq!(
+            (client.available, client.held, client.locked),
+            (10.0, 0.0, true)
+        );
+        let balance = client_store.trial_balance();
+        assert!(balance.is_balanced());
+        assert_eq!(balance.flows.withdrawals, 10.0);
+        assert_eq!(balance.flows.chargebacks, 0.0);
+    }
+
+    #[test]
+    fn client_store_state_saved_and_loaded() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        for (t_type, client, tx, amount) in [("deposit", 1, 1, 5.0), ("deposit", 2, 2, 3.0)] {
+            let transaction = registry.create_named(t_type, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+        let _ = client_store.references_mut().id("BANK-REF-1");
+        let withdrawal = registry.create_named("withdrawal", 2, 3, 9.0).unwrap();
+        let _ = client_store.execute_once("export-3", withdrawal.as_ref());
+        let mut snapshot = Vec::new();
+        client_store.save_state(&mut snapshot).unwrap();
+
+        let mut loaded = ClientStore::new();
+        loaded.load_state(&b"not a snapshot"[..]).unwrap_err();
+        loaded.load_state(snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.clients.len(), 2);
+        assert_eq!(
+            loaded.references().get("BANK-REF-1"),
+            Some(TransactionID::MAX)
+        );
+        assert_eq!(
+            loaded.idempotency_keys().get("export-3"),
+            client_store.idempotency_keys().get("export-3")
+        );
+        // Transactions of the previous run can be disputed by their owner only
+        let dispute = registry.create_named("dispute", 2, 1, 0.0).unwrap();
+        let err = loaded.execute(dispute.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WrongClient);
+        let dispute = registry.create_named("dispute", 1, 1, 0.0).unwrap();
+        loaded.execute(dispute.as_ref()).unwrap();
+        let client = loaded.clients.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (0.0, 5.0));
+    }
+
+    #[test]
+    fn client_store_deltas_saved_and_loaded() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let config = StoreConfig {
+            compaction: CompactionPolicy::Drop { window: 0 },
+            ..Default::default()
+        };
+        let mut client_store = ClientStore::with_config(config.clone());
+        let execute = |store: &mut ClientStore, t_type, client, tx, amount| {
+            let transaction = registry.create_named(t_type, client, tx, amount).unwrap();
+            store.execute(transaction.as_ref()).unwrap();
+        };
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            execute(&mut client_store, "deposit", client, tx, 5.0);
+        }
+        let mut snapshot = Vec::new();
+        client_store.save_delta(Vec::new()).unwrap_err();
+        client_store.save_checkpoint(&mut snapshot).unwrap();
+        assert_eq!(client_store.checkpoint_deltas(), Some(0));
+
+        execute(&mut client_store, "dispute", 1, 1, 0.0);
+        execute(&mut client_store, "deposit", 4, 4, 2.0);
+        client_store.set_tier(2, Tier::Premium);
+        let mut first = Vec::new();
+        assert_eq!(client_store.save_delta(&mut first).unwrap(), 1);
+        // The resolved entry is compacted out of the history
+        execute(&mut client_store, "resolve", 1, 1, 0.0);
+        execute(&mut client_store, "withdrawal", 3, 5, 1.0);
+        let mut second = Vec::new();
+        assert_eq!(client_store.save_delta(&mut second).unwrap(), 2);
+
+        let mut loaded = ClientStore::with_config(config.clone());
+        let deltas = [first.as_slice(), second.as_slice()];
+        assert_eq!(
+            loaded
+                .load_state_with_deltas(snapshot.as_slice(), deltas)
+                .unwrap(),
+            2
+        );
+        assert_eq!(loaded.checkpoint_deltas(), Some(2));
+        assert_eq!(loaded.clients.len(), 4);
+        for client in client_store.clients.values() {
+            let other = loaded.clients.get(&client.id).unwrap();
+            assert_eq!(
+                (other.available, other.held, other.tier),
+                (client.available, client.held, client.tier)
+            );
+            assert_eq!(other.client_history, client.client_history);
+        }
+        assert!(!loaded
+            .clients
+            .get(&1)
+            .unwrap()
+            .client_history
+            .contains_key(&1));
+        assert_eq!(loaded.trial_balance(), client_store.trial_balance());
+
+        // Deltas must follow each other, deltas of another snapshot are skipped
+        let mut loaded = ClientStore::with_config(config.clone());
+        loaded
+            .load_state_with_deltas(snapshot.as_slice(), [second.as_slice()])
+            .unwrap_err();
+        assert!(loaded.clients.is_empty());
+        let mut newer = Vec::new();
+        client_store.save_checkpoint(&mut newer).unwrap();
+        assert_eq!(
+            loaded
+                .load_state_with_deltas(newer.as_slice(), deltas)
+                .unwrap(),
+            0
+        );
+        assert_eq!(loaded.clients.len(), 4);
+    }
+
+    #[test]
+    fn client_store_rejects_disputes_by_other_clients() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let deposit = registry.create_named("deposit", 1, 1, 5.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let dispute = registry.create_named("dispute", 2, 1, 0.0).unwrap();
+        let err = client_store.execute(dispute.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WrongClient);
+        // Unknown transactions are still not found
+        let dispute = registry.create_named("dispute", 2, 7, 0.0).unwrap();
+        let err = client_store.execute(dispute.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TransactionNotFound);
+        assert!(!client_store.clients.contains_key(&2));
+    }
+
+    #[test]
+    fn client_store_retains_last_entries() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            history_retention: HistoryRetention {
+                last_entries: Some(2),
+                max_age: None,
+            },
+            ..Default::default()
+        });
+        for tx in 1..=3 {
+            let deposit = registry.create_named("deposit", 1, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        // Disputed entries are kept past the retention
+        let dispute = registry.create_named("dispute", 1, 2, 0.0).unwrap();
+        client_store.execute(dispute.as_ref()).unwrap();
+        for tx in 4..=5 {
+            let deposit = registry.create_named("deposit", 1, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        let client = client_store.clients.get(&1).unwrap();
+        let mut kept: Vec<_> = client.client_history.keys().copied().collect();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![2, 5]);
+        assert_eq!(client.total(), 25.0);
+
+        let dispute = registry.create_named("dispute", 1, 3, 0.0).unwrap();
+        let err = client_store.execute(dispute.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::HistoryPruned);
+        let resolve = registry.create_named("resolve", 1, 2, 0.0).unwrap();
+        client_store.execute(resolve.as_ref()).unwrap();
+        // Settled entries are pruned again
+        let deposit = registry.create_named("deposit", 1, 6, 5.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let client = client_store.clients.get(&1).unwrap();
+        let mut kept: Vec<_> = client.client_history.keys().copied().collect();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![5, 6]);
+    }
+
+    #[test]
+    fn client_store_prunes_aged_entries() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            history_retention: HistoryRetention {
+                last_entries: None,
+                max_age: Some(30 * DAY),
+            },
+            ..Default::default()
+        });
+        client_store.set_clock(ManualClock::new(0));
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let deposit = registry.create_named("deposit", client, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        client_store.set_clock(ManualClock::new(40 * DAY));
+        let deposit = registry.create_named("deposit", 1, 3, 5.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        assert!(!client_store
+            .clients
+            .get(&1)
+            .unwrap()
+            .client_history
+            .contains_key(&1));
+        // Idle clients are pruned on demand
+        assert!(client_store
+            .clients
+            .get(&2)
+            .unwrap()
+            .client_history
+            .contains_key(&2));
+        assert_eq!(client_store.prune_history(40 * DAY), 1);
+        assert!(client_store
+            .clients
+            .get(&2)
+            .unwrap()
+            .client_history
+            .is_empty());
+    }
+
+    #[test]
+    fn client_store_applies_amount_limits() {
+        use crate::config::AmountLimits;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            amount_limits: AmountLimits {
+                min: Some(0.0001),
+                max: Some(1_000_000.0),
+            },
+            ..Default::default()
+        });
+        let deposit = registry.create_named("deposit", 1, 1, 1_000_000.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let micro = registry.create_named("withdrawal", 1, 2, 0.00001).unwrap();
+        let err = client_store.execute(micro.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AmountBelowMinimum);
+        let over = registry.create_named("deposit", 1, 3, 1_000_000.5).unwrap();
+        let err = client_store.execute(over.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AmountAboveMaximum);
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 1_000_000.0);
+
+        // Disputes of deposits are not limited
+        let dispute = registry.create_named("dispute", 1, 1, 0.0).unwrap();
+        client_store.execute(dispute.as_ref()).unwrap();
+    }
+
+    #[test]
+    fn client_store_applies_daily_limits() {
+        use crate::clock::ManualClock;
+        use crate::config::DailyLimits;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            daily_limits: DailyLimits {
+                max_deposited: None,
+                max_withdrawn: Some(5000.0),
+            },
+            ..Default::default()
+        });
+        client_store.set_clock(ManualClock::new(DAY + 3600));
+        let deposit = registry.create_named("deposit", 1, 1, 20_000.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        for (tx, amount) in [(2, 3000.0), (3, 2000.0)] {
+            let withdrawal = registry.create_named("withdrawal", 1, tx, amount).unwrap();
+            client_store.execute(withdrawal.as_ref()).unwrap();
+        }
+        let over = registry.create_named("withdrawal", 1, 4, 0.01).unwrap();
+        let err = client_store.execute(over.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DailyLimitExceeded);
+
+        // The totals reset at the next day's boundary
+        client_store.set_clock(ManualClock::new(2 * DAY));
+        let withdrawal = registry.create_named("withdrawal", 1, 5, 5000.0).unwrap();
+        client_store.execute(withdrawal.as_ref()).unwrap();
+        let daily = client_store.clients.get(&1).unwrap().daily;
         assert_eq!(
-            client_store.clients.get(&1).unwrap().available,
-            4.5689 + 4.5689 + 4.5689 + 4.5689
+            (daily.day, daily.deposited, daily.withdrawn),
+            (2, 0.0, 5000.0)
         );
-        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
-        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+
+        // Earlier days are no longer counted
+        client_store.set_clock(ManualClock::new(DAY + 7200));
+        let late = registry.create_named("withdrawal", 1, 6, 1.0).unwrap();
+        let err = client_store.execute(late.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DailyLimitExceeded);
     }
 
     #[test]
-    fn client_store_add_available_transaction_multiple_with_different_clients() {
+    fn daily_limits_tolerate_rounding_errors() {
+        use crate::clock::ManualClock;
+        use crate::config::DailyLimits;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            daily_limits: DailyLimits {
+                max_deposited: Some(0.3),
+                max_withdrawn: None,
+            },
+            ..Default::default()
+        });
+        client_store.set_clock(ManualClock::new(0));
+        for (tx, amount) in [(1, 0.1), (2, 0.2)] {
+            let deposit = registry.create_named("deposit", 1, tx, amount).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        let over = registry.create_named("deposit", 1, 3, 0.0001).unwrap();
+        let err = client_store.execute(over.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DailyLimitExceeded);
+    }
+
+    #[test]
+    fn client_store_trial_balance() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
         let mut client_store = ClientStore::new();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
+        for (name, client, tx, amount) in [
+            ("deposit", 1, 1, 10.0),
+            ("deposit", 2, 2, 4.5),
+            ("withdrawal", 1, 3, 2.25),
+            ("dispute", 2, 2, 0.0),
+            ("chargeback", 2, 2, 0.0),
+            ("deposit", 1, 4, 1.0),
+            ("dispute", 1, 4, 0.0),
+        ] {
+            let transaction = registry.create_named(name, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+        let balance = client_store.trial_balance();
+        assert!(balance.is_balanced(), "{:?}", balance.violations);
         assert_eq!(
-            client_store.clients.get(&1).unwrap().available,
-            4.5689 + 4.5689 + 4.5689 + 4.5689
+            (
+                balance.flows.deposits,
+                balance.flows.withdrawals,
+                balance.flows.chargebacks
+            ),
+            (15.5, 2.25, 4.5)
         );
-        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
-        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
+        assert_eq!(balance.totals, 8.75);
+
+        // The flows of earlier runs are kept in the state
+        let mut snapshot = Vec::new();
+        client_store.save_state(&mut snapshot).unwrap();
+        let mut loaded = ClientStore::new();
+        loaded.load_state(snapshot.as_slice()).unwrap();
+        assert_eq!(loaded.trial_balance(), balance);
+
+        // Balances changed outside of transactions break it
+        loaded.clients.get_mut(&1).unwrap().held = -1.0;
+        let violations = loaded.trial_balance().violations;
+        assert_eq!(violations.len(), 2);
+    }
+
+    #[test]
+    fn client_store_reviews_dormant_clients() {
+        use crate::clock::ManualClock;
+        use crate::config::DormancyPolicy;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            dormancy: DormancyPolicy {
+                period: Some(365 * DAY),
+                freeze: true,
+            },
+            ..Default::default()
+        });
+        client_store.set_clock(ManualClock::new(0));
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            let deposit = registry.create_named("deposit", client, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        client_store.set_clock(ManualClock::new(200 * DAY));
+        let withdrawal = registry.create_named("withdrawal", 2, 4, 1.0).unwrap();
+        client_store.execute(withdrawal.as_ref()).unwrap();
+        // Administrative transactions are not activity
+        let verify = registry.create_named("verify", 3, 0, 0.0).unwrap();
+        client_store.execute(verify.as_ref()).unwrap();
+
+        let report = client_store.review_dormancy(400 * DAY);
+        let dormant: Vec<(ClientID, u64, bool)> = report
+            .clients
+            .iter()
+            .map(|client| (client.client, client.idle_days, client.frozen))
+            .collect();
+        assert_eq!(dormant, vec![(1, 400, true), (3, 400, true)]);
+        assert!(client_store.clients.get(&1).unwrap().frozen);
+        assert!(!client_store.clients.get(&2).unwrap().frozen);
         assert_eq!(
-            client_store.clients.get(&1).unwrap().available,
-            4.5689 + 4.5689 + 4.5689 + 4.5689
+            client_store.clients.get(&2).unwrap().last_activity,
+            Some(200 * DAY)
         );
-        assert_eq!(client_store.clients.get(&1).unwrap().held, 0.0);
-        assert_eq!(client_store.clients.get(&1).unwrap().locked, false);
     }
 
     #[test]
-    fn final_state_1_plus_1_transaction() {
-        // Use different transaction for testing
-        struct TestTransaction {}
-        impl Transaction for TestTransaction {
-            fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
-                // Add one to client
-                client.available += 1.0;
-                client.held += 1.0;
-                Ok(())
-            }
+    fn client_store_applies_tier_limits() {
+        use crate::config::TierLimits;
+        use crate::transaction::TransactionRegistry;
 
-            fn requested_client_id(&self) -> ClientID {
-                1
-            }
+        let registry = TransactionRegistry::new();
+        let limits = TierLimits {
+            max_balance: Some(1000.0),
+            max_withdrawal: Some(100.0),
+        };
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            tier_limits: [(Tier::Basic, limits)].into(),
+            ..Default::default()
+        });
+        client_store.set_tier(2, Tier::Premium);
+        for client in [1, 2] {
+            let deposit = registry.create_named("deposit", client, client as TransactionID, 900.0);
+            client_store.execute(deposit.unwrap().as_ref()).unwrap();
+        }
+        let over = registry.create_named("deposit", 1, 3, 200.0).unwrap();
+        let err = client_store.execute(over.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TierLimitExceeded);
+        let withdrawal = registry.create_named("withdrawal", 1, 4, 150.0).unwrap();
+        let err = client_store.execute(withdrawal.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TierLimitExceeded);
 
-            fn amount(&self) -> Option<Amount> {
-                Some(1.0)
-            }
+        // Premium clients are not limited
+        let deposit = registry.create_named("deposit", 2, 5, 200.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let withdrawal = registry.create_named("withdrawal", 2, 6, 150.0).unwrap();
+        client_store.execute(withdrawal.as_ref()).unwrap();
+        assert_eq!(client_store.clients.get(&2).unwrap().tier, Tier::Premium);
+
+        // Existing clients change tier
+        client_store.set_tier(1, Tier::Premium);
+        client_store.execute(over.as_ref()).unwrap();
+        assert_eq!(client_store.clients.get(&1).unwrap().total(), 1100.0);
+    }
+
+    #[test]
+    fn client_store_restricts_unverified_clients() {
+        use crate::config::VerificationPolicy;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            verification: VerificationPolicy {
+                max_withdrawal: Some(100.0),
+                block_deposits: false,
+            },
+            ..Default::default()
+        });
+        client_store.set_verified(2, true);
+        for client in [1, 2] {
+            let deposit = registry.create_named("deposit", client, client as TransactionID, 500.0);
+            client_store.execute(deposit.unwrap().as_ref()).unwrap();
         }
+        let small = registry.create_named("withdrawal", 1, 3, 50.0).unwrap();
+        client_store.execute(small.as_ref()).unwrap();
+        let large = registry.create_named("withdrawal", 1, 4, 150.0).unwrap();
+        let err = client_store.execute(large.as_ref()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unverified);
+        let large = registry.create_named("withdrawal", 2, 5, 150.0).unwrap();
+        client_store.execute(large.as_ref()).unwrap();
+
+        // Admin transactions do not reference the tx of another client
+        let verify = registry.create_named("verify", 1, 2, 0.0).unwrap();
+        client_store.execute(verify.as_ref()).unwrap();
+        assert!(client_store.clients.get(&1).unwrap().verified);
+        let large = registry.create_named("withdrawal", 1, 6, 150.0).unwrap();
+        client_store.execute(large.as_ref()).unwrap();
+        let unverify = registry.create_named("unverify", 1, 0, 0.0).unwrap();
+        client_store.execute(unverify.as_ref()).unwrap();
+        assert!(!client_store.clients.get(&1).unwrap().verified);
+    }
+
+    #[test]
+    fn client_store_memory_footprint() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
         let mut client_store = ClientStore::new();
-        client_store.execute(&TestTransaction {}).unwrap();
+        assert_eq!(client_store.memory_footprint().total(), 0);
+        for (client, tx) in [(1, 1), (1, 2), (2, 3)] {
+            let deposit = registry.create_named("deposit", client, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        let hold = registry.create_named("hold", 2, 4, 1.0).unwrap();
+        client_store.execute(hold.as_ref()).unwrap();
+
+        let footprint = client_store.memory_footprint();
+        assert_eq!(footprint.clients, 2 * size_of::<Client>());
+        assert_eq!(footprint.histories, 3 * ENTRY_SIZE);
         assert_eq!(
-            &client_store.get_current_state(true).unwrap(),
-            "client,available,held,total,locked\n1,1.0,1.0,2.0,false\n"
+            footprint.holds,
+            size_of::<TransactionID>() + size_of::<Amount>()
+        );
+        assert_eq!(
+            footprint.tracking,
+            4 * (size_of::<TransactionID>() + size_of::<ClientID>())
         );
     }
 
     #[test]
-    fn final_state_multiple_transactions() {
+    fn client_store_diagnostics() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
         let mut client_store = ClientStore::new();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
+        assert!(client_store.diagnostics().is_none());
+        client_store.enable_diagnostics(1);
+        for (tx, client) in [(1, 1), (2, 2), (3, 2)] {
+            let deposit = registry.create_named("deposit", client, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        // Rejected transactions are also tracked
+        let withdrawal = registry.create_named("withdrawal", 1, 4, 50.0).unwrap();
+        let _ = client_store.execute(withdrawal.as_ref()).unwrap_err();
+
+        let report = client_store.diagnostics().unwrap();
+        assert_eq!(report.slowest.len(), 1);
+        assert_eq!(report.busiest, vec![(1, 2)]);
+        assert_eq!(report.largest_histories, vec![(2, 2)]);
+        assert_eq!(report.history_entries, 3);
+    }
+
+    #[test]
+    fn client_store_accrues_interest() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        client_store.set_clock(ManualClock::new(0));
+        let deposit = registry.create_named("deposit", 1, 1, 100.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        let untimed = registry.create_named("deposit", 2, 2, 100.0).unwrap();
+        client_store.clock = None;
+        client_store.execute(untimed.as_ref()).unwrap();
+
+        let report = client_store.accrue_interest(0.05, 365 * DAY);
+        assert_eq!((report.applied, report.rejected), (1, 0));
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 105.0);
+        assert_eq!(client_store.clients.get(&2).unwrap().available, 100.0);
+        // Accrues from the previous accrual
+        let _ = client_store.accrue_interest(0.05, 438 * DAY);
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 106.05);
+        let tx = client_store
+            .references()
+            .get(&format!("interest-1-{}", 438 * DAY))
             .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
+        let history = client_store
+            .clients
+            .get(&1)
+            .unwrap()
+            .client_history
+            .get(&tx);
+        assert_eq!(history.unwrap().timestamp, Some(438 * DAY));
+    }
+
+    #[test]
+    fn interest_weighted_by_time_of_balances() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let clock = ManualClock::new(0);
+        client_store.set_clock(clock.clone());
+        let deposit = registry.create_named("deposit", 1, 1, 100.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        clock.set(73 * DAY);
+        let withdrawal = registry.create_named("withdrawal", 1, 2, 50.0).unwrap();
+        client_store.execute(withdrawal.as_ref()).unwrap();
+
+        // 100 for a fifth and 50 for four fifths of the year
+        let report = client_store.accrue_interest(0.05, 365 * DAY);
+        assert_eq!((report.applied, report.rejected), (1, 0));
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 53.0);
+    }
+
+    #[test]
+    fn interest_rounded_with_configured_mode() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let interest = |rounding| {
+            let mut client_store = ClientStore::with_config(StoreConfig {
+                rounding,
+                ..Default::default()
+            });
+            client_store.set_clock(ManualClock::new(0));
+            for (client, tx, amount) in [(1, 1, 1234.5), (2, 2, 1237.5)] {
+                let deposit = registry
+                    .create_named("deposit", client, tx, amount)
+                    .unwrap();
+                client_store.execute(deposit.as_ref()).unwrap();
+            }
+            // Accrues 0.12345 and 0.12375
+            let as_of = 365 * 86_400;
+            let _ = client_store.accrue_interest(0.0001, as_of);
+            [1, 2].map(|client| {
+                let reference = format!("interest-{}-{}", client, as_of);
+                let tx = client_store.references().get(&reference).unwrap();
+                let client = client_store.clients.get(&client).unwrap();
+                client.client_history.get(&tx).unwrap().amount
             })
-            .unwrap();
+        };
+        assert_eq!(interest(RoundingMode::HalfUp), [0.1235, 0.1238]);
+        assert_eq!(interest(RoundingMode::HalfEven), [0.1234, 0.1238]);
+        assert_eq!(interest(RoundingMode::Truncate), [0.1234, 0.1237]);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn final_state_fixed_point() {
+        let mut client_store = ClientStore::new();
         client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
+            .execute(&TestTransaction { id: 1, amount: 6.0 })
             .unwrap();
         client_store
             .execute(&TestTransaction {
-                id: 1,
+                id: 2,
                 amount: 4.5689,
             })
             .unwrap();
+        let options = OutputOptions {
+            sort: true,
+            fixed_point: true,
+            ..Default::default()
+        };
         assert_eq!(
-            client_store.clients.get(&1).unwrap().available,
-            4.5689 + 4.5689 + 4.5689 + 4.5689
+            &client_store.get_current_state_with_options(&options).unwrap(),
+            "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n2,4.5689,0.0000,4.5689,false\n"
         );
+    }
+
+    #[test]
+    fn client_store_ages_open_disputes() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        const DAY: Timestamp = 86_400;
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        client_store.set_clock(ManualClock::new(0));
+        for (client, tx) in [(1, 1), (2, 2)] {
+            let deposit = registry.create_named("deposit", client, tx, 5.0).unwrap();
+            client_store.execute(deposit.as_ref()).unwrap();
+        }
+        client_store.set_clock(ManualClock::new(50 * DAY));
+        let deposit = registry.create_named("deposit", 1, 3, 2.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        for (name, client, tx) in [
+            ("dispute", 1, 1),
+            ("dispute", 1, 3),
+            ("dispute", 2, 2),
+            ("resolve", 2, 2),
+        ] {
+            let transaction = registry.create_named(name, client, tx, 0.0).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let report = client_store.dispute_aging(100 * DAY).unwrap();
+        let buckets: Vec<(&str, u64, Amount)> = report
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.bucket, bucket.disputes, bucket.held))
+            .collect();
         assert_eq!(
-            &client_store.get_current_state(true).unwrap(),
-            "client,available,held,total,locked\n1,18.2756,0.0,18.2756,false\n"
+            buckets,
+            vec![
+                ("0-30", 0, 0.0),
+                ("31-60", 1, 2.0),
+                ("61-90", 0, 0.0),
+                ("90+", 1, 5.0)
+            ]
         );
     }
 
+    #[cfg(feature = "csv")]
     #[test]
-    fn final_state_multiple_transactions_multiple_clients() {
-        let mut client_store = ClientStore::new();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
-            .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
-            .unwrap();
+    fn client_store_exports_all_history() {
+        use crate::clock::ManualClock;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::with_config(StoreConfig {
+            history_memory_budget: Some(ENTRY_SIZE),
+            ..Default::default()
+        });
+        client_store.set_clock(ManualClock::new(1_700_000_000));
+        for (name, client, tx, amount) in [
+            ("deposit", 2, 3, 5.0),
+            ("deposit", 1, 2, 2.0),
+            ("withdrawal", 1, 1, 1.5),
+            ("dispute", 2, 3, 0.0),
+        ] {
+            let transaction = registry.create_named(name, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let mut export = Vec::new();
         client_store
-            .execute(&TestTransaction {
-                id: 1,
-                amount: 4.5689,
-            })
+            .export_all_history(&mut export, HistoryFormat::Csv)
             .unwrap();
-        client_store
-            .execute(&TestTransaction {
-                id: 2,
-                amount: 4.5689,
-            })
+        assert_eq!(
+            String::from_utf8(export).unwrap(),
+            "client,tx,amount,kind,state,timestamp\n\
+             1,1,1.5,withdrawal,undisputed,1700000000\n\
+             1,2,2.0,deposit,undisputed,1700000000\n\
+             2,3,5.0,deposit,disputed,1700000000\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn client_store_writes_sharded_state() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        for (client, tx) in [(ClientID::MAX, 1), (2, 2), (1, 3)] {
+            let transaction = registry.create_named("deposit", client, tx, 1.0).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let mut outputs = vec![Vec::new(); 3];
+        {
+            let options = OutputOptions::default();
+            let mut formats: Vec<Box<dyn OutputFormat + '_>> = outputs
+                .iter_mut()
+                .map(|output| Box::new(CsvFormat::new(output, &options)) as Box<dyn OutputFormat>)
+                .collect();
+            client_store
+                .write_sharded_state(&mut formats, ShardBy::Range, true)
+                .unwrap();
+        }
+        let header = "client,available,held,total,locked\n";
+        assert_eq!(
+            String::from_utf8(outputs[0].clone()).unwrap(),
+            format!("{}1,1.0,0.0,1.0,false\n2,1.0,0.0,1.0,false\n", header)
+        );
+        assert_eq!(String::from_utf8(outputs[1].clone()).unwrap(), header);
+        assert_eq!(
+            String::from_utf8(outputs[2].clone()).unwrap(),
+            format!("{}{},1.0,0.0,1.0,false\n", header, ClientID::MAX)
+        );
+    }
+
+    #[cfg(all(feature = "csv", feature = "anonymize"))]
+    #[test]
+    fn client_store_writes_anonymized_state() {
+        use crate::anonymize::Pseudonymizer;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            let transaction = registry.create_named("deposit", client, tx, 1.0).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let pseudonymizer = Pseudonymizer::new("secret");
+        let options = OutputOptions {
+            pseudonymizer: Some(pseudonymizer.clone()),
+            ..Default::default()
+        };
+        let state = client_store
+            .get_current_state_with_options(&options)
             .unwrap();
-        assert_eq!(&client_store.get_current_state(true).unwrap(), "client,available,held,total,locked\n1,18.2756,0.0,18.2756,false\n2,18.2756,0.0,18.2756,false\n");
+        let mut pseudonyms: Vec<String> = (1..=3)
+            .map(|client| pseudonymizer.pseudonym(client))
+            .collect();
+        pseudonyms.sort();
+        let expected: String = pseudonyms
+            .iter()
+            .map(|pseudonym| format!("{},1.0,0.0,1.0,false\n", pseudonym))
+            .collect();
+        assert_eq!(
+            state,
+            format!("client,available,held,total,locked\n{}", expected)
+        );
+    }
+
+    #[test]
+    fn client_store_publishes_snapshots() {
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let reader = client_store.enable_snapshots(2);
+        for tx in 1..=3 {
+            let transaction = registry.create_named("deposit", 1, tx, 1.0).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let snapshot = std::thread::spawn({
+            let reader = reader.clone();
+            move || reader.load()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(snapshot.executed, 2);
+        assert_eq!(
+            snapshot.get(1).map(|balances| balances.available),
+            Some(2.0)
+        );
+
+        client_store.refresh_snapshot();
+        assert_eq!(reader.load().executed, 3);
+        assert_eq!(
+            reader.load().get(1).map(|balances| balances.available),
+            Some(3.0)
+        );
+        assert_eq!(
+            snapshot.get(1).map(|balances| balances.available),
+            Some(2.0)
+        );
     }
 }
@@ -0,0 +1,79 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Timestamp;
+
+/// Source of the current time
+///
+/// Used by the ClientStore to timestamp transactions which do not
+/// carry a timestamp of their own.
+pub trait Clock {
+    /// Get the current time in seconds since the Unix epoch
+    fn now(&self) -> Timestamp;
+}
+
+/// Clock reading the system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// Simulated clock which only moves when it is set or advanced
+///
+/// Clones share the same time, so a clone kept by the caller can
+/// advance the clock of a ClientStore.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Create a new ManualClock starting at the time
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    /// Set the current time
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    /// Move the current time forward by a number of seconds
+    pub fn advance(&self, seconds: u64) {
+        let _ = self.now.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_shared_by_clones() {
+        let clock = ManualClock::new(100);
+        let shared = clock.clone();
+        shared.advance(20);
+        assert_eq!(clock.now(), 120);
+        clock.set(5);
+        assert_eq!(shared.now(), 5);
+    }
+}
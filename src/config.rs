@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{
+    amount::RoundingMode, client::Tier, error::TransactionError, storage::ClientStorage, Amount,
+    Timestamp,
+};
+
+/// Seconds in a day
+pub(crate) const DAY: u64 = 86_400;
+
+/// How transactions reusing an already applied transaction id are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Duplicates are executed like any other transaction.
+    #[default]
+    Apply,
+    /// Duplicates are rejected.
+    Reject,
+    /// Duplicates with identical fields are acknowledged as a no-op success.
+    ///
+    /// Allows for safe at-least-once delivery, e.g. from message queues.
+    /// Duplicates with different fields are rejected.
+    Idempotent,
+}
+
+/// How history entries of settled disputes are compacted
+///
+/// A dispute is settled when it is resolved or charged back. Settled entries
+/// can still be disputed again within the window, which is measured in
+/// transactions executed by the store after the settlement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionPolicy {
+    /// Settled entries are kept.
+    #[default]
+    Keep,
+    /// Settled entries are dropped once past the window.
+    Drop { window: u64 },
+    /// Settled entries are folded into the client's history summary
+    /// once past the window.
+    Summarize { window: u64 },
+}
+
+/// Retention of client history entries
+///
+/// Bounds the memory of histories on endless streams. Entries are pruned
+/// oldest first as transactions are executed, or with `ClientStore::prune_history`.
+/// Disputed entries are kept until the dispute is settled. Disputes, resolves
+/// and chargebacks of a pruned transaction are rejected with a `HistoryPruned` error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HistoryRetention {
+    /// Number of the most recent entries kept per client, unbounded if None
+    pub last_entries: Option<usize>,
+    /// Seconds entries are kept for, unbounded if None
+    ///
+    /// Only entries with a time are pruned by their age.
+    pub max_age: Option<u64>,
+}
+
+impl HistoryRetention {
+    /// Check if any entries are pruned
+    pub fn is_bounded(&self) -> bool {
+        self.last_entries.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Handling of disputes which would leave a client's available funds negative
+///
+/// A deposit can be disputed after it's funds were withdrawn, moving more
+/// funds to held than the client has available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeAvailablePolicy {
+    /// Such disputes are applied.
+    #[default]
+    Allow,
+    /// Such disputes are rejected with a `NegativeAvailable` error.
+    Reject,
+    /// Such disputes are applied and the client is flagged.
+    ///
+    /// Flagged clients are available from `ClientStore::flagged_clients`.
+    Flag,
+}
+
+/// Limit on a client's withdrawals within a rolling window of seconds
+///
+/// Withdrawals over the limit are rejected with a `VelocityExceeded` error.
+/// Only withdrawals with a time, from the input or the store's clock, are checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityRule {
+    /// At most `count` withdrawals within the window
+    MaxCount { count: u64, window: u64 },
+    /// At most `amount` withdrawn within the window
+    MaxAmount { amount: Amount, window: u64 },
+}
+
+impl VelocityRule {
+    /// Length of the rule's window in seconds
+    pub fn window(&self) -> u64 {
+        match *self {
+            Self::MaxCount { window, .. } | Self::MaxAmount { window, .. } => window,
+        }
+    }
+}
+
+/// Limits of the clients of a tier
+///
+/// Limits which are not set are not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TierLimits {
+    /// Deposits which would raise the client's total funds over this are rejected
+    pub max_balance: Option<Amount>,
+    /// Withdrawals of more than this are rejected
+    pub max_withdrawal: Option<Amount>,
+}
+
+/// Floor and ceiling of the amount of every deposit and withdrawal
+///
+/// Limits which are not set are not checked. Amounts below the minimum are
+/// rejected with an `AmountBelowMinimum` error, amounts above the maximum
+/// with an `AmountAboveMaximum` error.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AmountLimits {
+    /// Deposits and withdrawals of less than this are rejected
+    pub min: Option<Amount>,
+    /// Deposits and withdrawals of more than this are rejected
+    pub max: Option<Amount>,
+}
+
+/// Cumulative limits of every client per calendar day
+///
+/// Days are UTC calendar days of the transactions' times, so transactions
+/// without a time are not limited. Limits which are not set are not checked.
+/// Transactions over a limit are rejected with a `DailyLimitExceeded` error.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DailyLimits {
+    /// Deposits which would raise the amount deposited on the day over this are rejected
+    pub max_deposited: Option<Amount>,
+    /// Withdrawals which would raise the amount withdrawn on the day over this are rejected
+    pub max_withdrawn: Option<Amount>,
+}
+
+/// Detection of clients without activity
+///
+/// Clients are dormant once their last activity is at least the period ago.
+/// Clients without timestamped activity are never dormant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DormancyPolicy {
+    /// Seconds without activity after which a client is dormant, no detection if None
+    pub period: Option<u64>,
+    /// Dormant clients are frozen when they are reviewed
+    pub freeze: bool,
+}
+
+/// Restrictions on clients which are not verified
+///
+/// Restrictions which are not set are not checked. Restricted transactions
+/// are rejected with an `Unverified` error.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VerificationPolicy {
+    /// Withdrawals of more than this by unverified clients are rejected
+    ///
+    /// 0 blocks all withdrawals of unverified clients.
+    pub max_withdrawal: Option<Amount>,
+    /// Deposits to unverified clients are rejected
+    pub block_deposits: bool,
+}
+
+/// Day count convention of interest accrual
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DayCount {
+    /// Actual days elapsed over a 365 day year
+    #[default]
+    Actual365,
+    /// Actual days elapsed over a 360 day year
+    Actual360,
+    /// Days elapsed counting every month as 30 days, over a 360 day year
+    ///
+    /// Uses the US (bond basis) end of month rules.
+    Thirty360,
+}
+
+impl DayCount {
+    /// Fraction of a year between two times
+    pub fn year_fraction(&self, start: Timestamp, end: Timestamp) -> f64 {
+        let elapsed = end.saturating_sub(start) as f64 / DAY as f64;
+        match self {
+            Self::Actual365 => elapsed / 365.0,
+            Self::Actual360 => elapsed / 360.0,
+            Self::Thirty360 => {
+                let (y1, m1, d1) = civil_date(start);
+                let (y2, m2, d2) = civil_date(end);
+                let d1 = d1.min(30);
+                let d2 = if d1 == 30 { d2.min(30) } else { d2 };
+                let days = 360 * (y2 - y1) + 30 * (m2 - m1) + (d2 - d1);
+                days.max(0) as f64 / 360.0
+            }
+        }
+    }
+}
+
+/// Year, month and day of a time in the proleptic Gregorian calendar
+pub(crate) fn civil_date(timestamp: Timestamp) -> (i64, i64, i64) {
+    // Days since 0000-03-01, so leap days are the last day of the year
+    let days = (timestamp / DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Time of the start of a day in the proleptic Gregorian calendar
+///
+/// Returns None for invalid dates and days before 1970.
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+pub(crate) fn civil_timestamp(year: i64, month: i64, day: i64) -> Option<Timestamp> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let timestamp = u64::try_from(days).ok()? * DAY;
+    // Days past the end of the month, e.g. February 30th, roll over
+    (civil_date(timestamp).2 == day).then_some(timestamp)
+}
+
+/// Retries of transient failures of the storage backend
+///
+/// Only errors which are transient, like timeouts, are retried, with a delay
+/// doubling from the initial backoff up to the maximum. Rejections of
+/// transactions are never retried. Nothing is retried by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of times a failed operation is retried
+    pub retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Longest delay between retries
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run the operation, retrying it while it fails transiently.
+    pub(crate) fn run<T, F>(&self, mut operation: F) -> Result<T, TransactionError>
+    where
+        F: FnMut() -> Result<T, TransactionError>,
+    {
+        let mut attempts = 0;
+        loop {
+            match operation() {
+                Err(err) if err.is_transient() && attempts < self.retries => {
+                    thread::sleep(self.backoff(attempts));
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Get the delay before the retry following the number of attempts.
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempts.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+/// Configuration of a ClientStore
+///
+/// Default configuration matches the behavior of `ClientStore::new`.
+#[derive(Debug, Clone, Default)]
+pub struct StoreConfig {
+    /// Handling of transactions reusing an already applied transaction id
+    pub duplicate_policy: DuplicatePolicy,
+    /// Approximate memory budget in bytes for client histories
+    ///
+    /// When exceeded, the least recently used History entries are spilled
+    /// to a temporary file. Unbounded if None.
+    pub history_memory_budget: Option<usize>,
+    /// Compaction of history entries of settled disputes
+    pub compaction: CompactionPolicy,
+    /// Retention of history entries, all entries are kept by default
+    pub history_retention: HistoryRetention,
+    /// Skip recording withdrawals in client histories
+    ///
+    /// Withdrawals can then no longer be disputed, making disputes deposits
+    /// only. Cuts history memory for withdraw heavy workloads.
+    pub skip_withdrawal_history: bool,
+    /// Kind of storage used for clients
+    pub client_storage: ClientStorage,
+    /// Handling of disputes which would leave a client's available funds negative
+    pub negative_available: NegativeAvailablePolicy,
+    /// Limits on every client's withdrawals within rolling windows
+    pub velocity_rules: Vec<VelocityRule>,
+    /// Minimum and maximum amount of every deposit and withdrawal
+    pub amount_limits: AmountLimits,
+    /// Cumulative limits of every client's deposits and withdrawals per day
+    pub daily_limits: DailyLimits,
+    /// Detection of clients without activity of `ClientStore::review_dormancy`
+    pub dormancy: DormancyPolicy,
+    /// Day count convention of `ClientStore::accrue_interest`
+    pub day_count: DayCount,
+    /// Rounding mode of computed amounts, like accrued interest
+    pub rounding: RoundingMode,
+    /// Seconds after which authorization holds expire and their funds are released
+    ///
+    /// Only holds with a time expire. Holds never expire if None.
+    pub hold_expiry: Option<u64>,
+    /// Unlock the client when a chargeback is reversed
+    pub reversal_unlocks: bool,
+    /// Limits of the clients of every tier
+    ///
+    /// Clients of tiers without limits are not limited.
+    pub tier_limits: HashMap<Tier, TierLimits>,
+    /// Restrictions on clients which are not verified, none by default
+    pub verification: VerificationPolicy,
+    /// Retries of transient failures of the storage of spilled histories
+    pub storage_retry: RetryPolicy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_count_year_fractions() {
+        // 2024-01-31 and 2024-03-31, across a leap day
+        let start = 1_706_659_200;
+        let end = 1_711_843_200;
+        assert_eq!(civil_date(start), (2024, 1, 31));
+        assert_eq!(civil_date(end), (2024, 3, 31));
+        assert_eq!(civil_timestamp(2024, 1, 31), Some(start));
+        assert_eq!(civil_timestamp(2024, 2, 30), None);
+        assert_eq!(DayCount::Actual365.year_fraction(start, end), 60.0 / 365.0);
+        assert_eq!(DayCount::Actual360.year_fraction(start, end), 60.0 / 360.0);
+        assert_eq!(DayCount::Thirty360.year_fraction(start, end), 60.0 / 360.0);
+        assert_eq!(DayCount::Actual365.year_fraction(end, start), 0.0);
+    }
+
+    #[test]
+    fn only_transient_errors_retried() {
+        use crate::error::ErrorKind;
+
+        let policy = RetryPolicy {
+            retries: 2,
+            initial_backoff: Duration::ZERO,
+            ..Default::default()
+        };
+        let transient = || TransactionError::new(ErrorKind::Transient, "timed out".into());
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(transient())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err(transient())
+        });
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let result: Result<(), _> = policy.run(|| {
+            attempts += 1;
+            Err("Insufficent funds!".into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+        assert_eq!(
+            RetryPolicy::default().backoff(10),
+            RetryPolicy::default().max_backoff
+        );
+    }
+}
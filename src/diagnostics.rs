@@ -0,0 +1,344 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::Duration,
+};
+
+use crate::{
+    client::Client,
+    transaction::{Transaction, TransactionKind},
+    ClientID, IdMap, TransactionID,
+};
+
+/// Transaction executed by a ClientStore and the wall time it took
+#[derive(Debug, Clone, Copy)]
+pub struct SlowTransaction {
+    /// Position of the transaction among all executed transactions, from 1
+    pub index: u64,
+    /// Unique identifier of the transaction
+    pub transaction_id: TransactionID,
+    /// Client requested by the transaction
+    pub client: ClientID,
+    /// Kind of the transaction
+    pub kind: TransactionKind,
+    /// Wall time the execution took
+    pub elapsed: Duration,
+}
+
+impl SlowTransaction {
+    /// Key the slowest transactions are ordered by, earlier transactions first on ties
+    fn key(&self) -> (Duration, Reverse<u64>) {
+        (self.elapsed, Reverse(self.index))
+    }
+}
+
+impl PartialEq for SlowTransaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SlowTransaction {}
+
+impl PartialOrd for SlowTransaction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlowTransaction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Sub-buckets of every power of two of a LatencyHistogram
+const SUB_BUCKETS: u64 = 16;
+/// Bits of the sub-bucket of a LatencyHistogram
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+/// Number of buckets of a LatencyHistogram covering every u64 of nanoseconds
+const LATENCY_BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+/// Histogram of the wall times of transactions
+///
+/// Every power of two of nanoseconds is split into 16 buckets, so percentiles
+/// are within 1/16 of the exact value while memory stays fixed.
+#[derive(Debug, Clone)]
+struct LatencyHistogram {
+    /// Number of wall times in every bucket
+    buckets: Vec<u64>,
+    /// Number of wall times recorded
+    count: u64,
+    /// Longest wall time recorded
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; LATENCY_BUCKETS],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+
+    /// Bucket of a number of nanoseconds
+    fn bucket(nanos: u64) -> usize {
+        if nanos < SUB_BUCKETS {
+            return nanos as usize;
+        }
+        let exponent = 63 - nanos.leading_zeros();
+        let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) & (SUB_BUCKETS - 1);
+        ((exponent - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS + sub_bucket) as usize
+    }
+
+    /// Smallest number of nanoseconds of a bucket
+    fn lower_bound(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < SUB_BUCKETS {
+            return bucket;
+        }
+        let exponent = (bucket / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+        (SUB_BUCKETS + bucket % SUB_BUCKETS) << (exponent - SUB_BUCKET_BITS)
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(elapsed);
+    }
+
+    /// Wall time of the percentile, from 0 to 100, of the recorded wall times
+    fn percentile(&self, percentile: f64) -> Duration {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(Self::lower_bound(bucket)).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Wall time percentiles of the transactions of a kind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KindLatency {
+    /// Kind of the transactions
+    pub kind: TransactionKind,
+    /// Number of transactions of the kind
+    pub count: u64,
+    /// Median wall time
+    pub p50: Duration,
+    /// 90th percentile of the wall times
+    pub p90: Duration,
+    /// 99th percentile of the wall times
+    pub p99: Duration,
+    /// Longest wall time
+    pub max: Duration,
+}
+
+/// Report of the diagnostics of a ClientStore
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiagnosticsReport {
+    /// Slowest transactions, slowest first
+    pub slowest: Vec<SlowTransaction>,
+    /// Clients with the most transactions and their number of transactions, busiest first
+    pub busiest: Vec<(ClientID, u64)>,
+    /// Clients with the most history entries in memory and their number of entries, largest first
+    pub largest_histories: Vec<(ClientID, usize)>,
+    /// Number of history entries in memory over all clients
+    pub history_entries: usize,
+    /// Wall time percentiles of every kind of transaction executed, most frequent first
+    pub latencies: Vec<KindLatency>,
+}
+
+/// Diagnostics of the transactions executed by a ClientStore
+///
+/// Tracks the wall time of every transaction and the number of transactions
+/// of every client, to profile pathological inputs. Only the slowest
+/// transactions are kept, so memory is bounded by the number of clients.
+#[derive(Debug)]
+pub(crate) struct Diagnostics {
+    /// Number of entries in each list of the report
+    top: usize,
+    /// Number of transactions recorded
+    executed: u64,
+    /// Slowest transactions, fastest of them on top
+    slowest: BinaryHeap<Reverse<SlowTransaction>>,
+    /// Number of transactions of every client
+    counts: IdMap<ClientID, u64>,
+    /// Wall times of every kind of transaction
+    latencies: Vec<(TransactionKind, LatencyHistogram)>,
+}
+
+impl Diagnostics {
+    /// Create new Diagnostics reporting the top entries of every list
+    pub(crate) fn new(top: usize) -> Self {
+        Self {
+            top,
+            executed: 0,
+            slowest: BinaryHeap::with_capacity(top + 1),
+            counts: IdMap::default(),
+            latencies: Vec::new(),
+        }
+    }
+
+    /// Record the execution of a transaction, whether or not it succeeded.
+    pub(crate) fn record(&mut self, transaction: &dyn Transaction, elapsed: Duration) {
+        self.executed += 1;
+        let client = transaction.requested_client_id();
+        *self.counts.entry(client).or_default() += 1;
+        let kind = transaction.kind();
+        // Few kinds exist, so a linear search beats hashing
+        match self
+            .latencies
+            .iter_mut()
+            .find(|(recorded, _)| *recorded == kind)
+        {
+            Some((_, histogram)) => histogram.record(elapsed),
+            None => {
+                let mut histogram = LatencyHistogram::new();
+                histogram.record(elapsed);
+                self.latencies.push((kind, histogram));
+            }
+        }
+        self.slowest.push(Reverse(SlowTransaction {
+            index: self.executed,
+            transaction_id: transaction.transaction_id(),
+            client,
+            kind,
+            elapsed,
+        }));
+        if self.slowest.len() > self.top {
+            let _ = self.slowest.pop();
+        }
+    }
+
+    /// Report the diagnostics with the history sizes of the clients.
+    pub(crate) fn report<'c>(
+        &self,
+        clients: impl Iterator<Item = &'c Client>,
+    ) -> DiagnosticsReport {
+        let mut slowest: Vec<SlowTransaction> = self
+            .slowest
+            .iter()
+            .map(|Reverse(transaction)| *transaction)
+            .collect();
+        slowest.sort_by(|a, b| b.cmp(a));
+
+        let mut busiest: Vec<(ClientID, u64)> = self
+            .counts
+            .iter()
+            .map(|(id, count)| (*id, *count))
+            .collect();
+        busiest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        busiest.truncate(self.top);
+
+        let mut history_entries = 0;
+        let mut largest_histories: Vec<(ClientID, usize)> = clients
+            .map(|client| {
+                history_entries += client.client_history.len();
+                (client.id, client.client_history.len())
+            })
+            .collect();
+        largest_histories.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        largest_histories.truncate(self.top);
+
+        let mut latencies: Vec<KindLatency> = self
+            .latencies
+            .iter()
+            .map(|(kind, histogram)| KindLatency {
+                kind: *kind,
+                count: histogram.count,
+                p50: histogram.percentile(50.0),
+                p90: histogram.percentile(90.0),
+                p99: histogram.percentile(99.0),
+                max: histogram.max,
+            })
+            .collect();
+        latencies.sort_by(|a, b| b.count.cmp(&a.count).then(a.kind.name().cmp(b.kind.name())));
+
+        DiagnosticsReport {
+            slowest,
+            busiest,
+            largest_histories,
+            history_entries,
+            latencies,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::History;
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn top_entries_reported() {
+        let registry = TransactionRegistry::new();
+        let mut diagnostics = Diagnostics::new(2);
+        for (name, tx, client, micros) in [
+            ("deposit", 1, 1, 5),
+            ("deposit", 2, 2, 30),
+            ("withdrawal", 3, 1, 10),
+            ("deposit", 4, 3, 30),
+        ] {
+            let transaction = registry.create_named(name, client, tx, 1.0).unwrap();
+            diagnostics.record(transaction.as_ref(), Duration::from_micros(micros));
+        }
+
+        let mut client = Client::new(2);
+        let _ = client.client_history.insert(2, History::new(1.0));
+        let report = diagnostics.report([Client::new(1), client].iter());
+        let slowest: Vec<(u64, TransactionID)> = report
+            .slowest
+            .iter()
+            .map(|slow| (slow.index, slow.transaction_id))
+            .collect();
+        assert_eq!(slowest, vec![(2, 2), (4, 4)]);
+        assert_eq!(report.busiest, vec![(1, 2), (2, 1)]);
+        assert_eq!(report.largest_histories, vec![(2, 1), (1, 0)]);
+        assert_eq!(report.history_entries, 1);
+        let latencies: Vec<(TransactionKind, u64, Duration)> = report
+            .latencies
+            .iter()
+            .map(|latency| (latency.kind, latency.count, latency.max))
+            .collect();
+        assert_eq!(
+            latencies,
+            vec![
+                (TransactionKind::Deposit, 3, Duration::from_micros(30)),
+                (TransactionKind::Withdrawal, 1, Duration::from_micros(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let mut histogram = LatencyHistogram::new();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        for (percentile, micros) in [(50.0, 50), (90.0, 90), (99.0, 99), (100.0, 100)] {
+            let nanos = histogram.percentile(percentile).as_nanos() as f64;
+            let exact = micros as f64 * 1000.0;
+            assert!(
+                nanos <= exact && nanos > exact * 15.0 / 16.0,
+                "{}",
+                percentile
+            );
+        }
+        for nanos in [0, 1, 15, 16, 17, 1000, 123_456_789, u64::MAX] {
+            let bucket = LatencyHistogram::bucket(nanos);
+            assert!(bucket < LATENCY_BUCKETS);
+            assert!(LatencyHistogram::lower_bound(bucket) <= nanos);
+            assert!(
+                bucket + 1 == LATENCY_BUCKETS || LatencyHistogram::lower_bound(bucket + 1) > nanos
+            );
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::io::{Read, Result as IoResult};
+
+/// Size in bytes of the UTF-16 input decoded at once
+const DECODE_SIZE: usize = 8 * 1024;
+
+/// Encoding of the input detected from it's byte order mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Detect the encoding from the start of the input.
+    ///
+    /// Returns the encoding and the length of the byte order mark.
+    /// Input without a byte order mark is assumed to be UTF-8.
+    fn detect(start: &[u8]) -> (Self, usize) {
+        match start {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+            _ => (Encoding::Utf8, 0),
+        }
+    }
+}
+
+/// Reader which strips a byte order mark and transcodes UTF-16 input to UTF-8.
+///
+/// Files exported from Windows tools often start with a UTF-8 byte order mark
+/// or are UTF-16, which would otherwise end up in the first header.
+/// Invalid UTF-16 is replaced with the replacement character.
+pub struct DecodingReader<R> {
+    reader: R,
+    /// Encoding of the input, detected on the first read
+    encoding: Option<Encoding>,
+    /// Input read but not yet passed on or decoded
+    pending: Vec<u8>,
+    /// Decoded UTF-8 not yet passed on
+    decoded: Vec<u8>,
+    /// Position of the next byte to pass on in decoded
+    position: usize,
+    /// Reader has no more input
+    done: bool,
+}
+
+impl<R> DecodingReader<R>
+where
+    R: Read,
+{
+    /// Create a new DecodingReader over the input
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            encoding: None,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Encoding of the input, detecting it if not yet read
+    pub fn encoding(&mut self) -> IoResult<Encoding> {
+        match self.encoding {
+            Some(encoding) => Ok(encoding),
+            None => self.detect(),
+        }
+    }
+
+    fn detect(&mut self) -> IoResult<Encoding> {
+        while !self.done && self.pending.len() < 3 {
+            let wanted = (3 - self.pending.len()) as u64;
+            if (&mut self.reader)
+                .take(wanted)
+                .read_to_end(&mut self.pending)?
+                == 0
+            {
+                self.done = true;
+            }
+        }
+        let (encoding, bom) = Encoding::detect(&self.pending);
+        let _ = self.pending.drain(..bom);
+        self.encoding = Some(encoding);
+        Ok(encoding)
+    }
+
+    /// Decode the next part of UTF-16 input.
+    fn decode(&mut self, big_endian: bool) -> IoResult<()> {
+        self.decoded.clear();
+        self.position = 0;
+        while self.decoded.is_empty() && !(self.done && self.pending.is_empty()) {
+            if !self.done
+                && (&mut self.reader)
+                    .take(DECODE_SIZE as u64)
+                    .read_to_end(&mut self.pending)?
+                    == 0
+            {
+                self.done = true;
+            }
+            let mut units: Vec<u16> = self
+                .pending
+                .chunks_exact(2)
+                .map(|unit| {
+                    let unit = [unit[0], unit[1]];
+                    if big_endian {
+                        u16::from_be_bytes(unit)
+                    } else {
+                        u16::from_le_bytes(unit)
+                    }
+                })
+                .collect();
+            let mut used = units.len() * 2;
+            // A high surrogate may be paired with the next unit yet to be read
+            if !self.done && matches!(units.last(), Some(0xD800..=0xDBFF)) {
+                let _ = units.pop();
+                used -= 2;
+            }
+            if self.done && used < self.pending.len() {
+                // Dangling odd byte at the end of the input
+                used = self.pending.len();
+                units.push(0xFFFD);
+            }
+            let _ = self.pending.drain(..used);
+            for c in char::decode_utf16(units) {
+                let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+                let mut buf = [0; 4];
+                self.decoded
+                    .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for DecodingReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let big_endian = match self.encoding()? {
+            Encoding::Utf8 => {
+                if self.pending.is_empty() {
+                    return self.reader.read(buf);
+                }
+                let len = self.pending.len().min(buf.len());
+                buf[..len].copy_from_slice(&self.pending[..len]);
+                let _ = self.pending.drain(..len);
+                return Ok(len);
+            }
+            Encoding::Utf16Le => false,
+            Encoding::Utf16Be => true,
+        };
+        if self.position == self.decoded.len() {
+            self.decode(big_endian)?;
+        }
+        let len = (self.decoded.len() - self.position).min(buf.len());
+        buf[..len].copy_from_slice(&self.decoded[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(input: &[u8]) -> String {
+        let mut decoded = String::new();
+        let _ = DecodingReader::new(input)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        let mut bytes = if big_endian {
+            vec![0xFE, 0xFF]
+        } else {
+            vec![0xFF, 0xFE]
+        };
+        for unit in text.encode_utf16() {
+            if big_endian {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn utf8_passed_through() {
+        assert_eq!(decode(b"type,client"), "type,client");
+        assert_eq!(decode(b"ty"), "ty");
+        assert_eq!(decode(b""), "");
+    }
+
+    #[test]
+    fn utf8_bom_stripped() {
+        assert_eq!(decode(b"\xEF\xBB\xBFtype,client"), "type,client");
+    }
+
+    #[test]
+    fn utf16_transcoded() {
+        let text = "type,client,tx,amount\ndeposit,1,1,1.0 \u{1F4B0}\n";
+        assert_eq!(decode(&utf16(text, false)), text);
+        assert_eq!(decode(&utf16(text, true)), text);
+    }
+
+    #[test]
+    fn utf16_surrogate_across_reads() {
+        let text = "a".repeat(DECODE_SIZE / 2 - 1) + "\u{1F4B0}b";
+        assert_eq!(decode(&utf16(&text, false)), text);
+    }
+
+    #[test]
+    fn utf16_invalid_replaced() {
+        let mut bytes = utf16("a", false);
+        bytes.extend_from_slice(&[0x00, 0xD8, b'b', 0x00, b'c']);
+        assert_eq!(decode(&bytes), "a\u{FFFD}b\u{FFFD}");
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    aead::{consts::U12, rand_core::RngCore, AeadInPlace, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce, Tag,
+};
+
+use crate::error::TransactionError;
+
+/// Start of every encrypted file
+const MAGIC: &[u8; 4] = b"TXEN";
+
+/// Version of the encrypted layout
+const VERSION: u8 = 1;
+
+/// Size of the random nonce prefix of an encrypted file
+const PREFIX_SIZE: usize = 7;
+
+/// Size of the authentication tag of every chunk and record
+pub(crate) const TAG_SIZE: usize = 16;
+
+/// Largest plaintext of a chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Key of AES-256-GCM encryption
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: Key<Aes256Gcm>,
+}
+
+impl EncryptionKey {
+    /// Generate a random key
+    pub fn generate() -> Self {
+        Self {
+            key: Aes256Gcm::generate_key(OsRng),
+        }
+    }
+
+    /// Parse a key from 64 hexadecimal digits, ignoring surrounding whitespace
+    pub fn from_hex(hex: &str) -> Result<Self, TransactionError> {
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err("Encryption key must be 64 hexadecimal digits".into());
+        }
+        let mut key = Key::<Aes256Gcm>::default();
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or("Encryption key must be 64 hexadecimal digits")?;
+        }
+        Ok(Self { key })
+    }
+
+    /// Read a key from a file holding 64 hexadecimal digits
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TransactionError> {
+        Self::from_hex(&fs::read_to_string(path)?)
+    }
+
+    /// Get the key as 64 hexadecimal digits, e.g. to write a key file
+    pub fn to_hex(&self) -> String {
+        self.key
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.key)
+    }
+}
+
+/// Keys are not printed, so they do not leak into logs
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Writer encrypting everything written to it with AES-256-GCM
+///
+/// Data is encrypted in chunks of 64 KiB, each authenticated with it's
+/// position and whether it is the last chunk, so chunks can not be altered,
+/// reordered or cut off without the EncryptedReader noticing. The nonces are
+/// a random prefix of the file and the position of the chunk.
+pub struct EncryptedWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    cipher: Aes256Gcm,
+    prefix: [u8; PREFIX_SIZE],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W> EncryptedWriter<W>
+where
+    W: Write,
+{
+    /// Create a new EncryptedWriter with the key, writing the header.
+    pub fn new(mut writer: W, key: &EncryptionKey) -> Result<Self, TransactionError> {
+        let mut prefix = [0; PREFIX_SIZE];
+        OsRng.fill_bytes(&mut prefix);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&prefix)?;
+        Ok(Self {
+            writer,
+            cipher: key.cipher(),
+            prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE + TAG_SIZE),
+        })
+    }
+
+    /// Encrypt the remaining data as the last chunk, returning the underlying writer.
+    ///
+    /// Files which were not finished can not be read, as their end is missing.
+    pub fn finish(mut self) -> Result<W, TransactionError> {
+        self.seal(true)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Encrypt the buffered data as a chunk and write it.
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.prefix, self.counter, last);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| invalid("Encrypted file is too large"))?;
+        let len = self.buffer.len() as u32;
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &[], &mut self.buffer)
+            .map_err(|_| invalid("Couldn't encrypt chunk"))?;
+        self.writer.write_all(&[last as u8])?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&self.buffer)?;
+        self.writer.write_all(&tag)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W> Write for EncryptedWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.seal(false)?;
+        }
+        Ok(len)
+    }
+
+    /// Flush the underlying writer.
+    ///
+    /// Data of an incomplete chunk is only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reader decrypting a file written by an EncryptedWriter
+///
+/// Returns an error if the file was altered, cut off or encrypted with
+/// another key.
+pub struct EncryptedReader<R> {
+    reader: R,
+    cipher: Aes256Gcm,
+    prefix: [u8; PREFIX_SIZE],
+    counter: u32,
+    buffer: Vec<u8>,
+    position: usize,
+    last: bool,
+}
+
+impl<R> EncryptedReader<R>
+where
+    R: Read,
+{
+    /// Create a new EncryptedReader with the key, checking the header.
+    pub fn new(mut reader: R, key: &EncryptionKey) -> Result<Self, TransactionError> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err("Not an encrypted file".into());
+        }
+        if header[4] != VERSION {
+            return Err(format!("Unsupported encrypted file version {}", header[4]).into());
+        }
+        let mut prefix = [0; PREFIX_SIZE];
+        reader.read_exact(&mut prefix)?;
+        Ok(Self {
+            reader,
+            cipher: key.cipher(),
+            prefix,
+            counter: 0,
+            buffer: Vec::new(),
+            position: 0,
+            last: false,
+        })
+    }
+
+    /// Read and decrypt the next chunk.
+    fn open(&mut self) -> io::Result<()> {
+        let mut header = [0; 5];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(|_| invalid("Encrypted file is truncated"))?;
+        let last = match header[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("Encrypted file is corrupted")),
+        };
+        let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > CHUNK_SIZE {
+            return Err(invalid("Encrypted file is corrupted"));
+        }
+        self.buffer.resize(len, 0);
+        let mut tag = Tag::default();
+        self.reader
+            .read_exact(&mut self.buffer)
+            .and_then(|()| self.reader.read_exact(&mut tag))
+            .map_err(|_| invalid("Encrypted file is truncated"))?;
+        let nonce = chunk_nonce(&self.prefix, self.counter, last);
+        self.cipher
+            .decrypt_in_place_detached(&nonce, &[], &mut self.buffer, &tag)
+            .map_err(|_| invalid("Encrypted file was altered or the key is wrong"))?;
+        self.counter = self.counter.wrapping_add(1);
+        self.position = 0;
+        self.last = last;
+        Ok(())
+    }
+}
+
+impl<R> Read for EncryptedReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.last {
+                return Ok(0);
+            }
+            self.open()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Cipher of the records of a temporary file
+///
+/// Records are encrypted with a random key of the cipher and their offset as
+/// nonce, so offsets must never be reused, e.g. by only appending records.
+/// Neither the key nor the nonces are stored.
+pub(crate) struct RecordCipher {
+    cipher: Aes256Gcm,
+}
+
+impl RecordCipher {
+    /// Create a new RecordCipher with a random key
+    pub(crate) fn new() -> Self {
+        Self {
+            cipher: EncryptionKey::generate().cipher(),
+        }
+    }
+
+    /// Encrypt the record at the offset in place, returning it's tag.
+    pub(crate) fn seal(
+        &self,
+        offset: u64,
+        record: &mut [u8],
+    ) -> Result<[u8; TAG_SIZE], TransactionError> {
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&offset_nonce(offset), &[], record)
+            .map_err(|_| "Couldn't encrypt record")?;
+        Ok(tag.into())
+    }
+
+    /// Decrypt the record at the offset in place, checking it's tag.
+    pub(crate) fn open(
+        &self,
+        offset: u64,
+        record: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), TransactionError> {
+        let tag = Tag::from_exact_iter(tag.iter().copied()).ok_or("Corrupted encrypted record")?;
+        self.cipher
+            .decrypt_in_place_detached(&offset_nonce(offset), &[], record, &tag)
+            .map_err(|_| "Encrypted record was altered".into())
+    }
+}
+
+/// Nonce of a chunk, the prefix of the file, the chunk's position and if it is the last chunk
+fn chunk_nonce(prefix: &[u8; PREFIX_SIZE], counter: u32, last: bool) -> Nonce<U12> {
+    let mut nonce = Nonce::default();
+    nonce[..PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[PREFIX_SIZE..PREFIX_SIZE + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[PREFIX_SIZE + 4] = last as u8;
+    nonce
+}
+
+/// Nonce of a record, it's offset in the file
+fn offset_nonce(offset: u64) -> Nonce<U12> {
+    let mut nonce = Nonce::default();
+    nonce[..8].copy_from_slice(&offset.to_le_bytes());
+    nonce
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_file_round_trips() {
+        let key = EncryptionKey::generate();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let mut encrypted = EncryptedWriter::new(Vec::new(), &key).unwrap();
+        encrypted.write_all(&data).unwrap();
+        let encrypted = encrypted.finish().unwrap();
+        assert!(!encrypted.windows(64).any(|window| window == &data[..64]));
+
+        let mut decrypted = Vec::new();
+        EncryptedReader::new(encrypted.as_slice(), &key)
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        // Wrong keys, altered and truncated files are rejected
+        let other = EncryptionKey::generate();
+        let mut reader = EncryptedReader::new(encrypted.as_slice(), &other).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        let mut altered = encrypted.clone();
+        altered[100] ^= 1;
+        let mut reader = EncryptedReader::new(altered.as_slice(), &key).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        let truncated = &encrypted[..CHUNK_SIZE + 100];
+        let mut reader = EncryptedReader::new(truncated, &key).unwrap();
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+        assert!(EncryptedReader::new(&data[..], &key).is_err());
+    }
+
+    #[test]
+    fn key_parsed_from_hex() {
+        let key = EncryptionKey::generate();
+        let parsed = EncryptionKey::from_hex(&format!("{}\n", key.to_hex())).unwrap();
+        assert_eq!(parsed.to_hex(), key.to_hex());
+        assert!(EncryptionKey::from_hex("00ff").is_err());
+        assert!(EncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+        assert_eq!(format!("{:?}", key), "EncryptionKey(..)");
+    }
+
+    #[test]
+    fn records_bound_to_their_offset() {
+        let cipher = RecordCipher::new();
+        let mut record = *b"history record";
+        let tag = cipher.seal(64, &mut record).unwrap();
+        assert_ne!(&record, b"history record");
+        let mut moved = record;
+        assert!(cipher.open(128, &mut moved, &tag).is_err());
+        cipher.open(64, &mut record, &tag).unwrap();
+        assert_eq!(&record, b"history record");
+    }
+}
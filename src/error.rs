@@ -1,50 +1,298 @@
 use std::{
     fmt::{Display, Formatter, Result as FmtResult},
-    io::Error as IoError,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
     string::FromUtf8Error,
 };
 
+#[cfg(feature = "csv")]
 use csv::Error as CsvError;
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Severity of a processing problem
+///
+/// Ordered from the least to the most severe, so filters keep the problems
+/// of a level and above.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The row had no effect, but nothing was lost, e.g. a duplicate
+    #[default]
+    Warning,
+    /// The row was rejected
+    Error,
+    /// The input is corrupt and could not be read to it's end
+    Fatal,
+}
+
+/// Kind of a TransactionError
+///
+/// Every kind has a stable code, so automation can branch on the kind
+/// instead of parsing messages. Numbers and codes of kinds are never changed
+/// or reused, new kinds get the next number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(u16)]
+pub enum ErrorKind {
+    /// Error without a more specific kind
+    #[default]
+    Other = 0,
+    /// Dispute would leave the client's available funds negative
+    NegativeAvailable = 1,
+    /// Withdrawal would exceed a velocity rule of the client
+    VelocityExceeded = 2,
+    /// Transaction was denied by the store's RiskScorer
+    RiskDenied = 3,
+    /// Transaction can not move to the dispute state from it's current state
+    InvalidDisputeState = 4,
+    /// Transaction references a transaction of a different client
+    WrongClient = 5,
+    /// Transaction would leave the client's held funds negative
+    NegativeHeld = 6,
+    /// Transaction references a transaction pruned by the history retention
+    HistoryPruned = 7,
+    /// Transaction would exceed a limit of the client's tier
+    TierLimitExceeded = 8,
+    /// Transaction is restricted for clients which are not verified
+    Unverified = 9,
+    /// Transaction is not allowed while the client is frozen
+    Frozen = 10,
+    /// Transaction is not allowed while the client is locked
+    AccountLocked = 11,
+    /// Client's available funds don't cover the transaction
+    InsufficientFunds = 12,
+    /// Transaction references a transaction or hold which does not exist
+    TransactionNotFound = 13,
+    /// Transaction or hold was already applied
+    DuplicateTransaction = 14,
+    /// Input could not be read or decoded
+    CorruptInput = 15,
+    /// Temporary failure of I/O, e.g. a timeout, which may succeed when retried
+    Transient = 16,
+    /// Deposit or withdrawal is below the minimum transaction amount
+    AmountBelowMinimum = 17,
+    /// Deposit or withdrawal is above the maximum transaction amount
+    AmountAboveMaximum = 18,
+    /// Transaction would exceed a daily cumulative limit of the client
+    DailyLimitExceeded = 19,
+}
+
+impl ErrorKind {
+    /// Get the stable code of the kind, e.g. `INSUFFICIENT_FUNDS`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Other => "OTHER",
+            Self::NegativeAvailable => "NEGATIVE_AVAILABLE",
+            Self::VelocityExceeded => "VELOCITY_EXCEEDED",
+            Self::RiskDenied => "RISK_DENIED",
+            Self::InvalidDisputeState => "INVALID_DISPUTE_STATE",
+            Self::WrongClient => "WRONG_CLIENT",
+            Self::NegativeHeld => "NEGATIVE_HELD",
+            Self::HistoryPruned => "HISTORY_PRUNED",
+            Self::TierLimitExceeded => "TIER_LIMIT_EXCEEDED",
+            Self::Unverified => "UNVERIFIED",
+            Self::Frozen => "FROZEN",
+            Self::AccountLocked => "ACCOUNT_LOCKED",
+            Self::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            Self::TransactionNotFound => "TX_NOT_FOUND",
+            Self::DuplicateTransaction => "DUPLICATE_TX",
+            Self::CorruptInput => "CORRUPT_INPUT",
+            Self::Transient => "TRANSIENT",
+            Self::AmountBelowMinimum => "AMOUNT_BELOW_MINIMUM",
+            Self::AmountAboveMaximum => "AMOUNT_ABOVE_MAXIMUM",
+            Self::DailyLimitExceeded => "DAILY_LIMIT_EXCEEDED",
+        }
+    }
+
+    /// Get the severity of errors of the kind
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::DuplicateTransaction => Severity::Warning,
+            Self::CorruptInput | Self::Transient => Severity::Fatal,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Get the stable number of the kind
+    pub fn number(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Get the kind of the stable number, None if no kind has the number
+    pub fn from_number(number: u16) -> Option<Self> {
+        Some(match number {
+            0 => Self::Other,
+            1 => Self::NegativeAvailable,
+            2 => Self::VelocityExceeded,
+            3 => Self::RiskDenied,
+            4 => Self::InvalidDisputeState,
+            5 => Self::WrongClient,
+            6 => Self::NegativeHeld,
+            7 => Self::HistoryPruned,
+            8 => Self::TierLimitExceeded,
+            9 => Self::Unverified,
+            10 => Self::Frozen,
+            11 => Self::AccountLocked,
+            12 => Self::InsufficientFunds,
+            13 => Self::TransactionNotFound,
+            14 => Self::DuplicateTransaction,
+            15 => Self::CorruptInput,
+            16 => Self::Transient,
+            17 => Self::AmountBelowMinimum,
+            18 => Self::AmountAboveMaximum,
+            19 => Self::DailyLimitExceeded,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Serialized as it's code
+impl Serialize for ErrorKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.code())
+    }
+}
 
 /// Error type used when handling transactions.
 ///
 /// New type now to allow for easy usage.
 /// Improved error handling can be added later.
 #[derive(Debug)]
-pub struct TransactionError(String);
+pub struct TransactionError {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl TransactionError {
+    /// Create a new TransactionError of the kind
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self { kind, message }
+    }
+
+    /// Get the kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Get the stable code of the error's kind, e.g. `INSUFFICIENT_FUNDS`
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    /// Get the severity of the error's kind
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+
+    /// Check if the error is a temporary failure which may succeed when retried
+    ///
+    /// Rejections of transactions are never transient.
+    pub fn is_transient(&self) -> bool {
+        self.kind == ErrorKind::Transient
+    }
+}
 
 impl Display for TransactionError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.message)
     }
 }
 
+#[cfg(feature = "csv")]
 impl From<CsvError> for TransactionError {
     fn from(err: CsvError) -> Self {
-        Self(err.to_string())
+        let kind = match err.kind() {
+            csv::ErrorKind::Io(_) | csv::ErrorKind::Utf8 { .. } => ErrorKind::CorruptInput,
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, err.to_string())
     }
 }
 
 impl From<IoError> for TransactionError {
     fn from(err: IoError) -> Self {
-        Self(err.to_string())
+        let kind = match err.kind() {
+            IoErrorKind::Interrupted | IoErrorKind::WouldBlock | IoErrorKind::TimedOut => {
+                ErrorKind::Transient
+            }
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, err.to_string())
+    }
+}
+
+#[cfg(feature = "sql")]
+impl From<sqlx::Error> for TransactionError {
+    fn from(err: sqlx::Error) -> Self {
+        let kind = match err {
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => ErrorKind::Transient,
+            _ => ErrorKind::Other,
+        };
+        Self::new(kind, err.to_string())
     }
 }
 
 impl From<FromUtf8Error> for TransactionError {
     fn from(err: FromUtf8Error) -> Self {
-        Self(err.to_string())
+        Self::new(ErrorKind::Other, err.to_string())
     }
 }
 
 impl From<&str> for TransactionError {
     fn from(err: &str) -> Self {
-        Self(err.to_owned())
+        Self::new(ErrorKind::Other, err.to_owned())
     }
 }
 
 impl From<String> for TransactionError {
     fn from(err: String) -> Self {
-        Self(err)
+        Self::new(ErrorKind::Other, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_have_stable_codes() {
+        let err = TransactionError::new(ErrorKind::InsufficientFunds, "Insufficent funds!".into());
+        assert_eq!(err.code(), "INSUFFICIENT_FUNDS");
+        assert_eq!(err.kind().number(), 12);
+        assert_eq!(TransactionError::from("Unknown").code(), "OTHER");
+        assert_eq!(ErrorKind::TransactionNotFound.to_string(), "TX_NOT_FOUND");
+        for number in 0..=19 {
+            let kind = ErrorKind::from_number(number).unwrap();
+            assert_eq!(kind.number(), number);
+        }
+        assert_eq!(ErrorKind::from_number(20), None);
+    }
+
+    #[test]
+    fn errors_have_severities() {
+        assert_eq!(
+            ErrorKind::DuplicateTransaction.severity(),
+            Severity::Warning
+        );
+        assert_eq!(ErrorKind::InsufficientFunds.severity(), Severity::Error);
+        assert_eq!(ErrorKind::CorruptInput.severity(), Severity::Fatal);
+        assert!(Severity::Warning < Severity::Error && Severity::Error < Severity::Fatal);
+    }
+
+    #[test]
+    fn io_timeouts_are_transient() {
+        let timeout = IoError::new(IoErrorKind::TimedOut, "timed out");
+        assert!(TransactionError::from(timeout).is_transient());
+        let missing = IoError::new(IoErrorKind::NotFound, "not found");
+        assert!(!TransactionError::from(missing).is_transient());
     }
 }
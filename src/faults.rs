@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Read, Write},
+    thread,
+    time::Duration,
+};
+
+/// Failures injected by a FaultInjector
+///
+/// Operations are counted from one, every read or write call being one
+/// operation, so `fail_every: Some(3)` fails the third, sixth, ... call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultPlan {
+    /// Every Nth operation fails with an I/O error, without reading or writing
+    pub fail_every: Option<u64>,
+    /// Every Nth write is torn: the first half of it's buffer is written, then it fails
+    pub tear_every: Option<u64>,
+    /// Every Nth operation is delayed by the latency before it's done
+    pub slow_every: Option<u64>,
+    /// Delay of slow operations
+    pub latency: Duration,
+}
+
+impl FaultPlan {
+    fn hits(every: Option<u64>, operation: u64) -> bool {
+        every.is_some_and(|every| operation.is_multiple_of(every))
+    }
+}
+
+/// Reader or writer of stored state injecting failures, to exercise recovery paths
+///
+/// Wraps what states, deltas and other files are read from and written to,
+/// e.g. `ClientStore::save_state` and `ClientStore::load_state`, so the
+/// handling of failing, slow and torn storage can be tested locally.
+pub struct FaultInjector<T> {
+    inner: T,
+    plan: FaultPlan,
+    operations: u64,
+    injected: u64,
+}
+
+impl<T> FaultInjector<T> {
+    /// Create a new FaultInjector injecting the failures of the plan
+    pub fn new(inner: T, plan: FaultPlan) -> Self {
+        Self {
+            inner,
+            plan,
+            operations: 0,
+            injected: 0,
+        }
+    }
+
+    /// Get the number of failures injected so far, not counting delays
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Get the wrapped reader or writer
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Count an operation, delaying it if slow, and check if it fails.
+    fn operation(&mut self) -> io::Result<u64> {
+        self.operations += 1;
+        if FaultPlan::hits(self.plan.slow_every, self.operations) {
+            thread::sleep(self.plan.latency);
+        }
+        if FaultPlan::hits(self.plan.fail_every, self.operations) {
+            self.injected += 1;
+            return Err(io::Error::other("Injected I/O error"));
+        }
+        Ok(self.operations)
+    }
+}
+
+impl<T> Write for FaultInjector<T>
+where
+    T: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let operation = self.operation()?;
+        if FaultPlan::hits(self.plan.tear_every, operation) && !buf.is_empty() {
+            self.injected += 1;
+            self.inner.write_all(&buf[..buf.len() / 2])?;
+            return Err(io::Error::other("Injected torn write"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.operation()?;
+        self.inner.flush()
+    }
+}
+
+impl<T> Read for FaultInjector<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.operation()?;
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{client::ClientStore, handle_transactions_from_reader};
+
+    fn client_store() -> ClientStore {
+        let mut client_store = ClientStore::new();
+        let csv = "t_type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,3.0\ndispute,1,1,\n";
+        handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+        client_store
+    }
+
+    #[test]
+    fn failures_injected() {
+        let plan = FaultPlan {
+            fail_every: Some(2),
+            slow_every: Some(3),
+            latency: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let mut writer = FaultInjector::new(Vec::new(), plan);
+        assert!(writer.write_all(b"a").is_ok());
+        assert!(writer.write_all(b"b").is_err());
+        let start = Instant::now();
+        assert!(writer.write_all(b"c").is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(writer.injected(), 1);
+        assert_eq!(writer.into_inner(), b"ac");
+
+        let plan = FaultPlan {
+            tear_every: Some(1),
+            ..Default::default()
+        };
+        let mut writer = FaultInjector::new(Vec::new(), plan);
+        assert!(writer.write_all(b"abcd").is_err());
+        assert_eq!(writer.into_inner(), b"ab");
+    }
+
+    #[test]
+    fn failed_state_storage_detected() {
+        let client_store = client_store();
+        let plan = FaultPlan {
+            fail_every: Some(5),
+            ..Default::default()
+        };
+        let writer = FaultInjector::new(Vec::new(), plan);
+        assert!(client_store.save_state(writer).is_err());
+
+        // A torn state is not loaded as a shorter state
+        let plan = FaultPlan {
+            tear_every: Some(6),
+            ..Default::default()
+        };
+        let mut writer = FaultInjector::new(Vec::new(), plan);
+        assert!(client_store.save_state(&mut writer).is_err());
+        let torn = writer.into_inner();
+        assert!(ClientStore::new().load_state(torn.as_slice()).is_err());
+
+        let mut saved = Vec::new();
+        client_store.save_state(&mut saved).unwrap();
+        let plan = FaultPlan {
+            fail_every: Some(4),
+            ..Default::default()
+        };
+        let reader = FaultInjector::new(saved.as_slice(), plan);
+        assert!(ClientStore::new().load_state(reader).is_err());
+        ClientStore::new().load_state(saved.as_slice()).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use csv::{ReaderBuilder, Trim};
+
+use crate::{
+    amount::{round, RoundingMode},
+    client::ClientStore,
+    error::TransactionError,
+    handle_transactions_from_reader,
+    output::{OutputOptions, FIXED_DECIMAL_PLACES, STATUS_HEADER},
+    Amount, ClientID,
+};
+
+/// Columns compared as amounts, rounded to `FIXED_DECIMAL_PLACES`
+const AMOUNT_COLUMNS: [&str; 3] = ["available", "held", "total"];
+
+/// Rows of a final state by client, with their cells by column
+type StateRows = BTreeMap<ClientID, BTreeMap<String, String>>;
+
+/// Run the transactions of the input on a new store and compare the final
+/// state with the expected state, a golden file pinning the engine's behavior.
+///
+/// See `run_fixture_on`.
+pub fn run_fixture(input_csv: &str, expected_state_csv: &str) -> Result<(), TransactionError> {
+    run_fixture_on(&mut ClientStore::new(), input_csv, expected_state_csv)
+}
+
+/// Run the transactions of the input on the store and compare the final state
+/// with the expected state.
+///
+/// Both states are normalized before they are compared: rows are matched by
+/// client in any order, columns by their header in any order, and amounts are
+/// compared rounded to `FIXED_DECIMAL_PLACES`, so `1.5` matches `1.5000`.
+/// The expected state may leave out columns, and has a `status` column to
+/// compare statuses. Returns an error listing every difference.
+pub fn run_fixture_on(
+    client_store: &mut ClientStore,
+    input_csv: &str,
+    expected_state_csv: &str,
+) -> Result<(), TransactionError> {
+    let expected = state_rows(expected_state_csv)?;
+    handle_transactions_from_reader(input_csv.as_bytes(), client_store);
+    let options = OutputOptions {
+        status: expected
+            .values()
+            .any(|columns| columns.contains_key(STATUS_HEADER)),
+        ..Default::default()
+    };
+    let actual = state_rows(&client_store.get_current_state_with_options(&options)?)?;
+
+    let mut differences = Vec::new();
+    for (client, columns) in &expected {
+        let Some(actual_columns) = actual.get(client) else {
+            differences.push(format!("client {} is missing", client));
+            continue;
+        };
+        for (column, value) in columns {
+            let actual_value = actual_columns.get(column).map_or("", String::as_str);
+            if normalize(column, value) != normalize(column, actual_value) {
+                differences.push(format!(
+                    "client {} {}: expected {}, got {}",
+                    client, column, value, actual_value
+                ));
+            }
+        }
+    }
+    for client in actual
+        .keys()
+        .filter(|client| !expected.contains_key(client))
+    {
+        differences.push(format!("client {} is not expected", client));
+    }
+    if differences.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Final state differs from the fixture:\n  {}",
+        differences.join("\n  ")
+    )
+    .into())
+}
+
+/// Parse the rows of a final state by their client.
+fn state_rows(state_csv: &str) -> Result<StateRows, TransactionError> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(state_csv.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = StateRows::new();
+    for record in reader.records() {
+        let mut columns: BTreeMap<String, String> = headers
+            .iter()
+            .zip(record?.iter())
+            .map(|(header, value)| (header.to_owned(), value.to_owned()))
+            .collect();
+        let client = columns
+            .remove("client")
+            .and_then(|client| client.parse().ok())
+            .ok_or("Every row of a state needs a client")?;
+        if rows.insert(client, columns).is_some() {
+            return Err(format!("Client {} is in the state more than once", client).into());
+        }
+    }
+    Ok(rows)
+}
+
+/// Normalize a cell, rounding amounts and ignoring the case of other values.
+fn normalize(column: &str, value: &str) -> String {
+    match value.parse::<Amount>() {
+        Ok(amount) if AMOUNT_COLUMNS.contains(&column) => {
+            let places = FIXED_DECIMAL_PLACES as u32;
+            // Adding zero turns a negative zero into zero
+            let rounded = round(amount, places, RoundingMode::HalfUp) + 0.0;
+            format!("{:.*}", FIXED_DECIMAL_PLACES, rounded)
+        }
+        _ => value.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "t_type,client,tx,amount\n\
+                         deposit,2,1,1.5\n\
+                         deposit,1,2,2.25\n\
+                         dispute,1,2,\n";
+
+    #[test]
+    fn fixtures_normalized() {
+        run_fixture(
+            INPUT,
+            "client,available,held,total,locked\n1,0.0,2.25,2.25,false\n2,1.5,0.0,1.5,false\n",
+        )
+        .unwrap();
+        // Rows, columns and precision don't need to match the output
+        run_fixture(
+            INPUT,
+            "locked,client,total\nFALSE,2,1.50000\nfalse, 1 ,2.2500\n",
+        )
+        .unwrap();
+        run_fixture(INPUT, "client,status\n1,active\n2,active\n").unwrap();
+
+        let err = run_fixture(
+            INPUT,
+            "client,available,locked\n1,2.25,false\n3,0.0,false\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Final state differs from the fixture:\n  \
+             client 1 available: expected 2.25, got 0.0\n  \
+             client 3 is missing\n  \
+             client 2 is not expected"
+        );
+        assert!(run_fixture(INPUT, "client\n1\n1\n").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{self, Write};
+
+use crate::{Amount, ClientID, TransactionID};
+
+/// Settings of the generated transactions
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorOptions {
+    /// Number of clients transactions are spread across
+    pub clients: ClientID,
+    /// Number of rows generated
+    pub rows: u64,
+    /// Fraction of rows disputing an earlier deposit
+    pub dispute_ratio: f64,
+    /// Fraction of disputes which are later resolved
+    pub resolve_ratio: f64,
+    /// Fraction of disputes which are later charged back
+    pub chargeback_ratio: f64,
+    /// Fraction of withdrawals for more than the available funds
+    pub insufficient_funds_ratio: f64,
+    /// Seed of the random generator, the same seed generates the same rows
+    pub seed: u64,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            rows: 1000,
+            dispute_ratio: 0.05,
+            resolve_ratio: 0.5,
+            chargeback_ratio: 0.1,
+            insufficient_funds_ratio: 0.05,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64 random generator
+///
+/// Small and fully determined by it's seed, so generated files are
+/// reproducible across platforms and releases.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Random float in `[0, 1)`
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Random index in `[0, len)`
+    pub(crate) fn index(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+
+    /// Random amount with four decimal places in `[0.0001, max]`
+    pub(crate) fn amount(&mut self, max: Amount) -> Amount {
+        let units = (max * 10_000.0) as u64;
+        (1 + self.next_u64() % units.max(1)) as Amount / 10_000.0
+    }
+}
+
+/// Outcome decided for a dispute when it is opened
+#[derive(Clone, Copy)]
+enum Settlement {
+    Resolve,
+    Chargeback,
+}
+
+/// Generator of realistic random transactions
+///
+/// Tracks the balances of every client so withdrawals only exceed the
+/// available funds at the configured rate. Deposits and withdrawals go to
+/// unlocked clients and disputes only reference their earlier deposits.
+pub struct Generator {
+    options: GeneratorOptions,
+    rng: Rng,
+    next_tx: TransactionID,
+    /// Available funds of every client
+    available: Vec<Amount>,
+    /// Clients locked by a chargeback
+    locked: Vec<bool>,
+    /// Clients not locked by a chargeback
+    unlocked: Vec<ClientID>,
+    /// Deposits which can be disputed
+    deposits: Vec<(ClientID, TransactionID, Amount)>,
+    /// Open disputes which will be settled
+    disputes: Vec<(ClientID, TransactionID, Amount, Settlement)>,
+}
+
+impl Generator {
+    /// Create a new Generator of transactions with the options
+    pub fn new(options: GeneratorOptions) -> Self {
+        let clients = options.clients.max(1);
+        Self {
+            rng: Rng(options.seed),
+            options,
+            next_tx: 1,
+            available: vec![0.0; clients as usize + 1],
+            locked: vec![false; clients as usize + 1],
+            unlocked: (1..=clients).collect(),
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Write the header and all rows as CSV.
+    pub fn write<W>(&mut self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(writer, "t_type,client,tx,amount")?;
+        for _ in 0..self.options.rows {
+            self.write_row(&mut writer)?;
+        }
+        writer.flush()
+    }
+
+    fn write_row<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let roll = self.rng.next_f64();
+        if roll < self.options.dispute_ratio {
+            if let Some((client, tx, amount)) = self.take_deposit() {
+                let roll = self.rng.next_f64();
+                if roll < self.options.chargeback_ratio {
+                    self.disputes
+                        .push((client, tx, amount, Settlement::Chargeback));
+                } else if roll < self.options.chargeback_ratio + self.options.resolve_ratio {
+                    self.disputes
+                        .push((client, tx, amount, Settlement::Resolve));
+                }
+                self.available[client as usize] -= amount;
+                return writeln!(writer, "dispute,{},{},", client, tx);
+            }
+        } else if roll < self.options.dispute_ratio * 2.0 && !self.disputes.is_empty() {
+            let index = self.rng.index(self.disputes.len());
+            let (client, tx, amount, settlement) = self.disputes.swap_remove(index);
+            return match settlement {
+                Settlement::Resolve => {
+                    self.available[client as usize] += amount;
+                    writeln!(writer, "resolve,{},{},", client, tx)
+                }
+                Settlement::Chargeback => {
+                    if !self.locked[client as usize] {
+                        self.locked[client as usize] = true;
+                        self.unlocked.retain(|&unlocked| unlocked != client);
+                    }
+                    writeln!(writer, "chargeback,{},{},", client, tx)
+                }
+            };
+        }
+
+        // Once every client is locked, rows go to any client and are rejected
+        let client = match self.unlocked.len() {
+            0 => 1 + self.rng.index(self.available.len() - 1) as ClientID,
+            len => self.unlocked[self.rng.index(len)],
+        };
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        let available = self.available[client as usize];
+        if available > 0.0 && self.rng.next_f64() < 0.4 {
+            let amount = if self.rng.next_f64() < self.options.insufficient_funds_ratio {
+                available + self.rng.amount(100.0)
+            } else {
+                self.rng.amount(available)
+            };
+            if amount <= available && !self.locked[client as usize] {
+                self.available[client as usize] -= amount;
+            }
+            writeln!(writer, "withdrawal,{},{},{:.4}", client, tx, amount)
+        } else {
+            let amount = self.rng.amount(1000.0);
+            if !self.locked[client as usize] {
+                self.available[client as usize] += amount;
+                self.deposits.push((client, tx, amount));
+            }
+            writeln!(writer, "deposit,{},{},{:.4}", client, tx, amount)
+        }
+    }
+
+    /// Take a random deposit of an unlocked client to dispute.
+    fn take_deposit(&mut self) -> Option<(ClientID, TransactionID, Amount)> {
+        while !self.deposits.is_empty() {
+            let index = self.rng.index(self.deposits.len());
+            let deposit = self.deposits.swap_remove(index);
+            if !self.locked[deposit.0 as usize] {
+                return Some(deposit);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(options: GeneratorOptions) -> String {
+        let mut output = Vec::new();
+        Generator::new(options).write(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_rows() {
+        let options = GeneratorOptions {
+            clients: 5,
+            rows: 200,
+            dispute_ratio: 0.2,
+            seed: 42,
+            ..Default::default()
+        };
+        let first = generate(options.clone());
+        assert_eq!(first.lines().count(), 201);
+        assert_eq!(first, generate(options.clone()));
+        assert_ne!(first, generate(GeneratorOptions { seed: 7, ..options }));
+        assert!(first.contains("dispute,"));
+    }
+}
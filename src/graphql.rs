@@ -0,0 +1,448 @@
+use async_graphql::{
+    ComplexObject, EmptyMutation, EmptySubscription, Enum, InputObject, Object, OutputType,
+    Result as GraphQLResult, Schema, SimpleObject,
+};
+
+use crate::{
+    client::{Client, ClientStore, DisputeState},
+    error::TransactionError,
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Number of nodes of a page if `first` is not set
+const DEFAULT_PAGE: usize = 100;
+/// Largest number of nodes of a page
+const MAX_PAGE: usize = 1_000;
+
+/// Schema of the GraphQL query API
+pub type QuerySchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Status of a client, see `Client::status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum ClientStatus {
+    /// Client is neither locked nor frozen
+    Active,
+    /// Client is paused by compliance
+    Frozen,
+    /// Client was locked by a chargeback
+    Locked,
+}
+
+impl ClientStatus {
+    /// Get the status of the client
+    fn of(client: &Client) -> Self {
+        match client.status() {
+            "locked" => Self::Locked,
+            "frozen" => Self::Frozen,
+            _ => Self::Active,
+        }
+    }
+}
+
+/// Transaction in the history of a client
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct HistoryEntry {
+    /// Client of the transaction
+    pub client: ClientID,
+    /// Transaction id
+    pub tx: TransactionID,
+    /// Amount of the transaction
+    pub amount: Amount,
+    /// Kind of the transaction, e.g. `deposit`, if known
+    pub kind: Option<String>,
+    /// State of the transaction in the dispute lifecycle
+    pub state: DisputeState,
+    /// Time the transaction was executed, if known
+    pub timestamp: Option<Timestamp>,
+}
+
+impl HistoryEntry {
+    /// Get the cursor of the entry, `<client>:<tx>`
+    fn cursor(&self) -> String {
+        format!("{}:{}", self.client, self.tx)
+    }
+}
+
+/// Client with it's balances and history
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+#[graphql(complex, name = "Client")]
+pub struct ClientNode {
+    /// Client's unique identifier
+    pub id: ClientID,
+    /// Funds available to the client
+    pub available: Amount,
+    /// Funds held by disputes
+    pub held: Amount,
+    /// Client is locked
+    pub locked: bool,
+    /// Client is paused by compliance
+    pub frozen: bool,
+    /// Client passed identity verification
+    pub verified: bool,
+    /// Status of the client
+    pub status: ClientStatus,
+    /// History entries ordered by tx, including spilled entries
+    #[graphql(skip)]
+    pub history: Vec<HistoryEntry>,
+}
+
+#[ComplexObject]
+impl ClientNode {
+    /// Total funds of the client, available plus held
+    async fn total(&self) -> Amount {
+        self.available + self.held
+    }
+
+    /// Transactions of the client ordered by tx, optionally in a dispute state
+    async fn history(
+        &self,
+        state: Option<DisputeState>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> GraphQLResult<Page<HistoryEntry>> {
+        let entries: Vec<&HistoryEntry> = self
+            .history
+            .iter()
+            .filter(|entry| state.is_none_or(|state| entry.state == state))
+            .collect();
+        let after = entry_cursor(after)?;
+        let start = entries.partition_point(|entry| Some((entry.client, entry.tx)) <= after);
+        Ok(Page::of(&entries[start..], first, HistoryEntry::cursor))
+    }
+}
+
+impl ClientNode {
+    /// Create a new ClientNode with the balances of the client and an empty history
+    fn of(client: &Client) -> Self {
+        Self {
+            id: client.id,
+            available: client.available,
+            held: client.held,
+            locked: client.locked,
+            frozen: client.frozen,
+            verified: client.verified,
+            status: ClientStatus::of(client),
+            history: Vec::new(),
+        }
+    }
+
+    /// Get the cursor of the client, it's id
+    fn cursor(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+/// Filter of the clients query, every set field must match
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct ClientFilter {
+    /// Status of the client
+    pub status: Option<ClientStatus>,
+    /// Smallest total funds, inclusive
+    pub min_total: Option<Amount>,
+    /// Largest total funds, inclusive
+    pub max_total: Option<Amount>,
+    /// Client has or has no open disputes
+    pub has_open_disputes: Option<bool>,
+}
+
+impl ClientFilter {
+    /// Check if the client matches the filter
+    fn matches(&self, client: &ClientNode) -> bool {
+        let total = client.available + client.held;
+        self.status.is_none_or(|status| client.status == status)
+            && self.min_total.is_none_or(|min| total >= min)
+            && self.max_total.is_none_or(|max| total <= max)
+            && self.has_open_disputes.is_none_or(|open| {
+                client
+                    .history
+                    .iter()
+                    .any(|entry| entry.state == DisputeState::Disputed)
+                    == open
+            })
+    }
+}
+
+/// Page of a paginated query
+///
+/// Pass the `endCursor` as `after` to get the next page.
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+#[graphql(concrete(name = "ClientPage", params(ClientNode)))]
+#[graphql(concrete(name = "HistoryPage", params(HistoryEntry)))]
+pub struct Page<T: OutputType> {
+    /// Nodes of the page
+    pub nodes: Vec<T>,
+    /// More nodes follow the page
+    pub has_next_page: bool,
+    /// Cursor of the last node of the page
+    pub end_cursor: Option<String>,
+}
+
+impl<T> Page<T>
+where
+    T: OutputType + Clone,
+{
+    /// Create the page of the first nodes, DEFAULT_PAGE if not set and at most MAX_PAGE.
+    fn of(nodes: &[&T], first: Option<usize>, cursor: fn(&T) -> String) -> Self {
+        let first = first.unwrap_or(DEFAULT_PAGE).min(MAX_PAGE);
+        let page = &nodes[..first.min(nodes.len())];
+        Self {
+            nodes: page.iter().map(|node| (*node).clone()).collect(),
+            has_next_page: nodes.len() > page.len(),
+            end_cursor: page.last().map(|node| cursor(node)),
+        }
+    }
+}
+
+/// Parse the cursor of a history entry, `<client>:<tx>`.
+fn entry_cursor(after: Option<String>) -> GraphQLResult<Option<(ClientID, TransactionID)>> {
+    after
+        .map(|after| {
+            after
+                .split_once(':')
+                .and_then(|(client, tx)| Some((client.parse().ok()?, tx.parse().ok()?)))
+                .ok_or_else(|| format!("Invalid cursor {}", after).into())
+        })
+        .transpose()
+}
+
+/// Root of the GraphQL queries
+///
+/// Holds a copy of the clients and their histories taken when the schema is
+/// built, so queries never touch the store.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Clients ordered by their id
+    clients: Vec<ClientNode>,
+}
+
+impl Query {
+    /// Copy the clients and histories of the store.
+    pub fn of(store: &ClientStore) -> Result<Self, TransactionError> {
+        let mut clients: Vec<ClientNode> = store.clients.values().map(ClientNode::of).collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        store.for_each_history(|client_id, tx, history| {
+            if let Ok(index) = clients.binary_search_by_key(&client_id, |client| client.id) {
+                clients[index].history.push(HistoryEntry {
+                    client: client_id,
+                    tx,
+                    amount: history.amount,
+                    kind: history.kind.map(|kind| kind.name().to_owned()),
+                    state: history.state,
+                    timestamp: history.timestamp,
+                });
+            }
+            Ok(())
+        })?;
+        Ok(Self { clients })
+    }
+}
+
+#[Object]
+impl Query {
+    /// Get a client by it's id
+    async fn client(&self, id: ClientID) -> Option<&ClientNode> {
+        self.clients
+            .binary_search_by_key(&id, |client| client.id)
+            .ok()
+            .map(|index| &self.clients[index])
+    }
+
+    /// Clients ordered by their id matching the filter
+    async fn clients(
+        &self,
+        filter: Option<ClientFilter>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> GraphQLResult<Page<ClientNode>> {
+        let filter = filter.unwrap_or_default();
+        let after: Option<ClientID> = after
+            .map(|after| {
+                after
+                    .parse()
+                    .map_err(|_| format!("Invalid cursor {}", after))
+            })
+            .transpose()?;
+        let clients: Vec<&ClientNode> = self
+            .clients
+            .iter()
+            .filter(|client| after.is_none_or(|after| client.id > after))
+            .filter(|client| filter.matches(client))
+            .collect();
+        Ok(Page::of(&clients, first, ClientNode::cursor))
+    }
+
+    /// Disputed transactions ordered by client and tx, optionally of one client
+    async fn open_disputes(
+        &self,
+        client: Option<ClientID>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> GraphQLResult<Page<HistoryEntry>> {
+        let after = entry_cursor(after)?;
+        let disputes: Vec<&HistoryEntry> = self
+            .clients
+            .iter()
+            .filter(|node| client.is_none_or(|client| node.id == client))
+            .flat_map(|node| node.history.iter())
+            .filter(|entry| entry.state == DisputeState::Disputed)
+            .filter(|entry| after.is_none_or(|after| (entry.client, entry.tx) > after))
+            .collect();
+        Ok(Page::of(&disputes, first, HistoryEntry::cursor))
+    }
+}
+
+/// GraphQL query API over the clients of a ClientStore
+///
+/// The API answers queries of clients, their balances and histories, and
+/// of open disputes, with filters and cursor pagination. It serves a copy
+/// of the store taken when it was created. Queries are executed on a
+/// single threaded runtime, as resolvers never wait on IO.
+pub struct QueryApi {
+    schema: QuerySchema,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl QueryApi {
+    /// Create a new QueryApi serving the clients of the store
+    pub fn new(store: &ClientStore) -> Result<Self, TransactionError> {
+        let schema = Schema::build(Query::of(store)?, EmptyMutation, EmptySubscription)
+            .limit_depth(8)
+            .finish();
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        Ok(Self { schema, runtime })
+    }
+
+    /// Get the schema in the GraphQL schema definition language
+    pub fn sdl(&self) -> String {
+        self.schema.sdl()
+    }
+
+    /// Execute a GraphQL request, returning the JSON response.
+    ///
+    /// The body is a JSON request with a `query` and optional `variables`
+    /// and `operationName`. Errors of the query are part of the response,
+    /// a body which is not a request is an error.
+    pub fn execute(&self, body: &str) -> Result<String, TransactionError> {
+        let request: async_graphql::Request =
+            serde_json::from_str(body).map_err(|err| err.to_string())?;
+        let response = self.runtime.block_on(self.schema.execute(request));
+        Ok(serde_json::to_string(&response).map_err(|err| err.to_string())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::handle_transactions_from_reader;
+
+    fn query(api: &QueryApi, query: &str) -> Value {
+        let body = json!({ "query": query }).to_string();
+        serde_json::from_str(&api.execute(&body).unwrap()).unwrap()
+    }
+
+    fn store() -> ClientStore {
+        let csv = "t_type,client,tx,amount
+deposit,1,10,10.0
+withdrawal,1,11,1.0
+deposit,2,20,20.0
+withdrawal,2,21,1.0
+deposit,3,30,30.0
+withdrawal,3,31,1.0
+dispute,2,20,
+dispute,3,30,
+";
+        let mut client_store = ClientStore::default();
+        let report = handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+        assert_eq!(report.rejected, 0);
+        client_store
+    }
+
+    #[test]
+    fn query_clients_and_histories() {
+        let api = QueryApi::new(&store()).unwrap();
+        let response = query(
+            &api,
+            "{ client(id: 2) { available held total status history { nodes { tx kind state } } } }",
+        );
+        assert_eq!(
+            response,
+            json!({ "data": { "client": {
+                "available": -1.0,
+                "held": 20.0,
+                "total": 19.0,
+                "status": "ACTIVE",
+                "history": { "nodes": [
+                    { "tx": 20, "kind": "deposit", "state": "DISPUTED" },
+                    { "tx": 21, "kind": "withdrawal", "state": "UNDISPUTED" },
+                ] },
+            } } })
+        );
+        assert_eq!(
+            query(&api, "{ client(id: 4) { id } }"),
+            json!({ "data": { "client": null } })
+        );
+        assert!(api.sdl().contains("openDisputes"));
+    }
+
+    #[test]
+    fn query_filters_and_pages() {
+        let api = QueryApi::new(&store()).unwrap();
+        let response = query(
+            &api,
+            "{ clients(filter: { minTotal: 15 }, first: 1) { nodes { id } hasNextPage endCursor } }",
+        );
+        assert_eq!(
+            response["data"]["clients"],
+            json!({ "nodes": [{ "id": 2 }], "hasNextPage": true, "endCursor": "2" })
+        );
+        let response = query(
+            &api,
+            "{ clients(filter: { minTotal: 15 }, after: \"2\") { nodes { id } hasNextPage } }",
+        );
+        assert_eq!(
+            response["data"]["clients"],
+            json!({ "nodes": [{ "id": 3 }], "hasNextPage": false })
+        );
+        let response = query(
+            &api,
+            "{ clients(filter: { hasOpenDisputes: false }) { nodes { id } } }",
+        );
+        assert_eq!(response["data"]["clients"]["nodes"], json!([{ "id": 1 }]));
+
+        let response = query(
+            &api,
+            "{ openDisputes(first: 1) { nodes { client tx amount } endCursor } }",
+        );
+        assert_eq!(
+            response["data"]["openDisputes"],
+            json!({ "nodes": [{ "client": 2, "tx": 20, "amount": 20.0 }], "endCursor": "2:20" })
+        );
+        let response = query(
+            &api,
+            "{ openDisputes(after: \"2:20\") { nodes { tx } hasNextPage } }",
+        );
+        assert_eq!(
+            response["data"]["openDisputes"],
+            json!({ "nodes": [{ "tx": 30 }], "hasNextPage": false })
+        );
+        let response = query(
+            &api,
+            "{ client(id: 3) { history(state: UNDISPUTED) { nodes { tx } } } }",
+        );
+        assert_eq!(
+            response["data"]["client"]["history"]["nodes"],
+            json!([{ "tx": 31 }])
+        );
+    }
+
+    #[test]
+    fn query_errors_are_responses() {
+        let api = QueryApi::new(&store()).unwrap();
+        let response = query(&api, "{ openDisputes(after: \"2\") { endCursor } }");
+        assert_eq!(response["errors"][0]["message"], json!("Invalid cursor 2"));
+        assert!(query(&api, "{ unknown }")["errors"].is_array());
+        assert!(api.execute("not json").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::error::{ErrorKind, TransactionError};
+
+/// Outcome of the first row submitted with an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The transaction was applied
+    Applied,
+    /// The transaction was rejected with an error of the kind and message
+    Rejected(ErrorKind, String),
+}
+
+impl Outcome {
+    /// Get the outcome of executing a transaction
+    pub fn of(result: &Result<(), TransactionError>) -> Self {
+        match result {
+            Ok(()) => Self::Applied,
+            Err(err) => Self::Rejected(err.kind(), err.to_string()),
+        }
+    }
+
+    /// Get the result the outcome is replayed as
+    pub fn result(&self) -> Result<(), TransactionError> {
+        match self {
+            Self::Applied => Ok(()),
+            Self::Rejected(kind, message) => Err(TransactionError::new(*kind, message.clone())),
+        }
+    }
+}
+
+/// Outcomes of transactions by their idempotency key
+///
+/// Upstream exporters retrying a row may resubmit it with a new tx, but the
+/// same idempotency key. The first outcome of a key is kept, so the retry
+/// is answered with it instead of being applied again. Transient failures
+/// are not kept, so they can be retried.
+#[derive(Debug, Clone, Default)]
+pub struct IdempotencyKeys {
+    outcomes: HashMap<String, Outcome>,
+}
+
+impl IdempotencyKeys {
+    /// Create a new empty IdempotencyKeys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the outcome of the key, if it was seen
+    pub fn get(&self, key: &str) -> Option<&Outcome> {
+        self.outcomes.get(key)
+    }
+
+    /// Record the result of the first transaction of the key.
+    ///
+    /// Transient failures and keys which were already seen are not recorded.
+    pub fn record(&mut self, key: &str, result: &Result<(), TransactionError>) {
+        if result.as_ref().is_err_and(TransactionError::is_transient) {
+            return;
+        }
+        if !self.outcomes.contains_key(key) {
+            let _ = self.outcomes.insert(key.to_owned(), Outcome::of(result));
+        }
+    }
+
+    /// Get the number of keys seen
+    pub fn len(&self) -> usize {
+        self.outcomes.len()
+    }
+
+    /// Check if no key was seen
+    pub fn is_empty(&self) -> bool {
+        self.outcomes.is_empty()
+    }
+
+    /// Iterate over the keys and their outcomes
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &Outcome)> {
+        self.outcomes
+            .iter()
+            .map(|(key, outcome)| (key.as_str(), outcome))
+    }
+
+    /// Create IdempotencyKeys from the outcomes of keys
+    ///
+    /// Used to restore IdempotencyKeys saved in a state snapshot.
+    pub(crate) fn from_parts(outcomes: HashMap<String, Outcome>) -> Self {
+        Self { outcomes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientStore;
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn retries_answered_with_first_outcome() {
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let deposit = registry.create_named("deposit", 1, 1, 5.0).unwrap();
+        client_store
+            .execute_once("export-1", deposit.as_ref())
+            .unwrap();
+        // Resubmitted with a new tx
+        let retry = registry.create_named("deposit", 1, 2, 5.0).unwrap();
+        client_store
+            .execute_once("export-1", retry.as_ref())
+            .unwrap();
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 5.0);
+
+        let withdrawal = registry.create_named("withdrawal", 1, 3, 9.0).unwrap();
+        let err = client_store
+            .execute_once("export-2", withdrawal.as_ref())
+            .unwrap_err();
+        let deposit = registry.create_named("deposit", 1, 4, 9.0).unwrap();
+        client_store.execute(deposit.as_ref()).unwrap();
+        // The retry is rejected like the first submission, even though funds now suffice
+        let retry = registry.create_named("withdrawal", 1, 5, 9.0).unwrap();
+        let replayed = client_store
+            .execute_once("export-2", retry.as_ref())
+            .unwrap_err();
+        assert_eq!(
+            (replayed.kind(), replayed.to_string()),
+            (err.kind(), err.to_string())
+        );
+        assert_eq!(client_store.clients.get(&1).unwrap().available, 14.0);
+        assert_eq!(client_store.idempotency_keys().len(), 2);
+    }
+
+    #[test]
+    fn transient_failures_not_recorded() {
+        let mut keys = IdempotencyKeys::new();
+        keys.record(
+            "export-1",
+            &Err(TransactionError::new(
+                ErrorKind::Transient,
+                "timed out".into(),
+            )),
+        );
+        assert!(keys.get("export-1").is_none());
+        keys.record("export-1", &Ok(()));
+        keys.record("export-1", &Err("Insufficent funds!".into()));
+        assert_eq!(keys.get("export-1"), Some(&Outcome::Applied));
+    }
+}
@@ -0,0 +1,1199 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read, Sink, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use csv::{ByteRecord, ReaderBuilder, Terminator, Trim, Writer, WriterBuilder};
+use serde::Deserialize;
+
+use crate::{
+    alias::ClientAliases,
+    amount::AmountFormat,
+    client::ClientStore,
+    encoding::DecodingReader,
+    error::{Severity, TransactionError},
+    report::{PhaseTimer, ProcessingReport},
+    transaction::TransactionRegistry,
+    Amount, CsvLine, CsvLineType, Reference, Timestamp, TransactionID,
+};
+
+/// CSV input data structure borrowing from the input row
+///
+/// Amounts are kept as is so they can be parsed by an AmountFormat.
+/// Either the transaction id or the reference, or both, must be present.
+#[derive(Debug, Deserialize)]
+struct RawCsvLine<'a> {
+    t_type: CsvLineType,
+    #[serde(borrow)]
+    client: &'a str,
+    #[serde(default)]
+    tx: Option<TransactionID>,
+    #[serde(borrow)]
+    amount: Option<&'a str>,
+    #[serde(borrow, default)]
+    reference: Option<&'a str>,
+    #[serde(borrow, default)]
+    idempotency_key: Option<&'a str>,
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+impl CsvLine {
+    /// Parse a row of the fixed four column layout without serde.
+    ///
+    /// Columns are expected in the order type, client, tx, amount.
+    /// Clients are resolved by the aliases.
+    /// A missing or empty amount defaults to 0, others are parsed with parse_amount.
+    fn from_byte_record<F>(
+        record: &ByteRecord,
+        aliases: &ClientAliases,
+        parse_amount: F,
+    ) -> Result<Self, TransactionError>
+    where
+        F: Fn(&str) -> Result<Amount, TransactionError>,
+    {
+        fn field(record: &ByteRecord, index: usize) -> Result<&str, TransactionError> {
+            let field = record.get(index).unwrap_or_default();
+            std::str::from_utf8(field).map_err(|err| err.to_string().into())
+        }
+
+        if record.len() > 4 {
+            return Err(format!("Expected at most 4 fields, found {}", record.len()).into());
+        }
+        let t_type = CsvLineType::new(field(record, 0)?);
+        let client = aliases.resolve(field(record, 1)?)?;
+        let tx = field(record, 2)?
+            .parse()
+            .map_err(|err| format!("Invalid tx: {}", err))?;
+        let amount = match field(record, 3)? {
+            "" => 0.0,
+            amount => parse_amount(amount)?,
+        };
+        Ok(Self {
+            t_type,
+            client,
+            tx,
+            amount,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        })
+    }
+
+    /// Parse a row by it's headers with serde.
+    ///
+    /// Clients are resolved by the aliases.
+    /// A missing or empty amount defaults to 0, others are parsed with parse_amount.
+    /// A reference without a transaction id is resolved when the row is applied.
+    fn from_headers<F>(
+        record: &ByteRecord,
+        headers: &ByteRecord,
+        aliases: &ClientAliases,
+        parse_amount: F,
+    ) -> Result<Self, TransactionError>
+    where
+        F: Fn(&str) -> Result<Amount, TransactionError>,
+    {
+        let raw: RawCsvLine = record.deserialize(Some(headers))?;
+        let amount = match raw.amount {
+            None | Some("") => 0.0,
+            Some(amount) => parse_amount(amount)?,
+        };
+        let (tx, reference) = match (raw.tx, raw.reference) {
+            (Some(tx), None | Some("")) => (tx, None),
+            (Some(tx), Some(reference)) => (tx, Some(Reference::Linked(reference.to_owned()))),
+            (None, Some(reference)) if !reference.is_empty() => {
+                (0, Some(Reference::Unresolved(reference.to_owned())))
+            }
+            (None, _) => return Err("Missing tx or reference".into()),
+        };
+        Ok(Self {
+            t_type: raw.t_type,
+            client: aliases.resolve(raw.client)?,
+            tx,
+            amount,
+            reference,
+            idempotency_key: raw
+                .idempotency_key
+                .filter(|key| !key.is_empty())
+                .map(str::to_owned),
+            timestamp: raw.timestamp,
+        })
+    }
+
+    /// Check if the headers are the fixed four column layout.
+    fn is_fixed_layout(headers: &ByteRecord) -> bool {
+        headers.len() == 4
+            && matches!(&headers[0], b"type" | b"t_type")
+            && &headers[1] == b"client"
+            && &headers[2] == b"tx"
+            && &headers[3] == b"amount"
+    }
+}
+
+/// Parser used for CSV rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParserKind {
+    /// Rows are deserialized with serde by their headers.
+    #[default]
+    Serde,
+    /// Rows are parsed by hand for maximum throughput.
+    ///
+    /// Requires the fixed four column layout (type, client, tx, amount).
+    /// Falls back to serde for other layouts.
+    ByteRecord,
+}
+
+/// Names of the input columns for each transaction field
+///
+/// Allows input with different header names, e.g. `txn_type` or `customer_id`,
+/// to be handled without rewriting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    /// Column of the transaction type
+    pub t_type: String,
+    /// Column of the client
+    pub client: String,
+    /// Column of the transaction identifier
+    pub tx: String,
+    /// Column of the amount
+    pub amount: String,
+    /// Column of the external transaction reference
+    pub reference: String,
+    /// Column of the idempotency key of the row
+    pub idempotency_key: String,
+    /// Column of the transaction time in seconds since the Unix epoch
+    pub timestamp: String,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            t_type: "t_type".to_owned(),
+            client: "client".to_owned(),
+            tx: "tx".to_owned(),
+            amount: "amount".to_owned(),
+            reference: "reference".to_owned(),
+            idempotency_key: "idempotency_key".to_owned(),
+            timestamp: "timestamp".to_owned(),
+        }
+    }
+}
+
+impl ColumnMapping {
+    /// Rename the mapped headers to the engine's field names.
+    ///
+    /// Headers which are not mapped are kept as is.
+    fn rename(&self, headers: &ByteRecord) -> ByteRecord {
+        let fields = [
+            (&self.t_type, "t_type"),
+            (&self.client, "client"),
+            (&self.tx, "tx"),
+            (&self.amount, "amount"),
+            (&self.reference, "reference"),
+            (&self.idempotency_key, "idempotency_key"),
+            (&self.timestamp, "timestamp"),
+        ];
+        headers
+            .iter()
+            .map(|header| {
+                fields
+                    .iter()
+                    .find(|(column, _field)| column.as_bytes() == header)
+                    .map_or(header, |(_column, field)| field.as_bytes())
+            })
+            .collect()
+    }
+}
+
+/// Options for reading CSV input
+///
+/// By default fields are trimmed, rows may have a varying number of fields,
+/// the first row is the headers and fields are delimited by commas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub(crate) trim: bool,
+    pub(crate) flexible: bool,
+    pub(crate) has_headers: bool,
+    pub(crate) delimiter: u8,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            trim: true,
+            flexible: true,
+            has_headers: true,
+            delimiter: b',',
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Create new CsvOptions with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trim white space around fields and headers.
+    pub fn trim(mut self, yes: bool) -> Self {
+        self.trim = yes;
+        self
+    }
+
+    /// Allow rows with a different number of fields.
+    ///
+    /// If false, such rows are rejected.
+    pub fn flexible(mut self, yes: bool) -> Self {
+        self.flexible = yes;
+        self
+    }
+
+    /// Read the first row as headers.
+    ///
+    /// If false, rows are expected in the fixed four column layout (type, client, tx, amount).
+    pub fn has_headers(mut self, yes: bool) -> Self {
+        self.has_headers = yes;
+        self
+    }
+
+    /// Delimiter between fields
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+}
+
+/// Options for parsing CSV rows
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Settings of the CSV reader
+    pub csv: CsvOptions,
+    /// Parser used for CSV rows
+    pub parser: ParserKind,
+    /// Format of amounts in the input
+    pub amount_format: AmountFormat,
+    /// Names of the columns in the input
+    pub columns: ColumnMapping,
+    /// Strict RFC 4180 parsing
+    ///
+    /// Rows with a different number of fields than the headers, quotes
+    /// in fields, or stray carriage returns are rejected instead of
+    /// silently accepted. Transaction fields never contain quotes or
+    /// carriage returns, so any found are from malformed input.
+    pub strict: bool,
+    /// Least severe problems logged to stderr, all by default
+    pub log_level: Severity,
+    /// Least severe problems counted as rejected in the report, all by default
+    pub count_level: Severity,
+    /// Aliases of partners' client ids mapped while parsing
+    pub client_aliases: ClientAliases,
+}
+
+impl ParseOptions {
+    /// Create the CSV reader builder for the options.
+    pub(crate) fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        let _ = builder
+            .flexible(self.csv.flexible)
+            .has_headers(self.csv.has_headers)
+            .delimiter(self.csv.delimiter);
+        if self.strict {
+            // Only new lines end a row so stray carriage returns are kept in fields.
+            // Rows are trimmed by the RowParser after they are validated.
+            let _ = builder.terminator(Terminator::Any(b'\n'));
+        } else if self.csv.trim {
+            let _ = builder.trim(Trim::All);
+        }
+        builder
+    }
+}
+
+/// Parser of rows for the headers of an input
+pub(crate) struct RowParser<'o> {
+    headers: ByteRecord,
+    /// Rows are parsed with the hand rolled parser
+    fast_path: bool,
+    amount_format: &'o AmountFormat,
+    client_aliases: &'o ClientAliases,
+    /// Rows are validated strictly
+    strict: bool,
+    /// Rows are trimmed after strict validation
+    trim: bool,
+    /// Number of amounts rounded by the precision policy
+    rounded_amounts: AtomicU64,
+}
+
+impl<'o> RowParser<'o> {
+    pub(crate) fn new(mut headers: ByteRecord, options: &'o ParseOptions) -> Self {
+        if options.csv.has_headers {
+            headers.trim();
+            if options.columns != ColumnMapping::default() {
+                headers = options.columns.rename(&headers);
+            }
+        } else {
+            headers = ByteRecord::from(vec!["t_type", "client", "tx", "amount"]);
+        }
+        let fast_path =
+            options.parser == ParserKind::ByteRecord && CsvLine::is_fixed_layout(&headers);
+        Self {
+            headers,
+            fast_path,
+            amount_format: &options.amount_format,
+            client_aliases: &options.client_aliases,
+            strict: options.strict,
+            trim: options.csv.trim,
+            rounded_amounts: AtomicU64::new(0),
+        }
+    }
+
+    /// Parse a row with the hand rolled parser or by it's headers with serde.
+    ///
+    /// In strict mode the row is validated, then trimmed if enabled, before parsing.
+    pub(crate) fn parse(&self, record: &mut ByteRecord) -> Result<CsvLine, TransactionError> {
+        if self.strict {
+            if record.len() != self.headers.len() {
+                return Err(format!(
+                    "Expected {} fields, found {}",
+                    self.headers.len(),
+                    record.len()
+                )
+                .into());
+            }
+            let last = record.len() - 1;
+            for (index, field) in record.iter().enumerate() {
+                // Carriage return of a CRLF line ending
+                let field = match field.strip_suffix(b"\r") {
+                    Some(field) if index == last => field,
+                    _ => field,
+                };
+                if field.contains(&b'"') || field.contains(&b'\r') {
+                    return Err("Field contains a quote or carriage return".into());
+                }
+            }
+            if self.trim {
+                record.trim();
+            }
+        }
+        let record = &*record;
+        let parse_amount = |raw: &str| {
+            let (amount, rounded) = self.amount_format.parse_rounded(raw)?;
+            if rounded {
+                let _ = self.rounded_amounts.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(amount)
+        };
+        if self.fast_path {
+            CsvLine::from_byte_record(record, self.client_aliases, parse_amount)
+        } else {
+            CsvLine::from_headers(record, &self.headers, self.client_aliases, parse_amount)
+        }
+    }
+
+    /// Number of amounts rounded by the precision policy
+    pub(crate) fn rounded_amounts(&self) -> u64 {
+        self.rounded_amounts.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle transactions and execute them on the appropriate client.
+///
+/// Reader is assumed to be a reader over CSV data and the csv may use white space
+/// to make it more human readable. A byte order mark is stripped and UTF-16 input
+/// is transcoded to UTF-8.
+/// If an error occurs processing a single transaction, it is assumed to be an error
+/// on the client. The error will be logged to stderr and processing will continue.
+pub fn handle_transactions_from_reader<R>(reader: R, store: &mut ClientStore) -> ProcessingReport
+where
+    R: Read,
+{
+    handle_transactions_with_registry(reader, &TransactionRegistry::new(), store)
+}
+
+/// Handle transactions, including user defined types, and execute them on the appropriate client.
+///
+/// Same as `handle_transactions_from_reader`, but types not known to the engine are looked
+/// up in the registry. Rows with a type that is not registered are logged to stderr and ignored.
+pub fn handle_transactions_with_registry<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    store: &mut ClientStore,
+) -> ProcessingReport
+where
+    R: Read,
+{
+    handle_transactions_with_parse_options(reader, registry, &ParseOptions::default(), store)
+}
+
+/// Handle transactions with the parser and execute them on the appropriate client.
+///
+/// Same as `handle_transactions_with_registry`, but rows are parsed with the parser.
+pub fn handle_transactions_with_parser<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    parser: ParserKind,
+    store: &mut ClientStore,
+) -> ProcessingReport
+where
+    R: Read,
+{
+    let options = ParseOptions {
+        parser,
+        ..Default::default()
+    };
+    handle_transactions_with_parse_options(reader, registry, &options, store)
+}
+
+/// Handle transactions read with the CSV options and execute them on the appropriate client.
+///
+/// Same as `handle_transactions_from_reader`, but the CSV reader settings, like the delimiter
+/// or trimming, are taken from the options.
+pub fn handle_transactions_with_options<R>(
+    reader: R,
+    options: &CsvOptions,
+    store: &mut ClientStore,
+) -> ProcessingReport
+where
+    R: Read,
+{
+    let options = ParseOptions {
+        csv: options.clone(),
+        ..Default::default()
+    };
+    handle_transactions_with_parse_options(reader, &TransactionRegistry::new(), &options, store)
+}
+
+/// Handle transactions parsed with the options and execute them on the appropriate client.
+///
+/// Same as `handle_transactions_with_registry`, but rows are parsed with the options.
+/// Rows which can not be parsed are logged to stderr and ignored.
+pub fn handle_transactions_with_parse_options<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+) -> ProcessingReport
+where
+    R: Read,
+{
+    handle_rows::<R, Sink>(reader, registry, options, store, None)
+}
+
+/// Handle transactions parsed with the options and write rejected rows to a side CSV.
+///
+/// Same as `handle_transactions_with_parse_options`, but every row which can not be
+/// parsed or whose transaction is rejected is written to rejects as it was read, with
+/// the stable code of the error and the reason appended in `code` and `reason` columns, so the
+/// failures can be fixed and resubmitted.
+pub fn handle_transactions_with_rejects<R, W>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+    rejects: W,
+) -> ProcessingReport
+where
+    R: Read,
+    W: Write,
+{
+    let mut rejects = Rejects::new(rejects, options);
+    let report = handle_rows(reader, registry, options, store, Some(&mut rejects));
+    rejects.flush();
+    report
+}
+
+/// Handle the transactions of every CSV file in a directory as a single input.
+///
+/// Files with a `.csv` extension are handled in lexicographic order of their
+/// names against the same store, e.g. hourly shards of a settlement file. The
+/// report covers all files, with the report of every file in it's `files`.
+/// Returns an error if the directory or a file can not be opened.
+pub fn handle_transactions_from_directory<P>(
+    directory: P,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+) -> Result<ProcessingReport, TransactionError>
+where
+    P: AsRef<Path>,
+{
+    handle_directory::<Sink>(directory.as_ref(), registry, options, store, None)
+}
+
+/// Handle the transactions of every CSV file in a directory and write rejected rows to a side CSV.
+///
+/// Same as `handle_transactions_from_directory`, but rejected rows of all files
+/// are written to rejects as in `handle_transactions_with_rejects`. The headers
+/// of the first file are written as the headers of the rejected rows.
+pub fn handle_transactions_from_directory_with_rejects<P, W>(
+    directory: P,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+    rejects: W,
+) -> Result<ProcessingReport, TransactionError>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut rejects = Rejects::new(rejects, options);
+    let report = handle_directory(
+        directory.as_ref(),
+        registry,
+        options,
+        store,
+        Some(&mut rejects),
+    );
+    rejects.flush();
+    report
+}
+
+/// Get the CSV files of a directory in lexicographic order of their names.
+pub fn csv_files<P>(directory: P) -> Result<Vec<PathBuf>, TransactionError>
+where
+    P: AsRef<Path>,
+{
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "csv") {
+            files.push(path);
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Handle every CSV file of a directory, recording the report of every file.
+fn handle_directory<W>(
+    directory: &Path,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+    mut rejects: Option<&mut Rejects<W>>,
+) -> Result<ProcessingReport, TransactionError>
+where
+    W: Write,
+{
+    let mut report = ProcessingReport::default();
+    for path in csv_files(directory)? {
+        let file = File::open(&path)
+            .map_err(|err| format!("Couldn't open {}: {}", path.display(), err))?;
+        let file_report = handle_rows(
+            BufReader::new(file),
+            registry,
+            options,
+            store,
+            rejects.as_deref_mut(),
+        );
+        report.merge(&file_report);
+        report.files.push((path, file_report));
+    }
+    Ok(report)
+}
+
+/// Side CSV of rejected rows
+struct Rejects<W>
+where
+    W: Write,
+{
+    writer: Writer<W>,
+    /// Rows are written with headers
+    has_headers: bool,
+    /// Headers were written by a previous input
+    headers_written: bool,
+}
+
+impl<W> Rejects<W>
+where
+    W: Write,
+{
+    fn new(rejects: W, options: &ParseOptions) -> Self {
+        let writer = WriterBuilder::new()
+            .flexible(true)
+            .delimiter(options.csv.delimiter)
+            .from_writer(rejects);
+        Self {
+            writer,
+            has_headers: options.csv.has_headers,
+            headers_written: false,
+        }
+    }
+
+    /// Write the headers of the input with the code and reason columns, once.
+    fn headers(&mut self, headers: &ByteRecord) {
+        if self.has_headers && !self.headers_written {
+            self.headers_written = true;
+            self.write(headers, "code", "reason");
+        }
+    }
+
+    /// Write a row with the code and reason appended.
+    ///
+    /// Errors writing are logged to stderr.
+    fn write(&mut self, record: &ByteRecord, code: &str, reason: &str) {
+        let row = record.iter().chain([code.as_bytes(), reason.as_bytes()]);
+        if let Err(err) = self.writer.write_record(row) {
+            eprintln!("Couldn't write rejected transaction: {}", err);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            eprintln!("Couldn't write rejected transactions: {}", err);
+        }
+    }
+}
+
+/// Read, parse and apply every row, writing rejected rows if a writer is given.
+///
+/// Reading and parsing, and executing every row are timed in the report.
+fn handle_rows<R, W>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+    mut rejects: Option<&mut Rejects<W>>,
+) -> ProcessingReport
+where
+    R: Read,
+    W: Write,
+{
+    let mut timer = PhaseTimer::start();
+    let mut csv_reader = options
+        .reader_builder()
+        .from_reader(DecodingReader::new(reader));
+    let mut report = ProcessingReport::default();
+    // Rows are read into a single reused record and deserialized
+    // in place to avoid allocating for every row.
+    let headers = match csv_reader.byte_headers() {
+        Ok(headers) => headers.clone(),
+        Err(err) => {
+            // No rows can be parsed without the headers
+            let _ = apply(Err(err.into()), registry, options, store, &mut report);
+            report.timings.elapsed = timer.elapsed();
+            return report;
+        }
+    };
+    if let Some(rejects) = rejects.as_mut() {
+        rejects.headers(&headers);
+    }
+    let row_parser = RowParser::new(headers, options);
+    let mut record = ByteRecord::new();
+    let mut corrupt = false;
+    while !corrupt {
+        let read = match csv_reader.read_byte_record(&mut record) {
+            Ok(true) => Ok(()),
+            Ok(false) => break,
+            Err(err) => {
+                // The rest of the input can't be read after an IO error
+                corrupt = err.is_io_error();
+                Err(err.into())
+            }
+        };
+        // Rows may be trimmed while parsing, so rejected rows are kept as read
+        let raw = rejects.as_ref().map(|_| record.clone());
+        let current = read.and_then(|()| row_parser.parse(&mut record));
+        report.timings.parse += timer.lap();
+        if let Err(err) = apply(current, registry, options, store, &mut report) {
+            if let (Some(rejects), Some(raw)) = (rejects.as_mut(), raw) {
+                rejects.write(&raw, err.code(), &err.to_string());
+            }
+        }
+        report.timings.execute += timer.lap();
+    }
+    report.rounded_amounts = row_parser.rounded_amounts();
+    report.bytes_read = csv_reader.position().byte();
+    report.timings.elapsed = timer.elapsed();
+    report
+}
+
+/// Apply a parsed row to the store and record the outcome in the report.
+///
+/// Errors parsing or executing the transaction are returned. They are logged
+/// to stderr and counted in the report if they are at least as severe as the
+/// levels of the options.
+pub(crate) fn apply(
+    current: Result<CsvLine, TransactionError>,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+    report: &mut ProcessingReport,
+) -> Result<(), TransactionError> {
+    report.rows_read += 1;
+    let result = current
+        .and_then(|current| resolve_reference(current, store))
+        .and_then(|mut current| {
+            let key = current.idempotency_key.take();
+            let transaction = registry.create(current)?;
+            match key {
+                Some(key) => store.execute_once(&key, transaction.as_ref()),
+                None => store.execute(transaction.as_ref()),
+            }
+        });
+    match &result {
+        Ok(()) => report.applied += 1,
+        Err(err) => {
+            if err.severity() >= options.count_level {
+                report.reject(err);
+            }
+            if err.severity() >= options.log_level {
+                eprintln!("Couldn't handle transaction: {}", err);
+            }
+        }
+    }
+    result
+}
+
+/// Resolve the external reference of a row to it's transaction id.
+///
+/// Disputes, resolves and chargebacks must reference a known transaction,
+/// other rows without a transaction id are assigned a compact id.
+fn resolve_reference(
+    mut current: CsvLine,
+    store: &mut ClientStore,
+) -> Result<CsvLine, TransactionError> {
+    match current.reference.take() {
+        None => {}
+        Some(Reference::Linked(reference)) => {
+            store.references_mut().link(&reference, current.tx)?;
+        }
+        Some(Reference::Unresolved(reference)) => {
+            current.tx = match current.t_type {
+                CsvLineType::Dispute | CsvLineType::Resolve | CsvLineType::Chargeback => store
+                    .references()
+                    .get(&reference)
+                    .ok_or_else(|| format!("Unknown transaction reference {}", reference))?,
+                _ => store.references_mut().id(&reference),
+            };
+        }
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+#[allow(clippy::get_first)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn de_deposit() {
+        let data = "t_type,client,tx,amount\ndeposit,1,1,15\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: 15.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.get(0).unwrap();
+        assert_eq!(result, &expected);
+    }
+
+    #[test]
+    fn de_withdrawal() {
+        let data = "t_type,client,tx,amount\nwithdrawal,1,1,15\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: 15.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.get(0).unwrap();
+        assert_eq!(result, &expected);
+    }
+
+    #[test]
+    fn de_dispute() {
+        let data = "t_type,client,tx,amount\ndispute,1,1,\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.get(0).unwrap();
+        assert_eq!(result, &expected);
+    }
+
+    #[test]
+    fn de_resolve() {
+        let data = "t_type,client,tx,amount\nresolve,1,1,\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Resolve,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.get(0).unwrap();
+        assert_eq!(result, &expected);
+    }
+
+    #[test]
+    fn de_chargeback() {
+        let data = "t_type,client,tx,amount\nchargeback,1,1,\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Chargeback,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.get(0).unwrap();
+        assert_eq!(result, &expected);
+    }
+
+    #[test]
+    fn de_all() {
+        let data = "t_type,client,tx,amount\nwithdrawal,1,1,15\ndeposit,1,1,15\ndispute,1,1,\nresolve,1,1,\nchargeback,1,1,\n";
+        let expected_withdrawal = CsvLine {
+            t_type: CsvLineType::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: 15.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let expected_deposit = CsvLine {
+            t_type: CsvLineType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: 15.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let expected_dispute = CsvLine {
+            t_type: CsvLineType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let expected_resolve = CsvLine {
+            t_type: CsvLineType::Resolve,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let expected_chargeback = CsvLine {
+            t_type: CsvLineType::Chargeback,
+            client: 1,
+            tx: 1,
+            amount: 0.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 5);
+        let result_withdrawal = results.get(0).unwrap();
+        assert_eq!(result_withdrawal, &expected_withdrawal);
+        let result_deposit = results.get(1).unwrap();
+        assert_eq!(result_deposit, &expected_deposit);
+        let result_dispute = results.get(2).unwrap();
+        assert_eq!(result_dispute, &expected_dispute);
+        let result_resolve = results.get(3).unwrap();
+        assert_eq!(result_resolve, &expected_resolve);
+        let result_chargeback = results.get(4).unwrap();
+        assert_eq!(result_chargeback, &expected_chargeback);
+    }
+
+    #[test]
+    fn parse_byte_record() {
+        let data =
+            "t_type,client,tx,amount\nwithdrawal,1,1,15\ndispute,2,3,\nfee,1,4,0.5\nresolve,2,3\n";
+        let expected = vec![
+            CsvLine {
+                t_type: CsvLineType::Withdrawal,
+                client: 1,
+                tx: 1,
+                amount: 15.0,
+                reference: None,
+                idempotency_key: None,
+                timestamp: None,
+            },
+            CsvLine {
+                t_type: CsvLineType::Dispute,
+                client: 2,
+                tx: 3,
+                amount: 0.0,
+                reference: None,
+                idempotency_key: None,
+                timestamp: None,
+            },
+            CsvLine {
+                t_type: CsvLineType::Custom("fee".to_owned()),
+                client: 1,
+                tx: 4,
+                amount: 0.5,
+                reference: None,
+                idempotency_key: None,
+                timestamp: None,
+            },
+            CsvLine {
+                t_type: CsvLineType::Resolve,
+                client: 2,
+                tx: 3,
+                amount: 0.0,
+                reference: None,
+                idempotency_key: None,
+                timestamp: None,
+            },
+        ];
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        assert!(CsvLine::is_fixed_layout(reader.byte_headers().unwrap()));
+        let mut results = vec![];
+        for record in reader.byte_records() {
+            results.push(
+                CsvLine::from_byte_record(&record.unwrap(), &ClientAliases::new(), |raw| {
+                    AmountFormat::default().parse(raw)
+                })
+                .unwrap(),
+            )
+        }
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn rows_with_idempotency_key_applied_once() {
+        let data = "t_type, client, tx, amount, idempotency_key\n\
+                    deposit, 1, 1, 5.0, a1\n\
+                    deposit, 1, 2, 5.0, a1\n\
+                    deposit, 1, 3, 1.0,\n";
+        let mut store = ClientStore::new();
+        let report = handle_transactions_with_parse_options(
+            data.as_bytes(),
+            &TransactionRegistry::new(),
+            &ParseOptions::default(),
+            &mut store,
+        );
+        assert_eq!(report.applied, 3);
+        assert_eq!(store.clients.get(&1).unwrap().available, 6.0);
+        assert_eq!(store.idempotency_keys().len(), 1);
+    }
+
+    #[test]
+    fn partner_client_ids_mapped_by_aliases() {
+        let data = "t_type,client,tx,amount\n\
+                    deposit,ACME-17,1,5.0\n\
+                    deposit,globex:9912,2,2.0\n\
+                    withdrawal,3,3,1.0\n\
+                    deposit,ACME-18,4,1.0\n";
+        let mut client_aliases = ClientAliases::new();
+        client_aliases.insert("ACME-17".to_owned(), 3);
+        client_aliases.insert("globex:9912".to_owned(), 3);
+        for parser in [ParserKind::Serde, ParserKind::ByteRecord] {
+            let mut store = ClientStore::new();
+            let report = handle_transactions_with_parse_options(
+                data.as_bytes(),
+                &TransactionRegistry::new(),
+                &ParseOptions {
+                    parser,
+                    client_aliases: client_aliases.clone(),
+                    ..Default::default()
+                },
+                &mut store,
+            );
+            assert_eq!((report.applied, report.rejected), (3, 1));
+            assert_eq!(store.clients.len(), 1);
+            assert_eq!(store.clients.get(&3).unwrap().available, 6.0);
+        }
+    }
+
+    #[test]
+    fn rejected_rows_written_with_reason() {
+        let data = "t_type, client, tx, amount\n\
+                    deposit, 1, 1, 5.0\n\
+                    withdrawal, 1, 2, 9.0\n\
+                    deposit, x, 3, 1.0\n\
+                    deposit, 1, 4, 1.0\n";
+        let mut store = ClientStore::new();
+        let mut rejects = Vec::new();
+        let report = handle_transactions_with_rejects(
+            data.as_bytes(),
+            &TransactionRegistry::new(),
+            &ParseOptions::default(),
+            &mut store,
+            &mut rejects,
+        );
+        assert_eq!((report.applied, report.rejected), (2, 2));
+        let rejects = String::from_utf8(rejects).unwrap();
+        let rows: Vec<&str> = rejects.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], "t_type,client,tx,amount,code,reason");
+        assert!(rows[1].starts_with("withdrawal,1,2,9.0,INSUFFICIENT_FUNDS,"));
+        assert!(rows[2].starts_with("deposit,x,3,1.0,OTHER,"));
+    }
+
+    #[test]
+    fn problems_counted_by_severity() {
+        use crate::config::{DuplicatePolicy, StoreConfig};
+
+        let data = "t_type, client, tx, amount\n\
+                    deposit, 1, 1, 5.0\n\
+                    deposit, 1, 1, 5.0\n\
+                    withdrawal, 1, 2, 9.0\n";
+        let options = ParseOptions {
+            count_level: Severity::Error,
+            ..Default::default()
+        };
+        let mut store = ClientStore::with_config(StoreConfig {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Default::default()
+        });
+        let report = handle_transactions_with_parse_options(
+            data.as_bytes(),
+            &TransactionRegistry::new(),
+            &options,
+            &mut store,
+        );
+        assert_eq!(
+            (report.rows_read, report.applied, report.rejected),
+            (3, 1, 1)
+        );
+        assert_eq!(report.rejected_at_least(Severity::Error), 1);
+        assert_eq!(report.rejected_at_least(Severity::Fatal), 0);
+        assert_eq!(
+            report
+                .rejected_by_severity()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(Severity::Error, 1)]
+        );
+    }
+
+    #[test]
+    fn unreadable_headers_reported() {
+        struct Unreadable {}
+        impl Read for Unreadable {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("Disk failed"))
+            }
+        }
+
+        let mut store = ClientStore::new();
+        let report = handle_transactions_from_reader(Unreadable {}, &mut store);
+        assert_eq!((report.rows_read, report.rejected), (1, 1));
+        assert_eq!(report.rejected_at_least(Severity::Fatal), 1);
+    }
+
+    #[test]
+    fn parse_byte_record_invalid_client_errors() {
+        let record = ByteRecord::from(vec!["deposit", "-1", "1", "1.0"]);
+        // Loose error handling in place. Just verify an error is returned
+        CsvLine::from_byte_record(&record, &ClientAliases::new(), |raw| {
+            AmountFormat::default().parse(raw)
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn column_mapping_renames_headers() {
+        let columns = ColumnMapping {
+            t_type: "txn_type".to_owned(),
+            client: "customer_id".to_owned(),
+            tx: "reference".to_owned(),
+            amount: "value".to_owned(),
+            ..Default::default()
+        };
+        let headers = ByteRecord::from(vec![
+            "customer_id",
+            "txn_type",
+            "note",
+            "reference",
+            "value",
+        ]);
+        assert_eq!(
+            columns.rename(&headers),
+            ByteRecord::from(vec!["client", "t_type", "note", "tx", "amount"])
+        );
+    }
+
+    #[test]
+    fn de_custom() {
+        let data = "t_type,client,tx,amount\nfee,1,1,0.5\n";
+        let expected = CsvLine {
+            t_type: CsvLineType::Custom("fee".to_owned()),
+            client: 1,
+            tx: 1,
+            amount: 0.5,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
+        let mut results = vec![];
+        for result in reader.deserialize::<CsvLine>() {
+            results.push(result.unwrap())
+        }
+
+        assert_eq!(results.len(), 1);
+        let result = results.first().unwrap();
+        assert_eq!(result, &expected);
+    }
+}
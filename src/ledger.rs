@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use crate::TransactionID;
+
+/// Set of transaction ids seen by earlier runs
+///
+/// Ids are kept as ranges of consecutive ids, so the mostly sequential ids
+/// of exported files take a few entries no matter how many transactions were
+/// seen. The ledger is saved in the state snapshot, so a tx replayed by a
+/// later file is detected as a duplicate across runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionLedger {
+    /// Last id of every range by it's first id
+    ranges: BTreeMap<TransactionID, TransactionID>,
+}
+
+impl TransactionLedger {
+    /// Create a new empty TransactionLedger
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if the id was seen
+    pub fn contains(&self, id: TransactionID) -> bool {
+        self.ranges
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(_, &last)| id <= last)
+    }
+
+    /// Add the id, merging it with adjacent ranges.
+    ///
+    /// Returns false if the id was already seen.
+    pub fn insert(&mut self, id: TransactionID) -> bool {
+        let before = self
+            .ranges
+            .range(..=id)
+            .next_back()
+            .map(|(&first, &last)| (first, last));
+        if let Some((_, last)) = before {
+            if id <= last {
+                return false;
+            }
+        }
+        let first = match before {
+            Some((first, last)) if last.checked_add(1) == Some(id) => first,
+            _ => id,
+        };
+        let last = match id.checked_add(1).and_then(|next| self.ranges.get(&next)) {
+            Some(&last) => {
+                let _ = self.ranges.remove(&(id + 1));
+                last
+            }
+            None => id,
+        };
+        let _ = self.ranges.insert(first, last);
+        true
+    }
+
+    /// Get the number of ids seen
+    pub fn len(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|(&first, &last)| u64::from(last - first) + 1)
+            .sum()
+    }
+
+    /// Check if no id was seen
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Get the number of ranges the ids are kept in
+    pub fn range_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Iterate over the first and last id of every range in order
+    pub(crate) fn ranges(&self) -> impl Iterator<Item = (TransactionID, TransactionID)> + '_ {
+        self.ranges.iter().map(|(&first, &last)| (first, last))
+    }
+
+    /// Create a TransactionLedger from ordered, disjoint ranges
+    ///
+    /// Used to restore a TransactionLedger saved in a state snapshot.
+    pub(crate) fn from_ranges(ranges: BTreeMap<TransactionID, TransactionID>) -> Self {
+        Self { ranges }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_kept_as_ranges() {
+        let mut ledger = TransactionLedger::new();
+        for id in [1, 2, 3, 7, 5, 8, TransactionID::MAX] {
+            assert!(ledger.insert(id));
+        }
+        assert!(!ledger.insert(2));
+        assert_eq!(
+            ledger.ranges().collect::<Vec<_>>(),
+            vec![
+                (1, 3),
+                (5, 5),
+                (7, 8),
+                (TransactionID::MAX, TransactionID::MAX)
+            ]
+        );
+        // Filling the gap joins the ranges on both sides
+        assert!(ledger.insert(4));
+        assert!(ledger.insert(6));
+        assert_eq!(ledger.range_count(), 2);
+        assert_eq!(ledger.len(), 9);
+        assert!(ledger.contains(8));
+        assert!(!ledger.contains(0));
+        assert!(!ledger.contains(9));
+    }
+}
@@ -1,38 +1,199 @@
-use std::io::Read;
-
-use client::ClientStore;
-use csv::{ReaderBuilder, Trim};
-use serde::{self, Deserialize, Deserializer, Serialize};
-use transaction::Transaction;
-
+use std::fmt::{Formatter, Result as FmtResult};
+
+use serde::{
+    self,
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+
+pub mod alert;
+pub mod alias;
+pub mod amount;
+#[cfg(feature = "anonymize")]
+pub mod anonymize;
+pub mod atomic;
+pub mod audit;
 pub mod client;
-mod error;
-mod transaction;
+pub mod clock;
+pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod diagnostics;
+pub mod encoding;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod error;
+#[cfg(feature = "testing")]
+pub mod faults;
+#[cfg(feature = "csv")]
+pub mod fixture;
+pub mod generator;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod idempotency;
+#[cfg(feature = "csv")]
+mod ingest;
+pub mod ledger;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod loadtest;
+pub mod middleware;
+pub mod observer;
+pub mod output;
+#[cfg(all(feature = "csv", not(target_arch = "wasm32")))]
+pub mod parallel;
+pub mod projection;
+pub mod publisher;
+pub mod reference;
+#[cfg(feature = "http")]
+pub mod remote;
+pub mod report;
+pub mod risk;
+pub mod scheduler;
+#[cfg(feature = "csv")]
+pub mod settings;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod snapshot;
+pub mod spill;
+#[cfg(feature = "sql")]
+pub mod sql;
+mod state;
+#[cfg(feature = "csv")]
+pub mod statement;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transaction;
+pub mod trial;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
+
+#[cfg(feature = "csv")]
+pub use ingest::{
+    csv_files, handle_transactions_from_directory, handle_transactions_from_directory_with_rejects,
+    handle_transactions_from_reader, handle_transactions_with_options,
+    handle_transactions_with_parse_options, handle_transactions_with_parser,
+    handle_transactions_with_registry, handle_transactions_with_rejects, ColumnMapping, CsvOptions,
+    ParseOptions, ParserKind,
+};
 
 /// Unique Client Identifer
-type ClientID = u16;
+///
+/// Enable the `client-id-u32` or `client-id-u64` feature for more than
+/// 65,536 clients. The widest enabled identifier is used.
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+pub type ClientID = u16;
+/// Unique Client Identifer
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+pub type ClientID = u32;
+/// Unique Client Identifer
+#[cfg(feature = "client-id-u64")]
+pub type ClientID = u64;
 /// Unique Tranaction Identifier
-type TransactionID = u32;
+pub type TransactionID = u32;
+/// Hash map used for internal maps keyed by identifiers
+///
+/// Keys are small integers from trusted batch input, so a fast
+/// non-cryptographic hasher is used. Enable the `siphash` feature
+/// for the DoS resistant default hasher.
+#[cfg(not(feature = "siphash"))]
+pub type IdMap<K, V> = std::collections::HashMap<K, V, rustc_hash::FxBuildHasher>;
+/// Hash map used for internal maps keyed by identifiers
+#[cfg(feature = "siphash")]
+pub type IdMap<K, V> = std::collections::HashMap<K, V>;
+/// Hash set used for internal sets of identifiers
+#[cfg(not(feature = "siphash"))]
+pub type IdSet<K> = std::collections::HashSet<K, rustc_hash::FxBuildHasher>;
+/// Hash set used for internal sets of identifiers
+#[cfg(feature = "siphash")]
+pub type IdSet<K> = std::collections::HashSet<K>;
+
+/// Time of a transaction in seconds since the Unix epoch
+pub type Timestamp = u64;
+
 /// Amount type
 ///
 /// Easily changable if needed for more percision or
 /// if larger numbers are needed.
-type Amount = f64;
+pub type Amount = f64;
 
 /// Type of transaction from CSV input
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
-#[serde(rename = "type")]
+#[derive(Debug, PartialEq)]
 enum CsvLineType {
-    #[serde(rename = "chargeback")]
     Chargeback,
-    #[serde(rename = "deposit")]
+    ChargebackReversal,
     Deposit,
-    #[serde(rename = "dispute")]
     Dispute,
-    #[serde(rename = "resolve")]
+    Freeze,
+    Hold,
+    Interest,
+    Release,
     Resolve,
-    #[serde(rename = "withdrawal")]
+    Unfreeze,
+    Unverify,
+    Verify,
     Withdrawal,
+    /// Type not known to the engine.
+    ///
+    /// Resolved against the TransactionRegistry when converted
+    /// to a Transaction.
+    Custom(String),
+}
+
+impl CsvLineType {
+    /// Get the type of a type name from the input
+    ///
+    /// Unknown names are kept as a custom type.
+    fn new(t_type: &str) -> Self {
+        match t_type {
+            "chargeback" => Self::Chargeback,
+            "chargeback_reversal" => Self::ChargebackReversal,
+            "deposit" => Self::Deposit,
+            "dispute" => Self::Dispute,
+            "freeze" => Self::Freeze,
+            "hold" => Self::Hold,
+            "interest" => Self::Interest,
+            "release" => Self::Release,
+            "resolve" => Self::Resolve,
+            "unfreeze" => Self::Unfreeze,
+            "unverify" => Self::Unverify,
+            "verify" => Self::Verify,
+            "withdrawal" => Self::Withdrawal,
+            _ => Self::Custom(t_type.to_owned()),
+        }
+    }
+}
+
+/// Custom deserialize implementation to allow for unknown types
+///
+/// Unknown types are kept as is so they can be mapped to user
+/// defined transactions. Types are matched on the borrowed input,
+/// so only unknown types allocate.
+impl<'de> Deserialize<'de> for CsvLineType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CsvLineTypeVisitor;
+
+        impl Visitor<'_> for CsvLineTypeVisitor {
+            type Value = CsvLineType;
+
+            fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+                formatter.write_str("a transaction type")
+            }
+
+            fn visit_str<E>(self, t_type: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(CsvLineType::new(t_type))
+            }
+        }
+
+        deserializer.deserialize_str(CsvLineTypeVisitor)
+    }
 }
 
 /// CSV input data structure for transactions
@@ -52,6 +213,27 @@ struct CsvLine {
     /// this.
     #[serde(deserialize_with = "default_empty_amount_to_zero")]
     amount: Amount,
+    /// External reference of the transaction, if the input has one
+    #[serde(skip)]
+    reference: Option<Reference>,
+    /// Idempotency key of the row, if the input has one
+    #[serde(skip)]
+    idempotency_key: Option<String>,
+    /// Time of the transaction, if the input has one
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+}
+
+/// External transaction reference from the input
+///
+/// Only read from CSV input.
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+#[derive(Debug, PartialEq)]
+enum Reference {
+    /// Reference given with a transaction id, linked to that id
+    Linked(String),
+    /// Reference given without a transaction id, resolved to a compact id
+    Unresolved(String),
 }
 
 /// Custom deserializer to allow for empty Amount's to default to 0.
@@ -62,185 +244,3 @@ where
     let opt = Option::deserialize(deserializer)?;
     Ok(opt.unwrap_or(0.0))
 }
-
-/// Handle transactions and execute them on the appropriate client.
-///
-/// Reader is assumed to be a reader over CSV data and the csv may use white space
-/// to make it more human readable.
-/// If an error occurs processing a single transaction, it is assumed to be an error
-/// on the client. The error will be logged to stderr and processing will continue.
-pub fn handle_transactions_from_reader<R>(reader: R, store: &mut ClientStore)
-where
-    R: Read,
-{
-    let mut csv_reader = ReaderBuilder::new()
-        .flexible(true)
-        .trim(Trim::All)
-        .from_reader(reader);
-    for result in csv_reader.deserialize() {
-        let current: CsvLine = result.unwrap();
-        let transaction: Box<dyn Transaction> = current.into();
-        let _ = store
-            .execute(transaction.as_ref())
-            .map_err(|err| eprintln!("Couldn't handle transaction: {}", err));
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::CsvLineType;
-    use csv::ReaderBuilder;
-
-    #[test]
-    fn de_deposit() {
-        let data = "t_type,client,tx,amount\ndeposit,1,1,15\n";
-        let expected = CsvLine {
-            t_type: CsvLineType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: 15.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 1);
-        let result = results.get(0).unwrap();
-        assert_eq!(result, &expected);
-    }
-
-    #[test]
-    fn de_withdrawal() {
-        let data = "t_type,client,tx,amount\nwithdrawal,1,1,15\n";
-        let expected = CsvLine {
-            t_type: CsvLineType::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: 15.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 1);
-        let result = results.get(0).unwrap();
-        assert_eq!(result, &expected);
-    }
-
-    #[test]
-    fn de_dispute() {
-        let data = "t_type,client,tx,amount\ndispute,1,1,\n";
-        let expected = CsvLine {
-            t_type: CsvLineType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 1);
-        let result = results.get(0).unwrap();
-        assert_eq!(result, &expected);
-    }
-
-    #[test]
-    fn de_resolve() {
-        let data = "t_type,client,tx,amount\nresolve,1,1,\n";
-        let expected = CsvLine {
-            t_type: CsvLineType::Resolve,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 1);
-        let result = results.get(0).unwrap();
-        assert_eq!(result, &expected);
-    }
-
-    #[test]
-    fn de_chargeback() {
-        let data = "t_type,client,tx,amount\nchargeback,1,1,\n";
-        let expected = CsvLine {
-            t_type: CsvLineType::Chargeback,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 1);
-        let result = results.get(0).unwrap();
-        assert_eq!(result, &expected);
-    }
-
-    #[test]
-    fn de_all() {
-        let data = "t_type,client,tx,amount\nwithdrawal,1,1,15\ndeposit,1,1,15\ndispute,1,1,\nresolve,1,1,\nchargeback,1,1,\n";
-        let expected_withdrawal = CsvLine {
-            t_type: CsvLineType::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: 15.0,
-        };
-        let expected_deposit = CsvLine {
-            t_type: CsvLineType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: 15.0,
-        };
-        let expected_dispute = CsvLine {
-            t_type: CsvLineType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let expected_resolve = CsvLine {
-            t_type: CsvLineType::Resolve,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let expected_chargeback = CsvLine {
-            t_type: CsvLineType::Chargeback,
-            client: 1,
-            tx: 1,
-            amount: 0.0,
-        };
-        let mut reader = ReaderBuilder::new().from_reader(data.as_bytes());
-        let mut results = vec![];
-        for result in reader.deserialize::<CsvLine>() {
-            results.push(result.unwrap())
-        }
-
-        assert_eq!(results.len(), 5);
-        let result_withdrawal = results.get(0).unwrap();
-        assert_eq!(result_withdrawal, &expected_withdrawal);
-        let result_deposit = results.get(1).unwrap();
-        assert_eq!(result_deposit, &expected_deposit);
-        let result_dispute = results.get(2).unwrap();
-        assert_eq!(result_dispute, &expected_dispute);
-        let result_resolve = results.get(3).unwrap();
-        assert_eq!(result_resolve, &expected_resolve);
-        let result_chargeback = results.get(4).unwrap();
-        assert_eq!(result_chargeback, &expected_chargeback);
-    }
-}
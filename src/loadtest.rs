@@ -0,0 +1,367 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::ClientStore,
+    error::TransactionError,
+    generator::Rng,
+    report::ProcessingReport,
+    transaction::{TransactionKind, TransactionRegistry},
+    ClientID, TransactionID,
+};
+
+/// Number of transactions executed between checks of the duration
+const CHECK_EVERY: u64 = 1024;
+
+/// Largest number of deposits and disputes a worker keeps to reference
+const MAX_REFERENCES: usize = 65_536;
+
+/// Relative weights of the kinds of transactions of a load test
+///
+/// Disputes reference earlier deposits and resolves and chargebacks open
+/// disputes, so they are replaced by deposits until there is one to reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionMix {
+    /// Weight of deposits
+    pub deposit: f64,
+    /// Weight of withdrawals
+    pub withdrawal: f64,
+    /// Weight of disputes
+    pub dispute: f64,
+    /// Weight of resolves
+    pub resolve: f64,
+    /// Weight of chargebacks
+    pub chargeback: f64,
+}
+
+impl Default for TransactionMix {
+    fn default() -> Self {
+        Self {
+            deposit: 60.0,
+            withdrawal: 30.0,
+            dispute: 6.0,
+            resolve: 3.0,
+            chargeback: 1.0,
+        }
+    }
+}
+
+impl TransactionMix {
+    /// Pick a kind of transaction by it's weight, with a roll in `[0, 1)`
+    fn pick(&self, roll: f64) -> TransactionKind {
+        let weights = [
+            (TransactionKind::Deposit, self.deposit),
+            (TransactionKind::Withdrawal, self.withdrawal),
+            (TransactionKind::Dispute, self.dispute),
+            (TransactionKind::Resolve, self.resolve),
+            (TransactionKind::Chargeback, self.chargeback),
+        ];
+        let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        let mut roll = roll * total;
+        for (kind, weight) in weights {
+            if roll < weight {
+                return kind;
+            }
+            roll -= weight;
+        }
+        TransactionKind::Deposit
+    }
+}
+
+/// Parses a mix like `deposit:60,withdrawal:30,dispute:6`, kinds left out have no weight
+impl FromStr for TransactionMix {
+    type Err = TransactionError;
+
+    fn from_str(mix: &str) -> Result<Self, Self::Err> {
+        let mut parsed = Self {
+            deposit: 0.0,
+            withdrawal: 0.0,
+            dispute: 0.0,
+            resolve: 0.0,
+            chargeback: 0.0,
+        };
+        for part in mix.split(',') {
+            let (kind, weight) = part
+                .split_once(':')
+                .ok_or_else(|| format!("Mix entry {} is not <KIND>:<WEIGHT>", part.trim()))?;
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .ok()
+                .filter(|weight: &f64| weight.is_finite() && *weight >= 0.0)
+                .ok_or_else(|| format!("Weight of {} is not a positive number", kind.trim()))?;
+            match kind.trim() {
+                "deposit" => parsed.deposit = weight,
+                "withdrawal" => parsed.withdrawal = weight,
+                "dispute" => parsed.dispute = weight,
+                "resolve" => parsed.resolve = weight,
+                "chargeback" => parsed.chargeback = weight,
+                kind => return Err(format!("Unknown transaction kind {} in mix", kind).into()),
+            }
+        }
+        if parsed.deposit + parsed.withdrawal + parsed.dispute + parsed.resolve + parsed.chargeback
+            <= 0.0
+        {
+            return Err("Mix must have a positive weight".into());
+        }
+        Ok(parsed)
+    }
+}
+
+/// Settings of a load test
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadTestOptions {
+    /// How long transactions are executed
+    pub duration: Duration,
+    /// Number of workers executing transactions at the same time
+    ///
+    /// Clients are partitioned across the workers, every worker with it's own
+    /// ClientStore, as the clients of sharded stores are.
+    pub concurrency: usize,
+    /// Number of clients transactions are spread across
+    pub clients: ClientID,
+    /// Kinds of the executed transactions
+    pub mix: TransactionMix,
+    /// Seed of the random generators of the workers
+    pub seed: u64,
+}
+
+impl Default for LoadTestOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(10),
+            concurrency: 1,
+            clients: 1000,
+            mix: TransactionMix::default(),
+            seed: 0,
+        }
+    }
+}
+
+/// Result of a load test
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadTestReport {
+    /// Executed transactions of every worker, counted as rows
+    pub report: ProcessingReport,
+    /// Time from the start of the first until the end of the last worker
+    pub elapsed: Duration,
+    /// Number of workers
+    pub workers: usize,
+}
+
+impl LoadTestReport {
+    /// Get the sustained throughput in transactions per second
+    pub fn throughput(&self) -> f64 {
+        self.report.rows_read as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Get the fraction of transactions which were rejected
+    pub fn error_rate(&self) -> f64 {
+        self.report.rejected as f64 / self.report.rows_read.max(1) as f64
+    }
+}
+
+impl Display for LoadTestReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        writeln!(
+            f,
+            "Executed {} transactions on {} workers in {:.3}s ({:.0} transactions/sec)",
+            self.report.rows_read,
+            self.workers,
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )?;
+        write!(
+            f,
+            "Applied {}, rejected {} ({:.2}%)",
+            self.report.applied,
+            self.report.rejected,
+            self.error_rate() * 100.0
+        )?;
+        for (kind, count) in &self.report.rejected_by_kind {
+            write!(f, "\n  {}: {}", kind.code(), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Drive ClientStores with random transactions of the mix for the duration.
+///
+/// Unlike the Generator, transactions are created in memory as they are
+/// executed, so tests can run for any duration.
+pub fn run_load_test(options: &LoadTestOptions) -> LoadTestReport {
+    let workers = options.concurrency.max(1);
+    let start = Instant::now();
+    let reports: Vec<ProcessingReport> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| scope.spawn(move || run_worker(options, worker, workers)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("load test worker panicked"))
+            .collect()
+    });
+    let mut report = ProcessingReport::default();
+    for worker in &reports {
+        report.merge(worker);
+    }
+    LoadTestReport {
+        report,
+        elapsed: start.elapsed(),
+        workers,
+    }
+}
+
+/// Execute transactions of the clients of one worker until the duration passed.
+///
+/// Chargebacks lock their clients for good, so once every client of the
+/// worker is locked, it starts over with an empty store, instead of measuring
+/// only rejections of locked accounts.
+fn run_worker(options: &LoadTestOptions, worker: usize, workers: usize) -> ProcessingReport {
+    let registry = TransactionRegistry::new();
+    let mut rng = Rng(options.seed.wrapping_add(worker as u64));
+    let clients: Vec<ClientID> = (1..=options.clients.max(1))
+        .filter(|client| (*client as usize - 1) % workers == worker)
+        .collect();
+    let mut report = ProcessingReport::default();
+    if clients.is_empty() {
+        return report;
+    }
+    let mut client_store = ClientStore::new();
+    let mut unlocked = clients.clone();
+    let mut locked = vec![false; options.clients.max(1) as usize + 1];
+    let mut deposits: Vec<(ClientID, TransactionID)> = Vec::new();
+    let mut disputes: Vec<(ClientID, TransactionID)> = Vec::new();
+    let mut next_tx: TransactionID = 1;
+    let start = Instant::now();
+    while next_tx < TransactionID::MAX {
+        if report.rows_read % CHECK_EVERY == 0 && start.elapsed() >= options.duration {
+            break;
+        }
+        if unlocked.is_empty() {
+            client_store = ClientStore::new();
+            unlocked = clients.clone();
+            locked.fill(false);
+            deposits.clear();
+            disputes.clear();
+            next_tx = 1;
+        }
+        let mut kind = options.mix.pick(rng.next_f64());
+        let reference = match kind {
+            TransactionKind::Dispute => take(&mut deposits, &locked, &mut rng),
+            TransactionKind::Resolve | TransactionKind::Chargeback => {
+                take(&mut disputes, &locked, &mut rng)
+            }
+            _ => None,
+        };
+        let (client, tx, amount) = match reference {
+            Some((client, tx)) => {
+                if kind == TransactionKind::Dispute {
+                    remember(&mut disputes, (client, tx), &mut rng);
+                }
+                (client, tx, 0.0)
+            }
+            None => {
+                // Until there is something to reference, deposits are made instead
+                if kind != TransactionKind::Withdrawal {
+                    kind = TransactionKind::Deposit;
+                }
+                let client = unlocked[rng.index(unlocked.len())];
+                let tx = next_tx;
+                next_tx += 1;
+                if kind == TransactionKind::Deposit {
+                    remember(&mut deposits, (client, tx), &mut rng);
+                }
+                (client, tx, rng.amount(1000.0))
+            }
+        };
+        report.rows_read += 1;
+        let result = registry
+            .create_named(kind.name(), client, tx, amount)
+            .and_then(|transaction| client_store.execute(transaction.as_ref()));
+        match result {
+            Ok(()) => report.applied += 1,
+            Err(err) => {
+                report.reject(&err);
+                continue;
+            }
+        }
+        // Applied chargebacks lock their client
+        if kind == TransactionKind::Chargeback {
+            locked[client as usize] = true;
+            unlocked.retain(|unlocked| *unlocked != client);
+        }
+    }
+    report
+}
+
+/// Take a random reference of an unlocked client, dropping references of locked clients.
+fn take(
+    references: &mut Vec<(ClientID, TransactionID)>,
+    locked: &[bool],
+    rng: &mut Rng,
+) -> Option<(ClientID, TransactionID)> {
+    while !references.is_empty() {
+        let reference = references.swap_remove(rng.index(references.len()));
+        if !locked[reference.0 as usize] {
+            return Some(reference);
+        }
+    }
+    None
+}
+
+/// Keep a reference for later transactions, replacing a random one once there are too many.
+fn remember(
+    references: &mut Vec<(ClientID, TransactionID)>,
+    reference: (ClientID, TransactionID),
+    rng: &mut Rng,
+) {
+    if references.len() < MAX_REFERENCES {
+        references.push(reference);
+    } else {
+        let index = rng.index(references.len());
+        references[index] = reference;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_parsed() {
+        let mix: TransactionMix = "deposit:3, withdrawal:1".parse().unwrap();
+        assert_eq!(mix.deposit, 3.0);
+        assert_eq!(mix.dispute, 0.0);
+        assert_eq!(mix.pick(0.5), TransactionKind::Deposit);
+        assert_eq!(mix.pick(0.9), TransactionKind::Withdrawal);
+        assert!("deposit".parse::<TransactionMix>().is_err());
+        assert!("deposit:-1".parse::<TransactionMix>().is_err());
+        assert!("freeze:1".parse::<TransactionMix>().is_err());
+        assert!("deposit:0".parse::<TransactionMix>().is_err());
+    }
+
+    #[test]
+    fn load_test_runs_for_duration() {
+        let options = LoadTestOptions {
+            duration: Duration::from_millis(50),
+            concurrency: 2,
+            clients: 10,
+            ..Default::default()
+        };
+        let report = run_load_test(&options);
+        assert!(report.elapsed >= options.duration);
+        assert_eq!(report.workers, 2);
+        assert!(report.report.applied > 0);
+        assert_eq!(
+            report.report.rows_read,
+            report.report.applied + report.report.rejected
+        );
+        assert!(report.throughput() > 0.0);
+        assert!(report.to_string().starts_with("Executed "));
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{client::Client, error::TransactionError, transaction::Transaction};
+
+/// Outcome of a middleware inspecting a transaction
+pub enum MiddlewareAction {
+    /// Continue with the transaction as is.
+    Continue,
+    /// Continue with a different transaction in place of the inspected one.
+    ///
+    /// Used to enrich or rewrite a transaction, e.g. to inject a fee.
+    Replace(Box<dyn Transaction>),
+}
+
+/// Pre-execution middleware for transactions
+///
+/// Middleware registered on a ClientStore runs in the order it was added
+/// before a transaction is executed, forming a processing pipeline. Each
+/// middleware sees the transaction as left by the previous one and can
+/// inspect, rewrite, or veto it. Returning an error vetoes the transaction
+/// and it is not executed.
+///
+/// Middleware is also run by `ClientStore::simulate`, so it inspects
+/// transactions without side effects.
+pub trait TransactionMiddleware {
+    /// Inspect the transaction before it is executed.
+    ///
+    /// The client is the current state of the requested client, or None
+    /// if the client does not exist yet.
+    fn before_execute(
+        &self,
+        transaction: &dyn Transaction,
+        client: Option<&Client>,
+    ) -> Result<MiddlewareAction, TransactionError>;
+}
@@ -0,0 +1,118 @@
+use std::sync::mpsc::Sender;
+
+use crate::{
+    client::Client,
+    error::TransactionError,
+    transaction::{Transaction, TransactionKind},
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Balances of a client at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balances {
+    /// Funds available to the client
+    pub available: Amount,
+    /// Funds held by disputes
+    pub held: Amount,
+    /// Client is locked
+    pub locked: bool,
+}
+
+impl Balances {
+    /// Get the current balances of the client
+    pub fn of(client: &Client) -> Self {
+        Self {
+            available: client.available,
+            held: client.held,
+            locked: client.locked,
+        }
+    }
+
+    /// Get the total amount of funds
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+}
+
+/// Change of a client's state by an applied transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountEvent {
+    /// Client the transaction was applied to
+    pub client: ClientID,
+    /// Transaction which was applied
+    pub tx: TransactionID,
+    /// Kind of the transaction
+    pub kind: TransactionKind,
+    /// Balances before the transaction
+    pub before: Balances,
+    /// Balances after the transaction
+    pub after: Balances,
+    /// Time of the transaction, if known
+    pub timestamp: Option<Timestamp>,
+}
+
+impl AccountEvent {
+    /// Check if the transaction locked the client
+    pub fn locked(&self) -> bool {
+        !self.before.locked && self.after.locked
+    }
+
+    /// Check if the transaction unlocked the client
+    pub fn unlocked(&self) -> bool {
+        self.before.locked && !self.after.locked
+    }
+}
+
+/// Observer of the transaction lifecycle
+///
+/// Observers are notified by the ClientStore as transactions are executed,
+/// allowing notifications, fraud alerts, or audit sinks to be wired in
+/// without modifying the engine. All callbacks default to doing nothing
+/// so implementers only need to handle the events they care about.
+pub trait TransactionObserver {
+    /// Called after a transaction was successfully applied to the client.
+    fn on_applied(&mut self, _transaction: &dyn Transaction, _client: &Client) {}
+
+    /// Called after a transaction was rejected by the client.
+    ///
+    /// The client is in the state it was before the transaction.
+    fn on_rejected(&mut self, _transaction: &dyn Transaction, _error: &TransactionError) {}
+
+    /// Called when a transaction caused the client to become locked.
+    fn on_account_locked(&mut self, _transaction: &dyn Transaction, _client: &Client) {}
+
+    /// Called after a dispute was successfully opened on a previous transaction.
+    fn on_dispute_opened(&mut self, _transaction: &dyn Transaction, _client: &Client) {}
+
+    /// Called after a chargeback was successfully applied.
+    fn on_chargeback(&mut self, _transaction: &dyn Transaction, _client: &Client) {}
+
+    /// Called with the change of the client's state after a transaction was successfully applied.
+    fn on_event(&mut self, _event: &AccountEvent) {}
+}
+
+/// Observer calling a closure with every AccountEvent
+///
+/// Allows building real time views of clients as transactions are handled,
+/// instead of only reading the final state.
+pub struct EventCallback<F>(pub F)
+where
+    F: FnMut(&AccountEvent);
+
+impl<F> TransactionObserver for EventCallback<F>
+where
+    F: FnMut(&AccountEvent),
+{
+    fn on_event(&mut self, event: &AccountEvent) {
+        (self.0)(event)
+    }
+}
+
+/// Stream of AccountEvents to a channel
+///
+/// Events are dropped once the receiver is gone.
+impl TransactionObserver for Sender<AccountEvent> {
+    fn on_event(&mut self, event: &AccountEvent) {
+        let _ = self.send(event.clone());
+    }
+}
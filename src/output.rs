@@ -0,0 +1,1164 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "csv")]
+use csv::{Writer, WriterBuilder};
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize, Serializer};
+
+#[cfg(feature = "anonymize")]
+use crate::anonymize::Pseudonymizer;
+use crate::{
+    amount::{round, RoundingMode},
+    client::Client,
+    error::TransactionError,
+    Amount, ClientID,
+};
+#[cfg(any(feature = "csv", feature = "json"))]
+use crate::{
+    client::{DisputeState, History},
+    transaction::TransactionKind,
+    Timestamp, TransactionID,
+};
+
+/// Number of decimal places of fixed point amounts
+pub const FIXED_DECIMAL_PLACES: usize = 4;
+
+/// Largest number of decimal places amounts can be output with
+pub const MAX_OUTPUT_DECIMAL_PLACES: u32 = 6;
+
+/// Column names of the output
+pub const HEADERS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Column name of the status of clients
+pub const STATUS_HEADER: &str = "status";
+
+/// Column name of the marker of changed clients
+pub const CHANGED_HEADER: &str = "changed";
+
+/// Options for the output of the state of clients
+///
+/// By default clients are sorted by their client, so repeated runs
+/// over the same input produce identical output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    /// Clients are sorted by their client
+    ///
+    /// Unsorted clients are output in the order of the store, which may
+    /// differ between runs.
+    pub sort: bool,
+    /// Amounts always have exactly four decimal places, e.g. `6.0000`
+    pub fixed_point: bool,
+    /// Amounts always have exactly this many decimal places, up to `MAX_OUTPUT_DECIMAL_PLACES`
+    ///
+    /// Overrides `fixed_point`. Amounts are rounded with the `rounding` mode,
+    /// independent of the precision amounts are handled with.
+    pub decimal_places: Option<u32>,
+    /// Rounding mode of amounts output with a number of decimal places
+    pub rounding: RoundingMode,
+    /// Only clients changed since the last `ClientStore::clear_changes` are output
+    ///
+    /// Rows get a `changed` column so incremental output can be told apart
+    /// from a full snapshot.
+    pub changed_only: bool,
+    /// Rows of all clients get a `changed` column, true for the clients whose
+    /// balances or locked status differ from before the last `ClientStore::clear_changes`
+    pub mark_changed: bool,
+    /// Rows get a `status` column with the client's status
+    ///
+    /// The status is `active`, `frozen` or `locked`, so frozen accounts can
+    /// be told apart from accounts locked by a chargeback.
+    pub status: bool,
+    /// Clients are replaced with their pseudonyms, so the output can be shared
+    /// without revealing accounts
+    ///
+    /// Sorted clients are ordered by their pseudonyms with
+    /// `ClientStore::write_state_with_options`, so the order doesn't reveal them either.
+    #[cfg(feature = "anonymize")]
+    pub pseudonymizer: Option<Pseudonymizer>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            sort: true,
+            fixed_point: false,
+            decimal_places: None,
+            rounding: RoundingMode::default(),
+            changed_only: false,
+            mark_changed: false,
+            status: false,
+            #[cfg(feature = "anonymize")]
+            pseudonymizer: None,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Format an amount with the fixed number of decimal places of the options.
+    ///
+    /// Returns None if amounts are output as is.
+    fn fixed(&self, amount: Amount) -> Option<String> {
+        let places = self
+            .decimal_places
+            .or(self.fixed_point.then_some(FIXED_DECIMAL_PLACES as u32))?
+            .min(MAX_OUTPUT_DECIMAL_PLACES);
+        // Adding zero turns a negative zero into zero, so it is not output as `-0.00`
+        let rounded = round(amount, places, self.rounding) + 0.0;
+        Some(format!("{:.*}", places as usize, rounded))
+    }
+
+    /// Get the pseudonym of a client, if clients are anonymized.
+    #[cfg(feature = "anonymize")]
+    pub(crate) fn pseudonym(&self, client: ClientID) -> Option<String> {
+        let pseudonymizer = self.pseudonymizer.as_ref()?;
+        Some(pseudonymizer.pseudonym(client))
+    }
+
+    /// Get the pseudonym of a client, if clients are anonymized.
+    #[cfg(not(feature = "anonymize"))]
+    pub(crate) fn pseudonym(&self, _client: ClientID) -> Option<String> {
+        None
+    }
+
+    /// Get a client as it's output with the options
+    fn client(&self, client: ClientID) -> OutputClient {
+        match self.pseudonym(client) {
+            Some(pseudonym) => OutputClient::Pseudonym(pseudonym),
+            None => OutputClient::Id(client),
+        }
+    }
+
+    /// Column names of the output with the options
+    pub fn headers(&self) -> Vec<&'static str> {
+        let mut headers = HEADERS.to_vec();
+        if self.status {
+            headers.push(STATUS_HEADER);
+        }
+        if self.changed_column() {
+            headers.push(CHANGED_HEADER);
+        }
+        headers
+    }
+
+    /// Check if rows get a `changed` column
+    fn changed_column(&self) -> bool {
+        self.changed_only || self.mark_changed
+    }
+}
+
+/// Amount in the output
+struct OutputAmount {
+    amount: Amount,
+    /// Amount formatted with a fixed number of decimal places
+    fixed: Option<String>,
+}
+
+impl Serialize for OutputAmount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match &self.fixed {
+            Some(fixed) => serializer.serialize_str(fixed),
+            None => serializer.serialize_f64(self.amount),
+        }
+    }
+}
+
+/// Client in the output
+#[derive(Serialize)]
+#[serde(untagged)]
+enum OutputClient {
+    Id(ClientID),
+    Pseudonym(String),
+}
+
+impl Display for OutputClient {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Id(client) => client.fmt(f),
+            Self::Pseudonym(pseudonym) => pseudonym.fmt(f),
+        }
+    }
+}
+
+/// Output data structure for the state of a client
+///
+/// Kept separate from Client so the output does not depend on
+/// the internal representation of clients. Serialize it to implement
+/// an OutputFormat on top of a serde format.
+#[derive(Serialize)]
+pub struct ClientRow {
+    client: OutputClient,
+    available: OutputAmount,
+    held: OutputAmount,
+    total: OutputAmount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+    /// Marker of the `changed` column, empty if the client was not marked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<Option<bool>>,
+}
+
+impl ClientRow {
+    /// Create the output of the client's state
+    ///
+    /// The `changed` column of the options is left empty, see `ClientRow::marked`.
+    pub fn new(client: &Client, options: &OutputOptions) -> Self {
+        Self::with_marker(client, options, None)
+    }
+
+    /// Create the output of the client's state, marked as changed or not
+    pub fn marked(client: &Client, options: &OutputOptions, changed: bool) -> Self {
+        Self::with_marker(client, options, Some(changed))
+    }
+
+    fn with_marker(client: &Client, options: &OutputOptions, changed: Option<bool>) -> Self {
+        let amount = |amount| OutputAmount {
+            amount,
+            fixed: options.fixed(amount),
+        };
+        Self {
+            client: options.client(client.id),
+            available: amount(client.available),
+            held: amount(client.held),
+            total: amount(client.total()),
+            locked: client.locked,
+            status: options.status.then(|| client.status()),
+            changed: options.changed_column().then_some(changed),
+        }
+    }
+}
+
+/// How clients are partitioned into the shards of a sharded output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardBy {
+    /// Every shard holds a contiguous range of clients
+    #[default]
+    Range,
+    /// Clients are spread over the shards by a hash of their client
+    Hash,
+}
+
+impl ShardBy {
+    /// Shard of a client among a number of shards, the same in every run.
+    pub fn shard(&self, client: ClientID, shards: usize) -> usize {
+        let shards = shards.max(1);
+        match self {
+            Self::Range => {
+                (u128::from(client) * shards as u128 / (u128::from(ClientID::MAX) + 1)) as usize
+            }
+            Self::Hash => {
+                // Fibonacci hashing, wrapping like a u64 multiplication for every width of ClientID
+                let hash = (u128::from(client) * 0x9E37_79B9_7F4A_7C15) as u64 >> 32;
+                (hash % shards as u64) as usize
+            }
+        }
+    }
+}
+
+/// Path of a shard of a sharded output
+///
+/// The zero padded index of the shard is added to the file name before it's
+/// extensions, e.g. shard 3 of `state.csv.gz` is `state-03.csv.gz`.
+pub fn shard_path(path: &Path, shard: usize, shards: usize) -> PathBuf {
+    let width = (shards.saturating_sub(1).to_string().len()).max(2);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extensions) = match name.find('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name.as_str(), ""),
+    };
+    path.with_file_name(format!("{}-{:0width$}{}", stem, shard, extensions))
+}
+
+/// Compression of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Output is written as is
+    #[default]
+    None,
+    /// Gzip compressed output
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard compressed output
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Way the signature of a signed output is emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureMode {
+    /// Signature is written to a file named after the output with a `.sig` extension
+    #[default]
+    Detached,
+    /// Signature is appended to the output as a trailer line
+    Trailer,
+}
+
+/// Writer compressing everything written to it
+///
+/// Wrap the writer of an OutputFormat or a history export to compress it.
+/// `finish` must be called once everything is written to complete the
+/// compressed stream.
+pub enum CompressedWriter<W>
+where
+    W: Write,
+{
+    /// Uncompressed writer
+    None(W),
+    /// Gzip encoder
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+    /// Zstandard encoder
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W> CompressedWriter<W>
+where
+    W: Write,
+{
+    /// Create a new CompressedWriter with the compression
+    pub fn new(writer: W, compression: Compression) -> Result<Self, TransactionError> {
+        Ok(match compression {
+            Compression::None => Self::None(writer),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Self::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Self::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Complete the compressed stream and return the underlying writer.
+    pub fn finish(self) -> Result<W, TransactionError> {
+        let mut writer = match self {
+            Self::None(writer) => writer,
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W> Write for CompressedWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Self::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Format of exported history entries
+#[cfg(any(feature = "csv", feature = "json"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// CSV rows with a `client,tx,amount,kind,state,timestamp` header
+    #[cfg(feature = "csv")]
+    Csv,
+    /// A JSON object per line
+    #[cfg(feature = "json")]
+    JsonLines,
+}
+
+/// Output data structure for an exported history entry
+#[cfg(any(feature = "csv", feature = "json"))]
+#[derive(Serialize)]
+struct HistoryRow {
+    client: ClientID,
+    tx: TransactionID,
+    amount: Amount,
+    kind: Option<TransactionKind>,
+    state: DisputeState,
+    timestamp: Option<Timestamp>,
+}
+
+/// Writer of history entries in a HistoryFormat
+#[cfg(any(feature = "csv", feature = "json"))]
+pub(crate) enum HistoryWriter<W>
+where
+    W: Write,
+{
+    #[cfg(feature = "csv")]
+    Csv(Box<Writer<W>>),
+    #[cfg(feature = "json")]
+    JsonLines(W),
+}
+
+#[cfg(any(feature = "csv", feature = "json"))]
+impl<W> HistoryWriter<W>
+where
+    W: Write,
+{
+    /// Create a new HistoryWriter of the format
+    pub(crate) fn new(writer: W, format: HistoryFormat) -> Self {
+        match format {
+            #[cfg(feature = "csv")]
+            HistoryFormat::Csv => Self::Csv(Box::new(Writer::from_writer(writer))),
+            #[cfg(feature = "json")]
+            HistoryFormat::JsonLines => Self::JsonLines(writer),
+        }
+    }
+
+    /// Write a history entry of a client.
+    pub(crate) fn write(
+        &mut self,
+        client: ClientID,
+        tx: TransactionID,
+        history: &History,
+    ) -> Result<(), TransactionError> {
+        let row = HistoryRow {
+            client,
+            tx,
+            amount: history.amount,
+            kind: history.kind,
+            state: history.state,
+            timestamp: history.timestamp,
+        };
+        match self {
+            #[cfg(feature = "csv")]
+            Self::Csv(writer) => Ok(writer.serialize(row)?),
+            #[cfg(feature = "json")]
+            Self::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, &row).map_err(|err| err.to_string())?;
+                Ok(writer.write_all(b"\n")?)
+            }
+        }
+    }
+
+    /// Flush the written entries.
+    pub(crate) fn finish(&mut self) -> Result<(), TransactionError> {
+        match self {
+            #[cfg(feature = "csv")]
+            Self::Csv(writer) => Ok(writer.flush()?),
+            #[cfg(feature = "json")]
+            Self::JsonLines(writer) => Ok(writer.flush()?),
+        }
+    }
+}
+
+/// Format of the output of the state of clients
+///
+/// Implement to add new output formats. The header is written first,
+/// then every client, and finish is called once all clients are written.
+pub trait OutputFormat {
+    /// Write the header of the output.
+    fn write_header(&mut self) -> Result<(), TransactionError>;
+
+    /// Write the state of a client.
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError>;
+
+    /// Write the state of a client, marked as changed since the last `ClientStore::clear_changes` or not.
+    ///
+    /// The store writes clients with their marker, for formats with a `changed`
+    /// column. By default the marker is ignored and the client is written as is.
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        let _ = changed;
+        self.write_client(client)
+    }
+
+    /// Finish the output, flushing anything buffered.
+    fn finish(&mut self) -> Result<(), TransactionError>;
+}
+
+/// CSV output format
+///
+/// Default output format. Also used for TSV output with a tab delimiter.
+#[cfg(feature = "csv")]
+pub struct CsvFormat<W>
+where
+    W: Write,
+{
+    writer: Writer<W>,
+    options: OutputOptions,
+}
+
+#[cfg(feature = "csv")]
+impl<W> CsvFormat<W>
+where
+    W: Write,
+{
+    /// Create a new CsvFormat writing comma delimited rows
+    pub fn new(writer: W, options: &OutputOptions) -> Self {
+        Self::with_delimiter(writer, b',', options)
+    }
+
+    /// Create a new CsvFormat writing tab delimited rows
+    pub fn tsv(writer: W, options: &OutputOptions) -> Self {
+        Self::with_delimiter(writer, b'\t', options)
+    }
+
+    fn with_delimiter(writer: W, delimiter: u8, options: &OutputOptions) -> Self {
+        Self {
+            writer: WriterBuilder::new()
+                .has_headers(false)
+                .delimiter(delimiter)
+                .from_writer(writer),
+            options: options.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<W> OutputFormat for CsvFormat<W>
+where
+    W: Write,
+{
+    fn write_header(&mut self) -> Result<(), TransactionError> {
+        Ok(self.writer.write_record(self.options.headers())?)
+    }
+
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError> {
+        Ok(self
+            .writer
+            .serialize(ClientRow::new(client, &self.options))?)
+    }
+
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        Ok(self
+            .writer
+            .serialize(ClientRow::marked(client, &self.options, changed))?)
+    }
+
+    fn finish(&mut self) -> Result<(), TransactionError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Human friendly table output format for terminals
+///
+/// Columns are aligned, amounts have thousands separators and locked
+/// accounts can be highlighted. Clients are written once all clients are written,
+/// since the column widths depend on every client.
+pub struct TableFormat<W>
+where
+    W: Write,
+{
+    writer: W,
+    options: OutputOptions,
+    /// Highlight locked accounts with ANSI colors
+    color: bool,
+    /// Formatted cells of every client and if the client is locked
+    rows: Vec<(Vec<String>, bool)>,
+}
+
+impl<W> TableFormat<W>
+where
+    W: Write,
+{
+    /// Create a new TableFormat, highlighting locked accounts if color is true
+    pub fn new(writer: W, options: &OutputOptions, color: bool) -> Self {
+        Self {
+            writer,
+            options: options.clone(),
+            color,
+            rows: Vec::new(),
+        }
+    }
+
+    fn amount(&self, amount: Amount) -> String {
+        let amount = self.options.fixed(amount).unwrap_or_else(|| {
+            // Unlike Debug from 1e16 on, Display never uses an exponent, which can't be grouped.
+            // Whole amounts keep a decimal place like the other formats, e.g. `2.0`.
+            if amount.fract() == 0.0 {
+                format!("{:.1}", amount)
+            } else {
+                amount.to_string()
+            }
+        });
+        group_thousands(&amount)
+    }
+
+    /// Format the cells of the client, with an empty `changed` cell if it is not marked.
+    fn write_row(&mut self, client: &Client, changed: Option<bool>) {
+        let mut cells = vec![
+            self.options.client(client.id).to_string(),
+            self.amount(client.available),
+            self.amount(client.held),
+            self.amount(client.total()),
+            client.locked.to_string(),
+        ];
+        if self.options.status {
+            cells.push(client.status().to_owned());
+        }
+        if self.options.changed_column() {
+            cells.push(
+                changed
+                    .map(|changed| changed.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        self.rows.push((cells, client.locked));
+    }
+}
+
+impl<W> OutputFormat for TableFormat<W>
+where
+    W: Write,
+{
+    fn write_header(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.write_row(client, None);
+        Ok(())
+    }
+
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        self.write_row(client, Some(changed));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactionError> {
+        let headers = self.options.headers();
+        let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+        for (cells, _locked) in self.rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let header = headers
+            .iter()
+            .zip(&widths)
+            .map(|(header, &width)| format!("{:<width$}", header, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(self.writer, "{}", header.trim_end())?;
+        writeln!(
+            self.writer,
+            "{}",
+            widths
+                .iter()
+                .map(|&width| "-".repeat(width))
+                .collect::<Vec<_>>()
+                .join("  ")
+        )?;
+        for (cells, locked) in self.rows.iter() {
+            let row = cells
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(index, (cell, &width))| match index {
+                    // Amounts are right aligned so their decimal places line up
+                    1..=3 => format!("{:>width$}", cell, width = width),
+                    _ => format!("{:<width$}", cell, width = width),
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            let row = row.trim_end();
+            if *locked && self.color {
+                writeln!(self.writer, "\x1b[31m{}\x1b[0m", row)?;
+            } else {
+                writeln!(self.writer, "{}", row)?;
+            }
+        }
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Insert thousands separators into the whole part of a formatted amount.
+fn group_thousands(amount: &str) -> String {
+    let (sign, unsigned) = match amount.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", amount),
+    };
+    let (whole, fraction) = match unsigned.find('.') {
+        Some(index) => unsigned.split_at(index),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::from(sign);
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(fraction);
+    grouped
+}
+
+/// YAML output format
+///
+/// Clients are written as a list of maps once all clients are written.
+#[cfg(feature = "yaml")]
+pub struct YamlFormat<W>
+where
+    W: Write,
+{
+    writer: W,
+    options: OutputOptions,
+    rows: Vec<ClientRow>,
+}
+
+#[cfg(feature = "yaml")]
+impl<W> YamlFormat<W>
+where
+    W: Write,
+{
+    /// Create a new YamlFormat
+    pub fn new(writer: W, options: &OutputOptions) -> Self {
+        Self {
+            writer,
+            options: options.clone(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl<W> OutputFormat for YamlFormat<W>
+where
+    W: Write,
+{
+    fn write_header(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.rows.push(ClientRow::new(client, &self.options));
+        Ok(())
+    }
+
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        self.rows
+            .push(ClientRow::marked(client, &self.options, changed));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactionError> {
+        serde_yaml::to_writer(&mut self.writer, &self.rows).map_err(|err| err.to_string())?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// JSON output format
+///
+/// Clients are written as an array of objects once all clients are written.
+#[cfg(feature = "json")]
+pub struct JsonFormat<W>
+where
+    W: Write,
+{
+    writer: W,
+    options: OutputOptions,
+    rows: Vec<ClientRow>,
+}
+
+#[cfg(feature = "json")]
+impl<W> JsonFormat<W>
+where
+    W: Write,
+{
+    /// Create a new JsonFormat
+    pub fn new(writer: W, options: &OutputOptions) -> Self {
+        Self {
+            writer,
+            options: options.clone(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<W> OutputFormat for JsonFormat<W>
+where
+    W: Write,
+{
+    fn write_header(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.rows.push(ClientRow::new(client, &self.options));
+        Ok(())
+    }
+
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        self.rows
+            .push(ClientRow::marked(client, &self.options, changed));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactionError> {
+        serde_json::to_writer(&mut self.writer, &self.rows).map_err(|err| err.to_string())?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// TOML output format
+///
+/// Clients are written as an array of `clients` tables once all clients are written.
+#[cfg(feature = "toml")]
+pub struct TomlFormat<W>
+where
+    W: Write,
+{
+    writer: W,
+    options: OutputOptions,
+    rows: Vec<ClientRow>,
+}
+
+#[cfg(feature = "toml")]
+impl<W> TomlFormat<W>
+where
+    W: Write,
+{
+    /// Create a new TomlFormat
+    pub fn new(writer: W, options: &OutputOptions) -> Self {
+        Self {
+            writer,
+            options: options.clone(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "toml")]
+impl<W> OutputFormat for TomlFormat<W>
+where
+    W: Write,
+{
+    fn write_header(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    fn write_client(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.rows.push(ClientRow::new(client, &self.options));
+        Ok(())
+    }
+
+    fn write_marked_client(
+        &mut self,
+        client: &Client,
+        changed: bool,
+    ) -> Result<(), TransactionError> {
+        self.rows
+            .push(ClientRow::marked(client, &self.options, changed));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransactionError> {
+        #[derive(Serialize)]
+        struct State<'a> {
+            clients: &'a [ClientRow],
+        }
+
+        let state = toml::to_string(&State {
+            clients: &self.rows,
+        })
+        .map_err(|err| err.to_string())?;
+        self.writer.write_all(state.as_bytes())?;
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn tsv_output() {
+        let mut client = Client::new(1);
+        client.available = 1.5;
+        let mut output = Vec::new();
+        {
+            let mut format = CsvFormat::tsv(&mut output, &OutputOptions::default());
+            format.write_header().unwrap();
+            format.write_client(&client).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client\tavailable\theld\ttotal\tlocked\n1\t1.5\t0.0\t1.5\tfalse\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn decimal_places_output() {
+        let mut client = Client::new(1);
+        client.available = 2.125;
+        client.held = -0.001;
+        let csv = |options: &OutputOptions| {
+            let mut output = Vec::new();
+            {
+                let mut format = CsvFormat::new(&mut output, options);
+                format.write_client(&client).unwrap();
+                format.finish().unwrap();
+            }
+            String::from_utf8(output).unwrap()
+        };
+        let mut options = OutputOptions {
+            decimal_places: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(csv(&options), "1,2.13,0.00,2.12,false\n");
+        options.rounding = RoundingMode::HalfEven;
+        assert_eq!(csv(&options), "1,2.12,0.00,2.12,false\n");
+        options.decimal_places = Some(0);
+        assert_eq!(csv(&options), "1,2,0,2,false\n");
+    }
+
+    #[test]
+    fn thousands_grouped() {
+        assert_eq!(group_thousands("1.5"), "1.5");
+        assert_eq!(group_thousands("1234567.25"), "1,234,567.25");
+        assert_eq!(group_thousands("-123456"), "-123,456");
+
+        let table = TableFormat::new(Vec::new(), &OutputOptions::default(), false);
+        assert_eq!(table.amount(2.0), "2.0");
+        assert_eq!(table.amount(1e16), "10,000,000,000,000,000.0");
+        assert_eq!(table.amount(-1.5e17), "-150,000,000,000,000,000.0");
+        assert_eq!(table.amount(1e-7), "0.0000001");
+    }
+
+    #[test]
+    fn table_output() {
+        let mut first = Client::new(1);
+        first.available = 1234.5;
+        let mut second = Client::new(20);
+        second.held = 2.0;
+        second.locked = true;
+        let mut output = Vec::new();
+        {
+            let mut format = TableFormat::new(&mut output, &OutputOptions::default(), true);
+            format.write_header().unwrap();
+            format.write_client(&first).unwrap();
+            format.write_client(&second).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client  available  held  total    locked\n\
+             ------  ---------  ----  -------  ------\n\
+             1         1,234.5   0.0  1,234.5  false\n\
+             \x1b[31m20            0.0   2.0      2.0  true\x1b[0m\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn status_output() {
+        let mut frozen = Client::new(1);
+        frozen.frozen = true;
+        let mut locked = Client::new(2);
+        locked.frozen = true;
+        locked.locked = true;
+        let options = OutputOptions {
+            status: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        {
+            let mut format = CsvFormat::new(&mut output, &options);
+            format.write_header().unwrap();
+            format.write_client(&frozen).unwrap();
+            format.write_client(&locked).unwrap();
+            format.write_client(&Client::new(3)).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,status\n\
+             1,0.0,0.0,0.0,false,frozen\n\
+             2,0.0,0.0,0.0,true,locked\n\
+             3,0.0,0.0,0.0,false,active\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn changed_output() {
+        let options = OutputOptions {
+            mark_changed: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        {
+            let mut format = CsvFormat::new(&mut output, &options);
+            format.write_header().unwrap();
+            format.write_marked_client(&Client::new(1), true).unwrap();
+            format.write_marked_client(&Client::new(2), false).unwrap();
+            format.write_client(&Client::new(3)).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,changed\n\
+             1,0.0,0.0,0.0,false,true\n\
+             2,0.0,0.0,0.0,false,false\n\
+             3,0.0,0.0,0.0,false,\n"
+        );
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_output() {
+        let mut client = Client::new(1);
+        client.available = 1.5;
+        let mut output = Vec::new();
+        {
+            let mut format = YamlFormat::new(&mut output, &OutputOptions::default());
+            format.write_header().unwrap();
+            format.write_client(&client).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "- client: 1\n  available: 1.5\n  held: 0.0\n  total: 1.5\n  locked: false\n"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_output() {
+        let mut client = Client::new(1);
+        client.available = 1.5;
+        let mut output = Vec::new();
+        {
+            let mut format = JsonFormat::new(&mut output, &OutputOptions::default());
+            format.write_header().unwrap();
+            format.write_client(&client).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"[{"client":1,"available":1.5,"held":0.0,"total":1.5,"locked":false}]"#
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_output() {
+        let mut client = Client::new(1);
+        client.available = 1.5;
+        let mut output = Vec::new();
+        {
+            let mut format = TomlFormat::new(&mut output, &OutputOptions::default());
+            format.write_header().unwrap();
+            format.write_client(&client).unwrap();
+            format.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[[clients]]\nclient = 1\navailable = 1.5\nheld = 0.0\ntotal = 1.5\nlocked = false\n"
+        );
+    }
+
+    #[cfg(all(feature = "csv", feature = "gzip", feature = "zstd"))]
+    #[test]
+    fn compressed_output() {
+        use std::io::Read;
+
+        let mut client = Client::new(1);
+        client.available = 1.5;
+        let expected = "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n";
+        let compressed = |compression| {
+            let mut writer = CompressedWriter::new(Vec::new(), compression).unwrap();
+            {
+                let mut format = CsvFormat::new(&mut writer, &OutputOptions::default());
+                format.write_header().unwrap();
+                format.write_client(&client).unwrap();
+                format.finish().unwrap();
+            }
+            writer.finish().unwrap()
+        };
+
+        let plain = compressed(Compression::None);
+        assert_eq!(String::from_utf8(plain).unwrap(), expected);
+
+        let gzip = compressed(Compression::Gzip);
+        let mut output = String::new();
+        let _ = flate2::read::GzDecoder::new(gzip.as_slice())
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let zstd = compressed(Compression::Zstd);
+        let output = zstd::decode_all(zstd.as_slice()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn shards_of_clients() {
+        assert_eq!(ShardBy::Range.shard(0, 16), 0);
+        assert_eq!(ShardBy::Range.shard(ClientID::MAX, 16), 15);
+        assert_eq!(ShardBy::Range.shard(ClientID::MAX / 2, 2), 0);
+        assert_eq!(ShardBy::Range.shard(ClientID::MAX / 2 + 1, 2), 1);
+        for client in 0..1000 {
+            assert!(ShardBy::Hash.shard(client, 16) < 16);
+            assert_eq!(ShardBy::Hash.shard(client, 1), 0);
+        }
+        let shards: std::collections::BTreeSet<usize> = (0..64)
+            .map(|client| ShardBy::Hash.shard(client, 4))
+            .collect();
+        assert_eq!(shards.len(), 4);
+    }
+
+    #[test]
+    fn shard_paths() {
+        assert_eq!(
+            shard_path(Path::new("out/state.csv"), 3, 16),
+            PathBuf::from("out/state-03.csv")
+        );
+        assert_eq!(
+            shard_path(Path::new("state.csv.gz"), 7, 1000),
+            PathBuf::from("state-007.csv.gz")
+        );
+        assert_eq!(
+            shard_path(Path::new("state"), 0, 2),
+            PathBuf::from("state-00")
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::BTreeMap,
+    io::{Error as IoError, Read},
+    sync::{
+        mpsc::{channel, sync_channel},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use csv::ByteRecord;
+
+use crate::{
+    client::ClientStore,
+    encoding::DecodingReader,
+    error::TransactionError,
+    ingest::{apply, RowParser},
+    report::{PhaseTimer, ProcessingReport},
+    transaction::TransactionRegistry,
+    CsvLine, ParseOptions,
+};
+
+/// Approximate size in bytes of a chunk of input parsed by a single thread
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Number of parsed rows sent from the parser thread at a time
+const BATCH_SIZE: usize = 1024;
+
+/// Number of batches the parser thread may get ahead of the applier
+const PIPELINE_DEPTH: usize = 16;
+
+/// Reader splitting input into line aligned chunks
+struct ChunkReader<R> {
+    reader: R,
+    /// Partial line left over from the previous chunk
+    remainder: Vec<u8>,
+    /// Reader has no more input
+    done: bool,
+}
+
+impl<R> ChunkReader<R>
+where
+    R: Read,
+{
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            remainder: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Read the next chunk of complete lines of roughly the size.
+    ///
+    /// Returns None once the input is exhausted.
+    fn next_chunk(&mut self, size: usize) -> Result<Option<Vec<u8>>, IoError> {
+        if self.done && self.remainder.is_empty() {
+            return Ok(None);
+        }
+        let mut chunk = std::mem::take(&mut self.remainder);
+        while !self.done && chunk.len() < size {
+            let wanted = (size - chunk.len()) as u64;
+            if (&mut self.reader).take(wanted).read_to_end(&mut chunk)? == 0 {
+                self.done = true;
+            }
+        }
+        if !self.done {
+            match chunk.iter().rposition(|&byte| byte == b'\n') {
+                Some(end) => self.remainder = chunk.split_off(end + 1),
+                // A single line longer than the chunk, keep reading it
+                None => {
+                    self.remainder = chunk;
+                    return self.next_chunk(size * 2);
+                }
+            }
+        }
+        Ok(Some(chunk))
+    }
+}
+
+/// Parsed rows of a chunk
+type ParsedChunk = Vec<Result<CsvLine, TransactionError>>;
+
+/// Parse a chunk of complete lines without headers.
+fn parse_chunk(chunk: &[u8], options: &ParseOptions, row_parser: &RowParser) -> ParsedChunk {
+    let mut csv_reader = options
+        .reader_builder()
+        .has_headers(false)
+        .from_reader(chunk);
+    let mut record = ByteRecord::new();
+    let mut lines = Vec::new();
+    loop {
+        match csv_reader.read_byte_record(&mut record) {
+            Ok(true) => lines.push(row_parser.parse(&mut record)),
+            Ok(false) => break,
+            Err(err) => {
+                let io_error = err.is_io_error();
+                lines.push(Err(err.into()));
+                if io_error {
+                    break;
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Handle transactions with rows parsed on multiple threads.
+///
+/// Input is split into line aligned chunks which are parsed by a pool of
+/// worker threads. Every chunk is numbered as it is read, and parsed chunks
+/// are put back in order in a reorder buffer before their transactions are
+/// executed, so the result is the same as `handle_transactions_with_parse_options`.
+/// Since chunks are split on new lines, quoted fields may not contain new lines.
+pub fn handle_transactions_parallel<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    threads: usize,
+    store: &mut ClientStore,
+) -> Result<ProcessingReport, TransactionError>
+where
+    R: Read,
+{
+    handle_chunks(reader, registry, options, threads, CHUNK_SIZE, store)
+}
+
+/// Parse chunks of roughly the size on a pool of threads and apply them in order.
+fn handle_chunks<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    threads: usize,
+    chunk_size: usize,
+    store: &mut ClientStore,
+) -> Result<ProcessingReport, TransactionError>
+where
+    R: Read,
+{
+    let mut timer = PhaseTimer::start();
+    let threads = threads.max(1);
+    let mut chunks = ChunkReader::new(DecodingReader::new(reader));
+
+    let mut first = match chunks.next_chunk(chunk_size)? {
+        Some(chunk) => chunk,
+        None => return Ok(ProcessingReport::default()),
+    };
+    let mut bytes_read = first.len() as u64;
+    let (headers, rest) = if options.csv.has_headers {
+        let header_end = first
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(first.len(), |end| end + 1);
+        let rest = first.split_off(header_end);
+        let headers = options
+            .reader_builder()
+            .has_headers(false)
+            .from_reader(first.as_slice())
+            .byte_records()
+            .next()
+            .transpose()?
+            .unwrap_or_default();
+        (headers, rest)
+    } else {
+        (ByteRecord::new(), first)
+    };
+    let row_parser = RowParser::new(headers, options);
+
+    let (job_sender, jobs) = channel::<(u64, Vec<u8>)>();
+    let jobs = Mutex::new(jobs);
+    let (parsed_sender, parsed) = channel::<(u64, ParsedChunk, Duration)>();
+    let mut report = thread::scope(|scope| -> Result<ProcessingReport, TransactionError> {
+        for _ in 0..threads {
+            let parsed_sender = parsed_sender.clone();
+            let (jobs, row_parser) = (&jobs, &row_parser);
+            let _ = scope.spawn(move || loop {
+                let job = jobs.lock().expect("Parser thread panicked").recv();
+                let Ok((sequence, chunk)) = job else {
+                    break;
+                };
+                let parsing = PhaseTimer::start();
+                let lines = parse_chunk(&chunk, options, row_parser);
+                if parsed_sender
+                    .send((sequence, lines, parsing.elapsed()))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+        // Dropped on return so the workers stop once the queued chunks are parsed
+        let job_sender = job_sender;
+
+        let mut report = ProcessingReport::default();
+        // Parsed chunks waiting for the chunks before them, by their sequence number
+        let mut reorder: BTreeMap<u64, ParsedChunk> = BTreeMap::new();
+        let (mut read, mut applied) = (0, 0);
+        let mut next = Some(rest);
+        loop {
+            // Keep every worker busy with a chunk queued behind it
+            while read - applied < threads as u64 * 2 {
+                let Some(chunk) = next.take() else {
+                    break;
+                };
+                let _ = job_sender.send((read, chunk));
+                read += 1;
+                next = chunks.next_chunk(chunk_size)?;
+                bytes_read += next.as_ref().map_or(0, |chunk| chunk.len() as u64);
+            }
+            // Reading chunks is counted as parsing, waiting for the workers is not
+            report.timings.parse += timer.lap();
+            if applied == read {
+                return Ok(report);
+            }
+            let (sequence, lines, parsing) = parsed.recv().expect("Parser thread panicked");
+            report.timings.parse += parsing;
+            let _ = reorder.insert(sequence, lines);
+            let _ = timer.lap();
+            while let Some(lines) = reorder.remove(&applied) {
+                for current in lines {
+                    let _ = apply(current, registry, options, store, &mut report);
+                }
+                applied += 1;
+            }
+            report.timings.execute += timer.lap();
+        }
+    })?;
+    report.rounded_amounts = row_parser.rounded_amounts();
+    report.bytes_read = bytes_read;
+    report.timings.elapsed = timer.elapsed();
+    Ok(report)
+}
+
+/// Handle transactions with rows parsed on a dedicated thread.
+///
+/// The parser thread reads and parses rows while the calling thread executes
+/// the transactions parsed so far, so parsing and applying do not compete for
+/// one core. Rows are executed in their original order, so the result is the
+/// same as `handle_transactions_with_parse_options`. Unlike
+/// `handle_transactions_parallel`, quoted fields may contain new lines.
+///
+/// Parse time is the time the parser thread is busy, execute time the time
+/// the calling thread is, so the larger of them shows which one holds up the other.
+pub fn handle_transactions_pipelined<R>(
+    reader: R,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+) -> ProcessingReport
+where
+    R: Read + Send,
+{
+    let timer = PhaseTimer::start();
+    let (sender, receiver) = sync_channel::<Vec<Result<CsvLine, TransactionError>>>(PIPELINE_DEPTH);
+    thread::scope(|scope| {
+        let parser = scope.spawn(move || {
+            let mut parsing = ProcessingReport::default();
+            let mut timer = PhaseTimer::start();
+            let mut csv_reader = options
+                .reader_builder()
+                .from_reader(DecodingReader::new(reader));
+            let headers = match csv_reader.byte_headers() {
+                Ok(headers) => headers.clone(),
+                Err(err) => {
+                    let _ = sender.send(vec![Err(err.into())]);
+                    return parsing;
+                }
+            };
+            let row_parser = RowParser::new(headers, options);
+            let mut record = ByteRecord::new();
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            loop {
+                match csv_reader.read_byte_record(&mut record) {
+                    Ok(true) => batch.push(row_parser.parse(&mut record)),
+                    Ok(false) => break,
+                    Err(err) if err.is_io_error() => {
+                        batch.push(Err(err.into()));
+                        break;
+                    }
+                    Err(err) => batch.push(Err(err.into())),
+                }
+                if batch.len() == BATCH_SIZE {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                    parsing.timings.parse += timer.lap();
+                    if sender.send(full).is_err() {
+                        break;
+                    }
+                    // Waiting for the applier to catch up is not counted
+                    let _ = timer.lap();
+                }
+            }
+            parsing.timings.parse += timer.lap();
+            if !batch.is_empty() {
+                let _ = sender.send(batch);
+            }
+            parsing.rounded_amounts = row_parser.rounded_amounts();
+            parsing.bytes_read = csv_reader.position().byte();
+            parsing
+        });
+
+        let mut report = ProcessingReport::default();
+        let mut applying = PhaseTimer::start();
+        for batch in receiver.iter() {
+            // Waiting for the parser thread is not counted
+            let _ = applying.lap();
+            for current in batch {
+                let _ = apply(current, registry, options, store, &mut report);
+            }
+            report.timings.execute += applying.lap();
+        }
+        let parsing = parser.join().expect("Parser thread panicked");
+        report.rounded_amounts = parsing.rounded_amounts;
+        report.bytes_read = parsing.bytes_read;
+        report.timings.parse = parsing.timings.parse;
+        report.timings.elapsed = timer.elapsed();
+        report
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_line_aligned() {
+        let data = "deposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,1,3,1.0";
+        let mut chunks = ChunkReader::new(data.as_bytes());
+        assert_eq!(
+            chunks.next_chunk(20).unwrap().unwrap(),
+            b"deposit,1,1,1.0\n".to_vec()
+        );
+        assert_eq!(
+            chunks.next_chunk(40).unwrap().unwrap(),
+            b"deposit,1,2,1.0\ndeposit,1,3,1.0".to_vec()
+        );
+        assert_eq!(chunks.next_chunk(40).unwrap(), None);
+    }
+
+    #[test]
+    fn parsed_chunks_applied_in_order() {
+        let mut csv = String::from("t_type,client,tx,amount\n");
+        for tx in 1..=500 {
+            csv.push_str(&format!("deposit,{},{},{}.5\n", tx % 7, tx, tx));
+            if tx % 3 == 0 {
+                csv.push_str(&format!("withdrawal,{},{},{}\n", tx % 7, tx + 1000, tx));
+            }
+            if tx % 5 == 0 {
+                csv.push_str(&format!("dispute,{},{},\n", tx % 7, tx - 1));
+            }
+        }
+        let mut store = ClientStore::new();
+        let report = handle_chunks(
+            csv.as_bytes(),
+            &TransactionRegistry::new(),
+            &ParseOptions::default(),
+            4,
+            64,
+            &mut store,
+        )
+        .unwrap();
+        let mut expected_store = ClientStore::new();
+        let mut expected =
+            crate::handle_transactions_from_reader(csv.as_bytes(), &mut expected_store);
+        // Timings differ between runs
+        expected.timings = report.timings;
+        assert_eq!(report, expected);
+        assert_eq!(
+            store.get_current_state(true).unwrap(),
+            expected_store.get_current_state(true).unwrap()
+        );
+    }
+
+    #[test]
+    fn pipelined_batches_applied_in_order() {
+        let mut csv = String::from("t_type,client,tx,amount\n");
+        for tx in 1..=BATCH_SIZE as u32 * 2 + 1 {
+            csv.push_str(&format!("deposit,1,{},1.00001\n", tx));
+        }
+        csv.push_str("withdrawal,1,1,\"2049.0\"\nbogus\n");
+        let mut store = ClientStore::new();
+        let report = handle_transactions_pipelined(
+            csv.as_bytes(),
+            &TransactionRegistry::new(),
+            &ParseOptions::default(),
+            &mut store,
+        );
+        let mut expected_store = ClientStore::new();
+        let mut expected =
+            crate::handle_transactions_from_reader(csv.as_bytes(), &mut expected_store);
+        expected.timings = report.timings;
+        assert_eq!(report, expected);
+        assert_eq!(report.applied, BATCH_SIZE as u64 * 2 + 2);
+        assert_eq!(
+            store.get_current_state(true).unwrap(),
+            expected_store.get_current_state(true).unwrap()
+        );
+    }
+
+    #[test]
+    fn chunk_smaller_than_a_line() {
+        let data = "deposit,1,1,1.0\ndeposit,1,2,1.0\n";
+        let mut chunks = ChunkReader::new(data.as_bytes());
+        assert_eq!(
+            chunks.next_chunk(4).unwrap().unwrap(),
+            b"deposit,1,1,1.0\n".to_vec()
+        );
+    }
+}
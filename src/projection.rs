@@ -0,0 +1,340 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    observer::{AccountEvent, Balances},
+    transaction::TransactionKind,
+    Amount, ClientID, IdMap, Timestamp,
+};
+
+/// Seconds in a day
+const DAY: Timestamp = 86_400;
+
+/// Read model derived from the stream of AccountEvents
+///
+/// Projections are kept apart from the write side `ClientStore`, e.g. on
+/// another thread fed by a `Sender<AccountEvent>` observer, so they can be
+/// queried without touching the store. Every projection only keeps what it
+/// needs to answer it's queries.
+pub trait Projection {
+    /// Update the read model with an event.
+    fn apply(&mut self, event: &AccountEvent);
+
+    /// Update the read model with all the events, e.g. the pending events of a channel.
+    fn apply_all<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = AccountEvent>,
+        Self: Sized,
+    {
+        for event in events {
+            self.apply(&event);
+        }
+    }
+}
+
+/// Total funds of a client, ordered totally
+#[derive(Debug, Clone, Copy)]
+struct Total(Amount);
+
+impl PartialEq for Total {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Total {}
+
+impl PartialOrd for Total {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Total {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Balances of every client, indexed by their total funds
+#[derive(Debug, Clone, Default)]
+pub struct BalanceIndex {
+    /// Latest balances of every client
+    balances: IdMap<ClientID, Balances>,
+    /// Clients ordered by their total funds, then by their client
+    by_total: BTreeSet<(Total, ClientID)>,
+}
+
+impl BalanceIndex {
+    /// Create a new empty BalanceIndex
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the latest balances of the client
+    pub fn get(&self, client: ClientID) -> Option<&Balances> {
+        self.balances.get(&client)
+    }
+
+    /// Get the clients with the largest total funds, largest first
+    pub fn largest(&self, count: usize) -> Vec<(ClientID, Balances)> {
+        self.by_total
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(_, client)| (*client, self.balances[client]))
+            .collect()
+    }
+
+    /// Get the clients with the smallest total funds, smallest first
+    pub fn smallest(&self, count: usize) -> Vec<(ClientID, Balances)> {
+        self.by_total
+            .iter()
+            .take(count)
+            .map(|(_, client)| (*client, self.balances[client]))
+            .collect()
+    }
+
+    /// Get the number of clients indexed
+    pub fn len(&self) -> usize {
+        self.balances.len()
+    }
+
+    /// Check if no client is indexed
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty()
+    }
+}
+
+impl Projection for BalanceIndex {
+    fn apply(&mut self, event: &AccountEvent) {
+        if let Some(previous) = self.balances.insert(event.client, event.after) {
+            let _ = self
+                .by_total
+                .remove(&(Total(previous.total()), event.client));
+        }
+        let _ = self
+            .by_total
+            .insert((Total(event.after.total()), event.client));
+    }
+}
+
+/// Clients which are currently locked
+#[derive(Debug, Clone, Default)]
+pub struct LockedAccounts {
+    locked: BTreeSet<ClientID>,
+}
+
+impl LockedAccounts {
+    /// Create a new empty LockedAccounts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check if the client is locked
+    pub fn contains(&self, client: ClientID) -> bool {
+        self.locked.contains(&client)
+    }
+
+    /// Iterate over the locked clients in order
+    pub fn iter(&self) -> impl Iterator<Item = ClientID> + '_ {
+        self.locked.iter().copied()
+    }
+
+    /// Get the number of locked clients
+    pub fn len(&self) -> usize {
+        self.locked.len()
+    }
+
+    /// Check if no client is locked
+    pub fn is_empty(&self) -> bool {
+        self.locked.is_empty()
+    }
+}
+
+impl Projection for LockedAccounts {
+    fn apply(&mut self, event: &AccountEvent) {
+        if event.after.locked {
+            let _ = self.locked.insert(event.client);
+        } else {
+            let _ = self.locked.remove(&event.client);
+        }
+    }
+}
+
+/// Volume of the transactions of a day
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DayVolume {
+    /// Number of transactions applied
+    pub transactions: u64,
+    /// Funds deposited, including interest
+    pub deposited: Amount,
+    /// Funds withdrawn
+    pub withdrawn: Amount,
+}
+
+/// Volume of the transactions of every day, by the time of the events
+///
+/// Days are counted in UTC from the Unix epoch. Events without a time are
+/// counted separately.
+#[derive(Debug, Clone, Default)]
+pub struct DailyVolume {
+    days: BTreeMap<Timestamp, DayVolume>,
+    undated: DayVolume,
+}
+
+impl DailyVolume {
+    /// Create a new empty DailyVolume
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the volume of the day containing the time
+    pub fn on(&self, timestamp: Timestamp) -> DayVolume {
+        self.days
+            .get(&(timestamp / DAY))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Iterate over the start time of every day with transactions and it's volume, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = (Timestamp, DayVolume)> + '_ {
+        self.days.iter().map(|(day, volume)| (day * DAY, *volume))
+    }
+
+    /// Get the volume of the transactions without a time
+    pub fn undated(&self) -> DayVolume {
+        self.undated
+    }
+}
+
+impl Projection for DailyVolume {
+    fn apply(&mut self, event: &AccountEvent) {
+        let volume = match event.timestamp {
+            Some(timestamp) => self.days.entry(timestamp / DAY).or_default(),
+            None => &mut self.undated,
+        };
+        volume.transactions += 1;
+        match event.kind {
+            TransactionKind::Deposit | TransactionKind::Interest => {
+                volume.deposited += event.after.available - event.before.available
+            }
+            TransactionKind::Withdrawal => {
+                volume.withdrawn += event.before.available - event.after.available
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(
+        client: ClientID,
+        kind: TransactionKind,
+        before: Amount,
+        after: Amount,
+        timestamp: Option<Timestamp>,
+    ) -> AccountEvent {
+        let balances = |available| Balances {
+            available,
+            held: 0.0,
+            locked: false,
+        };
+        AccountEvent {
+            client,
+            tx: 1,
+            kind,
+            before: balances(before),
+            after: balances(after),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn balance_index_ordered_by_total() {
+        let mut index = BalanceIndex::new();
+        index.apply_all([
+            event(1, TransactionKind::Deposit, 0.0, 5.0, None),
+            event(2, TransactionKind::Deposit, 0.0, 3.0, None),
+            event(3, TransactionKind::Deposit, 0.0, 4.0, None),
+            event(2, TransactionKind::Deposit, 3.0, 9.0, None),
+        ]);
+        let largest: Vec<ClientID> = index.largest(2).iter().map(|(id, _)| *id).collect();
+        assert_eq!(largest, vec![2, 1]);
+        let smallest: Vec<ClientID> = index.smallest(5).iter().map(|(id, _)| *id).collect();
+        assert_eq!(smallest, vec![3, 1, 2]);
+        assert_eq!(index.get(2).map(Balances::total), Some(9.0));
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn locked_accounts_follow_events() {
+        let mut locked = LockedAccounts::new();
+        let mut chargeback = event(2, TransactionKind::Chargeback, 5.0, 5.0, None);
+        chargeback.after.locked = true;
+        locked.apply(&chargeback);
+        locked.apply(&event(1, TransactionKind::Deposit, 0.0, 1.0, None));
+        assert_eq!(locked.iter().collect::<Vec<_>>(), vec![2]);
+
+        let mut reversal = event(2, TransactionKind::ChargebackReversal, 5.0, 6.0, None);
+        reversal.before.locked = true;
+        locked.apply(&reversal);
+        assert!(locked.is_empty());
+    }
+
+    #[test]
+    fn daily_volume_by_day() {
+        let mut volume = DailyVolume::new();
+        volume.apply_all([
+            event(1, TransactionKind::Deposit, 0.0, 5.0, Some(DAY + 10)),
+            event(1, TransactionKind::Withdrawal, 5.0, 3.0, Some(DAY + 20)),
+            event(1, TransactionKind::Dispute, 3.0, 0.0, Some(2 * DAY)),
+            event(2, TransactionKind::Deposit, 0.0, 1.0, None),
+        ]);
+        assert_eq!(
+            volume.on(DAY),
+            DayVolume {
+                transactions: 2,
+                deposited: 5.0,
+                withdrawn: 2.0,
+            }
+        );
+        assert_eq!(
+            volume.iter().map(|(day, _)| day).collect::<Vec<_>>(),
+            vec![DAY, 2 * DAY]
+        );
+        assert_eq!(volume.undated().deposited, 1.0);
+        assert_eq!(volume.on(0), DayVolume::default());
+    }
+
+    #[test]
+    fn projections_fed_by_store() {
+        use crate::client::ClientStore;
+        use crate::transaction::TransactionRegistry;
+
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        client_store.add_observer(sender);
+        for (name, client, tx, amount) in [
+            ("deposit", 1, 1, 5.0),
+            ("deposit", 2, 2, 2.0),
+            ("dispute", 1, 1, 0.0),
+            ("chargeback", 1, 1, 0.0),
+        ] {
+            let transaction = registry.create_named(name, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let events: Vec<AccountEvent> = receiver.try_iter().collect();
+        let mut index = BalanceIndex::new();
+        let mut locked = LockedAccounts::new();
+        index.apply_all(events.clone());
+        locked.apply_all(events);
+        assert_eq!(index.largest(1)[0].0, 2);
+        assert!(locked.contains(1));
+    }
+}
@@ -0,0 +1,253 @@
+use std::sync::mpsc::Sender;
+
+use serde::Serialize;
+
+use crate::{
+    error::TransactionError,
+    observer::{AccountEvent, TransactionObserver},
+    transaction::TransactionKind,
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Structured account event published to downstream systems
+///
+/// Serialized with a `type` field naming the event, e.g.
+/// `{"type":"deposit_applied","client":1,"tx":1,"amount":1.5,"timestamp":null}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PublishedEvent {
+    /// Funds were deposited to the client
+    DepositApplied {
+        /// Client the funds were deposited to
+        client: ClientID,
+        /// Transaction of the deposit
+        tx: TransactionID,
+        /// Funds deposited
+        amount: Amount,
+        /// Time of the deposit, if known
+        timestamp: Option<Timestamp>,
+    },
+    /// Funds were withdrawn from the client
+    WithdrawalApplied {
+        /// Client the funds were withdrawn from
+        client: ClientID,
+        /// Transaction of the withdrawal
+        tx: TransactionID,
+        /// Funds withdrawn
+        amount: Amount,
+        /// Time of the withdrawal, if known
+        timestamp: Option<Timestamp>,
+    },
+    /// A dispute was opened on a previous transaction
+    DisputeOpened {
+        /// Client of the disputed transaction
+        client: ClientID,
+        /// Transaction which is disputed
+        tx: TransactionID,
+        /// Funds held by the dispute
+        held: Amount,
+    },
+    /// A dispute was resolved and it's funds released
+    DisputeResolved {
+        /// Client of the disputed transaction
+        client: ClientID,
+        /// Transaction which was disputed
+        tx: TransactionID,
+    },
+    /// A disputed transaction was charged back
+    ChargebackApplied {
+        /// Client of the charged back transaction
+        client: ClientID,
+        /// Transaction which was charged back
+        tx: TransactionID,
+    },
+    /// The client was locked
+    AccountLocked {
+        /// Client which was locked
+        client: ClientID,
+        /// Transaction which locked the client
+        tx: TransactionID,
+    },
+}
+
+impl PublishedEvent {
+    /// Get the events published for a change of a client's state.
+    ///
+    /// Kinds without a published event, like holds, publish nothing. A
+    /// transaction locking the client also publishes `AccountLocked`.
+    pub fn from_account_event(event: &AccountEvent) -> Vec<Self> {
+        let (client, tx) = (event.client, event.tx);
+        let mut events = Vec::with_capacity(2);
+        match event.kind {
+            TransactionKind::Deposit => events.push(Self::DepositApplied {
+                client,
+                tx,
+                amount: event.after.available - event.before.available,
+                timestamp: event.timestamp,
+            }),
+            TransactionKind::Withdrawal => events.push(Self::WithdrawalApplied {
+                client,
+                tx,
+                amount: event.before.available - event.after.available,
+                timestamp: event.timestamp,
+            }),
+            TransactionKind::Dispute => events.push(Self::DisputeOpened {
+                client,
+                tx,
+                held: event.after.held - event.before.held,
+            }),
+            TransactionKind::Resolve => events.push(Self::DisputeResolved { client, tx }),
+            TransactionKind::Chargeback => events.push(Self::ChargebackApplied { client, tx }),
+            _ => {}
+        }
+        if event.locked() {
+            events.push(Self::AccountLocked { client, tx });
+        }
+        events
+    }
+
+    /// Get the client of the event
+    pub fn client(&self) -> ClientID {
+        match self {
+            Self::DepositApplied { client, .. }
+            | Self::WithdrawalApplied { client, .. }
+            | Self::DisputeOpened { client, .. }
+            | Self::DisputeResolved { client, .. }
+            | Self::ChargebackApplied { client, .. }
+            | Self::AccountLocked { client, .. } => *client,
+        }
+    }
+}
+
+/// Publisher of account events to a downstream system
+///
+/// Publishers are added to a ClientStore with `EventPublishing`, which
+/// publishes the events of every applied transaction as it occurs.
+pub trait EventPublisher {
+    /// Publish an event.
+    fn publish(&mut self, event: &PublishedEvent) -> Result<(), TransactionError>;
+}
+
+/// Publish to a channel, e.g. to a thread notifying users
+///
+/// Publishing fails once the receiver is gone.
+impl EventPublisher for Sender<PublishedEvent> {
+    fn publish(&mut self, event: &PublishedEvent) -> Result<(), TransactionError> {
+        self.send(event.clone())
+            .map_err(|_| "Event receiver is gone".into())
+    }
+}
+
+/// Observer publishing the events of every applied transaction
+///
+/// Errors publishing are logged to stderr, they don't affect the transaction.
+pub struct EventPublishing<P>(pub P)
+where
+    P: EventPublisher;
+
+impl<P> TransactionObserver for EventPublishing<P>
+where
+    P: EventPublisher,
+{
+    fn on_event(&mut self, event: &AccountEvent) {
+        for published in PublishedEvent::from_account_event(event) {
+            if let Err(err) = self.0.publish(&published) {
+                eprintln!("Couldn't publish event: {}", err);
+            }
+        }
+    }
+}
+
+/// Kafka producer publishing through a Kafka REST proxy
+///
+/// Every event is produced to the topic as a JSON record keyed by it's
+/// client, so the events of a client stay in order on one partition.
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    /// URL of the topic on the REST proxy, e.g. `http://proxy:8082/topics/accounts`
+    url: String,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    /// Create a new KafkaPublisher producing to the topic through the REST proxy
+    pub fn new(proxy: &str, topic: &str) -> Self {
+        Self {
+            url: format!("{}/topics/{}", proxy.trim_end_matches('/'), topic),
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl EventPublisher for KafkaPublisher {
+    fn publish(&mut self, event: &PublishedEvent) -> Result<(), TransactionError> {
+        let body = serde_json::json!({
+            "records": [{ "key": event.client().to_string(), "value": event }],
+        });
+        let _ = ureq::post(&self.url)
+            .set("Content-Type", "application/vnd.kafka.json.v2+json")
+            .send_string(&body.to_string())
+            .map_err(|err| format!("Couldn't produce to {}: {}", self.url, err))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientStore;
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn events_published_to_channel() {
+        let registry = TransactionRegistry::new();
+        let mut client_store = ClientStore::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        client_store.add_observer(EventPublishing(sender));
+        for (name, client, tx, amount) in [
+            ("deposit", 1, 1, 5.0),
+            ("withdrawal", 1, 2, 1.0),
+            ("dispute", 1, 1, 0.0),
+            ("chargeback", 1, 1, 0.0),
+        ] {
+            let transaction = registry.create_named(name, client, tx, amount).unwrap();
+            client_store.execute(transaction.as_ref()).unwrap();
+        }
+
+        let events: Vec<PublishedEvent> = receiver.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                PublishedEvent::DepositApplied {
+                    client: 1,
+                    tx: 1,
+                    amount: 5.0,
+                    timestamp: None,
+                },
+                PublishedEvent::WithdrawalApplied {
+                    client: 1,
+                    tx: 2,
+                    amount: 1.0,
+                    timestamp: None,
+                },
+                PublishedEvent::DisputeOpened {
+                    client: 1,
+                    tx: 1,
+                    held: 5.0,
+                },
+                PublishedEvent::ChargebackApplied { client: 1, tx: 1 },
+                PublishedEvent::AccountLocked { client: 1, tx: 1 },
+            ]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn events_serialized_with_type() {
+        let event = PublishedEvent::AccountLocked { client: 2, tx: 7 };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"account_locked","client":2,"tx":7}"#
+        );
+    }
+}
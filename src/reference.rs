@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::{error::TransactionError, TransactionID};
+
+/// Map of external transaction references to compact transaction ids
+///
+/// References are arbitrary strings, such as a bank's reference or a UUID.
+/// UUIDs are compared case insensitively. A reference is either linked to
+/// the transaction id it was given with, or assigned a compact id counting
+/// down from `TransactionID::MAX` so it doesn't clash with numeric ids.
+#[derive(Debug)]
+pub struct ReferenceMap {
+    /// Transaction id of every known reference
+    ids: HashMap<String, TransactionID>,
+    /// Next compact id to assign
+    next: TransactionID,
+}
+
+impl Default for ReferenceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReferenceMap {
+    /// Create a new empty ReferenceMap
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            next: TransactionID::MAX,
+        }
+    }
+
+    /// Get the transaction id of the reference, if it is known
+    pub fn get(&self, reference: &str) -> Option<TransactionID> {
+        self.ids.get(normalize(reference).as_ref()).copied()
+    }
+
+    /// Get the transaction id of the reference, assigning a compact id if it is new.
+    pub fn id(&mut self, reference: &str) -> TransactionID {
+        if let Some(id) = self.get(reference) {
+            return id;
+        }
+        let id = self.next;
+        self.next -= 1;
+        let _ = self.ids.insert(normalize(reference).into_owned(), id);
+        id
+    }
+
+    /// Link the reference to a transaction id.
+    ///
+    /// Returns an error if the reference is already linked to a different id.
+    pub fn link(&mut self, reference: &str, id: TransactionID) -> Result<(), TransactionError> {
+        match self.get(reference) {
+            Some(linked) if linked != id => Err(format!(
+                "Reference {} is already used by transaction {}",
+                reference, linked
+            )
+            .into()),
+            Some(_) => Ok(()),
+            None => {
+                let _ = self.ids.insert(normalize(reference).into_owned(), id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Create a ReferenceMap from known references and the next compact id
+    ///
+    /// Used to restore a ReferenceMap saved in a state snapshot.
+    pub(crate) fn from_parts(ids: HashMap<String, TransactionID>, next: TransactionID) -> Self {
+        Self { ids, next }
+    }
+
+    /// Iterate over the known references and their transaction ids
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, TransactionID)> {
+        self.ids
+            .iter()
+            .map(|(reference, id)| (reference.as_str(), *id))
+    }
+
+    /// Next compact id to assign
+    pub(crate) fn next_id(&self) -> TransactionID {
+        self.next
+    }
+
+    /// Number of known references
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Check if no references are known
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Lowercase UUIDs so they match regardless of case, other references are kept as is.
+fn normalize(reference: &str) -> std::borrow::Cow<'_, str> {
+    let bytes = reference.as_bytes();
+    let is_uuid = bytes.len() == 36
+        && bytes.iter().enumerate().all(|(index, byte)| match index {
+            8 | 13 | 18 | 23 => *byte == b'-',
+            _ => byte.is_ascii_hexdigit(),
+        });
+    if is_uuid && bytes.iter().any(u8::is_ascii_uppercase) {
+        reference.to_ascii_lowercase().into()
+    } else {
+        reference.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_assigned_and_linked() {
+        let mut references = ReferenceMap::new();
+        let uuid = "6F9619FF-8B86-D011-B42D-00C04FC964FF";
+        assert_eq!(references.id(uuid), TransactionID::MAX);
+        assert_eq!(
+            references.get(&uuid.to_lowercase()),
+            Some(TransactionID::MAX)
+        );
+        assert_eq!(references.id("BANK-REF-1"), TransactionID::MAX - 1);
+        // Only UUIDs are case insensitive
+        assert_eq!(references.get("bank-ref-1"), None);
+
+        references.link("BANK-REF-2", 7).unwrap();
+        references.link("BANK-REF-2", 7).unwrap();
+        references.link("BANK-REF-2", 8).unwrap_err();
+        assert_eq!(references.get("BANK-REF-2"), Some(7));
+        assert_eq!(references.len(), 3);
+    }
+}
@@ -0,0 +1,467 @@
+#[cfg(feature = "object-store")]
+use std::env;
+use std::{
+    io::{self, Read},
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "object-store")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "object-store")]
+use sha2::{Digest, Sha256};
+
+use crate::error::TransactionError;
+#[cfg(feature = "object-store")]
+use crate::{clock::Clock, clock::SystemClock, config::civil_date};
+
+/// Hash of an empty payload, sent with every GET request
+#[cfg(feature = "object-store")]
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Check if a failed request may succeed when retried, on server errors and lost connections.
+pub(crate) fn retryable(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::Status(status, _response) => *status == 429 || *status >= 500,
+        ureq::Error::Transport(_transport) => true,
+    }
+}
+
+/// Delay before a retry, increasing with the number of attempts so far
+pub(crate) fn backoff(attempts: u32) -> Duration {
+    Duration::from_millis(500 << attempts.min(6))
+}
+
+/// Remote input streamed by the engine
+type RemoteReader = Box<dyn Read + Send + Sync>;
+
+/// Factory of the request for a URL
+///
+/// Called again for every retry, so signed requests are signed again.
+type RequestFactory = Box<dyn Fn() -> Result<ureq::Request, TransactionError> + Send + Sync>;
+
+/// Check if the path is a URL supported by `open_url`.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://") || is_object_url(path)
+}
+
+/// Check if the path is an object storage URL supported by `open_object`.
+pub fn is_object_url(path: &str) -> bool {
+    cfg!(feature = "object-store") && (path.starts_with("s3://") || path.starts_with("gs://"))
+}
+
+/// Open an `http://` or `https://` URL for reading, retrying failures.
+///
+/// The response body is streamed as it is read. Failed requests, server
+/// errors and connections lost while reading are retried up to the number
+/// of retries, with an increasing delay. A lost connection is resumed from
+/// the last byte read with a Range request, so the input is never read twice.
+/// With the `object-store` feature, object storage URLs are also supported.
+pub fn open_url(url: &str, retries: u32) -> Result<RemoteReader, TransactionError> {
+    #[cfg(feature = "object-store")]
+    if is_object_url(url) {
+        return open_object_with_retries(url, retries);
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(format!("Unsupported URL {}", url).into());
+    }
+    let owned = url.to_owned();
+    ResumingReader::open(url, retries, Box::new(move || Ok(ureq::get(&owned))))
+}
+
+/// Reader of a response body, resuming it with Range requests
+struct ResumingReader {
+    url: String,
+    request: RequestFactory,
+    /// Retries left
+    retries: u32,
+    /// Number of retries so far, used for the delay before the next one
+    attempts: u32,
+    /// Number of bytes read so far
+    offset: u64,
+    body: Option<RemoteReader>,
+}
+
+impl ResumingReader {
+    fn open(
+        url: &str,
+        retries: u32,
+        request: RequestFactory,
+    ) -> Result<RemoteReader, TransactionError> {
+        let mut reader = Self {
+            url: url.to_owned(),
+            request,
+            retries,
+            attempts: 0,
+            offset: 0,
+            body: None,
+        };
+        reader.body = Some(reader.call()?);
+        Ok(Box::new(reader))
+    }
+
+    /// Request the body from the current offset, retrying failures.
+    fn call(&mut self) -> Result<RemoteReader, TransactionError> {
+        loop {
+            let mut request = (self.request)()?;
+            if self.offset > 0 {
+                request = request.set("Range", &format!("bytes={}-", self.offset));
+            }
+            let err = match request.call() {
+                Ok(response) if self.offset > 0 && response.status() != 206 => {
+                    return Err(format!(
+                        "Couldn't resume {}: server does not support Range requests",
+                        self.url
+                    )
+                    .into());
+                }
+                Ok(response) => return Ok(Box::new(response.into_reader())),
+                Err(err) => err,
+            };
+            if !retryable(&err) || !self.retry(&err) {
+                return Err(format!("Couldn't read {}: {}", self.url, err).into());
+            }
+        }
+    }
+
+    /// Wait before the next retry, returning false if there are no retries left.
+    fn retry(&mut self, err: &dyn std::fmt::Display) -> bool {
+        if self.retries == 0 {
+            return false;
+        }
+        self.retries -= 1;
+        eprintln!(
+            "Retrying {} from byte {} after error: {}",
+            self.url, self.offset, err
+        );
+        thread::sleep(backoff(self.attempts));
+        self.attempts += 1;
+        true
+    }
+}
+
+impl Read for ResumingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let body = match &mut self.body {
+                Some(body) => body,
+                None => {
+                    let body = self
+                        .call()
+                        .map_err(|err| io::Error::other(err.to_string()))?;
+                    self.body.insert(body)
+                }
+            };
+            match body.read(buf) {
+                Ok(read) => {
+                    self.offset += read as u64;
+                    return Ok(read);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    if !self.retry(&err) {
+                        return Err(err);
+                    }
+                    self.body = None;
+                }
+            }
+        }
+    }
+}
+
+/// Open an object storage URL for reading.
+///
+/// The object is streamed as it is read, so it is never staged on disk or
+/// held in memory. Supported URLs are:
+///
+/// - `s3://<bucket>/<key>`, signed with the `AWS_ACCESS_KEY_ID`,
+///   `AWS_SECRET_ACCESS_KEY` and optional `AWS_SESSION_TOKEN` environment
+///   variables, in the `AWS_REGION` region (`us-east-1` by default). Without
+///   credentials the request is not signed. `AWS_ENDPOINT_URL` overrides the
+///   endpoint for S3 compatible stores, with path style URLs.
+/// - `gs://<bucket>/<object>`, authorized with the OAuth access token in the
+///   `GOOGLE_OAUTH_ACCESS_TOKEN` environment variable, if set.
+#[cfg(feature = "object-store")]
+pub fn open_object(url: &str) -> Result<RemoteReader, TransactionError> {
+    open_object_with_retries(url, 0)
+}
+
+/// Open an object storage URL for reading, retrying failures as in `open_url`.
+#[cfg(feature = "object-store")]
+fn open_object_with_retries(url: &str, retries: u32) -> Result<RemoteReader, TransactionError> {
+    let (scheme, location) = url
+        .split_once("://")
+        .ok_or_else(|| format!("Invalid object URL {}", url))?;
+    let (bucket, key) = location
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| format!("Object URL {} has no bucket or key", url))?;
+    let (bucket, key) = (bucket.to_owned(), key.to_owned());
+    let request: RequestFactory = match scheme {
+        "s3" => Box::new(move || s3_request(&bucket, &key)),
+        "gs" => Box::new(move || {
+            let url = format!(
+                "https://storage.googleapis.com/{}/{}",
+                bucket,
+                uri_encode(&key, false)
+            );
+            let request = ureq::get(&url);
+            Ok(match env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+                Ok(token) => request.set("Authorization", &format!("Bearer {}", token)),
+                Err(_) => request,
+            })
+        }),
+        _ => return Err(format!("Unsupported object URL {}", url).into()),
+    };
+    ResumingReader::open(url, retries, request)
+}
+
+#[cfg(feature = "object-store")]
+/// Create the GET request of an S3 object, signed if credentials are set.
+fn s3_request(bucket: &str, key: &str) -> Result<ureq::Request, TransactionError> {
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_owned());
+    let (url, host, path) = match env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => {
+            let endpoint = endpoint.trim_end_matches('/');
+            let host = endpoint
+                .split_once("://")
+                .map_or(endpoint, |(_scheme, host)| host);
+            let path = format!("/{}/{}", bucket, uri_encode(key, false));
+            (format!("{}{}", endpoint, path), host.to_owned(), path)
+        }
+        Err(_) => {
+            let host = format!("{}.s3.{}.amazonaws.com", bucket, region);
+            let path = format!("/{}", uri_encode(key, false));
+            (format!("https://{}{}", host, path), host, path)
+        }
+    };
+    let request = ureq::get(&url);
+    let (access_key, secret_key) = match (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        (Ok(access_key), Ok(secret_key)) => (access_key, secret_key),
+        _ => return Ok(request),
+    };
+    let date_time = amz_date(SystemClock.now());
+    let token = env::var("AWS_SESSION_TOKEN").ok();
+    let mut headers = vec![
+        ("host", host.as_str()),
+        ("x-amz-content-sha256", EMPTY_SHA256),
+        ("x-amz-date", date_time.as_str()),
+    ];
+    if let Some(token) = &token {
+        headers.push(("x-amz-security-token", token));
+    }
+    let scope = format!("{}/{}/s3/aws4_request", &date_time[..8], region);
+    let (signed_headers, signature) = sign(
+        &secret_key,
+        &date_time,
+        &region,
+        "s3",
+        &path,
+        &headers,
+        EMPTY_SHA256,
+    );
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature
+    );
+    Ok(headers
+        .iter()
+        .filter(|(name, _value)| *name != "host")
+        .fold(request, |request, (name, value)| request.set(name, value))
+        .set("Authorization", &authorization))
+}
+
+#[cfg(feature = "object-store")]
+/// Sign a GET request without a query with AWS Signature Version 4.
+///
+/// Headers must be lowercase and sorted by name. Returns the signed
+/// header names and the signature.
+fn sign(
+    secret_key: &str,
+    date_time: &str,
+    region: &str,
+    service: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+) -> (String, String) {
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _value)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let canonical_request = format!(
+        "GET\n{}\n\n{}\n{}\n{}",
+        path, canonical_headers, signed_headers, payload_hash
+    );
+    let date = &date_time[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        date_time,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        });
+    (signed_headers, hex(&hmac(&key, string_to_sign.as_bytes())))
+}
+
+#[cfg(feature = "object-store")]
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "object-store")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(feature = "object-store")]
+/// Format a time as an ISO 8601 basic date time, e.g. `20150830T123600Z`
+fn amz_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_date(timestamp);
+    let seconds = timestamp % 86_400;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(feature = "object-store")]
+/// Percent encode all but unreserved characters, and `/` unless encode_slash is set.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn sign_matches_aws_test_suite() {
+        // get-vanilla from the AWS Signature Version 4 test suite
+        let (signed_headers, signature) = sign(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830T123600Z",
+            "us-east-1",
+            "service",
+            "/",
+            &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            EMPTY_SHA256,
+        );
+        assert_eq!(signed_headers, "host;x-amz-date");
+        assert_eq!(
+            signature,
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+        assert_eq!(amz_date(1_440_938_160), "20150830T123600Z");
+    }
+
+    #[test]
+    fn urls() {
+        assert!(is_url("https://partner.example/settlement.csv"));
+        assert!(!is_url("data/settlement.csv"));
+        assert!(open_url("ftp://partner.example/settlement.csv", 0).is_err());
+        // Nothing listens on the discard port, so the request fails without retries
+        assert!(open_url("http://127.0.0.1:9/settlement.csv", 0).is_err());
+    }
+
+    #[test]
+    fn lost_connection_resumed_with_range() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        let body = "t_type,client,tx,amount\ndeposit,1,1,5.0\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/settlement.csv", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut ranges = Vec::new();
+            for (split, connection) in [10, body.len()].into_iter().zip(listener.incoming()) {
+                let mut connection = connection.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&connection).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        range = Some(value.trim_end_matches('-').parse::<usize>().unwrap());
+                    }
+                }
+                let (status, start) = match range {
+                    Some(start) => ("206 Partial Content", start),
+                    None => ("200 OK", 0),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len() - start
+                );
+                // The first response is cut short after a few bytes
+                let sent = &body[start..split];
+                connection.write_all(head.as_bytes()).unwrap();
+                connection.write_all(sent.as_bytes()).unwrap();
+                ranges.push(range);
+            }
+            ranges
+        });
+
+        let mut input = String::new();
+        open_url(&url, 1)
+            .unwrap()
+            .read_to_string(&mut input)
+            .unwrap();
+        assert_eq!(input, body);
+        assert_eq!(server.join().unwrap(), vec![None, Some(10)]);
+    }
+
+    #[cfg(feature = "object-store")]
+    #[test]
+    fn object_urls() {
+        assert!(is_object_url("s3://bucket/2024/settlement.csv"));
+        assert!(is_object_url("gs://bucket/settlement.csv"));
+        assert!(!is_object_url("data/settlement.csv"));
+        assert_eq!(
+            uri_encode("2024/settlement file+1.csv", false),
+            "2024/settlement%20file%2B1.csv"
+        );
+        assert!(open_object("s3://bucket").is_err());
+        assert!(open_object("ftp://bucket/settlement.csv").is_err());
+    }
+}
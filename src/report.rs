@@ -0,0 +1,436 @@
+#[cfg(any(feature = "csv", feature = "json"))]
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+#[cfg(feature = "csv")]
+use csv::Writer;
+use serde::Serialize;
+
+use crate::{
+    error::{ErrorKind, Severity, TransactionError},
+    Amount, ClientID, Timestamp,
+};
+
+/// Seconds in a day
+const DAY: Timestamp = 86_400;
+
+/// Age buckets of open disputes by their label and last day, the last bucket is unbounded
+const AGING_BUCKETS: [(&str, Option<u64>); 4] = [
+    ("0-30", Some(30)),
+    ("31-60", Some(60)),
+    ("61-90", Some(90)),
+    ("90+", None),
+];
+
+/// Label of the bucket of open disputes without a time
+const UNDATED_BUCKET: &str = "undated";
+
+/// Report of processing input rows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessingReport {
+    /// Number of rows read from the input
+    pub rows_read: u64,
+    /// Number of rows whose transaction was applied
+    pub applied: u64,
+    /// Number of rows which could not be parsed or whose transaction was rejected
+    pub rejected: u64,
+    /// Number of rejected rows by the kind of their error
+    pub rejected_by_kind: BTreeMap<ErrorKind, u64>,
+    /// Number of amounts rounded to the maximum number of decimal places
+    pub rounded_amounts: u64,
+    /// Number of bytes of input read, after decoding
+    pub bytes_read: u64,
+    /// Time spent in every phase of processing
+    pub timings: PhaseTimings,
+    /// Reports of every input file, in the order they were handled
+    ///
+    /// Only set when the input is a directory of files.
+    pub files: Vec<(PathBuf, ProcessingReport)>,
+}
+
+/// Time spent in the phases of processing, to find the bottleneck of a pipeline
+///
+/// Rows parsed on other threads are parsed while earlier rows are executed,
+/// so parse and execute time may add up to more than the elapsed time. The
+/// phase taking about as long as the elapsed time is the bottleneck.
+/// Nothing is timed on wasm32, which has no clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Time from the start until the end of processing
+    pub elapsed: Duration,
+    /// Time reading and parsing rows, summed over the threads parsing them
+    pub parse: Duration,
+    /// Time executing transactions, including writing rejected rows
+    pub execute: Duration,
+    /// Time writing the final state
+    ///
+    /// Set by the caller writing it, the state is written after processing.
+    pub output: Duration,
+}
+
+impl ProcessingReport {
+    /// Record a row rejected with the error.
+    pub fn reject(&mut self, err: &TransactionError) {
+        self.rejected += 1;
+        *self.rejected_by_kind.entry(err.kind()).or_default() += 1;
+    }
+
+    /// Get the number of rejected rows by the severity of their error
+    pub fn rejected_by_severity(&self) -> BTreeMap<Severity, u64> {
+        let mut counts = BTreeMap::new();
+        for (kind, count) in &self.rejected_by_kind {
+            *counts.entry(kind.severity()).or_default() += count;
+        }
+        counts
+    }
+
+    /// Get the number of rejected rows at least as severe as the severity
+    pub fn rejected_at_least(&self, severity: Severity) -> u64 {
+        self.rejected_by_kind
+            .iter()
+            .filter(|(kind, _)| kind.severity() >= severity)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Get the number of rows read per second of the elapsed time
+    pub fn rows_per_sec(&self) -> f64 {
+        per_sec(self.rows_read, self.timings.elapsed)
+    }
+
+    /// Get the number of bytes of input read per second of the elapsed time
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes_read, self.timings.elapsed)
+    }
+
+    /// Add the counts and timings of another report to this report.
+    ///
+    /// Reports are added as if they were processed one after another.
+    /// Reports of files in the other report are not added.
+    pub fn merge(&mut self, other: &ProcessingReport) {
+        self.rows_read += other.rows_read;
+        self.applied += other.applied;
+        self.rejected += other.rejected;
+        for (kind, count) in &other.rejected_by_kind {
+            *self.rejected_by_kind.entry(*kind).or_default() += count;
+        }
+        self.rounded_amounts += other.rounded_amounts;
+        self.bytes_read += other.bytes_read;
+        self.timings.elapsed += other.timings.elapsed;
+        self.timings.parse += other.timings.parse;
+        self.timings.execute += other.timings.execute;
+        self.timings.output += other.timings.output;
+    }
+}
+
+/// Get a count per second of a duration, or 0 if no time passed.
+fn per_sec(count: u64, duration: Duration) -> f64 {
+    let seconds = duration.as_secs_f64();
+    if seconds > 0.0 {
+        count as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Clock splitting the time of processing into phases
+///
+/// Every lap reads the clock once, so timing a row takes a clock read per
+/// phase. The clock is not read on wasm32, where there is none.
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+pub(crate) struct PhaseTimer {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: Instant,
+    #[cfg(not(target_arch = "wasm32"))]
+    lap: Instant,
+}
+
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+impl PhaseTimer {
+    /// Start timing
+    pub(crate) fn start() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        let now = Instant::now();
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: now,
+            #[cfg(not(target_arch = "wasm32"))]
+            lap: now,
+        }
+    }
+
+    /// Get the time since the previous lap, or the start, and start the next lap.
+    pub(crate) fn lap(&mut self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let now = Instant::now();
+            let lap = now - self.lap;
+            self.lap = now;
+            lap
+        }
+        #[cfg(target_arch = "wasm32")]
+        Duration::ZERO
+    }
+
+    /// Get the time since the start
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.start.elapsed()
+        }
+        #[cfg(target_arch = "wasm32")]
+        Duration::ZERO
+    }
+}
+
+/// Open disputes of an age bucket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AgingBucket {
+    /// Range of ages in days, e.g. `31-60`
+    pub bucket: &'static str,
+    /// Number of open disputes
+    pub disputes: u64,
+    /// Amount held by the open disputes
+    pub held: Amount,
+}
+
+/// Report of open disputes grouped by the age of the disputed transaction
+///
+/// The age is the number of days since the transaction was executed, from
+/// it's history timestamp. Disputes of transactions without a time are
+/// counted in an `undated` bucket, which is only reported if not empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeAgingReport {
+    /// Time the ages are calculated at
+    pub as_of: Timestamp,
+    /// Buckets of 0-30, 31-60, 61-90 and 90+ days, followed by undated disputes if any
+    pub buckets: Vec<AgingBucket>,
+}
+
+impl DisputeAgingReport {
+    /// Create an empty report at a time
+    pub fn new(as_of: Timestamp) -> Self {
+        Self {
+            as_of,
+            buckets: AGING_BUCKETS
+                .iter()
+                .map(|(bucket, _last_day)| AgingBucket {
+                    bucket,
+                    disputes: 0,
+                    held: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Add an open dispute of a transaction executed at a time.
+    pub fn add(&mut self, timestamp: Option<Timestamp>, held: Amount) {
+        let index = match timestamp {
+            Some(timestamp) => {
+                let days = self.as_of.saturating_sub(timestamp) / DAY;
+                AGING_BUCKETS
+                    .iter()
+                    .position(|(_bucket, last_day)| last_day.is_none_or(|last| days <= last))
+                    .unwrap_or(AGING_BUCKETS.len() - 1)
+            }
+            None => {
+                if self.buckets.len() == AGING_BUCKETS.len() {
+                    self.buckets.push(AgingBucket {
+                        bucket: UNDATED_BUCKET,
+                        disputes: 0,
+                        held: 0.0,
+                    });
+                }
+                AGING_BUCKETS.len()
+            }
+        };
+        let bucket = &mut self.buckets[index];
+        bucket.disputes += 1;
+        bucket.held += held;
+    }
+
+    /// Write the buckets as CSV rows with a `bucket,disputes,held` header.
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W>(&self, writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        let mut writer = Writer::from_writer(writer);
+        for bucket in &self.buckets {
+            writer.serialize(bucket)?;
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Write the buckets as a JSON array of objects.
+    #[cfg(feature = "json")]
+    pub fn write_json<W>(&self, mut writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        serde_json::to_writer(&mut writer, &self.buckets).map_err(|err| err.to_string())?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Client without activity for the dormancy period
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DormantClient {
+    /// Dormant client
+    pub client: ClientID,
+    /// Time of the client's latest activity
+    pub last_activity: Timestamp,
+    /// Whole days since the client's latest activity
+    pub idle_days: u64,
+    /// Client is frozen
+    pub frozen: bool,
+}
+
+/// Report of the clients dormant at a time, ordered by client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DormancyReport {
+    /// Time the clients were reviewed at
+    pub as_of: Timestamp,
+    /// Dormant clients
+    pub clients: Vec<DormantClient>,
+}
+
+impl DormancyReport {
+    /// Create an empty report at a time
+    pub fn new(as_of: Timestamp) -> Self {
+        Self {
+            as_of,
+            clients: Vec::new(),
+        }
+    }
+
+    /// Add a dormant client with the time of it's latest activity.
+    pub fn add(&mut self, client: ClientID, last_activity: Timestamp, frozen: bool) {
+        self.clients.push(DormantClient {
+            client,
+            last_activity,
+            idle_days: self.as_of.saturating_sub(last_activity) / DAY,
+            frozen,
+        });
+    }
+
+    /// Write the clients as CSV rows with a `client,last_activity,idle_days,frozen` header.
+    #[cfg(feature = "csv")]
+    pub fn write_csv<W>(&self, writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        let mut writer = Writer::from_writer(writer);
+        for client in &self.clients {
+            writer.serialize(client)?;
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Write the clients as a JSON array of objects.
+    #[cfg(feature = "json")]
+    pub fn write_json<W>(&self, mut writer: W) -> Result<(), TransactionError>
+    where
+        W: Write,
+    {
+        serde_json::to_writer(&mut writer, &self.clients).map_err(|err| err.to_string())?;
+        Ok(writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_counted_by_kind() {
+        let mut report = ProcessingReport::default();
+        report.reject(&"Insufficent funds!".into());
+        report.reject(&TransactionError::new(
+            ErrorKind::WrongClient,
+            "Transaction 1 belongs to client 1, not client 2.".to_owned(),
+        ));
+        report.reject(&"Insufficent funds!".into());
+        assert_eq!(report.rejected, 3);
+        assert_eq!(
+            report.rejected_by_kind.into_iter().collect::<Vec<_>>(),
+            vec![(ErrorKind::Other, 2), (ErrorKind::WrongClient, 1)]
+        );
+    }
+
+    #[test]
+    fn throughput_over_elapsed_time() {
+        let mut report = ProcessingReport {
+            rows_read: 1000,
+            bytes_read: 20_000,
+            ..Default::default()
+        };
+        assert_eq!(report.rows_per_sec(), 0.0);
+        report.timings.elapsed = Duration::from_millis(500);
+        report.timings.parse = Duration::from_millis(200);
+        assert_eq!(report.rows_per_sec(), 2000.0);
+        assert_eq!(report.bytes_per_sec(), 40_000.0);
+
+        let other = report.clone();
+        report.merge(&other);
+        assert_eq!(report.timings.elapsed, Duration::from_secs(1));
+        assert_eq!(report.timings.parse, Duration::from_millis(400));
+        assert_eq!(report.rows_per_sec(), 2000.0);
+    }
+
+    #[test]
+    fn disputes_aged_into_buckets() {
+        let as_of = 100 * DAY;
+        let mut report = DisputeAgingReport::new(as_of);
+        report.add(Some(as_of - 30 * DAY), 1.0);
+        report.add(Some(as_of - 31 * DAY), 2.0);
+        report.add(Some(as_of - 91 * DAY), 4.0);
+        report.add(Some(as_of + DAY), 8.0);
+        let counts: Vec<(&str, u64, Amount)> = report
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.bucket, bucket.disputes, bucket.held))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("0-30", 2, 9.0),
+                ("31-60", 1, 2.0),
+                ("61-90", 0, 0.0),
+                ("90+", 1, 4.0)
+            ]
+        );
+
+        report.add(None, 0.5);
+        assert_eq!(report.buckets.len(), 5);
+        assert_eq!(report.buckets[4].bucket, "undated");
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn dormancy_report_csv() {
+        let mut report = DormancyReport::new(400 * DAY);
+        report.add(7, 10 * DAY + 5, false);
+        let mut output = Vec::new();
+        report.write_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,last_activity,idle_days,frozen\n7,864005,389,false\n"
+        );
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn aging_report_csv() {
+        let mut report = DisputeAgingReport::new(0);
+        report.add(Some(0), 1.5);
+        let mut output = Vec::new();
+        report.write_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "bucket,disputes,held\n0-30,1,1.5\n31-60,0,0.0\n61-90,0,0.0\n90+,0,0.0\n"
+        );
+    }
+}
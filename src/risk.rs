@@ -0,0 +1,105 @@
+use crate::{
+    client::Client,
+    transaction::{Transaction, TransactionKind},
+    Amount,
+};
+
+/// Decision of a RiskScorer on a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RiskDecision {
+    /// The transaction is applied.
+    #[default]
+    Allow,
+    /// The transaction is applied and it's funds are held.
+    ///
+    /// Same as disputing the transaction once it is applied, so a later
+    /// resolve releases the funds and a chargeback reverses them. Withdrawals
+    /// are only made once resolved, their funds are held until then.
+    Review,
+    /// The transaction is rejected with a `RiskDenied` error.
+    Deny,
+}
+
+/// Fraud signal hook scoring deposits and withdrawals
+///
+/// A scorer set on a ClientStore is called before every deposit and
+/// withdrawal is applied, after middleware has run. It is also called by
+/// `ClientStore::simulate`, so it scores transactions without side effects.
+pub trait RiskScorer {
+    /// Score the transaction.
+    ///
+    /// The client is the current state of the requested client, or None
+    /// if the client does not exist yet.
+    fn score(&self, transaction: &dyn Transaction, client: Option<&Client>) -> RiskDecision;
+}
+
+/// Rule based RiskScorer
+///
+/// Rules which are not set are not checked. Deny rules are checked first.
+#[derive(Debug, Clone, Default)]
+pub struct RuleBasedScorer {
+    /// Transactions of at least this amount are denied
+    pub deny_amount: Option<Amount>,
+    /// Transactions of at least this amount are reviewed
+    pub review_amount: Option<Amount>,
+    /// Withdrawals of more than this fraction of the client's total funds are reviewed
+    pub review_withdrawal_ratio: Option<f64>,
+    /// Deposits to clients which do not exist yet are reviewed
+    pub review_new_clients: bool,
+}
+
+impl RiskScorer for RuleBasedScorer {
+    fn score(&self, transaction: &dyn Transaction, client: Option<&Client>) -> RiskDecision {
+        let amount = transaction.amount().unwrap_or_default();
+        if self.deny_amount.is_some_and(|deny| amount >= deny) {
+            return RiskDecision::Deny;
+        }
+        let large = self.review_amount.is_some_and(|review| amount >= review);
+        let draining = transaction.kind() == TransactionKind::Withdrawal
+            && self
+                .review_withdrawal_ratio
+                .is_some_and(|ratio| amount > ratio * client.map_or(0.0, Client::total));
+        let new_client = transaction.kind() == TransactionKind::Deposit
+            && self.review_new_clients
+            && client.is_none();
+        if large || draining || new_client {
+            RiskDecision::Review
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionRegistry;
+
+    #[test]
+    fn rule_based_scorer_reviews_draining_withdrawals_and_new_clients() {
+        let registry = TransactionRegistry::new();
+        let scorer = RuleBasedScorer {
+            review_withdrawal_ratio: Some(0.5),
+            review_new_clients: true,
+            ..Default::default()
+        };
+        let mut client = Client::new(1);
+        client.available = 10.0;
+        let deposit = registry.create_named("deposit", 1, 1, 10.0).unwrap();
+        assert_eq!(scorer.score(deposit.as_ref(), None), RiskDecision::Review);
+        assert_eq!(
+            scorer.score(deposit.as_ref(), Some(&client)),
+            RiskDecision::Allow
+        );
+        let small = registry.create_named("withdrawal", 1, 2, 5.0).unwrap();
+        let large = registry.create_named("withdrawal", 1, 3, 6.0).unwrap();
+        assert_eq!(
+            scorer.score(small.as_ref(), Some(&client)),
+            RiskDecision::Allow
+        );
+        assert_eq!(
+            scorer.score(large.as_ref(), Some(&client)),
+            RiskDecision::Review
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    client::ClientStore,
+    clock::Clock,
+    error::TransactionError,
+    report::ProcessingReport,
+    transaction::{Timestamped, Transaction, TransactionRegistry},
+    Amount, ClientID, Timestamp,
+};
+
+/// Definition of a transaction repeated at a fixed interval
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recurring {
+    /// Type name of the transaction, e.g. `deposit`, `withdrawal` or a registered type
+    pub t_type: String,
+    /// Client to execute the transactions on
+    pub client: ClientID,
+    /// Amount of every transaction
+    pub amount: Amount,
+    /// Seconds between transactions, at least 1
+    pub interval: u64,
+    /// Time of the first transaction
+    pub start: Timestamp,
+}
+
+/// Recurring definition and the time it is next due
+#[derive(Debug)]
+struct Schedule {
+    id: u64,
+    recurring: Recurring,
+    next: Timestamp,
+    /// Number of transactions materialized so far
+    runs: u64,
+}
+
+/// Scheduler of recurring transactions
+///
+/// Materializes the transactions of every definition which are due when
+/// a simulated or wall clock advances. Transactions are timestamped with
+/// the time they were due, and missed runs are caught up in order.
+///
+/// Materialized transactions are assigned a transaction id for the
+/// reference `schedule-<id>-<run>` from the store's ReferenceMap, so they
+/// can be disputed by that reference.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    /// Create a new Scheduler with no definitions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a recurring definition, returning it's id.
+    pub fn add(&mut self, recurring: Recurring) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.schedules.push(Schedule {
+            id,
+            next: recurring.start,
+            recurring,
+            runs: 0,
+        });
+        id
+    }
+
+    /// Remove a recurring definition, returning it if it exists.
+    pub fn remove(&mut self, id: u64) -> Option<Recurring> {
+        let index = self
+            .schedules
+            .iter()
+            .position(|schedule| schedule.id == id)?;
+        Some(self.schedules.remove(index).recurring)
+    }
+
+    /// Time the next transaction of any definition is due
+    pub fn next_due(&self) -> Option<Timestamp> {
+        self.schedules.iter().map(|schedule| schedule.next).min()
+    }
+
+    /// Materialize and execute the transactions due at the clock's current time.
+    pub fn tick<C>(
+        &mut self,
+        clock: &C,
+        registry: &TransactionRegistry,
+        store: &mut ClientStore,
+    ) -> ProcessingReport
+    where
+        C: Clock + ?Sized,
+    {
+        self.run_until(clock.now(), registry, store)
+    }
+
+    /// Materialize and execute the transactions due at or before the time.
+    ///
+    /// Transactions are executed in the order they were due. Errors are
+    /// logged to stderr and the transaction counted as rejected.
+    pub fn run_until(
+        &mut self,
+        now: Timestamp,
+        registry: &TransactionRegistry,
+        store: &mut ClientStore,
+    ) -> ProcessingReport {
+        let mut report = ProcessingReport::default();
+        while let Some(schedule) = self
+            .schedules
+            .iter_mut()
+            .filter(|schedule| schedule.next <= now)
+            .min_by_key(|schedule| (schedule.next, schedule.id))
+        {
+            report.rows_read += 1;
+            match Self::materialize(schedule, registry, store)
+                .and_then(|transaction| store.execute(transaction.as_ref()))
+            {
+                Ok(()) => report.applied += 1,
+                Err(err) => {
+                    report.reject(&err);
+                    eprintln!("Couldn't handle scheduled transaction: {}", err);
+                }
+            }
+            schedule.runs += 1;
+            schedule.next = schedule
+                .next
+                .saturating_add(schedule.recurring.interval.max(1));
+        }
+        report
+    }
+
+    /// Create the transaction of the schedule's next run.
+    fn materialize(
+        schedule: &Schedule,
+        registry: &TransactionRegistry,
+        store: &mut ClientStore,
+    ) -> Result<Box<dyn Transaction>, TransactionError> {
+        let reference = format!("schedule-{}-{}", schedule.id, schedule.runs);
+        let tx = store.references_mut().id(&reference);
+        let recurring = &schedule.recurring;
+        let transaction =
+            registry.create_named(&recurring.t_type, recurring.client, tx, recurring.amount)?;
+        Ok(Box::new(Timestamped::new(transaction, schedule.next)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn scheduler_catches_up_due_runs() {
+        let registry = TransactionRegistry::new();
+        let mut store = ClientStore::new();
+        let mut scheduler = Scheduler::new();
+        let clock = ManualClock::new(0);
+        let _ = scheduler.add(Recurring {
+            t_type: "deposit".to_owned(),
+            client: 1,
+            amount: 10.0,
+            interval: 100,
+            start: 50,
+        });
+        let rent = scheduler.add(Recurring {
+            t_type: "withdrawal".to_owned(),
+            client: 1,
+            amount: 15.0,
+            interval: 200,
+            start: 60,
+        });
+
+        assert_eq!(scheduler.tick(&clock, &registry, &mut store).rows_read, 0);
+        clock.advance(300);
+        // Deposits at 50, 150 and 250, withdrawals at 60 and 260
+        let report = scheduler.tick(&clock, &registry, &mut store);
+        assert_eq!((report.applied, report.rejected), (4, 1));
+        assert_eq!(store.clients.get(&1).unwrap().available, 15.0);
+        assert_eq!(scheduler.next_due(), Some(350));
+
+        assert!(scheduler.remove(rent).is_some());
+        let tx = store.references().get("schedule-0-0").unwrap();
+        let history = store.clients.get(&1).unwrap().client_history.get(&tx);
+        assert_eq!(history.unwrap().timestamp, Some(50));
+    }
+}
@@ -0,0 +1,1094 @@
+use std::{env, fmt::Display, fs::File, path::PathBuf, str::FromStr, time::Duration};
+
+use serde::{
+    de::{value::StrDeserializer, DeserializeOwned, IntoDeserializer},
+    Deserialize,
+};
+
+#[cfg(feature = "anonymize")]
+use crate::anonymize::Pseudonymizer;
+use crate::{
+    alias::ClientAliases,
+    amount::RoundingMode,
+    client::Tier,
+    config::{
+        AmountLimits, DailyLimits, DayCount, DormancyPolicy, DuplicatePolicy, HistoryRetention,
+        NegativeAvailablePolicy, RetryPolicy, StoreConfig, TierLimits, VelocityRule,
+        VerificationPolicy,
+    },
+    error::{Severity, TransactionError},
+    output::{Compression, OutputOptions, ShardBy, SignatureMode, MAX_OUTPUT_DECIMAL_PLACES},
+    storage::ClientStorage,
+    Amount, ClientID, CsvOptions, ParseOptions, ParserKind,
+};
+
+/// Prefix of the environment variables of the settings
+pub const ENV_PREFIX: &str = "TRANSACTIONS_";
+
+/// Settings of a run of the engine
+///
+/// Settings are layered: the environment, a config file and command line
+/// flags each give a Settings, which are combined with `merge`. Settings not
+/// given by any layer keep the engine's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Options of the CSV reader
+    pub input: InputSettings,
+    /// Policies of the ClientStore
+    pub store: StoreSettings,
+    /// Limits on withdrawals
+    pub limits: LimitSettings,
+    /// Limits and clients of the account tiers
+    pub tiers: TierSettings,
+    /// Restrictions on unverified clients and the verified clients
+    pub verification: VerificationSettings,
+    /// Format and options of the output
+    pub output: OutputSettings,
+    /// Paths the state is loaded from and saved to
+    pub state: StateSettings,
+    /// Reporting of the run
+    pub log: LogSettings,
+    /// Webhooks called on critical account events
+    pub webhooks: WebhookSettings,
+}
+
+/// Options of the CSV reader
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputSettings {
+    /// Delimiter between fields, an ASCII character
+    pub delimiter: Option<char>,
+    /// Read the first row as headers
+    pub has_headers: Option<bool>,
+    /// Trim white space around fields and headers
+    pub trim: Option<bool>,
+    /// Allow rows with a different number of fields
+    pub flexible: Option<bool>,
+    /// Strict RFC 4180 parsing
+    pub strict: Option<bool>,
+    /// Parser used for CSV rows
+    pub parser: Option<ParserKind>,
+    /// Times failed requests of a URL input are retried
+    pub retries: Option<u32>,
+    /// CSV file mapping partners' client ids to internal clients
+    pub client_aliases: Option<PathBuf>,
+    /// Query reading transactions from the database given as input
+    pub sql_query: Option<String>,
+    /// Column the rows of the SQL query are ordered by
+    pub sql_sequence: Option<String>,
+    /// Client the entries of an OFX, QIF or camt.053 statement are applied to
+    pub statement_client: Option<ClientID>,
+}
+
+/// Policies of the ClientStore
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreSettings {
+    /// Handling of transactions reusing an already applied transaction id
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    /// Handling of disputes which would leave a client's available funds negative
+    pub negative_available: Option<NegativeAvailablePolicy>,
+    /// Kind of storage used for clients
+    pub client_storage: Option<ClientStorage>,
+    /// Approximate memory budget in bytes for client histories
+    pub history_memory_budget: Option<usize>,
+    /// Number of the most recent history entries kept per client
+    pub retain_last_entries: Option<usize>,
+    /// Seconds history entries are kept for
+    pub retain_seconds: Option<u64>,
+    /// Skip recording withdrawals in client histories
+    pub skip_withdrawal_history: Option<bool>,
+    /// Seconds after which authorization holds expire
+    pub hold_expiry: Option<u64>,
+    /// Unlock the client when a chargeback is reversed
+    pub reversal_unlocks: Option<bool>,
+    /// Seconds without activity after which a client is dormant
+    pub dormancy_period: Option<u64>,
+    /// Dormant clients are frozen at the end of the run
+    pub freeze_dormant: Option<bool>,
+    /// Day count convention of interest accrual
+    pub day_count: Option<DayCount>,
+    /// Number of times transient failures of the storage of spilled histories are retried
+    pub storage_retries: Option<u32>,
+    /// Milliseconds before the first retry, doubling with every retry
+    pub storage_retry_backoff_ms: Option<u64>,
+}
+
+/// Limits on withdrawals and transaction amounts
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitSettings {
+    /// Limits on every client's withdrawals within rolling windows
+    pub velocity: Option<Vec<VelocityLimit>>,
+    /// Deposits and withdrawals of less than this are rejected
+    pub min_amount: Option<Amount>,
+    /// Deposits and withdrawals of more than this are rejected
+    pub max_amount: Option<Amount>,
+    /// Maximum amount every client may deposit per calendar day
+    pub max_daily_deposit: Option<Amount>,
+    /// Maximum amount every client may withdraw per calendar day
+    pub max_daily_withdrawal: Option<Amount>,
+}
+
+/// Limit on a client's withdrawals within a rolling window of seconds
+///
+/// Either or both of `max_count` and `max_amount` may be given.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityLimit {
+    /// Length of the window in seconds
+    pub window: u64,
+    /// Most withdrawals within the window
+    pub max_count: Option<u64>,
+    /// Most withdrawn within the window
+    pub max_amount: Option<Amount>,
+}
+
+/// Limits and clients of the account tiers
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierSettings {
+    /// Basic tier, the tier of clients which were not assigned one
+    pub basic: TierLimitSettings,
+    /// Premium tier
+    pub premium: TierLimitSettings,
+}
+
+/// Limits and clients of an account tier
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TierLimitSettings {
+    /// Deposits which would raise a client's total funds over this are rejected
+    pub max_balance: Option<Amount>,
+    /// Withdrawals of more than this are rejected
+    pub max_withdrawal: Option<Amount>,
+    /// Clients assigned to the tier
+    pub clients: Option<Vec<ClientID>>,
+}
+
+impl TierLimitSettings {
+    /// Merge the settings of a tier of a higher priority layer into these.
+    fn merge(&mut self, other: TierLimitSettings) {
+        merge(&mut self.max_balance, other.max_balance);
+        merge(&mut self.max_withdrawal, other.max_withdrawal);
+        merge(&mut self.clients, other.clients);
+    }
+
+    /// Limits of the tier, if any are set
+    fn limits(&self) -> Option<TierLimits> {
+        (self.max_balance.is_some() || self.max_withdrawal.is_some()).then_some(TierLimits {
+            max_balance: self.max_balance,
+            max_withdrawal: self.max_withdrawal,
+        })
+    }
+}
+
+/// Restrictions on unverified clients and the verified clients
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationSettings {
+    /// Withdrawals of more than this by unverified clients are rejected
+    pub max_withdrawal: Option<Amount>,
+    /// Deposits to unverified clients are rejected
+    pub block_deposits: Option<bool>,
+    /// Clients which are verified
+    pub clients: Option<Vec<ClientID>>,
+}
+
+/// Format and options of the output
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSettings {
+    /// Name of the output format, e.g. `csv` or `table`
+    pub format: Option<String>,
+    /// Only clients changed by the run are output
+    pub changed_only: Option<bool>,
+    /// Rows get a column marking the clients changed by the run
+    pub mark_changed: Option<bool>,
+    /// Rows get a column with the client's status
+    pub status: Option<bool>,
+    /// Clients are sorted by their client, the default
+    pub sort: Option<bool>,
+    /// Amounts always have exactly four decimal places
+    pub fixed_point: Option<bool>,
+    /// Amounts always have exactly this many decimal places, from 0 to 6
+    pub decimal_places: Option<u32>,
+    /// Rounding mode of amounts, in the output and of accrued interest
+    pub rounding: Option<RoundingMode>,
+    /// Compression of the final state, none by default
+    pub compress: Option<Compression>,
+    /// Number of files the final state is partitioned into, named after the path
+    pub shards: Option<usize>,
+    /// How clients are partitioned into the shards, by range by default
+    pub shard_by: Option<ShardBy>,
+    /// File the final state is written to instead of stdout
+    pub path: Option<PathBuf>,
+    /// URL of a Postgres database the final state is upserted into
+    pub postgres: Option<String>,
+    /// History entries are also upserted into the database
+    pub postgres_history: Option<bool>,
+    /// File holding the Ed25519 secret key the output is signed with, as 64
+    /// hexadecimal digits
+    pub signing_key_file: Option<PathBuf>,
+    /// Ed25519 secret key the output is signed with, as 64 hexadecimal digits
+    pub signing_key: Option<String>,
+    /// How the signature of the output is emitted, a detached file by default
+    pub signature: Option<SignatureMode>,
+    /// Clients are replaced with pseudonyms, keyed randomly per run unless a key is given
+    pub anonymize: Option<bool>,
+    /// File holding the secret key of the pseudonyms, which anonymizes the output
+    pub anonymize_key_file: Option<PathBuf>,
+    /// Secret key of the pseudonyms, which anonymizes the output
+    pub anonymize_key: Option<String>,
+}
+
+/// Paths the state is loaded from and saved to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StateSettings {
+    /// State saved by a previous run, which the run starts from
+    pub load: Option<PathBuf>,
+    /// File the state is saved to after the run
+    pub save: Option<PathBuf>,
+    /// Number of deltas saved on top of a full state before the state is
+    /// saved in full again, only full states are saved by default
+    pub full_every: Option<u32>,
+    /// File holding the key the state and it's deltas are encrypted with,
+    /// as 64 hexadecimal digits
+    pub key_file: Option<PathBuf>,
+    /// Key the state and it's deltas are encrypted with, as 64 hexadecimal
+    /// digits, e.g. from `TRANSACTIONS_STATE_KEY`
+    pub key: Option<String>,
+}
+
+/// Reporting of the run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// File rejected rows are written to, with the reason
+    pub reject_file: Option<PathBuf>,
+    /// File the audit trail of rejected transactions is written to, with their reason code
+    pub audit_file: Option<PathBuf>,
+    /// File the clients dormant at the end of the run are written to
+    pub dormancy_report: Option<PathBuf>,
+    /// Least severe problems logged to stderr
+    pub level: Option<Severity>,
+    /// Least severe problems counted as rejected
+    pub count_level: Option<Severity>,
+    /// Least severe problems failing the run with a non-zero exit
+    pub fail_level: Option<Severity>,
+    /// Print a summary of the run to stderr
+    pub summary: Option<bool>,
+    /// Check the global invariants with a trial balance after the run,
+    /// failing it with a non-zero exit if any is broken
+    pub verify: Option<bool>,
+    /// Print diagnostics with the number of slowest transactions, busiest
+    /// clients and largest histories to stderr
+    pub diagnostics: Option<usize>,
+}
+
+/// Webhooks called on critical account events
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    /// URLs posted a JSON payload when an account gets locked, a chargeback
+    /// is applied or a balance goes negative
+    pub urls: Option<Vec<String>>,
+    /// Times failed calls are retried
+    pub retries: Option<u32>,
+}
+
+impl Settings {
+    /// Parse settings from a TOML config file.
+    ///
+    /// Sections and keys are those of the settings, e.g. `format` in an
+    /// `[output]` section. Unknown keys are an error.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(config: &str) -> Result<Self, TransactionError> {
+        toml::from_str(config).map_err(|err| err.to_string().into())
+    }
+
+    /// Read settings from `TRANSACTIONS_*` environment variables.
+    ///
+    /// See `from_vars` for the names of the variables.
+    pub fn from_env() -> Result<Self, TransactionError> {
+        Self::from_vars(env::vars())
+    }
+
+    /// Read settings from `TRANSACTIONS_*` variables.
+    ///
+    /// Every setting is named by the prefix, it's section and it's key in
+    /// upper case, e.g. `TRANSACTIONS_OUTPUT_FORMAT` or `TRANSACTIONS_STATE_SAVE`.
+    /// Clients of a tier and verified clients are comma separated lists of clients.
+    /// Velocity limits are a comma separated list of `count:<COUNT>/<WINDOW>`
+    /// and `amount:<AMOUNT>/<WINDOW>` limits. Variables without the prefix are
+    /// ignored, and unknown variables with the prefix or invalid values are an error.
+    pub fn from_vars<I>(vars: I) -> Result<Self, TransactionError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut settings = Settings::default();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let value = value.as_str();
+            match key {
+                "INPUT_DELIMITER" => settings.input.delimiter = Some(parse(&name, value)?),
+                "INPUT_HAS_HEADERS" => settings.input.has_headers = Some(parse(&name, value)?),
+                "INPUT_TRIM" => settings.input.trim = Some(parse(&name, value)?),
+                "INPUT_FLEXIBLE" => settings.input.flexible = Some(parse(&name, value)?),
+                "INPUT_STRICT" => settings.input.strict = Some(parse(&name, value)?),
+                "INPUT_PARSER" => settings.input.parser = Some(variant(&name, value)?),
+                "INPUT_RETRIES" => settings.input.retries = Some(parse(&name, value)?),
+                "INPUT_CLIENT_ALIASES" => settings.input.client_aliases = Some(value.into()),
+                "INPUT_SQL_QUERY" => settings.input.sql_query = Some(value.to_owned()),
+                "INPUT_SQL_SEQUENCE" => settings.input.sql_sequence = Some(value.to_owned()),
+                "INPUT_STATEMENT_CLIENT" => {
+                    settings.input.statement_client = Some(parse(&name, value)?)
+                }
+                "STORE_DUPLICATE_POLICY" => {
+                    settings.store.duplicate_policy = Some(variant(&name, value)?)
+                }
+                "STORE_NEGATIVE_AVAILABLE" => {
+                    settings.store.negative_available = Some(variant(&name, value)?)
+                }
+                "STORE_CLIENT_STORAGE" => {
+                    settings.store.client_storage = Some(variant(&name, value)?)
+                }
+                "STORE_HISTORY_MEMORY_BUDGET" => {
+                    settings.store.history_memory_budget = Some(parse(&name, value)?)
+                }
+                "STORE_RETAIN_LAST_ENTRIES" => {
+                    settings.store.retain_last_entries = Some(parse(&name, value)?)
+                }
+                "STORE_RETAIN_SECONDS" => {
+                    settings.store.retain_seconds = Some(parse(&name, value)?)
+                }
+                "STORE_SKIP_WITHDRAWAL_HISTORY" => {
+                    settings.store.skip_withdrawal_history = Some(parse(&name, value)?)
+                }
+                "STORE_HOLD_EXPIRY" => settings.store.hold_expiry = Some(parse(&name, value)?),
+                "STORE_REVERSAL_UNLOCKS" => {
+                    settings.store.reversal_unlocks = Some(parse(&name, value)?)
+                }
+                "STORE_DORMANCY_PERIOD" => {
+                    settings.store.dormancy_period = Some(parse(&name, value)?)
+                }
+                "STORE_FREEZE_DORMANT" => {
+                    settings.store.freeze_dormant = Some(parse(&name, value)?)
+                }
+                "STORE_DAY_COUNT" => settings.store.day_count = Some(variant(&name, value)?),
+                "STORE_STORAGE_RETRIES" => {
+                    settings.store.storage_retries = Some(parse(&name, value)?)
+                }
+                "STORE_STORAGE_RETRY_BACKOFF_MS" => {
+                    settings.store.storage_retry_backoff_ms = Some(parse(&name, value)?)
+                }
+                "LIMITS_VELOCITY" => {
+                    settings.limits.velocity = Some(velocity_limits(&name, value)?)
+                }
+                "LIMITS_MIN_AMOUNT" => settings.limits.min_amount = Some(parse(&name, value)?),
+                "LIMITS_MAX_AMOUNT" => settings.limits.max_amount = Some(parse(&name, value)?),
+                "LIMITS_MAX_DAILY_DEPOSIT" => {
+                    settings.limits.max_daily_deposit = Some(parse(&name, value)?)
+                }
+                "LIMITS_MAX_DAILY_WITHDRAWAL" => {
+                    settings.limits.max_daily_withdrawal = Some(parse(&name, value)?)
+                }
+                "TIERS_BASIC_MAX_BALANCE" => {
+                    settings.tiers.basic.max_balance = Some(parse(&name, value)?)
+                }
+                "TIERS_BASIC_MAX_WITHDRAWAL" => {
+                    settings.tiers.basic.max_withdrawal = Some(parse(&name, value)?)
+                }
+                "TIERS_BASIC_CLIENTS" => settings.tiers.basic.clients = Some(list(&name, value)?),
+                "TIERS_PREMIUM_MAX_BALANCE" => {
+                    settings.tiers.premium.max_balance = Some(parse(&name, value)?)
+                }
+                "TIERS_PREMIUM_MAX_WITHDRAWAL" => {
+                    settings.tiers.premium.max_withdrawal = Some(parse(&name, value)?)
+                }
+                "TIERS_PREMIUM_CLIENTS" => {
+                    settings.tiers.premium.clients = Some(list(&name, value)?)
+                }
+                "VERIFICATION_MAX_WITHDRAWAL" => {
+                    settings.verification.max_withdrawal = Some(parse(&name, value)?)
+                }
+                "VERIFICATION_BLOCK_DEPOSITS" => {
+                    settings.verification.block_deposits = Some(parse(&name, value)?)
+                }
+                "VERIFICATION_CLIENTS" => settings.verification.clients = Some(list(&name, value)?),
+                "OUTPUT_FORMAT" => settings.output.format = Some(value.to_owned()),
+                "OUTPUT_CHANGED_ONLY" => settings.output.changed_only = Some(parse(&name, value)?),
+                "OUTPUT_MARK_CHANGED" => settings.output.mark_changed = Some(parse(&name, value)?),
+                "OUTPUT_STATUS" => settings.output.status = Some(parse(&name, value)?),
+                "OUTPUT_SORT" => settings.output.sort = Some(parse(&name, value)?),
+                "OUTPUT_FIXED_POINT" => settings.output.fixed_point = Some(parse(&name, value)?),
+                "OUTPUT_DECIMAL_PLACES" => {
+                    settings.output.decimal_places = Some(parse(&name, value)?)
+                }
+                "OUTPUT_ROUNDING" => settings.output.rounding = Some(variant(&name, value)?),
+                "OUTPUT_COMPRESS" => settings.output.compress = Some(variant(&name, value)?),
+                "OUTPUT_SHARDS" => settings.output.shards = Some(parse(&name, value)?),
+                "OUTPUT_SHARD_BY" => settings.output.shard_by = Some(variant(&name, value)?),
+                "OUTPUT_PATH" => settings.output.path = Some(value.into()),
+                "OUTPUT_POSTGRES" => settings.output.postgres = Some(value.to_owned()),
+                "OUTPUT_POSTGRES_HISTORY" => {
+                    settings.output.postgres_history = Some(parse(&name, value)?)
+                }
+                "OUTPUT_SIGNING_KEY_FILE" => settings.output.signing_key_file = Some(value.into()),
+                "OUTPUT_SIGNING_KEY" => settings.output.signing_key = Some(value.to_owned()),
+                "OUTPUT_SIGNATURE" => settings.output.signature = Some(variant(&name, value)?),
+                "OUTPUT_ANONYMIZE" => settings.output.anonymize = Some(parse(&name, value)?),
+                "OUTPUT_ANONYMIZE_KEY_FILE" => {
+                    settings.output.anonymize_key_file = Some(value.into())
+                }
+                "OUTPUT_ANONYMIZE_KEY" => settings.output.anonymize_key = Some(value.to_owned()),
+                "STATE_LOAD" => settings.state.load = Some(value.into()),
+                "STATE_SAVE" => settings.state.save = Some(value.into()),
+                "STATE_FULL_EVERY" => settings.state.full_every = Some(parse(&name, value)?),
+                "STATE_KEY_FILE" => settings.state.key_file = Some(value.into()),
+                "STATE_KEY" => settings.state.key = Some(value.to_owned()),
+                "LOG_REJECT_FILE" => settings.log.reject_file = Some(value.into()),
+                "LOG_AUDIT_FILE" => settings.log.audit_file = Some(value.into()),
+                "LOG_DORMANCY_REPORT" => settings.log.dormancy_report = Some(value.into()),
+                "LOG_LEVEL" => settings.log.level = Some(variant(&name, value)?),
+                "LOG_COUNT_LEVEL" => settings.log.count_level = Some(variant(&name, value)?),
+                "LOG_FAIL_LEVEL" => settings.log.fail_level = Some(variant(&name, value)?),
+                "LOG_SUMMARY" => settings.log.summary = Some(parse(&name, value)?),
+                "LOG_VERIFY" => settings.log.verify = Some(parse(&name, value)?),
+                "LOG_DIAGNOSTICS" => settings.log.diagnostics = Some(parse(&name, value)?),
+                "WEBHOOKS_URLS" => settings.webhooks.urls = Some(list(&name, value)?),
+                "WEBHOOKS_RETRIES" => settings.webhooks.retries = Some(parse(&name, value)?),
+                _ => return Err(format!("Unknown setting {}", name).into()),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Merge settings of a higher priority layer into these.
+    ///
+    /// Every setting given by the other layer replaces the setting here.
+    pub fn merge(mut self, other: Settings) -> Self {
+        let input = &mut self.input;
+        merge(&mut input.delimiter, other.input.delimiter);
+        merge(&mut input.has_headers, other.input.has_headers);
+        merge(&mut input.trim, other.input.trim);
+        merge(&mut input.flexible, other.input.flexible);
+        merge(&mut input.strict, other.input.strict);
+        merge(&mut input.parser, other.input.parser);
+        merge(&mut input.retries, other.input.retries);
+        merge(&mut input.client_aliases, other.input.client_aliases);
+        merge(&mut input.sql_query, other.input.sql_query);
+        merge(&mut input.sql_sequence, other.input.sql_sequence);
+        merge(&mut input.statement_client, other.input.statement_client);
+
+        let store = &mut self.store;
+        merge(&mut store.duplicate_policy, other.store.duplicate_policy);
+        merge(
+            &mut store.negative_available,
+            other.store.negative_available,
+        );
+        merge(&mut store.client_storage, other.store.client_storage);
+        merge(
+            &mut store.history_memory_budget,
+            other.store.history_memory_budget,
+        );
+        merge(
+            &mut store.retain_last_entries,
+            other.store.retain_last_entries,
+        );
+        merge(&mut store.retain_seconds, other.store.retain_seconds);
+        merge(
+            &mut store.skip_withdrawal_history,
+            other.store.skip_withdrawal_history,
+        );
+        merge(&mut store.hold_expiry, other.store.hold_expiry);
+        merge(&mut store.reversal_unlocks, other.store.reversal_unlocks);
+        merge(&mut store.dormancy_period, other.store.dormancy_period);
+        merge(&mut store.freeze_dormant, other.store.freeze_dormant);
+        merge(&mut store.day_count, other.store.day_count);
+        merge(&mut store.storage_retries, other.store.storage_retries);
+        merge(
+            &mut store.storage_retry_backoff_ms,
+            other.store.storage_retry_backoff_ms,
+        );
+
+        merge(&mut self.limits.velocity, other.limits.velocity);
+        merge(&mut self.limits.min_amount, other.limits.min_amount);
+        merge(&mut self.limits.max_amount, other.limits.max_amount);
+        merge(
+            &mut self.limits.max_daily_deposit,
+            other.limits.max_daily_deposit,
+        );
+        merge(
+            &mut self.limits.max_daily_withdrawal,
+            other.limits.max_daily_withdrawal,
+        );
+
+        self.tiers.basic.merge(other.tiers.basic);
+        self.tiers.premium.merge(other.tiers.premium);
+
+        let verification = &mut self.verification;
+        merge(
+            &mut verification.max_withdrawal,
+            other.verification.max_withdrawal,
+        );
+        merge(
+            &mut verification.block_deposits,
+            other.verification.block_deposits,
+        );
+        merge(&mut verification.clients, other.verification.clients);
+
+        let output = &mut self.output;
+        merge(&mut output.format, other.output.format);
+        merge(&mut output.changed_only, other.output.changed_only);
+        merge(&mut output.mark_changed, other.output.mark_changed);
+        merge(&mut output.status, other.output.status);
+        merge(&mut output.sort, other.output.sort);
+        merge(&mut output.fixed_point, other.output.fixed_point);
+        merge(&mut output.decimal_places, other.output.decimal_places);
+        merge(&mut output.rounding, other.output.rounding);
+        merge(&mut output.compress, other.output.compress);
+        merge(&mut output.shards, other.output.shards);
+        merge(&mut output.shard_by, other.output.shard_by);
+        merge(&mut output.path, other.output.path);
+        merge(&mut output.postgres, other.output.postgres);
+        merge(&mut output.postgres_history, other.output.postgres_history);
+        merge(&mut output.signing_key_file, other.output.signing_key_file);
+        merge(&mut output.signing_key, other.output.signing_key);
+        merge(&mut output.signature, other.output.signature);
+        merge(&mut output.anonymize, other.output.anonymize);
+        merge(
+            &mut output.anonymize_key_file,
+            other.output.anonymize_key_file,
+        );
+        merge(&mut output.anonymize_key, other.output.anonymize_key);
+
+        merge(&mut self.state.load, other.state.load);
+        merge(&mut self.state.save, other.state.save);
+        merge(&mut self.state.full_every, other.state.full_every);
+        merge(&mut self.state.key_file, other.state.key_file);
+        merge(&mut self.state.key, other.state.key);
+
+        merge(&mut self.log.reject_file, other.log.reject_file);
+        merge(&mut self.log.audit_file, other.log.audit_file);
+        merge(&mut self.log.dormancy_report, other.log.dormancy_report);
+        merge(&mut self.log.level, other.log.level);
+        merge(&mut self.log.count_level, other.log.count_level);
+        merge(&mut self.log.fail_level, other.log.fail_level);
+        merge(&mut self.log.summary, other.log.summary);
+        merge(&mut self.log.verify, other.log.verify);
+        merge(&mut self.log.diagnostics, other.log.diagnostics);
+        merge(&mut self.webhooks.urls, other.webhooks.urls);
+        merge(&mut self.webhooks.retries, other.webhooks.retries);
+        self
+    }
+
+    /// Options for parsing the input
+    ///
+    /// Returns an error if the delimiter is not an ASCII character, or the
+    /// client aliases can not be read.
+    pub fn parse_options(&self) -> Result<ParseOptions, TransactionError> {
+        let defaults = CsvOptions::default();
+        let delimiter = match self.input.delimiter {
+            Some(delimiter) if delimiter.is_ascii() => delimiter as u8,
+            Some(delimiter) => {
+                return Err(format!("Delimiter {:?} is not an ASCII character", delimiter).into())
+            }
+            None => defaults.delimiter,
+        };
+        let client_aliases = match &self.input.client_aliases {
+            Some(path) => ClientAliases::from_csv(File::open(path).map_err(|err| {
+                format!("Couldn't open client aliases {}: {}", path.display(), err)
+            })?)?,
+            None => ClientAliases::new(),
+        };
+        Ok(ParseOptions {
+            csv: CsvOptions::new()
+                .delimiter(delimiter)
+                .has_headers(self.input.has_headers.unwrap_or(defaults.has_headers))
+                .trim(self.input.trim.unwrap_or(defaults.trim))
+                .flexible(self.input.flexible.unwrap_or(defaults.flexible)),
+            parser: self.input.parser.unwrap_or_default(),
+            strict: self.input.strict.unwrap_or_default(),
+            log_level: self.log.level.unwrap_or_default(),
+            count_level: self.log.count_level.unwrap_or_default(),
+            client_aliases,
+            ..Default::default()
+        })
+    }
+
+    /// Configuration of the ClientStore
+    pub fn store_config(&self) -> StoreConfig {
+        let store = &self.store;
+        let velocity_rules = self
+            .limits
+            .velocity
+            .iter()
+            .flatten()
+            .flat_map(|limit| {
+                let count = limit.max_count.map(|count| VelocityRule::MaxCount {
+                    count,
+                    window: limit.window,
+                });
+                let amount = limit.max_amount.map(|amount| VelocityRule::MaxAmount {
+                    amount,
+                    window: limit.window,
+                });
+                count.into_iter().chain(amount)
+            })
+            .collect();
+        StoreConfig {
+            duplicate_policy: store.duplicate_policy.unwrap_or_default(),
+            history_memory_budget: store.history_memory_budget,
+            history_retention: HistoryRetention {
+                last_entries: store.retain_last_entries,
+                max_age: store.retain_seconds,
+            },
+            skip_withdrawal_history: store.skip_withdrawal_history.unwrap_or_default(),
+            client_storage: store.client_storage.unwrap_or_default(),
+            negative_available: store.negative_available.unwrap_or_default(),
+            velocity_rules,
+            amount_limits: AmountLimits {
+                min: self.limits.min_amount,
+                max: self.limits.max_amount,
+            },
+            daily_limits: DailyLimits {
+                max_deposited: self.limits.max_daily_deposit,
+                max_withdrawn: self.limits.max_daily_withdrawal,
+            },
+            day_count: store.day_count.unwrap_or_default(),
+            rounding: self.output.rounding.unwrap_or_default(),
+            hold_expiry: store.hold_expiry,
+            reversal_unlocks: store.reversal_unlocks.unwrap_or_default(),
+            dormancy: DormancyPolicy {
+                period: store.dormancy_period,
+                freeze: store.freeze_dormant.unwrap_or_default(),
+            },
+            tier_limits: [
+                (Tier::Basic, self.tiers.basic.limits()),
+                (Tier::Premium, self.tiers.premium.limits()),
+            ]
+            .into_iter()
+            .filter_map(|(tier, limits)| Some((tier, limits?)))
+            .collect(),
+            verification: VerificationPolicy {
+                max_withdrawal: self.verification.max_withdrawal,
+                block_deposits: self.verification.block_deposits.unwrap_or_default(),
+            },
+            storage_retry: {
+                let defaults = RetryPolicy::default();
+                RetryPolicy {
+                    retries: store.storage_retries.unwrap_or(defaults.retries),
+                    initial_backoff: store
+                        .storage_retry_backoff_ms
+                        .map_or(defaults.initial_backoff, Duration::from_millis),
+                    ..defaults
+                }
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Clients assigned to a tier, to be set with `ClientStore::set_tier`
+    pub fn tier_assignments(&self) -> Vec<(ClientID, Tier)> {
+        let tiers = [
+            (&self.tiers.basic, Tier::Basic),
+            (&self.tiers.premium, Tier::Premium),
+        ];
+        tiers
+            .into_iter()
+            .flat_map(|(settings, tier)| {
+                settings
+                    .clients
+                    .iter()
+                    .flatten()
+                    .map(move |client| (*client, tier))
+            })
+            .collect()
+    }
+
+    /// Clients which are verified, to be set with `ClientStore::set_verified`
+    pub fn verified_clients(&self) -> &[ClientID] {
+        self.verification.clients.as_deref().unwrap_or_default()
+    }
+
+    /// Options of the output
+    ///
+    /// Returns an error if the number of decimal places is over `MAX_OUTPUT_DECIMAL_PLACES`,
+    /// or if the key of an anonymized output can't be read. Without a key, every
+    /// call anonymizes with a new random key.
+    pub fn output_options(&self) -> Result<OutputOptions, TransactionError> {
+        if let Some(places) = self
+            .output
+            .decimal_places
+            .filter(|places| *places > MAX_OUTPUT_DECIMAL_PLACES)
+        {
+            return Err(format!(
+                "Output can have at most {} decimal places, not {}",
+                MAX_OUTPUT_DECIMAL_PLACES, places
+            )
+            .into());
+        }
+        #[cfg(not(feature = "anonymize"))]
+        self.pseudonymizer()?;
+        Ok(OutputOptions {
+            sort: self.output.sort.unwrap_or(true),
+            fixed_point: self.output.fixed_point.unwrap_or_default(),
+            decimal_places: self.output.decimal_places,
+            rounding: self.output.rounding.unwrap_or_default(),
+            changed_only: self.output.changed_only.unwrap_or_default(),
+            mark_changed: self.output.mark_changed.unwrap_or_default(),
+            status: self.output.status.unwrap_or_default(),
+            #[cfg(feature = "anonymize")]
+            pseudonymizer: self.pseudonymizer()?,
+        })
+    }
+
+    /// Pseudonymizer of the output, if it is anonymized
+    #[cfg(feature = "anonymize")]
+    fn pseudonymizer(&self) -> Result<Option<Pseudonymizer>, TransactionError> {
+        let output = &self.output;
+        Ok(match (&output.anonymize_key, &output.anonymize_key_file) {
+            (Some(key), _) => Some(Pseudonymizer::new(key)),
+            (None, Some(path)) => Some(Pseudonymizer::from_file(path)?),
+            (None, None) if output.anonymize.unwrap_or_default() => Some(Pseudonymizer::random()),
+            (None, None) => None,
+        })
+    }
+
+    /// Pseudonymizer of the output, which needs the `anonymize` feature
+    #[cfg(not(feature = "anonymize"))]
+    fn pseudonymizer(&self) -> Result<Option<std::convert::Infallible>, TransactionError> {
+        let output = &self.output;
+        if output.anonymize.unwrap_or_default()
+            || output.anonymize_key.is_some()
+            || output.anonymize_key_file.is_some()
+        {
+            return Err("Anonymizing the output needs the anonymize feature".into());
+        }
+        Ok(None)
+    }
+}
+
+/// Replace a setting with the one of a higher priority layer, if given.
+fn merge<T>(setting: &mut Option<T>, other: Option<T>) {
+    if other.is_some() {
+        *setting = other;
+    }
+}
+
+/// Parse the value of a variable.
+fn parse<T>(name: &str, value: &str) -> Result<T, TransactionError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("Invalid {} {:?}: {}", name, value, err).into())
+}
+
+/// Parse the comma separated values of a variable.
+fn list<T>(name: &str, value: &str) -> Result<Vec<T>, TransactionError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .split(',')
+        .map(|item| parse(name, item.trim()))
+        .collect()
+}
+
+/// Parse the value of a variable naming a variant, e.g. `idempotent`.
+fn variant<T>(name: &str, value: &str) -> Result<T, TransactionError>
+where
+    T: DeserializeOwned,
+{
+    let deserializer: StrDeserializer<serde::de::value::Error> = value.into_deserializer();
+    T::deserialize(deserializer)
+        .map_err(|err| format!("Invalid {} {:?}: {}", name, value, err).into())
+}
+
+/// Parse a comma separated list of velocity limits, e.g. `count:5/86400,amount:1000/86400`.
+fn velocity_limits(name: &str, value: &str) -> Result<Vec<VelocityLimit>, TransactionError> {
+    value
+        .split(',')
+        .map(|limit| {
+            let invalid = || format!("Invalid {} limit {:?}", name, limit);
+            let (kind, rest) = limit.trim().split_once(':').ok_or_else(invalid)?;
+            let (max, window) = rest.split_once('/').ok_or_else(invalid)?;
+            let mut limit = VelocityLimit {
+                window: window.parse().map_err(|_| invalid())?,
+                ..Default::default()
+            };
+            match kind {
+                "count" => limit.max_count = Some(max.parse().map_err(|_| invalid())?),
+                "amount" => limit.max_amount = Some(max.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid().into()),
+            }
+            Ok(limit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_layers_win() {
+        let file = Settings {
+            output: OutputSettings {
+                format: Some("table".to_owned()),
+                sort: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let flags = Settings {
+            output: OutputSettings {
+                format: Some("csv".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let merged = file.merge(flags);
+        assert_eq!(merged.output.format.as_deref(), Some("csv"));
+        assert_eq!(merged.output.sort, Some(true));
+        assert!(merged.output_options().unwrap().sort);
+        assert!(Settings::default().output_options().unwrap().sort);
+    }
+
+    #[test]
+    fn non_ascii_delimiter_errors() {
+        let mut settings = Settings::default();
+        settings.input.delimiter = Some('|');
+        assert_eq!(settings.parse_options().unwrap().csv.delimiter, b'|');
+        settings.input.delimiter = Some('¦');
+        assert!(settings.parse_options().is_err());
+    }
+
+    #[test]
+    fn too_many_decimal_places_errors() {
+        let mut settings = Settings::default();
+        settings.output.decimal_places = Some(6);
+        assert!(settings.output_options().is_ok());
+        settings.output.decimal_places = Some(7);
+        assert!(settings.output_options().is_err());
+    }
+
+    #[test]
+    fn anonymized_output_keyed() {
+        let mut settings = Settings::default();
+        settings.output.anonymize_key = Some("secret".to_owned());
+        #[cfg(feature = "anonymize")]
+        assert_eq!(
+            settings.output_options().unwrap().pseudonymizer,
+            Some(Pseudonymizer::new("secret"))
+        );
+        #[cfg(not(feature = "anonymize"))]
+        assert!(settings.output_options().is_err());
+        let vars = [("TRANSACTIONS_OUTPUT_ANONYMIZE", "true")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        assert_eq!(
+            Settings::from_vars(vars).unwrap().output.anonymize,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn environment_variables_parsed() {
+        let vars = [
+            ("PATH", "/usr/bin"),
+            ("TRANSACTIONS_OUTPUT_FORMAT", "tsv"),
+            ("TRANSACTIONS_OUTPUT_STATUS", "true"),
+            ("TRANSACTIONS_OUTPUT_DECIMAL_PLACES", "2"),
+            ("TRANSACTIONS_OUTPUT_ROUNDING", "half_even"),
+            ("TRANSACTIONS_OUTPUT_COMPRESS", "none"),
+            ("TRANSACTIONS_OUTPUT_SHARDS", "16"),
+            ("TRANSACTIONS_OUTPUT_SHARD_BY", "hash"),
+            ("TRANSACTIONS_OUTPUT_SIGNATURE", "trailer"),
+            (
+                "TRANSACTIONS_WEBHOOKS_URLS",
+                "https://alerts.example/locked, https://risk.example/hook",
+            ),
+            ("TRANSACTIONS_INPUT_STRICT", "true"),
+            ("TRANSACTIONS_STATE_LOAD", "state/yesterday.bin"),
+            ("TRANSACTIONS_STATE_KEY_FILE", "/etc/transactions/state.key"),
+            ("TRANSACTIONS_STORE_DUPLICATE_POLICY", "reject"),
+            (
+                "TRANSACTIONS_LIMITS_VELOCITY",
+                "count:5/86400, amount:250.5/3600",
+            ),
+            ("TRANSACTIONS_TIERS_PREMIUM_CLIENTS", "3, 4"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let settings = Settings::from_vars(vars).unwrap();
+        assert_eq!(settings.output.format.as_deref(), Some("tsv"));
+        let options = settings.output_options().unwrap();
+        assert!(options.status);
+        assert_eq!(options.decimal_places, Some(2));
+        assert_eq!(options.rounding, RoundingMode::HalfEven);
+        assert_eq!(settings.store_config().rounding, RoundingMode::HalfEven);
+        assert_eq!(settings.output.compress, Some(Compression::None));
+        assert_eq!(settings.output.shards, Some(16));
+        assert_eq!(settings.output.shard_by, Some(ShardBy::Hash));
+        assert_eq!(settings.output.signature, Some(SignatureMode::Trailer));
+        assert_eq!(
+            settings.webhooks.urls,
+            Some(vec![
+                "https://alerts.example/locked".to_owned(),
+                "https://risk.example/hook".to_owned()
+            ])
+        );
+        assert_eq!(settings.input.strict, Some(true));
+        assert_eq!(
+            settings.state.load,
+            Some(PathBuf::from("state/yesterday.bin"))
+        );
+        assert_eq!(
+            settings.state.key_file,
+            Some(PathBuf::from("/etc/transactions/state.key"))
+        );
+        assert_eq!(
+            settings.tier_assignments(),
+            vec![(3, Tier::Premium), (4, Tier::Premium)]
+        );
+        let store = settings.store_config();
+        assert_eq!(store.duplicate_policy, DuplicatePolicy::Reject);
+        assert_eq!(
+            store.velocity_rules,
+            vec![
+                VelocityRule::MaxCount {
+                    count: 5,
+                    window: 86_400
+                },
+                VelocityRule::MaxAmount {
+                    amount: 250.5,
+                    window: 3600
+                },
+            ]
+        );
+
+        for (name, value) in [
+            ("TRANSACTIONS_OUTPUT_FROMAT", "csv"),
+            ("TRANSACTIONS_INPUT_STRICT", "yes"),
+            ("TRANSACTIONS_STORE_DAY_COUNT", "actual366"),
+            ("TRANSACTIONS_LIMITS_VELOCITY", "count:5"),
+        ] {
+            let vars = [(name.to_owned(), value.to_owned())];
+            assert!(Settings::from_vars(vars).is_err(), "{}", name);
+        }
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn config_file_parsed() {
+        let config = r#"
+            [input]
+            delimiter = ";"
+            strict = true
+            parser = "byte_record"
+
+            [store]
+            duplicate_policy = "idempotent"
+            negative_available = "flag"
+            day_count = "actual360"
+            hold_expiry = 604800
+            dormancy_period = 31536000
+            freeze_dormant = true
+
+            [limits]
+            min_amount = 0.0001
+            max_daily_withdrawal = 5000.0
+
+            [[limits.velocity]]
+            window = 86400
+            max_count = 5
+            max_amount = 1000.0
+
+            [tiers.basic]
+            max_balance = 1000.0
+
+            [tiers.premium]
+            clients = [7, 9]
+
+            [verification]
+            max_withdrawal = 0.0
+            clients = [7]
+
+            [output]
+            format = "table"
+            changed_only = true
+            mark_changed = true
+
+            [state]
+            save = "state/today.bin"
+            full_every = 24
+
+            [log]
+            summary = false
+        "#;
+        let settings = Settings::from_toml(config).unwrap();
+        let options = settings.parse_options().unwrap();
+        assert_eq!(options.csv.delimiter, b';');
+        assert!(options.strict);
+        assert_eq!(options.parser, ParserKind::ByteRecord);
+        let store = settings.store_config();
+        assert_eq!(store.duplicate_policy, DuplicatePolicy::Idempotent);
+        assert_eq!(store.negative_available, NegativeAvailablePolicy::Flag);
+        assert_eq!(store.day_count, DayCount::Actual360);
+        assert_eq!(store.hold_expiry, Some(604_800));
+        assert_eq!(
+            store.dormancy,
+            DormancyPolicy {
+                period: Some(31_536_000),
+                freeze: true
+            }
+        );
+        assert_eq!(
+            store.velocity_rules,
+            vec![
+                VelocityRule::MaxCount {
+                    count: 5,
+                    window: 86_400
+                },
+                VelocityRule::MaxAmount {
+                    amount: 1000.0,
+                    window: 86_400
+                },
+            ]
+        );
+        assert_eq!(
+            store.tier_limits.get(&Tier::Basic).unwrap().max_balance,
+            Some(1000.0)
+        );
+        assert!(!store.tier_limits.contains_key(&Tier::Premium));
+        assert_eq!(
+            settings.tier_assignments(),
+            vec![(7, Tier::Premium), (9, Tier::Premium)]
+        );
+        assert_eq!(store.verification.max_withdrawal, Some(0.0));
+        assert_eq!(
+            store.amount_limits,
+            AmountLimits {
+                min: Some(0.0001),
+                max: None
+            }
+        );
+        assert_eq!(store.daily_limits.max_withdrawn, Some(5000.0));
+        assert_eq!(settings.verified_clients(), &[7]);
+        assert!(settings.output_options().unwrap().changed_only);
+        assert!(settings.output_options().unwrap().mark_changed);
+        assert_eq!(settings.state.save, Some(PathBuf::from("state/today.bin")));
+        assert_eq!(settings.state.full_every, Some(24));
+        assert_eq!(settings.log.summary, Some(false));
+
+        assert!(Settings::from_toml("[output]\nfromat = \"csv\"").is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::error::TransactionError;
+
+/// Start of the trailer line holding the signature of a report
+pub const TRAILER_PREFIX: &str = "# ed25519 ";
+
+/// Key signing final state reports with Ed25519
+#[derive(Clone)]
+pub struct ReportSigner {
+    key: SigningKey,
+}
+
+impl ReportSigner {
+    /// Create a new ReportSigner from a secret key of 64 hexadecimal digits,
+    /// ignoring surrounding whitespace
+    pub fn from_hex(hex: &str) -> Result<Self, TransactionError> {
+        let secret = from_hex::<32>(hex).ok_or("Signing key must be 64 hexadecimal digits")?;
+        Ok(Self {
+            key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    /// Read the secret key from a file holding 64 hexadecimal digits
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, TransactionError> {
+        Self::from_hex(&fs::read_to_string(path)?)
+    }
+
+    /// Get the public key verifying the signatures, as 64 hexadecimal digits
+    pub fn public_key(&self) -> String {
+        to_hex(self.key.verifying_key().as_bytes())
+    }
+
+    /// Sign the report, returning the signature as 128 hexadecimal digits
+    pub fn sign(&self, report: &[u8]) -> String {
+        to_hex(&self.key.sign(report).to_bytes())
+    }
+}
+
+/// Keys are not printed, so they do not leak into logs
+impl Debug for ReportSigner {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "ReportSigner({})", self.public_key())
+    }
+}
+
+/// Verify the signature of a report with the public key of it's signer.
+///
+/// Keys and signatures are hexadecimal, as returned by the ReportSigner.
+pub fn verify(public_key: &str, report: &[u8], signature: &str) -> Result<(), TransactionError> {
+    let public_key = from_hex::<32>(public_key)
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or("Public key must be 64 hexadecimal digits")?;
+    let signature = from_hex::<64>(signature).ok_or("Signature must be 128 hexadecimal digits")?;
+    public_key
+        .verify_strict(report, &Signature::from_bytes(&signature))
+        .map_err(|_| "Signature does not match the report".into())
+}
+
+/// Split a report signed with a trailer line into the report and it's signature.
+///
+/// Returns None if the report does not end with a trailer line.
+pub fn split_trailer(signed: &[u8]) -> Option<(&[u8], &str)> {
+    let body = signed.strip_suffix(b"\n").unwrap_or(signed);
+    let start = body
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let trailer = std::str::from_utf8(&body[start..]).ok()?;
+    let signature = trailer.strip_prefix(TRAILER_PREFIX)?;
+    Some((&signed[..start], signature))
+}
+
+/// Writer keeping a copy of everything written to it, to sign it once complete
+///
+/// Ed25519 signs the whole report at once, so the report is kept in memory
+/// until it is signed.
+pub struct SigningWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    report: Vec<u8>,
+}
+
+impl<W> SigningWriter<W>
+where
+    W: Write,
+{
+    /// Create a new SigningWriter
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            report: Vec::new(),
+        }
+    }
+
+    /// Sign everything written, returning the underlying writer and the signature.
+    pub fn finish(self, signer: &ReportSigner) -> (W, String) {
+        let signature = signer.sign(&self.report);
+        (self.writer, signature)
+    }
+
+    /// Sign everything written and append the signature as a trailer line,
+    /// returning the underlying writer.
+    ///
+    /// The signature covers everything before the trailer line, including the
+    /// line break ending a report which did not end with one.
+    pub fn finish_with_trailer(mut self, signer: &ReportSigner) -> Result<W, TransactionError> {
+        if !self.report.is_empty() && !self.report.ends_with(b"\n") {
+            self.write_all(b"\n")?;
+        }
+        let (mut writer, signature) = self.finish(signer);
+        writeln!(writer, "{}{}", TRAILER_PREFIX, signature)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W> Write for SigningWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.report.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Parse N bytes from 2N hexadecimal digits, ignoring surrounding whitespace
+fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim();
+    if hex.len() != 2 * N || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+
+    #[test]
+    fn reports_signed_and_verified() {
+        let signer = ReportSigner::from_hex(SECRET).unwrap();
+        // Test vector 1 of RFC 8032
+        assert_eq!(
+            signer.public_key(),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let report = b"client,available,held,total,locked\n1,1.5,0,1.5,false\n";
+        let signature = signer.sign(report);
+        verify(&signer.public_key(), report, &signature).unwrap();
+        assert!(verify(&signer.public_key(), b"client\n", &signature).is_err());
+        assert!(ReportSigner::from_hex("00").is_err());
+        assert!(!format!("{:?}", signer).contains(SECRET));
+    }
+
+    #[test]
+    fn trailer_signs_the_report_before_it() {
+        let signer = ReportSigner::from_hex(SECRET).unwrap();
+        let mut writer = SigningWriter::new(Vec::new());
+        writer.write_all(b"client,available\n1,1.5").unwrap();
+        let signed = writer.finish_with_trailer(&signer).unwrap();
+
+        let (report, signature) = split_trailer(&signed).unwrap();
+        assert_eq!(report, b"client,available\n1,1.5\n");
+        verify(&signer.public_key(), report, signature).unwrap();
+        assert!(verify(&signer.public_key(), b"client,available\n", signature).is_err());
+        assert_eq!(split_trailer(b"client,available\n"), None);
+    }
+}
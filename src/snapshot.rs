@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::{client::Client, observer::Balances, ClientID, IdMap};
+
+/// Immutable balances of all clients at a point in time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceSnapshot {
+    /// Number of transactions executed by the store when the snapshot was taken
+    pub executed: u64,
+    /// Balances of every client
+    pub balances: IdMap<ClientID, Balances>,
+}
+
+impl BalanceSnapshot {
+    /// Take a snapshot of the clients.
+    pub fn of<'c>(executed: u64, clients: impl Iterator<Item = &'c Client>) -> Self {
+        Self {
+            executed,
+            balances: clients
+                .map(|client| (client.id, Balances::of(client)))
+                .collect(),
+        }
+    }
+
+    /// Get the balances of the client, None if the client did not exist yet
+    pub fn get(&self, client: ClientID) -> Option<&Balances> {
+        self.balances.get(&client)
+    }
+}
+
+/// Reader of the latest BalanceSnapshot of a ClientStore
+///
+/// Readers can be cloned and sent to other threads, e.g. the handlers of
+/// query endpoints. A new snapshot replaces the previous one with an atomic
+/// pointer swap, so loading a snapshot takes no lock and is not blocked by
+/// a snapshot being published. A snapshot stays valid for as long as it is
+/// held, also after newer snapshots were published.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotReader(Arc<ArcSwap<BalanceSnapshot>>);
+
+impl SnapshotReader {
+    /// Get the latest snapshot
+    pub fn load(&self) -> Arc<BalanceSnapshot> {
+        self.0.load_full()
+    }
+
+    /// Replace the latest snapshot.
+    fn store(&self, snapshot: BalanceSnapshot) {
+        self.0.store(Arc::new(snapshot));
+    }
+}
+
+/// Snapshots of a ClientStore refreshed every number of transactions
+#[derive(Debug)]
+pub(crate) struct Snapshots {
+    /// Number of transactions between snapshots
+    every: u64,
+    /// Number of transactions executed
+    executed: u64,
+    reader: SnapshotReader,
+}
+
+impl Snapshots {
+    /// Create new Snapshots refreshed every number of transactions
+    pub(crate) fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            executed: 0,
+            reader: SnapshotReader::default(),
+        }
+    }
+
+    /// Get a new reader of the snapshots
+    pub(crate) fn reader(&self) -> SnapshotReader {
+        self.reader.clone()
+    }
+
+    /// Count an executed transaction, returns true if a new snapshot is due.
+    pub(crate) fn tick(&mut self) -> bool {
+        self.executed += 1;
+        self.executed.is_multiple_of(self.every)
+    }
+
+    /// Publish a snapshot of the clients to the readers.
+    ///
+    /// The snapshot is built from every client, so a publish costs time and
+    /// memory linear in the number of clients. It runs on the thread executing
+    /// transactions, which pays about clients / every per transaction.
+    pub(crate) fn publish<'c>(&self, clients: impl Iterator<Item = &'c Client>) {
+        self.reader
+            .store(BalanceSnapshot::of(self.executed, clients));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_keep_their_snapshot() {
+        let mut snapshots = Snapshots::new(2);
+        let reader = snapshots.reader();
+        assert!(reader.load().balances.is_empty());
+
+        let mut client = Client::new(1);
+        client.available = 1.0;
+        assert!(!snapshots.tick());
+        assert!(snapshots.tick());
+        snapshots.publish([&client].into_iter());
+        let first = reader.load();
+
+        client.available = 2.0;
+        snapshots.publish([&client].into_iter());
+        assert_eq!(first.executed, 2);
+        assert_eq!(first.get(1).map(|balances| balances.available), Some(1.0));
+        assert_eq!(
+            reader.load().get(1).map(|balances| balances.available),
+            Some(2.0)
+        );
+        assert!(reader.load().get(2).is_none());
+    }
+}
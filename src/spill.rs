@@ -0,0 +1,418 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    mem::size_of,
+};
+
+#[cfg(feature = "encryption")]
+use crate::encryption::{RecordCipher, TAG_SIZE};
+use crate::{
+    client::{Client, DisputeState, History},
+    error::TransactionError,
+    storage::Clients,
+    transaction::TransactionKind,
+    ClientID, IdMap, TransactionID,
+};
+
+/// Size of a Client ID on disk.
+const CLIENT_ID_SIZE: usize = size_of::<ClientID>();
+
+/// Size of a History record on disk.
+///
+/// Client ID + amount (8 bytes) + flags (1 byte) + timestamp (8 bytes)
+const RECORD_SIZE: usize = CLIENT_ID_SIZE + 17;
+
+/// Size of a History record in the file, with the tag of it's encryption
+#[cfg(feature = "encryption")]
+const STORED_SIZE: usize = RECORD_SIZE + TAG_SIZE;
+
+/// Size of a History record in the file
+#[cfg(not(feature = "encryption"))]
+const STORED_SIZE: usize = RECORD_SIZE;
+
+/// Bits of the flags holding the dispute state
+pub(crate) const STATE_MASK: u8 = 7;
+
+/// Flag of a History record with a timestamp
+pub(crate) const TIMESTAMP_FLAG: u8 = 8;
+
+/// Offset of the bits of the flags holding the kind of the transaction
+const KIND_SHIFT: u8 = 4;
+
+/// Kinds of transactions with a history entry by their value in the flags
+///
+/// Custom kinds are stored as unknown.
+const KINDS: [Option<TransactionKind>; 4] = [
+    None,
+    Some(TransactionKind::Deposit),
+    Some(TransactionKind::Interest),
+    Some(TransactionKind::Withdrawal),
+];
+
+/// Dispute states by their value in the flags
+pub(crate) const STATES: [DisputeState; 5] = [
+    DisputeState::Undisputed,
+    DisputeState::Disputed,
+    DisputeState::Resolved,
+    DisputeState::ChargedBack,
+    DisputeState::Reversed,
+];
+
+/// Number of dead records in the file before it is compacted
+const MIN_DEAD_RECORDS: u64 = 1024;
+
+/// Estimated size of a History entry held in memory.
+pub(crate) const ENTRY_SIZE: usize = size_of::<TransactionID>() + size_of::<History>();
+
+/// Bounded in-memory layer for client histories.
+///
+/// Keeps at most a budgeted number of History entries in memory, across all
+/// clients. When the budget is exceeded, the least recently used entries are
+/// spilled to a temporary file and restored when a transaction references them.
+/// With the `encryption` feature, spilled entries are encrypted with a random
+/// key of the HistorySpill, which is never stored.
+///
+/// Restored and forgotten entries leave dead records in the file, so it is
+/// rewritten with only the spilled entries once dead records outnumber them.
+pub struct HistorySpill {
+    /// Maximum number of History entries kept in memory
+    capacity: usize,
+    /// Counter used to order entries by their last use
+    tick: u64,
+    /// Last use of every in-memory entry
+    last_used: IdMap<(ClientID, TransactionID), u64>,
+    /// In-memory entries ordered by their last use, oldest first
+    by_age: BTreeMap<u64, (ClientID, TransactionID)>,
+    /// Temporary file holding spilled entries.
+    ///
+    /// Created with the first spill.
+    file: Option<File>,
+    /// Number of records in the file, including dead ones
+    records: u64,
+    /// Offset of every spilled entry in the file
+    index: IdMap<(ClientID, TransactionID), u64>,
+    /// Cipher of the spilled entries
+    #[cfg(feature = "encryption")]
+    cipher: RecordCipher,
+}
+
+impl HistorySpill {
+    /// Create a new HistorySpill with a memory budget in bytes
+    ///
+    /// The memory used by an entry is estimated, so the budget is approximate.
+    /// At least one entry is always kept in memory.
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            capacity: (memory_budget / ENTRY_SIZE).max(1),
+            tick: 0,
+            last_used: IdMap::default(),
+            by_age: BTreeMap::new(),
+            file: None,
+            records: 0,
+            index: IdMap::default(),
+            #[cfg(feature = "encryption")]
+            cipher: RecordCipher::new(),
+        }
+    }
+
+    /// Number of entries currently spilled to disk
+    pub fn spilled(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Iterate over the clients and transaction ids of the spilled entries
+    pub fn spilled_entries(&self) -> impl Iterator<Item = (ClientID, TransactionID)> + '_ {
+        self.index.keys().copied()
+    }
+
+    /// Mark an in-memory entry of the client as most recently used.
+    ///
+    /// Entries not in the client's history are ignored.
+    pub fn touch(&mut self, client: &Client, transaction_id: TransactionID) {
+        if !client.client_history.contains_key(&transaction_id) {
+            return;
+        }
+        let key = (client.id, transaction_id);
+        // The in-memory entry replaces any spilled entry
+        let _ = self.index.remove(&key);
+        self.tick += 1;
+        if let Some(previous) = self.last_used.insert(key, self.tick) {
+            let _ = self.by_age.remove(&previous);
+        }
+        let _ = self.by_age.insert(self.tick, key);
+    }
+
+    /// Forget an entry, whether it is in memory or spilled.
+    ///
+    /// Used when an entry is removed from a client's history.
+    pub fn forget(&mut self, client_id: ClientID, transaction_id: TransactionID) {
+        let key = (client_id, transaction_id);
+        if let Some(previous) = self.last_used.remove(&key) {
+            let _ = self.by_age.remove(&previous);
+        }
+        let _ = self.index.remove(&key);
+    }
+
+    /// Restore a spilled entry into the client's history.
+    ///
+    /// Does nothing if the entry is in memory or was never spilled.
+    pub fn restore(
+        &mut self,
+        client: &mut Client,
+        transaction_id: TransactionID,
+    ) -> Result<(), TransactionError> {
+        if client.client_history.contains_key(&transaction_id) {
+            return Ok(());
+        }
+        if let Some(history) = self.read(client.id, transaction_id)? {
+            let _ = client.client_history.insert(transaction_id, history);
+            self.touch(client, transaction_id);
+        }
+        Ok(())
+    }
+
+    /// Read a spilled entry without restoring it.
+    pub fn read(
+        &self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+    ) -> Result<Option<History>, TransactionError> {
+        let (offset, mut file) = match (self.index.get(&(client_id, transaction_id)), &self.file) {
+            (Some(offset), Some(file)) => (*offset, file),
+            _ => return Ok(None),
+        };
+        let mut record = [0; STORED_SIZE];
+        let _ = file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut record)?;
+        #[cfg(feature = "encryption")]
+        {
+            let (record, tag) = record.split_at_mut(RECORD_SIZE);
+            self.cipher.open(offset, record, tag)?;
+        }
+        let mut amount = [0; 8];
+        amount.copy_from_slice(&record[CLIENT_ID_SIZE..CLIENT_ID_SIZE + 8]);
+        let flags = record[CLIENT_ID_SIZE + 8];
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&record[CLIENT_ID_SIZE + 9..RECORD_SIZE]);
+        Ok(Some(History {
+            amount: f64::from_le_bytes(amount),
+            state: STATES
+                .get((flags & STATE_MASK) as usize)
+                .copied()
+                .ok_or("Corrupted spilled history record")?,
+            timestamp: (flags & TIMESTAMP_FLAG != 0).then(|| u64::from_le_bytes(timestamp)),
+            kind: flags_kind(flags),
+        }))
+    }
+
+    /// Spill least recently used entries until there is room for more entries.
+    pub fn make_room(
+        &mut self,
+        clients: &mut Clients,
+        entries: usize,
+    ) -> Result<(), TransactionError> {
+        while self.last_used.len() + entries > self.capacity {
+            let (_tick, (client_id, transaction_id)) = match self.by_age.pop_first() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            let _ = self.last_used.remove(&(client_id, transaction_id));
+            let history = match clients
+                .get_mut(&client_id)
+                .and_then(|client| client.client_history.remove(&transaction_id))
+            {
+                Some(history) => history,
+                None => continue,
+            };
+            if let Err(err) = self.write(client_id, transaction_id, &history) {
+                // Keep the entry in memory rather than losing it
+                if let Some(client) = clients.get_mut(&client_id) {
+                    let _ = client.client_history.insert(transaction_id, history);
+                    self.touch(client, transaction_id);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a spilled entry to the file, compacting it first if needed.
+    fn write(
+        &mut self,
+        client_id: ClientID,
+        transaction_id: TransactionID,
+        history: &History,
+    ) -> Result<(), TransactionError> {
+        self.compact()?;
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(tempfile::tempfile()?),
+        };
+        let offset = append(
+            file,
+            #[cfg(feature = "encryption")]
+            &self.cipher,
+            client_id,
+            history,
+        )?;
+        self.records += 1;
+        let _ = self.index.insert((client_id, transaction_id), offset);
+        Ok(())
+    }
+
+    /// Rewrite the file with only the spilled entries if dead records outnumber them.
+    fn compact(&mut self) -> Result<(), TransactionError> {
+        let live = self.index.len() as u64;
+        let dead = self.records - live;
+        if dead < MIN_DEAD_RECORDS || dead <= live {
+            return Ok(());
+        }
+        let mut file = tempfile::tempfile()?;
+        // Offsets of the old file are reused, so records are sealed with a new key
+        #[cfg(feature = "encryption")]
+        let cipher = RecordCipher::new();
+        let mut index = IdMap::default();
+        for &(client_id, transaction_id) in self.index.keys() {
+            if let Some(history) = self.read(client_id, transaction_id)? {
+                let offset = append(
+                    &mut file,
+                    #[cfg(feature = "encryption")]
+                    &cipher,
+                    client_id,
+                    &history,
+                )?;
+                let _ = index.insert((client_id, transaction_id), offset);
+            }
+        }
+        self.file = Some(file);
+        self.records = index.len() as u64;
+        self.index = index;
+        #[cfg(feature = "encryption")]
+        {
+            self.cipher = cipher;
+        }
+        Ok(())
+    }
+}
+
+/// Append a History record to the file, returning it's offset.
+fn append(
+    file: &mut File,
+    #[cfg(feature = "encryption")] cipher: &RecordCipher,
+    client_id: ClientID,
+    history: &History,
+) -> Result<u64, TransactionError> {
+    let mut record = [0; STORED_SIZE];
+    record[..CLIENT_ID_SIZE].copy_from_slice(&client_id.to_le_bytes());
+    record[CLIENT_ID_SIZE..CLIENT_ID_SIZE + 8].copy_from_slice(&history.amount.to_le_bytes());
+    record[CLIENT_ID_SIZE + 8] = history_flags(history);
+    if let Some(timestamp) = history.timestamp {
+        record[CLIENT_ID_SIZE + 9..RECORD_SIZE].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    let offset = file.seek(SeekFrom::End(0))?;
+    #[cfg(feature = "encryption")]
+    {
+        let tag = cipher.seal(offset, &mut record[..RECORD_SIZE])?;
+        record[RECORD_SIZE..].copy_from_slice(&tag);
+    }
+    file.write_all(&record)?;
+    Ok(offset)
+}
+
+/// Flags of a History record holding it's dispute state, kind and if it has a timestamp
+pub(crate) fn history_flags(history: &History) -> u8 {
+    let mut flags = STATES
+        .iter()
+        .position(|state| *state == history.state)
+        .unwrap_or_default() as u8;
+    if history.timestamp.is_some() {
+        flags |= TIMESTAMP_FLAG;
+    }
+    let kind = KINDS
+        .iter()
+        .position(|kind| *kind == history.kind)
+        .unwrap_or_default() as u8;
+    flags | kind << KIND_SHIFT
+}
+
+/// Kind of the transaction of a History record from it's flags
+pub(crate) fn flags_kind(flags: u8) -> Option<TransactionKind> {
+    KINDS.get((flags >> KIND_SHIFT) as usize).copied().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill_and_restore_least_recently_used() {
+        let mut clients = Clients::default();
+        let mut client = Client::new(157);
+        let mut spill = HistorySpill::new(2 * ENTRY_SIZE);
+        for transaction_id in 1..=2 {
+            let _ = client
+                .client_history
+                .insert(transaction_id, History::new(transaction_id as f64));
+            spill.touch(&client, transaction_id);
+        }
+        let _ = clients.insert(157, client);
+
+        spill.make_room(&mut clients, 1).unwrap();
+        let client = clients.get_mut(&157).unwrap();
+        assert_eq!(spill.spilled(), 1);
+        assert_eq!(client.client_history.get(&1), None);
+        assert_eq!(client.client_history.get(&2), Some(&History::new(2.0)));
+
+        spill.restore(client, 1).unwrap();
+        assert_eq!(spill.spilled(), 0);
+        assert_eq!(client.client_history.get(&1), Some(&History::new(1.0)));
+    }
+
+    #[test]
+    fn spill_file_compacted() {
+        let mut clients = Clients::default();
+        let mut client = Client::new(157);
+        let mut spill = HistorySpill::new(ENTRY_SIZE);
+        for transaction_id in 1..=2 {
+            let _ = client
+                .client_history
+                .insert(transaction_id, History::new(transaction_id as f64));
+            spill.touch(&client, transaction_id);
+        }
+        let _ = clients.insert(157, client);
+        spill.make_room(&mut clients, 0).unwrap();
+
+        // Every restore leaves a dead record of the entry
+        for transaction_id in (1..=2).cycle().take(3 * MIN_DEAD_RECORDS as usize) {
+            spill
+                .restore(clients.get_mut(&157).unwrap(), transaction_id)
+                .unwrap();
+            spill.make_room(&mut clients, 0).unwrap();
+        }
+        assert_eq!(spill.spilled(), 1);
+        assert!(spill.records <= MIN_DEAD_RECORDS + 1);
+        let length = spill.file.as_ref().unwrap().metadata().unwrap().len();
+        assert_eq!(length, spill.records * STORED_SIZE as u64);
+        assert_eq!(spill.read(157, 1).unwrap(), Some(History::new(1.0)));
+    }
+
+    #[test]
+    fn spilled_entry_keeps_timestamp() {
+        let mut clients = Clients::default();
+        let mut client = Client::new(157);
+        let mut spill = HistorySpill::new(ENTRY_SIZE);
+        let history = History {
+            amount: 1.5,
+            state: DisputeState::ChargedBack,
+            timestamp: Some(1_700_000_000),
+            kind: Some(TransactionKind::Withdrawal),
+        };
+        let _ = client.client_history.insert(1, history.clone());
+        spill.touch(&client, 1);
+        let _ = clients.insert(157, client);
+
+        spill.make_room(&mut clients, 1).unwrap();
+        assert_eq!(spill.read(157, 1).unwrap(), Some(history));
+    }
+}
@@ -0,0 +1,394 @@
+use sqlx::{
+    any::{install_default_drivers, AnyRow},
+    AnyConnection, Connection, PgConnection, Postgres, QueryBuilder, Row,
+};
+
+use crate::{
+    client::ClientStore,
+    error::TransactionError,
+    ingest::apply,
+    report::{PhaseTimer, ProcessingReport},
+    transaction::TransactionRegistry,
+    Amount, CsvLine, CsvLineType, ParseOptions, TransactionID,
+};
+
+/// Query reading transactions from a Postgres or MySQL database
+///
+/// The query selects the `t_type`, `client`, `tx` and `amount` columns and
+/// the sequence column, an integer increasing with every transaction, e.g.
+/// `SELECT id, kind AS t_type, client, tx, amount::text AS amount FROM ledger`.
+/// Rows are fetched in batches ordered by the sequence, so transactions are
+/// applied in the order they were recorded without holding the whole result.
+/// Amounts may be floats or text, `NUMERIC` columns are cast to text to
+/// keep their exact value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlSource {
+    /// URL of the database, e.g. `postgres://user@host/ledger` or `mysql://user@host/ledger`
+    url: String,
+    /// Query selecting the transactions
+    query: String,
+    /// Column the rows are ordered by
+    sequence: String,
+    /// Number of rows fetched at once
+    batch_size: u32,
+}
+
+impl SqlSource {
+    /// Create a new SqlSource reading the query ordered by the sequence column
+    pub fn new(url: &str, query: &str, sequence: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            query: query.to_owned(),
+            sequence: sequence.to_owned(),
+            batch_size: 10_000,
+        }
+    }
+
+    /// Number of rows fetched at once, 10,000 by default
+    pub fn batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Get the query of the batch following the sequence, if any.
+    fn batch(&self, after: Option<i64>) -> String {
+        let filter = match after {
+            Some(after) => format!(" WHERE {} > {}", self.sequence, after),
+            None => String::new(),
+        };
+        format!(
+            "SELECT * FROM ({}) AS source{} ORDER BY {} LIMIT {}",
+            self.query, filter, self.sequence, self.batch_size
+        )
+    }
+}
+
+/// Check if the URL is a database URL supported by `SqlSource`.
+pub fn is_database_url(url: &str) -> bool {
+    ["postgres://", "postgresql://", "mysql://", "mariadb://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// Handle transactions read by the SQL query and execute them on the appropriate client.
+///
+/// Rows which can not be decoded or whose transaction is rejected are
+/// handled like rejected CSV rows, see `handle_transactions_with_parse_options`.
+/// Returns an error if the database can not be connected to or the query fails,
+/// transactions of the batches read before are kept applied.
+pub fn handle_transactions_from_sql(
+    source: &SqlSource,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+) -> Result<ProcessingReport, TransactionError> {
+    install_default_drivers();
+    runtime()?.block_on(async {
+        let mut connection = AnyConnection::connect(&source.url).await?;
+        let mut report = ProcessingReport::default();
+        let mut timer = PhaseTimer::start();
+        let mut after = None;
+        loop {
+            // Every batch has a different query, so it is not prepared
+            let rows = sqlx::query(&source.batch(after))
+                .persistent(false)
+                .fetch_all(&mut connection)
+                .await?;
+            // Fetching rows is counted as reading them
+            report.timings.parse += timer.lap();
+            for row in &rows {
+                let current = line(row, options, &mut report);
+                let _ = apply(current, registry, options, store, &mut report);
+            }
+            report.timings.execute += timer.lap();
+            match rows.last() {
+                Some(row) if rows.len() as u64 >= u64::from(source.batch_size) => {
+                    after = Some(row.try_get::<i64, _>(source.sequence.as_str())?)
+                }
+                _ => break,
+            }
+        }
+        connection.close().await?;
+        report.timings.elapsed = timer.elapsed();
+        Ok(report)
+    })
+}
+
+/// Exporter upserting the final state into Postgres tables
+///
+/// Every client is upserted into the clients table by it's client, and with
+/// a history table every history entry by it's client and tx, so a reporting
+/// database is updated directly by the batch job. Rows are written in
+/// batches, each in a transaction of it's own. Missing tables are created:
+///
+/// ```sql
+/// CREATE TABLE clients (client BIGINT PRIMARY KEY, available DOUBLE PRECISION,
+///     held DOUBLE PRECISION, total DOUBLE PRECISION, locked BOOLEAN, status TEXT);
+/// CREATE TABLE history (client BIGINT, tx BIGINT, amount DOUBLE PRECISION, kind TEXT,
+///     state TEXT, timestamp BIGINT, PRIMARY KEY (client, tx));
+/// ```
+///
+/// Table names are part of the SQL, so they must come from trusted configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgExporter {
+    /// URL of the database, e.g. `postgres://user@host/reporting`
+    url: String,
+    /// Table clients are upserted into
+    clients_table: String,
+    /// Table history entries are upserted into, if exported
+    history_table: Option<String>,
+    /// Number of rows written in a transaction
+    batch_size: usize,
+}
+
+/// Row of the clients table
+type ClientRow = (i64, Amount, Amount, Amount, bool, &'static str);
+
+/// Row of the history table
+type HistoryRow = (
+    i64,
+    i64,
+    Amount,
+    Option<&'static str>,
+    &'static str,
+    Option<i64>,
+);
+
+impl PgExporter {
+    /// Create a new PgExporter upserting clients into the `clients` table
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            clients_table: "clients".to_owned(),
+            history_table: None,
+            batch_size: 1_000,
+        }
+    }
+
+    /// Table clients are upserted into, `clients` by default
+    pub fn clients_table(mut self, table: &str) -> Self {
+        self.clients_table = table.to_owned();
+        self
+    }
+
+    /// Also upsert every history entry into the table
+    pub fn history_table(mut self, table: &str) -> Self {
+        self.history_table = Some(table.to_owned());
+        self
+    }
+
+    /// Number of rows written in a transaction, 1,000 by default
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Upsert the clients, and history entries if enabled, of the store.
+    ///
+    /// Returns an error if the database can not be connected to or a batch
+    /// fails, batches written before are kept.
+    pub fn export(&self, store: &ClientStore) -> Result<(), TransactionError> {
+        let mut clients = Vec::with_capacity(store.clients.len());
+        for client in store.clients.values() {
+            clients.push((
+                bigint(client.id)?,
+                client.available,
+                client.held,
+                client.total(),
+                client.locked,
+                client.status(),
+            ));
+        }
+        clients.sort_unstable_by_key(|row: &ClientRow| row.0);
+        let mut history: Vec<HistoryRow> = Vec::new();
+        if self.history_table.is_some() {
+            store.for_each_history(|client_id, tx, entry| {
+                history.push((
+                    bigint(client_id)?,
+                    tx.into(),
+                    entry.amount,
+                    entry.kind.map(|kind| kind.name()),
+                    entry.state.name(),
+                    entry.timestamp.map(bigint).transpose()?,
+                ));
+                Ok(())
+            })?;
+        }
+
+        runtime()?.block_on(async {
+            let mut connection = PgConnection::connect(&self.url).await?;
+            let _ = sqlx::raw_sql(&self.create_tables())
+                .execute(&mut connection)
+                .await?;
+            for batch in clients.chunks(self.batch_size) {
+                let mut query = QueryBuilder::<Postgres>::new(format!(
+                    "INSERT INTO {} (client, available, held, total, locked, status) ",
+                    self.clients_table
+                ));
+                let _ = query
+                    .push_values(batch, |mut row, client| {
+                        let _ = row
+                            .push_bind(client.0)
+                            .push_bind(client.1)
+                            .push_bind(client.2)
+                            .push_bind(client.3)
+                            .push_bind(client.4)
+                            .push_bind(client.5);
+                    })
+                    .push(
+                        " ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, \
+                         held = EXCLUDED.held, total = EXCLUDED.total, \
+                         locked = EXCLUDED.locked, status = EXCLUDED.status",
+                    );
+                let mut transaction = connection.begin().await?;
+                let _ = query.build().execute(&mut *transaction).await?;
+                transaction.commit().await?;
+            }
+            if let Some(table) = &self.history_table {
+                for batch in history.chunks(self.batch_size) {
+                    let mut query = QueryBuilder::<Postgres>::new(format!(
+                        "INSERT INTO {} (client, tx, amount, kind, state, timestamp) ",
+                        table
+                    ));
+                    let _ = query
+                        .push_values(batch, |mut row, entry| {
+                            let _ = row
+                                .push_bind(entry.0)
+                                .push_bind(entry.1)
+                                .push_bind(entry.2)
+                                .push_bind(entry.3)
+                                .push_bind(entry.4)
+                                .push_bind(entry.5);
+                        })
+                        .push(
+                            " ON CONFLICT (client, tx) DO UPDATE SET amount = EXCLUDED.amount, \
+                             kind = EXCLUDED.kind, state = EXCLUDED.state, \
+                             timestamp = EXCLUDED.timestamp",
+                        );
+                    let mut transaction = connection.begin().await?;
+                    let _ = query.build().execute(&mut *transaction).await?;
+                    transaction.commit().await?;
+                }
+            }
+            connection.close().await?;
+            Ok(())
+        })
+    }
+
+    /// Get the statements creating the tables if they are missing.
+    fn create_tables(&self) -> String {
+        let mut sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (client BIGINT PRIMARY KEY, \
+             available DOUBLE PRECISION, held DOUBLE PRECISION, total DOUBLE PRECISION, \
+             locked BOOLEAN, status TEXT);",
+            self.clients_table
+        );
+        if let Some(table) = &self.history_table {
+            sql.push_str(&format!(
+                " CREATE TABLE IF NOT EXISTS {} (client BIGINT, tx BIGINT, \
+                 amount DOUBLE PRECISION, kind TEXT, state TEXT, timestamp BIGINT, \
+                 PRIMARY KEY (client, tx));",
+                table
+            ));
+        }
+        sql
+    }
+}
+
+/// Create the runtime database calls are driven by.
+fn runtime() -> Result<tokio::runtime::Runtime, TransactionError> {
+    Ok(tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?)
+}
+
+/// Convert a client or time to a Postgres `BIGINT`.
+fn bigint<T>(value: T) -> Result<i64, TransactionError>
+where
+    T: Into<u128>,
+{
+    let value = value.into();
+    i64::try_from(value).map_err(|_| format!("{} is too large for a BIGINT", value).into())
+}
+
+/// Decode a row of the query.
+///
+/// Clients may be integers or text, which is resolved by the client aliases,
+/// amounts floats or text parsed by the amount format.
+fn line(
+    row: &AnyRow,
+    options: &ParseOptions,
+    report: &mut ProcessingReport,
+) -> Result<CsvLine, TransactionError> {
+    let t_type: String = row.try_get("t_type")?;
+    let client = match row.try_get::<i64, _>("client") {
+        Ok(client) => client.to_string(),
+        Err(_) => row.try_get("client")?,
+    };
+    let tx: i64 = row.try_get("tx")?;
+    let amount = match row.try_get::<Option<f64>, _>("amount") {
+        Ok(amount) => amount.map(|amount| amount.to_string()),
+        Err(_) => row.try_get("amount")?,
+    };
+    let amount = match amount.as_deref() {
+        None | Some("") => 0.0,
+        Some(amount) => {
+            let (amount, rounded) = options.amount_format.parse_rounded(amount)?;
+            report.rounded_amounts += u64::from(rounded);
+            amount
+        }
+    };
+    Ok(CsvLine {
+        t_type: CsvLineType::new(&t_type),
+        client: options.client_aliases.resolve(&client)?,
+        tx: TransactionID::try_from(tx).map_err(|err| format!("Invalid tx {}: {}", tx, err))?,
+        amount,
+        reference: None,
+        idempotency_key: None,
+        timestamp: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_ordered_by_sequence() {
+        let source = SqlSource::new(
+            "postgres://engine@localhost/ledger",
+            "SELECT id, kind AS t_type, client, tx, amount FROM ledger",
+            "id",
+        )
+        .batch_size(500);
+        assert_eq!(
+            source.batch(None),
+            "SELECT * FROM (SELECT id, kind AS t_type, client, tx, amount FROM ledger) AS source \
+             ORDER BY id LIMIT 500"
+        );
+        assert_eq!(
+            source.batch(Some(1500)),
+            "SELECT * FROM (SELECT id, kind AS t_type, client, tx, amount FROM ledger) AS source \
+             WHERE id > 1500 ORDER BY id LIMIT 500"
+        );
+        assert!(is_database_url(&source.url));
+        assert!(!is_database_url("https://example.com/ledger.csv"));
+    }
+
+    #[test]
+    fn export_tables_created_if_missing() {
+        let exporter = PgExporter::new("postgres://engine@localhost/reporting")
+            .clients_table("balances")
+            .history_table("entries");
+        let sql = exporter.create_tables();
+        assert!(sql.starts_with("CREATE TABLE IF NOT EXISTS balances (client BIGINT PRIMARY KEY,"));
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS entries (client BIGINT, tx BIGINT,"));
+        assert!(!PgExporter::new(&exporter.url)
+            .create_tables()
+            .contains("history"));
+        assert_eq!(
+            bigint(u64::MAX).unwrap_err().to_string(),
+            format!("{} is too large for a BIGINT", u64::MAX)
+        );
+    }
+}
@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    mem::size_of,
+};
+
+use crate::{
+    client::{Client, DailyTotals, History, HistorySummary, Tier},
+    error::{ErrorKind, TransactionError},
+    idempotency::{IdempotencyKeys, Outcome},
+    ledger::TransactionLedger,
+    reference::ReferenceMap,
+    spill::{flags_kind, history_flags, STATES, STATE_MASK, TIMESTAMP_FLAG},
+    trial::Flows,
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Start of every state snapshot
+const MAGIC: &[u8; 4] = b"TXST";
+
+/// Start of every state delta
+const DELTA_MAGIC: &[u8; 4] = b"TXDL";
+
+/// Version of the snapshot layout
+///
+/// Snapshots are only loaded by the version which saved them.
+const VERSION: u8 = 12;
+
+/// Offset basis of the FNV-1a digest of a snapshot
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Prime of the FNV-1a digest of a snapshot
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Add the bytes to a FNV-1a digest.
+fn digest(mut digest: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        digest ^= u64::from(*byte);
+        digest = digest.wrapping_mul(FNV_PRIME);
+    }
+    digest
+}
+
+/// Writer of a binary state snapshot
+///
+/// Values are written little endian. History entries use the same flags as
+/// spilled history records, so the dispute state, kind and timestamp are kept.
+/// A digest of the written bytes identifies the snapshot, so deltas can name
+/// the snapshot they apply to.
+pub(crate) struct StateWriter<W> {
+    writer: W,
+    digest: u64,
+}
+
+impl<W> StateWriter<W>
+where
+    W: Write,
+{
+    /// Create a new StateWriter, writing the snapshot header.
+    pub(crate) fn new(writer: W) -> Result<Self, TransactionError> {
+        let mut state = Self {
+            writer,
+            digest: FNV_OFFSET,
+        };
+        state.write(MAGIC)?;
+        state.write(&[VERSION, size_of::<ClientID>() as u8])?;
+        Ok(state)
+    }
+
+    /// Create a new StateWriter of a delta, writing the delta header.
+    ///
+    /// The header names the digest of the snapshot the delta applies to and
+    /// the sequence number of the delta on top of it.
+    pub(crate) fn delta(writer: W, base: u64, sequence: u32) -> Result<Self, TransactionError> {
+        let mut state = Self {
+            writer,
+            digest: FNV_OFFSET,
+        };
+        state.write(DELTA_MAGIC)?;
+        state.write(&[VERSION, size_of::<ClientID>() as u8])?;
+        state.u64(base)?;
+        state.u32(sequence)?;
+        Ok(state)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), TransactionError> {
+        self.digest = digest(self.digest, bytes);
+        Ok(self.writer.write_all(bytes)?)
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> Result<(), TransactionError> {
+        self.write(&[value])
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> Result<(), TransactionError> {
+        self.write(&value.to_le_bytes())
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> Result<(), TransactionError> {
+        self.write(&value.to_le_bytes())
+    }
+
+    pub(crate) fn amount(&mut self, value: Amount) -> Result<(), TransactionError> {
+        self.write(&value.to_le_bytes())
+    }
+
+    pub(crate) fn client_id(&mut self, value: ClientID) -> Result<(), TransactionError> {
+        self.write(&value.to_le_bytes())
+    }
+
+    /// Write a length prefixed string.
+    pub(crate) fn str(&mut self, value: &str) -> Result<(), TransactionError> {
+        self.u64(value.len() as u64)?;
+        self.write(value.as_bytes())
+    }
+
+    /// Write a client with it's history and holds.
+    ///
+    /// Spilled entries of the client's history are written after the in-memory entries.
+    pub(crate) fn client(
+        &mut self,
+        client: &Client,
+        spilled: &[(TransactionID, History)],
+    ) -> Result<(), TransactionError> {
+        self.client_fields(client)?;
+        self.u64((client.client_history.len() + spilled.len()) as u64)?;
+        let spilled = spilled.iter().map(|(tx, history)| (tx, history));
+        for (transaction_id, history) in client.client_history.iter().chain(spilled) {
+            self.u32(*transaction_id)?;
+            self.history(history)?;
+        }
+        self.holds(client)
+    }
+
+    /// Write the fields of a client, without it's history and holds.
+    pub(crate) fn client_fields(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.client_id(client.id)?;
+        self.amount(client.available)?;
+        self.amount(client.held)?;
+        self.u8(client.locked as u8)?;
+        self.u8(client.tier as u8)?;
+        self.u8(client.verified as u8)?;
+        self.u8(client.frozen as u8)?;
+        self.u64(client.daily.day)?;
+        self.amount(client.daily.deposited)?;
+        self.amount(client.daily.withdrawn)?;
+        self.u8(client.last_activity.is_some() as u8)?;
+        self.u64(client.last_activity.unwrap_or_default())?;
+        self.u64(client.compacted_history.entries)?;
+        self.amount(client.compacted_history.amount)
+    }
+
+    /// Write the open holds of a client.
+    pub(crate) fn holds(&mut self, client: &Client) -> Result<(), TransactionError> {
+        self.u64(client.holds.len() as u64)?;
+        for (transaction_id, amount) in &client.holds {
+            self.u32(*transaction_id)?;
+            self.amount(*amount)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn history(&mut self, history: &History) -> Result<(), TransactionError> {
+        self.amount(history.amount)?;
+        self.u8(history_flags(history))?;
+        self.u64(history.timestamp.unwrap_or_default())
+    }
+
+    /// Write the references with their ids.
+    pub(crate) fn references(&mut self, references: &ReferenceMap) -> Result<(), TransactionError> {
+        self.u32(references.next_id())?;
+        self.u64(references.len() as u64)?;
+        for (reference, id) in references.iter() {
+            self.str(reference)?;
+            self.u32(id)?;
+        }
+        Ok(())
+    }
+
+    /// Write the idempotency keys with their outcomes.
+    pub(crate) fn idempotency_keys(
+        &mut self,
+        keys: &IdempotencyKeys,
+    ) -> Result<(), TransactionError> {
+        self.u64(keys.len() as u64)?;
+        for (key, outcome) in keys.iter() {
+            self.str(key)?;
+            match outcome {
+                Outcome::Applied => self.u8(0)?,
+                Outcome::Rejected(kind, message) => {
+                    self.u8(1)?;
+                    self.u32(kind.number().into())?;
+                    self.str(message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the ranges of the ledger.
+    pub(crate) fn ledger(&mut self, ledger: &TransactionLedger) -> Result<(), TransactionError> {
+        self.u64(ledger.range_count() as u64)?;
+        for (first, last) in ledger.ranges() {
+            self.u32(first)?;
+            self.u32(last)?;
+        }
+        Ok(())
+    }
+
+    /// Write the flows of funds.
+    pub(crate) fn flows(&mut self, flows: &Flows) -> Result<(), TransactionError> {
+        self.amount(flows.net)?;
+        self.amount(flows.deposits)?;
+        self.amount(flows.withdrawals)?;
+        self.amount(flows.chargebacks)?;
+        self.amount(flows.other)
+    }
+
+    /// Flush the snapshot to the underlying writer.
+    ///
+    /// Returns the digest of the written snapshot.
+    pub(crate) fn finish(mut self) -> Result<u64, TransactionError> {
+        self.writer.flush()?;
+        Ok(self.digest)
+    }
+}
+
+/// Reader of a binary state snapshot written by a StateWriter
+pub(crate) struct StateReader<R> {
+    reader: R,
+    digest: u64,
+}
+
+impl<R> StateReader<R>
+where
+    R: Read,
+{
+    /// Create a new StateReader, checking the snapshot header.
+    ///
+    /// Returns an error if the input is not a snapshot of this version
+    /// and client id width.
+    pub(crate) fn new(reader: R) -> Result<Self, TransactionError> {
+        Self::open(reader, MAGIC, "snapshot")
+    }
+
+    /// Create a new StateReader of a delta, checking the delta header.
+    ///
+    /// Returns the reader with the digest of the snapshot the delta applies
+    /// to and the sequence number of the delta.
+    pub(crate) fn delta(reader: R) -> Result<(Self, u64, u32), TransactionError> {
+        let mut state = Self::open(reader, DELTA_MAGIC, "delta")?;
+        let base = state.u64()?;
+        let sequence = state.u32()?;
+        Ok((state, base, sequence))
+    }
+
+    fn open(reader: R, magic: &[u8; 4], name: &str) -> Result<Self, TransactionError> {
+        let mut state = Self {
+            reader,
+            digest: FNV_OFFSET,
+        };
+        let header: [u8; 6] = state.bytes()?;
+        if &header[..4] != magic {
+            return Err(format!("Not a state {}", name).into());
+        }
+        if header[4] != VERSION || header[5] as usize != size_of::<ClientID>() {
+            return Err(format!(
+                "Unsupported state {} version {} with {} byte client ids",
+                name, header[4], header[5]
+            )
+            .into());
+        }
+        Ok(state)
+    }
+
+    /// Get the digest of the bytes read so far
+    ///
+    /// Once a whole snapshot is read, this is the digest returned by the
+    /// StateWriter which wrote it.
+    pub(crate) fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], TransactionError> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        self.digest = digest(self.digest, &bytes);
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, TransactionError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, TransactionError> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, TransactionError> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn amount(&mut self) -> Result<Amount, TransactionError> {
+        self.bytes().map(Amount::from_le_bytes)
+    }
+
+    pub(crate) fn client_id(&mut self) -> Result<ClientID, TransactionError> {
+        self.bytes().map(ClientID::from_le_bytes)
+    }
+
+    /// Read a length prefixed string.
+    pub(crate) fn str(&mut self) -> Result<String, TransactionError> {
+        let len = self.u64()?;
+        let mut bytes = Vec::new();
+        let read = (&mut self.reader).take(len).read_to_end(&mut bytes)?;
+        if read as u64 != len {
+            return Err("Truncated state snapshot".into());
+        }
+        self.digest = digest(self.digest, &bytes);
+        String::from_utf8(bytes).map_err(|_| "Corrupted state snapshot string".into())
+    }
+
+    /// Read a client with it's history and holds.
+    pub(crate) fn client(&mut self) -> Result<Client, TransactionError> {
+        let mut client = self.client_fields()?;
+        for _ in 0..self.u64()? {
+            let transaction_id = self.u32()?;
+            let history = self.history()?;
+            let _ = client.client_history.insert(transaction_id, history);
+        }
+        self.holds(&mut client)?;
+        Ok(client)
+    }
+
+    /// Read the fields of a client, without it's history and holds.
+    pub(crate) fn client_fields(&mut self) -> Result<Client, TransactionError> {
+        let mut client = Client::new(self.client_id()?);
+        client.available = self.amount()?;
+        client.held = self.amount()?;
+        client.locked = self.u8()? != 0;
+        client.tier = match self.u8()? {
+            0 => Tier::Basic,
+            1 => Tier::Premium,
+            _ => return Err("Corrupted state snapshot tier".into()),
+        };
+        client.verified = self.u8()? != 0;
+        client.frozen = self.u8()? != 0;
+        client.daily = DailyTotals {
+            day: self.u64()?,
+            deposited: self.amount()?,
+            withdrawn: self.amount()?,
+        };
+        let active = self.u8()? != 0;
+        let last_activity = self.u64()?;
+        client.last_activity = active.then_some(last_activity);
+        client.compacted_history = HistorySummary {
+            entries: self.u64()?,
+            amount: self.amount()?,
+        };
+        Ok(client)
+    }
+
+    /// Read the open holds of a client, replacing it's holds.
+    pub(crate) fn holds(&mut self, client: &mut Client) -> Result<(), TransactionError> {
+        client.holds.clear();
+        for _ in 0..self.u64()? {
+            let transaction_id = self.u32()?;
+            let amount = self.amount()?;
+            let _ = client.holds.insert(transaction_id, amount);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn history(&mut self) -> Result<History, TransactionError> {
+        let amount = self.amount()?;
+        let flags = self.u8()?;
+        let timestamp: Timestamp = self.u64()?;
+        Ok(History {
+            amount,
+            state: STATES
+                .get((flags & STATE_MASK) as usize)
+                .copied()
+                .ok_or("Corrupted state snapshot history")?,
+            timestamp: (flags & TIMESTAMP_FLAG != 0).then_some(timestamp),
+            kind: flags_kind(flags),
+        })
+    }
+
+    /// Read the references with their ids.
+    pub(crate) fn references(&mut self) -> Result<ReferenceMap, TransactionError> {
+        let next = self.u32()?;
+        let mut ids = HashMap::new();
+        for _ in 0..self.u64()? {
+            let reference = self.str()?;
+            let _ = ids.insert(reference, self.u32()?);
+        }
+        Ok(ReferenceMap::from_parts(ids, next))
+    }
+
+    /// Read the idempotency keys with their outcomes.
+    pub(crate) fn idempotency_keys(&mut self) -> Result<IdempotencyKeys, TransactionError> {
+        let mut outcomes = HashMap::new();
+        for _ in 0..self.u64()? {
+            let key = self.str()?;
+            let outcome = match self.u8()? {
+                0 => Outcome::Applied,
+                1 => {
+                    let kind = u16::try_from(self.u32()?)
+                        .ok()
+                        .and_then(ErrorKind::from_number)
+                        .ok_or("Corrupted state snapshot error kind")?;
+                    Outcome::Rejected(kind, self.str()?)
+                }
+                _ => return Err("Corrupted state snapshot outcome".into()),
+            };
+            let _ = outcomes.insert(key, outcome);
+        }
+        Ok(IdempotencyKeys::from_parts(outcomes))
+    }
+
+    /// Read the ranges of the ledger.
+    pub(crate) fn ledger(&mut self) -> Result<TransactionLedger, TransactionError> {
+        let mut ranges = BTreeMap::new();
+        let mut next = Some(0);
+        for _ in 0..self.u64()? {
+            let first = self.u32()?;
+            let last = self.u32()?;
+            // Ranges are written in order and never overlap
+            if next.is_none_or(|next| first < next) || last < first {
+                return Err("Corrupted state snapshot ledger".into());
+            }
+            next = last.checked_add(1);
+            let _ = ranges.insert(first, last);
+        }
+        Ok(TransactionLedger::from_ranges(ranges))
+    }
+
+    /// Read the flows of funds.
+    pub(crate) fn flows(&mut self) -> Result<Flows, TransactionError> {
+        Ok(Flows {
+            net: self.amount()?,
+            deposits: self.amount()?,
+            withdrawals: self.amount()?,
+            chargebacks: self.amount()?,
+            other: self.amount()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DisputeState;
+    use crate::transaction::TransactionKind;
+
+    #[test]
+    fn client_round_trips() {
+        let mut client = Client::new(157);
+        client.available = 1.5;
+        client.held = 2.0;
+        client.locked = true;
+        client.tier = Tier::Premium;
+        client.verified = true;
+        client.frozen = true;
+        client.daily = DailyTotals {
+            day: 19_737,
+            deposited: 100.0,
+            withdrawn: 25.0,
+        };
+        client.last_activity = Some(1_700_000_000);
+        client.compacted_history.entries = 3;
+        let _ = client.holds.insert(9, 0.5);
+        let _ = client.client_history.insert(1, History::new(2.0));
+        let spilled = History {
+            amount: 4.0,
+            state: DisputeState::Disputed,
+            timestamp: Some(1_700_000_000),
+            kind: Some(TransactionKind::Deposit),
+        };
+
+        let mut snapshot = Vec::new();
+        let mut writer = StateWriter::new(&mut snapshot).unwrap();
+        writer.client(&client, &[(2, spilled.clone())]).unwrap();
+        let mut references = ReferenceMap::new();
+        let _ = references.id("BANK-REF-1");
+        writer.references(&references).unwrap();
+        let digest = writer.finish().unwrap();
+
+        let mut reader = StateReader::new(snapshot.as_slice()).unwrap();
+        let loaded = reader.client().unwrap();
+        assert_eq!((loaded.id, loaded.available, loaded.held), (157, 1.5, 2.0));
+        assert!(loaded.locked);
+        assert_eq!(loaded.tier, Tier::Premium);
+        assert!(loaded.verified);
+        assert!(loaded.frozen);
+        assert_eq!(loaded.daily, client.daily);
+        assert_eq!(loaded.last_activity, Some(1_700_000_000));
+        assert_eq!(loaded.compacted_history.entries, 3);
+        assert_eq!(loaded.holds.get(&9), Some(&0.5));
+        assert_eq!(loaded.client_history.get(&1), Some(&History::new(2.0)));
+        assert_eq!(loaded.client_history.get(&2), Some(&spilled));
+        let mut references = reader.references().unwrap();
+        assert_eq!(references.get("BANK-REF-1"), Some(TransactionID::MAX));
+        assert_eq!(references.id("BANK-REF-2"), TransactionID::MAX - 1);
+        assert_eq!(reader.digest(), digest);
+        reader.u8().unwrap_err();
+    }
+
+    #[test]
+    fn delta_names_its_snapshot() {
+        let mut delta = Vec::new();
+        let mut writer = StateWriter::delta(&mut delta, 0xfeed, 3).unwrap();
+        writer.str("BANK-REF-1").unwrap();
+        let _ = writer.finish().unwrap();
+
+        let (mut reader, base, sequence) = StateReader::delta(delta.as_slice()).unwrap();
+        assert_eq!((base, sequence), (0xfeed, 3));
+        assert_eq!(reader.str().unwrap(), "BANK-REF-1");
+        assert!(StateReader::new(delta.as_slice()).is_err());
+        assert!(StateReader::delta(&MAGIC[..]).is_err());
+    }
+
+    #[test]
+    fn other_input_is_not_a_snapshot() {
+        let input = "t_type,client,tx,amount\n";
+        assert!(StateReader::new(input.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+use std::{collections::HashMap, io::Read, path::Path};
+
+use crate::{
+    amount::AmountFormat,
+    client::ClientStore,
+    config::civil_timestamp,
+    error::TransactionError,
+    ingest::apply,
+    report::{PhaseTimer, ProcessingReport},
+    transaction::TransactionRegistry,
+    Amount, ClientID, CsvLine, CsvLineType, ParseOptions, Reference, Timestamp,
+};
+
+/// Format of a bank statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementFormat {
+    /// Open Financial Exchange, SGML or XML, also used by `.qfx` files
+    Ofx,
+    /// Quicken Interchange Format
+    Qif,
+    /// ISO 20022 camt.053 bank to customer statement XML
+    #[cfg(feature = "camt")]
+    Camt053,
+}
+
+impl StatementFormat {
+    /// Get the format of a statement file by it's extension, `.ofx`, `.qfx` or `.qif`,
+    /// and with the `camt` feature `.xml` or `.camt`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ofx" | "qfx" => Some(Self::Ofx),
+            "qif" => Some(Self::Qif),
+            #[cfg(feature = "camt")]
+            "xml" | "camt" => Some(Self::Camt053),
+            _ => None,
+        }
+    }
+
+    /// Parse the entries of a statement, parsing amounts with the format.
+    ///
+    /// Returns an error if the input is not a statement of the format.
+    /// Entries which can not be parsed are returned as errors.
+    pub fn parse(
+        &self,
+        input: &str,
+        amount_format: &AmountFormat,
+    ) -> Result<Vec<Result<StatementEntry, TransactionError>>, TransactionError> {
+        match self {
+            Self::Ofx => parse_ofx(input, amount_format),
+            Self::Qif => Ok(parse_qif(input, amount_format)),
+            #[cfg(feature = "camt")]
+            Self::Camt053 => parse_camt053(input, amount_format),
+        }
+    }
+}
+
+/// Entry of a bank statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementEntry {
+    /// Id of the entry given by the bank, OFX's `FITID`, a QIF check number or
+    /// camt.053's `AcctSvcrRef`
+    pub id: Option<String>,
+    /// Amount of the entry, negative for debits
+    pub amount: Amount,
+    /// Time the entry was posted, if known
+    pub timestamp: Option<Timestamp>,
+    /// Payee or memo of the entry
+    pub payee: Option<String>,
+}
+
+impl StatementEntry {
+    /// Get the row of the entry for the client.
+    ///
+    /// Credits are deposits and debits withdrawals. The transaction id is
+    /// resolved from the reference when the row is applied.
+    fn line(&self, client: ClientID, reference: String) -> CsvLine {
+        let t_type = if self.amount < 0.0 {
+            CsvLineType::Withdrawal
+        } else {
+            CsvLineType::Deposit
+        };
+        CsvLine {
+            t_type,
+            client,
+            tx: 0,
+            amount: self.amount.abs(),
+            reference: Some(Reference::Unresolved(reference)),
+            idempotency_key: None,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Handle the entries of a bank statement of the client and execute them.
+///
+/// Credits are deposited to and debits withdrawn from the client. Entries
+/// are referenced by the client and their id, or without an id by their
+/// date, amount, payee and occurrence, so importing a statement twice is
+/// caught as duplicates unless the store applies duplicates. Entries which
+/// can not be parsed or whose transaction is rejected are handled like
+/// rejected CSV rows.
+/// Returns an error if the input can not be read or is not a statement.
+pub fn handle_transactions_from_statement<R>(
+    mut reader: R,
+    format: StatementFormat,
+    client: ClientID,
+    registry: &TransactionRegistry,
+    options: &ParseOptions,
+    store: &mut ClientStore,
+) -> Result<ProcessingReport, TransactionError>
+where
+    R: Read,
+{
+    let mut timer = PhaseTimer::start();
+    // Statements of legacy banks are often Latin-1 encoded
+    let mut input = Vec::new();
+    let _ = reader.read_to_end(&mut input)?;
+    let input = String::from_utf8_lossy(&input);
+    let entries = format.parse(&input, &options.amount_format)?;
+
+    let mut report = ProcessingReport {
+        bytes_read: input.len() as u64,
+        ..Default::default()
+    };
+    report.timings.parse = timer.lap();
+    let mut occurrences: HashMap<String, u32> = HashMap::new();
+    for entry in entries {
+        let current = entry.map(|entry| {
+            let reference = match &entry.id {
+                Some(id) => format!("{}:{}", client, id),
+                None => {
+                    let key = format!(
+                        "{}:{}:{}:{}",
+                        client,
+                        entry.timestamp.unwrap_or_default(),
+                        entry.amount,
+                        entry.payee.as_deref().unwrap_or_default()
+                    );
+                    let occurrence = occurrences.entry(key.clone()).or_default();
+                    *occurrence += 1;
+                    format!("{}:{}", key, occurrence)
+                }
+            };
+            entry.line(client, reference)
+        });
+        let _ = apply(current, registry, options, store, &mut report);
+    }
+    report.timings.execute = timer.lap();
+    report.timings.elapsed = timer.elapsed();
+    Ok(report)
+}
+
+/// Parse the `STMTTRN` entries of an OFX statement.
+///
+/// Tags are read the same whether they are closed, as in OFX 2, or not, as
+/// in the SGML of OFX 1.
+fn parse_ofx(
+    input: &str,
+    amount_format: &AmountFormat,
+) -> Result<Vec<Result<StatementEntry, TransactionError>>, TransactionError> {
+    if !input.to_ascii_uppercase().contains("<OFX>") {
+        return Err("Not an OFX statement".into());
+    }
+    let mut entries = Vec::new();
+    let mut current: Option<OfxEntry> = None;
+    for token in input.split('<').skip(1) {
+        let (tag, value) = token.split_once('>').ok_or("Malformed OFX tag")?;
+        let value = value.trim();
+        let tag = tag.trim().to_ascii_uppercase();
+        if tag == "STMTTRN" {
+            current = Some(OfxEntry::default());
+            continue;
+        }
+        let Some(entry) = current.as_mut() else {
+            continue;
+        };
+        match tag.as_str() {
+            "/STMTTRN" => {
+                if let Some(entry) = current.take() {
+                    entries.push(entry.parse(amount_format));
+                }
+            }
+            "FITID" => entry.id = Some(value.to_owned()),
+            "TRNAMT" => entry.amount = Some(value.to_owned()),
+            "DTPOSTED" => entry.date = Some(value.to_owned()),
+            "NAME" | "PAYEE" => entry.payee = Some(value.to_owned()),
+            "MEMO" if entry.payee.is_none() => entry.payee = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Fields of an OFX entry as they were read
+#[derive(Default)]
+struct OfxEntry {
+    id: Option<String>,
+    amount: Option<String>,
+    date: Option<String>,
+    payee: Option<String>,
+}
+
+impl OfxEntry {
+    /// Parse the fields of the entry.
+    fn parse(self, amount_format: &AmountFormat) -> Result<StatementEntry, TransactionError> {
+        let amount = self.amount.as_deref().ok_or("OFX entry without TRNAMT")?;
+        Ok(StatementEntry {
+            id: self.id.filter(|id| !id.is_empty()),
+            amount: signed_amount(amount, amount_format)?,
+            timestamp: self.date.as_deref().map(ofx_date).transpose()?,
+            payee: self.payee.map(|payee| unescape(&payee)),
+        })
+    }
+}
+
+/// Parse an OFX date, `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]`, to a time.
+fn ofx_date(raw: &str) -> Result<Timestamp, TransactionError> {
+    let invalid = || format!("Invalid OFX date {}", raw);
+    let digits = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(raw, |end| &raw[..end]);
+    let number = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        digits
+            .get(range)
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let day = civil_timestamp(number(0..4)?, number(4..6)?, number(6..8)?).ok_or_else(invalid)?;
+    let seconds = if digits.len() >= 14 {
+        number(8..10)? * 3600 + number(10..12)? * 60 + number(12..14)?
+    } else {
+        0
+    };
+    // Offset of the time zone in hours, e.g. `[-5:EST]` or `[+5.50:IST]`
+    let offset = match raw.split_once('[') {
+        Some((_time, zone)) => {
+            let hours = zone
+                .split([':', ']'])
+                .next()
+                .and_then(|hours| hours.trim().parse::<f64>().ok())
+                .ok_or_else(invalid)?;
+            (hours * 3600.0) as i64
+        }
+        None => 0,
+    };
+    let timestamp = day as i64 + seconds - offset;
+    Timestamp::try_from(timestamp).map_err(|_| invalid().into())
+}
+
+/// Replace the entities of an OFX value.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Parse the entries of a QIF statement.
+///
+/// Every entry ends with a `^` line. Account and type headers, starting with
+/// `!`, and split fields are skipped.
+fn parse_qif(
+    input: &str,
+    amount_format: &AmountFormat,
+) -> Vec<Result<StatementEntry, TransactionError>> {
+    let mut entries = Vec::new();
+    let mut current = QifEntry::default();
+    for line in input.lines() {
+        let line = line.trim();
+        let mut chars = line.chars();
+        let Some(code) = chars.next() else {
+            continue;
+        };
+        let value = chars.as_str().trim();
+        match code {
+            '!' => {}
+            '^' => entries.push(std::mem::take(&mut current).parse(amount_format)),
+            'D' => current.date = Some(value.to_owned()),
+            'T' => current.amount = Some(value.to_owned()),
+            'U' if current.amount.is_none() => current.amount = Some(value.to_owned()),
+            'N' => current.id = Some(value.to_owned()),
+            'P' => current.payee = Some(value.to_owned()),
+            'M' if current.payee.is_none() => current.payee = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Fields of a QIF entry as they were read
+#[derive(Default)]
+struct QifEntry {
+    id: Option<String>,
+    amount: Option<String>,
+    date: Option<String>,
+    payee: Option<String>,
+}
+
+impl QifEntry {
+    /// Parse the fields of the entry.
+    fn parse(self, amount_format: &AmountFormat) -> Result<StatementEntry, TransactionError> {
+        let amount = self.amount.as_deref().ok_or("QIF entry without amount")?;
+        // Amounts have thousands separators, e.g. `-1,234.56`
+        let amount = amount.replace(',', "");
+        Ok(StatementEntry {
+            id: self.id.filter(|id| !id.is_empty()),
+            amount: signed_amount(&amount, amount_format)?,
+            timestamp: self.date.as_deref().map(qif_date).transpose()?,
+            payee: self.payee,
+        })
+    }
+}
+
+/// Parse a QIF date, e.g. `1/15/2024`, `01/15/24` or `1/15'24`, to a time.
+///
+/// Years with two digits are from 1970 to 2069, after an apostrophe always
+/// from 2000.
+fn qif_date(raw: &str) -> Result<Timestamp, TransactionError> {
+    let invalid = || format!("Invalid QIF date {}", raw);
+    let mut fields = raw
+        .split(['/', '\'', '-', '.'])
+        .map(|field| field.trim().parse::<i64>().ok());
+    let (Some(Some(month)), Some(Some(day)), Some(Some(mut year)), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(invalid().into());
+    };
+    if year < 100 {
+        year += if raw.contains('\'') || year < 70 {
+            2000
+        } else {
+            1900
+        };
+    }
+    Ok(civil_timestamp(year, month, day).ok_or_else(invalid)?)
+}
+
+/// Parse the booked `Ntry` entries of a camt.053 statement.
+///
+/// Pending and informational entries are not settled yet and skipped.
+/// Amounts are unsigned in camt.053, the `CdtDbtInd` of an entry tells
+/// credits from debits. Elements are matched by their local name, so every
+/// version of the schema is read.
+#[cfg(feature = "camt")]
+fn parse_camt053(
+    input: &str,
+    amount_format: &AmountFormat,
+) -> Result<Vec<Result<StatementEntry, TransactionError>>, TransactionError> {
+    let document = roxmltree::Document::parse(input)
+        .map_err(|err| format!("Invalid camt.053 statement: {}", err))?;
+    let root = document.root_element();
+    if !root.has_tag_name("Document") || child(root, &["BkToCstmrStmt"]).is_none() {
+        return Err("Not a camt.053 statement".into());
+    }
+    // Amounts always have a dot decimal separator and no thousands separator
+    let amount_format = AmountFormat {
+        thousands_separator: None,
+        decimal_separator: '.',
+        ..amount_format.clone()
+    };
+    let mut entries = Vec::new();
+    for entry in root.descendants().filter(|node| node.has_tag_name("Ntry")) {
+        // The status is a code element from camt.053.001.08 on
+        let status = child(entry, &["Sts", "Cd"]).or_else(|| child(entry, &["Sts"]));
+        if status.and_then(text).is_some_and(|status| status != "BOOK") {
+            continue;
+        }
+        entries.push(camt_entry(entry, &amount_format));
+    }
+    Ok(entries)
+}
+
+/// Parse a camt.053 entry.
+#[cfg(feature = "camt")]
+fn camt_entry(
+    entry: roxmltree::Node,
+    amount_format: &AmountFormat,
+) -> Result<StatementEntry, TransactionError> {
+    let field = |path: &[&str]| child(entry, path).and_then(text);
+    let amount = field(&["Amt"]).ok_or("camt.053 entry without Amt")?;
+    let amount = amount_format.parse(amount)?;
+    let (amount, party) = match field(&["CdtDbtInd"]) {
+        Some("CRDT") => (amount, "Dbtr"),
+        Some("DBIT") => (-amount, "Cdtr"),
+        indicator => {
+            return Err(format!(
+                "Invalid camt.053 credit debit indicator {}",
+                indicator.unwrap_or_default()
+            )
+            .into())
+        }
+    };
+    let timestamp = field(&["BookgDt", "Dt"])
+        .or_else(|| field(&["BookgDt", "DtTm"]))
+        .or_else(|| field(&["ValDt", "Dt"]))
+        .map(iso_date)
+        .transpose()?;
+    // The first transaction of the entry has the references and parties
+    let details = child(entry, &["NtryDtls", "TxDtls"]);
+    let detail = |path: &[&str]| {
+        details
+            .and_then(|details| child(details, path))
+            .and_then(text)
+    };
+    let id = field(&["AcctSvcrRef"])
+        .or_else(|| field(&["NtryRef"]))
+        .or_else(|| detail(&["Refs", "AcctSvcrRef"]))
+        .or_else(|| detail(&["Refs", "EndToEndId"]).filter(|id| *id != "NOTPROVIDED"));
+    // The party is wrapped in a `Pty` element from camt.053.001.08 on
+    let payee = detail(&["RltdPties", party, "Nm"])
+        .or_else(|| detail(&["RltdPties", party, "Pty", "Nm"]))
+        .or_else(|| detail(&["RmtInf", "Ustrd"]))
+        .or_else(|| field(&["AddtlNtryInf"]));
+    Ok(StatementEntry {
+        id: id.map(str::to_owned),
+        amount,
+        timestamp,
+        payee: payee.map(str::to_owned),
+    })
+}
+
+/// Get the element at the path of child element names.
+#[cfg(feature = "camt")]
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    path: &[&str],
+) -> Option<roxmltree::Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| {
+        node.children().find(|child| child.has_tag_name(*name))
+    })
+}
+
+/// Get the trimmed text of an element, if it is not empty.
+#[cfg(feature = "camt")]
+fn text<'a>(node: roxmltree::Node<'a, '_>) -> Option<&'a str> {
+    node.text().map(str::trim).filter(|text| !text.is_empty())
+}
+
+/// Parse an ISO 8601 date, `YYYY-MM-DD[THH:MM:SS[.sss]][Z|±HH:MM]`, to a time.
+#[cfg(feature = "camt")]
+fn iso_date(raw: &str) -> Result<Timestamp, TransactionError> {
+    let invalid = || format!("Invalid date {}", raw);
+    let (date, time) = raw.split_once('T').unwrap_or((raw, ""));
+    let mut fields = date.splitn(3, '-').map(|field| field.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(invalid().into());
+    };
+    let day = civil_timestamp(year, month, day).ok_or_else(invalid)?;
+    let clock = |raw: &str| -> Result<i64, String> {
+        let mut fields = raw.split(':').map(|field| field.parse::<i64>().ok());
+        let mut seconds = 0;
+        for unit in [3600, 60, 1] {
+            match fields.next() {
+                Some(Some(value)) => seconds += value * unit,
+                Some(None) => return Err(invalid()),
+                None => break,
+            }
+        }
+        Ok(seconds)
+    };
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(index) => {
+            let zone = &time[index..];
+            let offset = if zone == "Z" {
+                0
+            } else if let Some(behind) = zone.strip_prefix('-') {
+                -clock(behind)?
+            } else {
+                clock(&zone[1..])?
+            };
+            (&time[..index], offset)
+        }
+        None => (time, 0),
+    };
+    // Fractions of seconds are dropped
+    let time = time.split('.').next().unwrap_or_default();
+    let seconds = if time.is_empty() { 0 } else { clock(time)? };
+    let timestamp = day as i64 + seconds - offset;
+    Timestamp::try_from(timestamp).map_err(|_| invalid().into())
+}
+
+/// Parse an amount with a sign.
+fn signed_amount(raw: &str, amount_format: &AmountFormat) -> Result<Amount, TransactionError> {
+    let raw = raw.trim();
+    match raw.strip_prefix('-') {
+        Some(debit) => Ok(-amount_format.parse(debit)?),
+        None => amount_format.parse(raw.strip_prefix('+').unwrap_or(raw)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ofx_entries_parsed() {
+        let ofx = "OFXHEADER:100\nDATA:OFXSGML\n\n<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>\
+                   <BANKTRANLIST>\n\
+                   <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240115120000[-5:EST]\
+                   <TRNAMT>1500.00<FITID>2024011501<NAME>Payroll &amp; Co\n\
+                   </STMTTRN>\n\
+                   <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240116<TRNAMT>-42.10\
+                   <FITID>2024011602<MEMO>Groceries</STMTTRN>\n\
+                   <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240117<TRNAMT>abc</STMTTRN>\n\
+                   </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+        let entries = StatementFormat::Ofx
+            .parse(ofx, &AmountFormat::default())
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].as_ref().unwrap(),
+            &StatementEntry {
+                id: Some("2024011501".to_owned()),
+                amount: 1500.0,
+                // 17:00 UTC
+                timestamp: Some(1_705_338_000),
+                payee: Some("Payroll & Co".to_owned()),
+            }
+        );
+        let debit = entries[1].as_ref().unwrap();
+        assert_eq!(
+            (debit.amount, debit.timestamp, debit.payee.as_deref()),
+            (-42.1, Some(1_705_363_200), Some("Groceries"))
+        );
+        entries[2].as_ref().unwrap_err();
+
+        StatementFormat::Ofx
+            .parse("t_type,client,tx,amount\n", &AmountFormat::default())
+            .unwrap_err();
+    }
+
+    #[cfg(feature = "camt")]
+    #[test]
+    fn camt053_booked_entries_parsed() {
+        let camt = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+              <BkToCstmrStmt><Stmt>
+                <Ntry>
+                  <Amt Ccy="EUR">1500.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+                  <BookgDt><Dt>2024-01-15</Dt></BookgDt>
+                  <AcctSvcrRef>2024011501</AcctSvcrRef>
+                  <NtryDtls><TxDtls><RltdPties><Dbtr><Nm>Payroll &amp; Co</Nm></Dbtr></RltdPties></TxDtls></NtryDtls>
+                </Ntry>
+                <Ntry>
+                  <Amt Ccy="EUR">42.10</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>
+                  <BookgDt><DtTm>2024-01-16T10:00:00+01:00</DtTm></BookgDt>
+                  <NtryDtls><TxDtls>
+                    <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+                    <RltdPties><Cdtr><Pty><Nm>Groceries</Nm></Pty></Cdtr></RltdPties>
+                  </TxDtls></NtryDtls>
+                </Ntry>
+                <Ntry>
+                  <Amt Ccy="EUR">10.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>PDNG</Sts>
+                </Ntry>
+                <Ntry><Amt Ccy="EUR">5.00</Amt><Sts>BOOK</Sts></Ntry>
+              </Stmt></BkToCstmrStmt>
+            </Document>"#;
+        let entries = StatementFormat::Camt053
+            .parse(camt, &AmountFormat::comma_decimal())
+            .unwrap();
+        // The pending entry is skipped
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0].as_ref().unwrap(),
+            &StatementEntry {
+                id: Some("2024011501".to_owned()),
+                amount: 1500.0,
+                timestamp: Some(1_705_276_800),
+                payee: Some("Payroll & Co".to_owned()),
+            }
+        );
+        assert_eq!(
+            entries[1].as_ref().unwrap(),
+            &StatementEntry {
+                id: None,
+                amount: -42.1,
+                // 09:00 UTC
+                timestamp: Some(1_705_395_600),
+                payee: Some("Groceries".to_owned()),
+            }
+        );
+        entries[2].as_ref().unwrap_err();
+
+        StatementFormat::Camt053
+            .parse("<OFX></OFX>", &AmountFormat::default())
+            .unwrap_err();
+        assert_eq!(
+            StatementFormat::from_path(Path::new("statements/2024-01.xml")),
+            Some(StatementFormat::Camt053)
+        );
+    }
+
+    #[test]
+    fn qif_entries_applied_to_client() {
+        let qif = "!Type:Bank\n\
+                   D1/15/2024\nT1,500.00\nPPayroll\n^\n\
+                   D1/16'24\nT-42.10\nN1001\nPGroceries\n^\n\
+                   D01/17/24\nT-5.00\nPCoffee\n^\n\
+                   D01/17/24\nT-5.00\nPCoffee\n^\n";
+        let mut store = ClientStore::new();
+        let report = handle_transactions_from_statement(
+            qif.as_bytes(),
+            StatementFormat::Qif,
+            7,
+            &TransactionRegistry::new(),
+            &ParseOptions::default(),
+            &mut store,
+        )
+        .unwrap();
+        assert_eq!((report.applied, report.rejected), (4, 0));
+        assert_eq!(store.clients.get(&7).unwrap().available, 1447.9);
+        // Identical entries are told apart by their occurrence
+        assert_eq!(store.references().len(), 4);
+        assert_eq!(
+            qif_date("1/17/24").unwrap(),
+            civil_timestamp(2024, 1, 17).unwrap()
+        );
+        assert_eq!(
+            StatementFormat::from_path(Path::new("statements/january.QFX")),
+            Some(StatementFormat::Ofx)
+        );
+    }
+}
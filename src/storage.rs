@@ -0,0 +1,183 @@
+use serde::Deserialize;
+
+use crate::{client::Client, error::TransactionError, ClientID, IdMap};
+
+/// Largest client id stored by dense storage, bounding the memory of it's vector
+const MAX_DENSE_ID: u128 = u16::MAX as u128;
+
+/// Kind of storage used for clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientStorage {
+    /// Clients are stored in a hash map.
+    #[default]
+    Hashed,
+    /// Clients are stored in a vector indexed by their id.
+    ///
+    /// Client ids are small, so this avoids hashing on every lookup at
+    /// the cost of memory proportional to the largest client id. Clients
+    /// with ids above 65535 can not be stored.
+    Dense,
+}
+
+/// Collection of clients by their unique identifer.
+pub enum Clients {
+    /// Hash map of a client's unique identifer to a client.
+    Hashed(IdMap<ClientID, Client>),
+    /// Vector of clients indexed by their unique identifer, and their number.
+    Dense(Vec<Option<Client>>, usize),
+}
+
+impl Clients {
+    /// Create a new empty collection using the kind of storage
+    pub fn new(storage: ClientStorage) -> Self {
+        match storage {
+            ClientStorage::Hashed => Self::Hashed(IdMap::default()),
+            ClientStorage::Dense => Self::Dense(Vec::new(), 0),
+        }
+    }
+
+    /// Get the client with the id
+    pub fn get(&self, id: &ClientID) -> Option<&Client> {
+        match self {
+            Self::Hashed(clients) => clients.get(id),
+            Self::Dense(clients, _) => clients.get(*id as usize).and_then(Option::as_ref),
+        }
+    }
+
+    /// Get the client with the id for modification
+    pub fn get_mut(&mut self, id: &ClientID) -> Option<&mut Client> {
+        match self {
+            Self::Hashed(clients) => clients.get_mut(id),
+            Self::Dense(clients, _) => clients.get_mut(*id as usize).and_then(Option::as_mut),
+        }
+    }
+
+    /// Check if a client with the id can be stored
+    ///
+    /// Returns an error if the id is too large for dense storage.
+    pub fn check_id(&self, id: ClientID) -> Result<(), TransactionError> {
+        match self {
+            Self::Dense(..) if u128::from(id) > MAX_DENSE_ID => Err(format!(
+                "Client {} exceeds the largest id of dense storage, {}",
+                id, MAX_DENSE_ID
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Insert a client with the id, returning the client it replaced
+    ///
+    /// Returns an error if the id is too large for dense storage.
+    pub fn insert(
+        &mut self,
+        id: ClientID,
+        client: Client,
+    ) -> Result<Option<Client>, TransactionError> {
+        self.check_id(id)?;
+        match self {
+            Self::Hashed(clients) => Ok(clients.insert(id, client)),
+            Self::Dense(clients, len) => {
+                let index = id as usize;
+                if clients.len() <= index {
+                    clients.resize_with(index + 1, || None);
+                }
+                let replaced = clients[index].replace(client);
+                if replaced.is_none() {
+                    *len += 1;
+                }
+                Ok(replaced)
+            }
+        }
+    }
+
+    /// Check if a client with the id is stored
+    pub fn contains_key(&self, id: &ClientID) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Number of stored clients
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Hashed(clients) => clients.len(),
+            Self::Dense(_, len) => *len,
+        }
+    }
+
+    /// Check if no clients are stored
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Hashed(clients) => clients.is_empty(),
+            Self::Dense(_, len) => *len == 0,
+        }
+    }
+
+    /// Iterate over all stored clients in an arbitrary order
+    pub fn values(&self) -> impl Iterator<Item = &Client> {
+        // Only one of the iterators is set, chained to avoid boxing
+        let (hashed, dense) = match self {
+            Self::Hashed(clients) => (Some(clients.values()), None),
+            Self::Dense(clients, _) => (None, Some(clients.iter().flatten())),
+        };
+        hashed
+            .into_iter()
+            .flatten()
+            .chain(dense.into_iter().flatten())
+    }
+
+    /// Iterate over all stored clients for modification in an arbitrary order
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+        let (hashed, dense) = match self {
+            Self::Hashed(clients) => (Some(clients.values_mut()), None),
+            Self::Dense(clients, _) => (None, Some(clients.iter_mut().flatten())),
+        };
+        hashed
+            .into_iter()
+            .flatten()
+            .chain(dense.into_iter().flatten())
+    }
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Self::new(ClientStorage::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_clients() {
+        let mut clients = Clients::new(ClientStorage::Dense);
+        assert!(clients.is_empty());
+        assert!(clients.insert(157, Client::new(157)).unwrap().is_none());
+        assert!(clients.insert(3, Client::new(3)).unwrap().is_none());
+        assert!(clients.contains_key(&157));
+        assert!(!clients.contains_key(&4));
+        assert!(!clients.contains_key(&1000));
+        assert_eq!(clients.len(), 2);
+        clients.get_mut(&3).unwrap().available = 1.0;
+        assert_eq!(clients.get(&3).unwrap().available, 1.0);
+        let ids: Vec<ClientID> = clients.values().map(|client| client.id).collect();
+        assert_eq!(ids, vec![3, 157]);
+        assert!(clients.insert(3, Client::new(3)).unwrap().is_some());
+        assert_eq!(clients.len(), 2);
+    }
+
+    #[cfg(any(feature = "client-id-u32", feature = "client-id-u64"))]
+    #[test]
+    fn dense_clients_bounded() {
+        let mut clients = Clients::new(ClientStorage::Dense);
+        clients.insert(70_000, Client::new(70_000)).unwrap_err();
+        assert!(clients.is_empty());
+        // Hashed storage has no bound
+        let mut clients = Clients::new(ClientStorage::Hashed);
+        assert!(clients
+            .insert(70_000, Client::new(70_000))
+            .unwrap()
+            .is_none());
+    }
+}
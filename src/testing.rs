@@ -0,0 +1,405 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::Range,
+};
+
+use arbitrary::{Arbitrary, Unstructured};
+use proptest::{collection::vec, prelude::*, sample::select};
+
+use crate::{
+    client::{Client, ClientStore, DisputeState, History},
+    error::TransactionError,
+    generator::Rng,
+    transaction::{Transaction, TransactionKind, TransactionRegistry},
+    Amount, ClientID, CsvLine, CsvLineType, IdMap, TransactionID,
+};
+
+/// Built in kinds of transactions
+const KINDS: [TransactionKind; 13] = [
+    TransactionKind::Chargeback,
+    TransactionKind::ChargebackReversal,
+    TransactionKind::Deposit,
+    TransactionKind::Dispute,
+    TransactionKind::Freeze,
+    TransactionKind::Hold,
+    TransactionKind::Interest,
+    TransactionKind::Release,
+    TransactionKind::Resolve,
+    TransactionKind::Unfreeze,
+    TransactionKind::Unverify,
+    TransactionKind::Verify,
+    TransactionKind::Withdrawal,
+];
+
+/// Largest generated amount in units of the fourth decimal place
+const MAX_AMOUNT_UNITS: u64 = 10_000_000_000;
+
+/// Transaction input row for property tests and fuzzing
+///
+/// Deposits, holds, interest and withdrawals have an amount, other kinds reference a
+/// previous transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionRow {
+    /// Kind of the transaction
+    pub kind: TransactionKind,
+    /// Client to execute the transaction on
+    pub client: ClientID,
+    /// Transaction identifier
+    pub tx: TransactionID,
+    /// Amount of the transaction
+    pub amount: Option<Amount>,
+}
+
+impl TransactionRow {
+    /// Format the row as a CSV line of the fixed four column layout
+    pub fn to_csv(&self) -> String {
+        let t_type = self.kind.name();
+        match self.amount {
+            Some(amount) => format!("{},{},{},{}", t_type, self.client, self.tx, amount),
+            None => format!("{},{},{},", t_type, self.client, self.tx),
+        }
+    }
+
+    /// Create the transaction of the row.
+    ///
+    /// Custom kinds are looked up in the registry.
+    pub fn transaction(
+        &self,
+        registry: &TransactionRegistry,
+    ) -> Result<Box<dyn Transaction>, TransactionError> {
+        let t_type = match self.kind {
+            TransactionKind::Chargeback => CsvLineType::Chargeback,
+            TransactionKind::ChargebackReversal => CsvLineType::ChargebackReversal,
+            TransactionKind::Deposit => CsvLineType::Deposit,
+            TransactionKind::Dispute => CsvLineType::Dispute,
+            TransactionKind::Freeze => CsvLineType::Freeze,
+            TransactionKind::Hold => CsvLineType::Hold,
+            TransactionKind::Interest => CsvLineType::Interest,
+            TransactionKind::Release => CsvLineType::Release,
+            TransactionKind::Resolve => CsvLineType::Resolve,
+            TransactionKind::Unfreeze => CsvLineType::Unfreeze,
+            TransactionKind::Unverify => CsvLineType::Unverify,
+            TransactionKind::Verify => CsvLineType::Verify,
+            TransactionKind::Withdrawal => CsvLineType::Withdrawal,
+            TransactionKind::Custom(name) => CsvLineType::Custom(name.to_owned()),
+        };
+        registry.create(CsvLine {
+            t_type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount.unwrap_or(0.0),
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        })
+    }
+
+    fn new(kind: TransactionKind, client: ClientID, tx: TransactionID, amount: Amount) -> Self {
+        let amount = match kind {
+            TransactionKind::Deposit
+            | TransactionKind::Hold
+            | TransactionKind::Interest
+            | TransactionKind::Withdrawal => Some(amount),
+            _ => None,
+        };
+        Self {
+            kind,
+            client,
+            tx,
+            amount,
+        }
+    }
+}
+
+/// Strategy for non-negative amounts with at most four decimal places
+pub fn amount() -> impl Strategy<Value = Amount> {
+    (0..=MAX_AMOUNT_UNITS).prop_map(|units| units as Amount / 10_000.0)
+}
+
+/// Strategy for rows of built in transactions
+///
+/// Clients and transaction ids are drawn from `1..=clients` and `1..=txs`,
+/// so small bounds make disputes likely to reference earlier transactions.
+pub fn transaction_row(
+    clients: ClientID,
+    txs: TransactionID,
+) -> impl Strategy<Value = TransactionRow> {
+    (
+        select(KINDS.to_vec()),
+        1..=clients.max(1),
+        1..=txs.max(1),
+        amount(),
+    )
+        .prop_map(|(kind, client, tx, amount)| TransactionRow::new(kind, client, tx, amount))
+}
+
+/// Strategy for CSV input of up to `max_rows` transactions, including the headers
+pub fn csv_input(max_rows: usize) -> impl Strategy<Value = String> {
+    vec(transaction_row(10, 100), 0..=max_rows).prop_map(|rows| {
+        let mut csv = String::from("t_type,client,tx,amount\n");
+        for row in rows {
+            csv.push_str(&row.to_csv());
+            csv.push('\n');
+        }
+        csv
+    })
+}
+
+/// Strategy for consistent clients
+///
+/// Held funds are the sum of the client's disputed history entries.
+pub fn client() -> impl Strategy<Value = Client> {
+    (
+        any::<ClientID>(),
+        amount(),
+        vec((any::<TransactionID>(), amount(), any::<bool>()), 0..8),
+        any::<bool>(),
+    )
+        .prop_map(|(id, available, history, locked)| {
+            client_with_history(id, available, history, locked)
+        })
+}
+
+fn client_with_history(
+    id: ClientID,
+    available: Amount,
+    history: Vec<(TransactionID, Amount, bool)>,
+    locked: bool,
+) -> Client {
+    let mut client = Client::new(id);
+    client.available = available;
+    client.locked = locked;
+    client.client_history = history
+        .into_iter()
+        .map(|(tx, amount, dispute)| {
+            (
+                tx,
+                History {
+                    amount,
+                    state: if dispute {
+                        DisputeState::Disputed
+                    } else {
+                        DisputeState::Undisputed
+                    },
+                    timestamp: None,
+                    kind: None,
+                },
+            )
+        })
+        .collect::<IdMap<_, _>>();
+    client.held = client
+        .client_history
+        .values()
+        .filter(|history| history.is_disputed())
+        .map(|history| history.amount)
+        .sum();
+    client
+}
+
+/// Seeded random sequence of transactions, which is replayed exactly from it's seed
+///
+/// Rows are drawn like `transaction_row`, but from a generator fully determined
+/// by the seed, so a failing seed in a bug report reproduces the failure on any
+/// platform and release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzCase {
+    /// Seed of the random generator
+    pub seed: u64,
+    /// Number of transactions
+    pub rows: usize,
+    /// Clients are drawn from `1..=clients`
+    pub clients: ClientID,
+    /// Transaction ids are drawn from `1..=txs`
+    pub txs: TransactionID,
+}
+
+impl FuzzCase {
+    /// Create a new FuzzCase of a thousand transactions of ten clients
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rows: 1000,
+            clients: 10,
+            txs: 100,
+        }
+    }
+
+    /// Generate the transactions of the case
+    pub fn transactions(&self) -> Vec<TransactionRow> {
+        let mut rng = Rng(self.seed);
+        (0..self.rows)
+            .map(|_| {
+                let kind = KINDS[rng.index(KINDS.len())];
+                let client = 1 + rng.index(self.clients.max(1) as usize) as ClientID;
+                let tx = 1 + rng.index(self.txs.max(1) as usize) as TransactionID;
+                let units = rng.next_u64() % (MAX_AMOUNT_UNITS + 1);
+                TransactionRow::new(kind, client, tx, units as Amount / 10_000.0)
+            })
+            .collect()
+    }
+
+    /// Format the transactions as CSV input, including the headers, e.g. to attach to a bug report
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("t_type,client,tx,amount\n");
+        for row in self.transactions() {
+            csv.push_str(&row.to_csv());
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Execute the transactions on the store, checking it after every transaction.
+    ///
+    /// Rejected transactions are expected and only the check fails the run, with
+    /// the transaction after which it failed.
+    pub fn run<F>(&self, client_store: &mut ClientStore, mut check: F) -> Result<(), FuzzFailure>
+    where
+        F: FnMut(&ClientStore) -> Result<(), String>,
+    {
+        let registry = TransactionRegistry::new();
+        for (row, transaction) in self.transactions().into_iter().enumerate() {
+            if let Ok(created) = transaction.transaction(&registry) {
+                let _ = client_store.execute(created.as_ref());
+            }
+            if let Err(message) = check(client_store) {
+                return Err(FuzzFailure {
+                    case: self.clone(),
+                    row,
+                    transaction,
+                    message,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check failed by a FuzzCase
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzFailure {
+    /// Case which failed, replayed with `FuzzCase::run`
+    pub case: FuzzCase,
+    /// Index of the transaction after which the check failed
+    pub row: usize,
+    /// Transaction after which the check failed
+    pub transaction: TransactionRow,
+    /// Reason given by the check
+    pub message: String,
+}
+
+impl Display for FuzzFailure {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "Seed {} failed after row {} ({}): {}",
+            self.case.seed,
+            self.row,
+            self.transaction.to_csv(),
+            self.message
+        )
+    }
+}
+
+/// Run the cases of every seed on a new store, returning the first failure.
+///
+/// The cases are `FuzzCase::new` of the seed with the number of rows.
+pub fn fuzz<F>(seeds: Range<u64>, rows: usize, mut check: F) -> Result<(), FuzzFailure>
+where
+    F: FnMut(&ClientStore) -> Result<(), String>,
+{
+    for seed in seeds {
+        let case = FuzzCase {
+            rows,
+            ..FuzzCase::new(seed)
+        };
+        case.run(&mut ClientStore::new(), &mut check)?;
+    }
+    Ok(())
+}
+
+/// Check of a fuzzed store that it's trial balance has no broken invariants
+pub fn balanced(client_store: &ClientStore) -> Result<(), String> {
+    match client_store.trial_balance().violations.first() {
+        Some(violation) => Err(violation.to_string()),
+        None => Ok(()),
+    }
+}
+
+fn arbitrary_amount(u: &mut Unstructured) -> arbitrary::Result<Amount> {
+    Ok(u.int_in_range(0..=MAX_AMOUNT_UNITS)? as Amount / 10_000.0)
+}
+
+impl<'a> Arbitrary<'a> for TransactionRow {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::new(
+            *u.choose(&KINDS)?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            arbitrary_amount(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Client {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let id = u.arbitrary()?;
+        let available = arbitrary_amount(u)?;
+        let entries = u.arbitrary_len::<(TransactionID, u64, bool)>()?.min(8);
+        let mut history = Vec::with_capacity(entries);
+        for _ in 0..entries {
+            history.push((u.arbitrary()?, arbitrary_amount(u)?, u.arbitrary()?));
+        }
+        Ok(client_with_history(id, available, history, u.arbitrary()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_transactions_from_reader;
+
+    #[test]
+    fn fuzz_cases_replayed_from_seed() {
+        let case = FuzzCase::new(7);
+        assert_eq!(case.transactions().len(), 1000);
+        assert_eq!(case.transactions(), FuzzCase::new(7).transactions());
+        assert_ne!(case.transactions(), FuzzCase::new(8).transactions());
+        assert_eq!(case.to_csv().lines().count(), 1001);
+        fuzz(0..20, 200, balanced).unwrap();
+
+        let charged_back = |client_store: &ClientStore| {
+            if client_store.trial_balance().flows.chargebacks > 0.0 {
+                return Err("charged back".to_owned());
+            }
+            Ok(())
+        };
+        let failure = fuzz(0..100, 1000, charged_back).unwrap_err();
+        assert_eq!(failure.transaction.kind, TransactionKind::Chargeback);
+        let replayed = failure
+            .case
+            .run(&mut ClientStore::new(), charged_back)
+            .unwrap_err();
+        assert_eq!(replayed, failure);
+        assert!(failure
+            .to_string()
+            .starts_with(&format!("Seed {} failed", failure.case.seed)));
+    }
+
+    proptest! {
+        #[test]
+        fn every_row_is_handled(csv in csv_input(50)) {
+            let mut client_store = ClientStore::new();
+            let report = handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+            prop_assert_eq!(report.rows_read, csv.lines().count() as u64 - 1);
+            prop_assert_eq!(report.applied + report.rejected, report.rows_read);
+        }
+
+        #[test]
+        fn rows_create_their_transaction(row in transaction_row(10, 100)) {
+            let transaction = row.transaction(&TransactionRegistry::new()).unwrap();
+            prop_assert_eq!(transaction.kind(), row.kind);
+            prop_assert_eq!(transaction.requested_client_id(), row.client);
+            prop_assert_eq!(transaction.amount(), row.amount);
+        }
+    }
+}
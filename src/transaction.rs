@@ -1,7 +1,12 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Serializer};
+
 use crate::{
-    client::{Client, History},
-    error::TransactionError,
-    Amount, ClientID, CsvLine, CsvLineType, TransactionID,
+    amount::{exceeds, settle},
+    client::{Client, DisputeState, History},
+    error::{ErrorKind, TransactionError},
+    Amount, ClientID, CsvLine, CsvLineType, Timestamp, TransactionID,
 };
 
 /// Transaction trait
@@ -25,30 +30,274 @@ pub trait Transaction {
     /// Generic method for getting the transaction's amount.
     /// Not all transations have an amount so an option is returned.
     fn amount(&self) -> Option<Amount>;
+
+    /// Get the unique Transaction ID of this transaction
+    ///
+    /// Generic method for getting the transaction's id. For transactions
+    /// referencing a previous transaction, this is the referenced id.
+    fn transaction_id(&self) -> TransactionID;
+
+    /// Get the kind of this transaction
+    ///
+    /// Generic method for identifying the transaction without downcasting.
+    fn kind(&self) -> TransactionKind;
+
+    /// Get the time of this transaction
+    ///
+    /// Transactions without a time of their own are timestamped by the
+    /// ClientStore's clock, if it has one.
+    fn timestamp(&self) -> Option<Timestamp> {
+        None
+    }
+}
+
+/// Transaction with the time it happened
+///
+/// Wraps another transaction, e.g. one read from input with a timestamp column.
+pub struct Timestamped {
+    /// Wrapped transaction
+    transaction: Box<dyn Transaction>,
+    /// Time of the transaction
+    timestamp: Timestamp,
+}
+
+impl Timestamped {
+    /// Create a new Timestamped transaction
+    pub fn new(transaction: Box<dyn Transaction>, timestamp: Timestamp) -> Self {
+        Self {
+            transaction,
+            timestamp,
+        }
+    }
 }
 
-impl From<CsvLine> for Box<dyn Transaction> {
-    fn from(csv_line: CsvLine) -> Self {
-        match csv_line.t_type {
-            CsvLineType::Chargeback => {
-                Box::new(Chargeback::new(csv_line.tx, csv_line.client)) as Box<dyn Transaction>
+impl Transaction for Timestamped {
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        self.transaction.execute(client)
+    }
+
+    fn requested_client_id(&self) -> ClientID {
+        self.transaction.requested_client_id()
+    }
+
+    fn amount(&self) -> Option<Amount> {
+        self.transaction.amount()
+    }
+
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction.transaction_id()
+    }
+
+    fn kind(&self) -> TransactionKind {
+        self.transaction.kind()
+    }
+
+    fn timestamp(&self) -> Option<Timestamp> {
+        Some(self.timestamp)
+    }
+}
+
+/// Kind of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransactionKind {
+    /// Reverse a disputed transaction and lock the account
+    Chargeback,
+    /// Restore the funds of a charged back transaction
+    ChargebackReversal,
+    /// Credit funds to an account
+    Deposit,
+    /// Hold the funds of a previous transaction
+    Dispute,
+    /// Pause an account for compliance
+    Freeze,
+    /// Move funds from available to held for an authorization
+    Hold,
+    /// Credit accrued interest to an account
+    Interest,
+    /// Release the held funds of a previous hold
+    Release,
+    /// Release the held funds of a disputed transaction
+    Resolve,
+    /// Lift the compliance pause of an account
+    Unfreeze,
+    /// Revoke the verification of a client
+    Unverify,
+    /// Mark a client as verified
+    Verify,
+    /// Debit funds from an account
+    Withdrawal,
+    /// User defined transaction registered by name
+    Custom(&'static str),
+}
+
+impl TransactionKind {
+    /// Check if the kind is an administrative transaction
+    ///
+    /// Administrative transactions change a client's status. Their tx does
+    /// not reference a previous transaction.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Self::Freeze | Self::Unfreeze | Self::Verify | Self::Unverify
+        )
+    }
+
+    /// Get the type name of the kind in the input, e.g. `deposit`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Chargeback => "chargeback",
+            Self::ChargebackReversal => "chargeback_reversal",
+            Self::Deposit => "deposit",
+            Self::Dispute => "dispute",
+            Self::Freeze => "freeze",
+            Self::Hold => "hold",
+            Self::Interest => "interest",
+            Self::Release => "release",
+            Self::Resolve => "resolve",
+            Self::Unfreeze => "unfreeze",
+            Self::Unverify => "unverify",
+            Self::Verify => "verify",
+            Self::Withdrawal => "withdrawal",
+            Self::Custom(name) => name,
+        }
+    }
+}
+
+impl Serialize for TransactionKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Constructor for a user defined transaction
+///
+/// Called with the transaction id, client id, and amount from the input.
+/// Amount is 0 if the input did not have one.
+pub type TransactionConstructor =
+    Box<dyn Fn(TransactionID, ClientID, Amount) -> Box<dyn Transaction>>;
+
+/// Registry of user defined transaction types.
+///
+/// Maps input type names, such as `fee` or `adjustment`, to user defined
+/// Transaction implementations. Built in types are always handled by the
+/// engine and can not be overridden.
+#[derive(Default)]
+pub struct TransactionRegistry {
+    /// Map of a type name to the constructor of its transaction.
+    constructors: HashMap<String, TransactionConstructor>,
+}
+
+impl TransactionRegistry {
+    /// Create a new TransactionRegistry with no user defined types
+    pub fn new() -> Self {
+        Self {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Register a constructor for a user defined type name.
+    ///
+    /// Returns an error if the name belongs to a built in type.
+    /// Registering a name twice replaces the previous constructor.
+    pub fn register<F>(&mut self, name: &str, constructor: F) -> Result<(), TransactionError>
+    where
+        F: Fn(TransactionID, ClientID, Amount) -> Box<dyn Transaction> + 'static,
+    {
+        if matches!(
+            name,
+            "chargeback"
+                | "chargeback_reversal"
+                | "deposit"
+                | "dispute"
+                | "freeze"
+                | "hold"
+                | "interest"
+                | "release"
+                | "resolve"
+                | "unfreeze"
+                | "unverify"
+                | "verify"
+                | "withdrawal"
+        ) {
+            return Err(format!("Can not register built in transaction type {}", name).into());
+        }
+        let _ = self
+            .constructors
+            .insert(name.to_owned(), Box::new(constructor));
+        Ok(())
+    }
+
+    /// Create the transaction for a type name of the input.
+    ///
+    /// Same as reading a row with the type, client, tx and amount from the input,
+    /// for embedders submitting transactions without CSV. Amount is ignored by
+    /// types without one.
+    pub fn create_named(
+        &self,
+        t_type: &str,
+        client: ClientID,
+        tx: TransactionID,
+        amount: Amount,
+    ) -> Result<Box<dyn Transaction>, TransactionError> {
+        self.create(CsvLine {
+            t_type: CsvLineType::new(t_type),
+            client,
+            tx,
+            amount,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        })
+    }
+
+    /// Create the transaction for an input line.
+    ///
+    /// Built in types are created directly. Other types are created with
+    /// their registered constructor, or an error is returned if the type
+    /// is not registered. Lines with a timestamp create a Timestamped transaction.
+    pub(crate) fn create(
+        &self,
+        csv_line: CsvLine,
+    ) -> Result<Box<dyn Transaction>, TransactionError> {
+        let timestamp = csv_line.timestamp;
+        let transaction: Box<dyn Transaction> = match csv_line.t_type {
+            CsvLineType::Chargeback => Box::new(Chargeback::new(csv_line.tx, csv_line.client)),
+            CsvLineType::ChargebackReversal => {
+                Box::new(ChargebackReversal::new(csv_line.tx, csv_line.client))
             }
             CsvLineType::Deposit => {
                 Box::new(Deposit::new(csv_line.tx, csv_line.client, csv_line.amount))
-                    as Box<dyn Transaction>
             }
             CsvLineType::Withdrawal => Box::new(Withdrawal::new(
                 csv_line.tx,
                 csv_line.client,
                 csv_line.amount,
-            )) as Box<dyn Transaction>,
-            CsvLineType::Dispute => {
-                Box::new(Dispute::new(csv_line.tx, csv_line.client)) as Box<dyn Transaction>
+            )),
+            CsvLineType::Dispute => Box::new(Dispute::new(csv_line.tx, csv_line.client)),
+            CsvLineType::Hold => Box::new(Hold::new(csv_line.tx, csv_line.client, csv_line.amount)),
+            CsvLineType::Release => Box::new(Release::new(csv_line.tx, csv_line.client)),
+            CsvLineType::Interest => {
+                Box::new(Interest::new(csv_line.tx, csv_line.client, csv_line.amount))
             }
-            CsvLineType::Resolve => {
-                Box::new(Resolve::new(csv_line.tx, csv_line.client)) as Box<dyn Transaction>
+            CsvLineType::Resolve => Box::new(Resolve::new(csv_line.tx, csv_line.client)),
+            CsvLineType::Freeze => Box::new(Freeze::new(csv_line.tx, csv_line.client, true)),
+            CsvLineType::Unfreeze => Box::new(Freeze::new(csv_line.tx, csv_line.client, false)),
+            CsvLineType::Unverify => {
+                Box::new(Verification::new(csv_line.tx, csv_line.client, false))
             }
-        }
+            CsvLineType::Verify => Box::new(Verification::new(csv_line.tx, csv_line.client, true)),
+            CsvLineType::Custom(name) => match self.constructors.get(&name) {
+                Some(constructor) => constructor(csv_line.tx, csv_line.client, csv_line.amount),
+                None => return Err(format!("Unknown transaction type {}", name).into()),
+            },
+        };
+        Ok(match timestamp {
+            Some(timestamp) => Box::new(Timestamped::new(transaction, timestamp)),
+            None => transaction,
+        })
     }
 }
 
@@ -80,8 +329,12 @@ impl Transaction for Deposit {
     /// The deposit is also added to the client history in case it needs to be disputed.
     fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
         if client.locked {
-            return Err("Could not deposit funds. Account is locked.".into());
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not deposit funds. Account is locked.".to_owned(),
+            ));
         }
+        check_frozen(client, self.transaction_id)?;
         client.available += self.amount;
         client
             .client_history
@@ -100,6 +353,16 @@ impl Transaction for Deposit {
     fn amount(&self) -> Option<Amount> {
         Some(self.amount)
     }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Deposit
+    }
 }
 
 struct Withdrawal {
@@ -127,10 +390,17 @@ impl Transaction for Withdrawal {
     /// it needs to be disputed.
     fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
         if client.locked {
-            return Err("Could not withdrawal funds. Account is locked.".into());
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not withdrawal funds. Account is locked.".to_owned(),
+            ));
         }
+        check_frozen(client, self.transaction_id)?;
         if client.available < self.amount {
-            Err("Insufficent funds!".into())
+            Err(TransactionError::new(
+                ErrorKind::InsufficientFunds,
+                "Insufficent funds!".to_owned(),
+            ))
         } else {
             client.available -= self.amount;
             client
@@ -151,8 +421,257 @@ impl Transaction for Withdrawal {
     fn amount(&self) -> Option<Amount> {
         Some(self.amount)
     }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Withdrawal
+    }
+}
+/// Interest Transaction
+///
+/// Credits interest accrued by `ClientStore::accrue_interest`, or read from the input.
+pub(crate) struct Interest {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+    amount: Amount,
+}
+
+impl Interest {
+    /// Create a new Interest for a client with an amount and a specific transaction id
+    pub fn new(transaction_id: TransactionID, client_id: ClientID, amount: Amount) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+            amount,
+        }
+    }
+}
+
+impl Transaction for Interest {
+    /// Add interest to available balance of the client
+    ///
+    /// Same as a deposit, the interest is added to the client history so it can be disputed.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        if client.locked {
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not credit interest. Account is locked.".to_owned(),
+            ));
+        }
+        client.available += self.amount;
+        client
+            .client_history
+            .insert(self.transaction_id, History::new(self.amount));
+        Ok(())
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Interest has an associated amount
+    fn amount(&self) -> Option<Amount> {
+        Some(self.amount)
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Interest
+    }
+}
+
+/// Authorization hold Transaction
+///
+/// Moves funds from available to held without disputing a transaction,
+/// until they are released or the hold expires.
+struct Hold {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+    amount: Amount,
+}
+
+impl Hold {
+    /// Create a new Hold for a client with an amount and a specific transaction id
+    pub fn new(transaction_id: TransactionID, client_id: ClientID, amount: Amount) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+            amount,
+        }
+    }
+}
+
+impl Transaction for Hold {
+    /// Hold funds of the client
+    ///
+    /// If the client is not locked and has enough available funds, the funds
+    /// are moved to held and the hold is recorded so it can be released.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        if client.locked {
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not hold funds. Account is locked.".to_owned(),
+            ));
+        }
+        check_frozen(client, self.transaction_id)?;
+        if client.holds.contains_key(&self.transaction_id) {
+            return Err(TransactionError::new(
+                ErrorKind::DuplicateTransaction,
+                format!("Hold {} is already open.", self.transaction_id),
+            ));
+        }
+        if client.available < self.amount {
+            return Err(TransactionError::new(
+                ErrorKind::InsufficientFunds,
+                "Insufficent funds!".to_owned(),
+            ));
+        }
+        client.available -= self.amount;
+        client.held += self.amount;
+        let _ = client.holds.insert(self.transaction_id, self.amount);
+        Ok(())
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Holds have an associated amount
+    fn amount(&self) -> Option<Amount> {
+        Some(self.amount)
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Hold
+    }
+}
+
+/// Check the amount can be removed from the client's held funds.
+///
+/// Check the client is not frozen before moving it's funds.
+///
+/// Frozen clients can not deposit, withdraw or hold funds. Disputes of
+/// their previous transactions are still processed.
+fn check_frozen(client: &Client, transaction_id: TransactionID) -> Result<(), TransactionError> {
+    if client.frozen {
+        return Err(TransactionError::new(
+            ErrorKind::Frozen,
+            format!(
+                "Transaction {} is not allowed, client {} is frozen.",
+                transaction_id, client.id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Held funds should always cover the amount, so this only fails if the
+/// client's state is inconsistent, e.g. after a partial dispute. Held funds
+/// short of the amount by a rounding error cover it.
+fn check_held(
+    held: Amount,
+    amount: Amount,
+    transaction_id: TransactionID,
+    client_id: ClientID,
+) -> Result<(), TransactionError> {
+    if exceeds(amount, held) {
+        return Err(TransactionError::new(
+            ErrorKind::NegativeHeld,
+            format!(
+                "Transaction {} would leave client {} with negative held funds.",
+                transaction_id, client_id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Release Transaction of an authorization hold
+pub(crate) struct Release {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+}
+
+impl Release {
+    /// Create a new Release for a client on a specific hold
+    pub fn new(transaction_id: TransactionID, client_id: ClientID) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+        }
+    }
+}
+
+impl Transaction for Release {
+    /// Release the funds of an open hold back to available.
+    ///
+    /// Releases of a locked account are allowed, so expired holds are
+    /// always released.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        match client.holds.get(&self.transaction_id).copied() {
+            Some(amount) => {
+                check_held(client.held, amount, self.transaction_id, self.client_id)?;
+                let _ = client.holds.remove(&self.transaction_id);
+                client.held = settle(client.held - amount, amount);
+                client.available += amount;
+                Ok(())
+            }
+            None => Err(TransactionError::new(
+                ErrorKind::TransactionNotFound,
+                format!(
+                    "Specified hold {} for client {} is not open.",
+                    self.transaction_id, self.client_id
+                ),
+            )),
+        }
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Releases reference a previous hold, so there is no amount
+    fn amount(&self) -> Option<Amount> {
+        None
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Release
+    }
 }
-struct Dispute {
+
+pub(crate) struct Dispute {
     transaction_id: TransactionID,
     client_id: ClientID,
 }
@@ -172,32 +691,29 @@ impl Transaction for Dispute {
     ///
     /// Mark a previous transaction as dispute and transation funds to held.
     /// If account is locked or the referenced transaction is not found,
-    /// the dispute is ignored. If the transaction is already under dispute,
-    /// the most recent dispute is also ignored.
+    /// the dispute is ignored. If the transaction is already under dispute
+    /// or was charged back, the most recent dispute is also ignored.
     fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
         if client.locked {
-            return Err("Could not dispute funds. Account is locked.".into());
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not dispute funds. Account is locked.".to_owned(),
+            ));
         }
         match client.client_history.get_mut(&self.transaction_id) {
             Some(history) => {
-                if !history.dispute {
-                    history.dispute = true;
-                    client.available -= history.amount;
-                    client.held += history.amount;
-                    Ok(())
-                } else {
-                    Err(format!(
-                        "Specified transaction {} for client {} is not already disputed.",
-                        self.transaction_id, self.client_id
-                    )
-                    .into())
-                }
+                history.transition(DisputeState::Disputed, self.transaction_id, self.client_id)?;
+                client.available -= history.amount;
+                client.held += history.amount;
+                Ok(())
             }
-            None => Err(format!(
-                "No transaction {} found for client {}",
-                self.transaction_id, self.client_id
-            )
-            .into()),
+            None => Err(TransactionError::new(
+                ErrorKind::TransactionNotFound,
+                format!(
+                    "No transaction {} found for client {}",
+                    self.transaction_id, self.client_id
+                ),
+            )),
         }
     }
 
@@ -213,6 +729,16 @@ impl Transaction for Dispute {
     fn amount(&self) -> Option<Amount> {
         None
     }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Dispute
+    }
 }
 
 struct Resolve {
@@ -239,28 +765,33 @@ impl Transaction for Resolve {
     /// the resolve is also ignored.
     fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
         if client.locked {
-            return Err("Could not resolve funds. Account is locked.".into());
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not resolve funds. Account is locked.".to_owned(),
+            ));
         }
         match client.client_history.get_mut(&self.transaction_id) {
             Some(history) => {
-                if history.dispute {
-                    history.dispute = false;
-                    client.available += history.amount;
-                    client.held -= history.amount;
-                    Ok(())
-                } else {
-                    Err(format!(
-                        "Specified transaction {} for client {} is not being disputed.",
-                        self.transaction_id, self.client_id
-                    )
-                    .into())
+                if history.state.can_become(DisputeState::Resolved) {
+                    check_held(
+                        client.held,
+                        history.amount,
+                        self.transaction_id,
+                        self.client_id,
+                    )?;
                 }
+                history.transition(DisputeState::Resolved, self.transaction_id, self.client_id)?;
+                client.available += history.amount;
+                client.held = settle(client.held - history.amount, history.amount);
+                Ok(())
             }
-            None => Err(format!(
-                "No transaction {} found for client {}",
-                self.transaction_id, self.client_id
-            )
-            .into()),
+            None => Err(TransactionError::new(
+                ErrorKind::TransactionNotFound,
+                format!(
+                    "No transaction {} found for client {}",
+                    self.transaction_id, self.client_id
+                ),
+            )),
         }
     }
 
@@ -276,6 +807,16 @@ impl Transaction for Resolve {
     fn amount(&self) -> Option<Amount> {
         None
     }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Resolve
+    }
 }
 
 struct Chargeback {
@@ -302,28 +843,37 @@ impl Transaction for Chargeback {
     /// the chargeback is also ignored.
     fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
         if client.locked {
-            return Err("Could not chargeback funds. Account is locked.".into());
+            return Err(TransactionError::new(
+                ErrorKind::AccountLocked,
+                "Could not chargeback funds. Account is locked.".to_owned(),
+            ));
         }
         match client.client_history.get_mut(&self.transaction_id) {
             Some(history) => {
-                if history.dispute {
-                    history.dispute = false;
-                    client.held -= history.amount;
-                    client.locked = true;
-                    Ok(())
-                } else {
-                    Err(format!(
-                        "Specified transaction {} for client {} is not being disputed.",
-                        self.transaction_id, self.client_id
-                    )
-                    .into())
+                if history.state.can_become(DisputeState::ChargedBack) {
+                    check_held(
+                        client.held,
+                        history.amount,
+                        self.transaction_id,
+                        self.client_id,
+                    )?;
                 }
+                history.transition(
+                    DisputeState::ChargedBack,
+                    self.transaction_id,
+                    self.client_id,
+                )?;
+                client.held = settle(client.held - history.amount, history.amount);
+                client.locked = true;
+                Ok(())
             }
-            None => Err(format!(
-                "No transaction {} found for client {}",
-                self.transaction_id, self.client_id
-            )
-            .into()),
+            None => Err(TransactionError::new(
+                ErrorKind::TransactionNotFound,
+                format!(
+                    "No transaction {} found for client {}",
+                    self.transaction_id, self.client_id
+                ),
+            )),
         }
     }
 
@@ -339,11 +889,203 @@ impl Transaction for Chargeback {
     fn amount(&self) -> Option<Amount> {
         None
     }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Chargeback
+    }
+}
+
+/// Chargeback reversal Transaction
+///
+/// Card networks sometimes reverse chargebacks, returning the funds to the client.
+struct ChargebackReversal {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+}
+
+impl ChargebackReversal {
+    /// Create a new ChargebackReversal for a client on a specific transaction
+    pub fn new(transaction_id: TransactionID, client_id: ClientID) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+        }
+    }
+}
+
+impl Transaction for ChargebackReversal {
+    /// Reverse the chargeback of a transaction.
+    ///
+    /// Restore the charged back amount to available and re-record the
+    /// history entry as reversed, so it can be disputed again.
+    /// Allowed on locked accounts, as the chargeback locked the account.
+    /// If the referenced transaction is not found or was not charged back,
+    /// the reversal is ignored.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        match client.client_history.get_mut(&self.transaction_id) {
+            Some(history) => {
+                history.transition(DisputeState::Reversed, self.transaction_id, self.client_id)?;
+                client.available += history.amount;
+                Ok(())
+            }
+            None => Err(TransactionError::new(
+                ErrorKind::TransactionNotFound,
+                format!(
+                    "No transaction {} found for client {}",
+                    self.transaction_id, self.client_id
+                ),
+            )),
+        }
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Chargeback reversals do not have an associated amount, rather they
+    /// refrence a previous transaction.
+    fn amount(&self) -> Option<Amount> {
+        None
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::ChargebackReversal
+    }
+}
+
+/// Verification Transaction
+///
+/// Administrative transaction setting whether a client passed identity verification.
+struct Verification {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+    verified: bool,
+}
+
+impl Verification {
+    /// Create a new Verification for a client, verifying or unverifying it
+    pub fn new(transaction_id: TransactionID, client_id: ClientID, verified: bool) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+            verified,
+        }
+    }
+}
+
+impl Transaction for Verification {
+    /// Set whether the client is verified.
+    ///
+    /// Allowed on locked accounts.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        client.verified = self.verified;
+        Ok(())
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Verifications change the client's status, so there is no amount
+    fn amount(&self) -> Option<Amount> {
+        None
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        if self.verified {
+            TransactionKind::Verify
+        } else {
+            TransactionKind::Unverify
+        }
+    }
+}
+
+/// Freeze Transaction
+///
+/// Administrative transaction pausing or resuming a client's account.
+/// Unlike the lock of a chargeback, a freeze can be lifted.
+pub(crate) struct Freeze {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+    frozen: bool,
+}
+
+impl Freeze {
+    /// Create a new Freeze for a client, freezing or unfreezing it
+    pub fn new(transaction_id: TransactionID, client_id: ClientID, frozen: bool) -> Self {
+        Self {
+            transaction_id,
+            client_id,
+            frozen,
+        }
+    }
+}
+
+impl Transaction for Freeze {
+    /// Set whether the client is frozen.
+    ///
+    /// Allowed on locked accounts.
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        client.frozen = self.frozen;
+        Ok(())
+    }
+
+    // Get the Client ID this transaction is meant to run against
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    /// Get the Amount of this transaction
+    ///
+    /// Freezes change the client's status, so there is no amount
+    fn amount(&self) -> Option<Amount> {
+        None
+    }
+
+    // Get the Transaction ID of this transaction
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    // Get the kind of this transaction
+    fn kind(&self) -> TransactionKind {
+        if self.frozen {
+            TransactionKind::Freeze
+        } else {
+            TransactionKind::Unfreeze
+        }
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::error::ErrorKind;
 
     #[test]
     fn deposit_345_4823_to_empty_client() {
@@ -406,7 +1148,9 @@ mod tests {
             client.client_history.get(&1),
             Some(&History {
                 amount: 5.0,
-                dispute: true
+                state: DisputeState::Disputed,
+                timestamp: None,
+                kind: None,
             })
         );
     }
@@ -429,7 +1173,9 @@ mod tests {
             client.client_history.get(&1),
             Some(&History {
                 amount: 5.0,
-                dispute: false
+                state: DisputeState::Resolved,
+                timestamp: None,
+                kind: None,
             })
         );
     }
@@ -453,11 +1199,84 @@ mod tests {
             client.client_history.get(&1),
             Some(&History {
                 amount: 5.0,
-                dispute: false
+                state: DisputeState::ChargedBack,
+                timestamp: None,
+                kind: None,
             })
         );
     }
 
+    #[test]
+    fn reverse_chargeback() {
+        let mut client = Client::new(157);
+        let deposit = Deposit::new(1, 157, 5.0);
+        let reversal = ChargebackReversal::new(1, 157);
+
+        deposit.execute(&mut client).unwrap();
+        // Loose error handling in place. Just verify an error is returned
+        reversal.execute(&mut client).unwrap_err();
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        Chargeback::new(1, 157).execute(&mut client).unwrap();
+        reversal.execute(&mut client).unwrap();
+        assert_eq!(client.available, 5.0000);
+        assert_eq!(client.held, 0.0000);
+        assert!(client.locked);
+        assert_eq!(
+            client.client_history.get(&1).unwrap().state,
+            DisputeState::Reversed
+        );
+        reversal.execute(&mut client).unwrap_err();
+    }
+
+    #[test]
+    fn negative_held_errors() {
+        let mut client = Client::new(157);
+        Deposit::new(1, 157, 5.0).execute(&mut client).unwrap();
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        // Held funds lost outside of the dispute
+        client.held = 2.0;
+        let err = Resolve::new(1, 157).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NegativeHeld);
+        let err = Chargeback::new(1, 157).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NegativeHeld);
+        assert_eq!((client.available, client.held), (0.0, 2.0));
+        assert!(client.client_history.get(&1).unwrap().is_disputed());
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn rounding_errors_of_held_tolerated() {
+        let mut client = Client::new(157);
+        Deposit::new(1, 157, 0.7).execute(&mut client).unwrap();
+        Deposit::new(2, 157, 0.1).execute(&mut client).unwrap();
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        Dispute::new(2, 157).execute(&mut client).unwrap();
+        Resolve::new(1, 157).execute(&mut client).unwrap();
+        Resolve::new(2, 157).execute(&mut client).unwrap();
+        assert_eq!(client.held, 0.0);
+        assert!((client.available - 0.8).abs() < 1e-9);
+        assert_eq!(
+            client.client_history.get(&2).unwrap().state,
+            DisputeState::Resolved
+        );
+    }
+
+    #[test]
+    fn illegal_dispute_transitions_error() {
+        let mut client = Client::new(157);
+        Deposit::new(1, 157, 5.0).execute(&mut client).unwrap();
+        let err = Resolve::new(1, 157).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidDisputeState);
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        Resolve::new(1, 157).execute(&mut client).unwrap();
+        let err = Chargeback::new(1, 157).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidDisputeState);
+        // Resolved transactions can be disputed again
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        assert!(client.client_history.get(&1).unwrap().is_disputed());
+        assert_eq!((client.available, client.held), (0.0, 5.0));
+    }
+
     #[test]
     fn deposit_to_locked_account_errors() {
         let amount = 345.4823;
@@ -469,6 +1288,31 @@ mod tests {
         transaction.execute(&mut client).unwrap_err();
     }
 
+    #[test]
+    fn frozen_account_paused() {
+        let mut client = Client::new(157);
+        Deposit::new(1, 157, 10.0).execute(&mut client).unwrap();
+        Freeze::new(2, 157, true).execute(&mut client).unwrap();
+        assert!(client.frozen);
+        assert!(!client.locked);
+
+        let err = Deposit::new(3, 157, 1.0).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Frozen);
+        let err = Withdrawal::new(4, 157, 1.0)
+            .execute(&mut client)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Frozen);
+        let err = Hold::new(5, 157, 1.0).execute(&mut client).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Frozen);
+        // Disputes of previous transactions are still processed
+        Dispute::new(1, 157).execute(&mut client).unwrap();
+        assert_eq!(client.held, 10.0);
+
+        Freeze::new(6, 157, false).execute(&mut client).unwrap();
+        Deposit::new(7, 157, 1.0).execute(&mut client).unwrap();
+        assert_eq!(client.available, 1.0);
+    }
+
     #[test]
     fn withdrawal_from_locked_account_errors() {
         let amount = 345.4823;
@@ -568,4 +1412,86 @@ mod tests {
         // Loose error handling in place. Just verify an error is returned
         chargeback.execute(&mut client).unwrap_err();
     }
+
+    #[test]
+    fn transaction_id_and_kind_accessors() {
+        let transactions: Vec<(Box<dyn Transaction>, TransactionKind)> = vec![
+            (
+                Box::new(Deposit::new(1, 157, 1.0)),
+                TransactionKind::Deposit,
+            ),
+            (
+                Box::new(Withdrawal::new(1, 157, 1.0)),
+                TransactionKind::Withdrawal,
+            ),
+            (Box::new(Dispute::new(1, 157)), TransactionKind::Dispute),
+            (
+                Box::new(Interest::new(1, 157, 1.0)),
+                TransactionKind::Interest,
+            ),
+            (Box::new(Hold::new(1, 157, 1.0)), TransactionKind::Hold),
+            (Box::new(Release::new(1, 157)), TransactionKind::Release),
+            (
+                Box::new(ChargebackReversal::new(1, 157)),
+                TransactionKind::ChargebackReversal,
+            ),
+            (Box::new(Resolve::new(1, 157)), TransactionKind::Resolve),
+            (
+                Box::new(Chargeback::new(1, 157)),
+                TransactionKind::Chargeback,
+            ),
+        ];
+        for (transaction, kind) in transactions {
+            assert_eq!(transaction.transaction_id(), 1);
+            assert_eq!(transaction.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn registry_rejects_built_in_type() {
+        let mut registry = TransactionRegistry::new();
+        registry
+            .register("deposit", |transaction_id, client_id, amount| {
+                Box::new(Deposit::new(transaction_id, client_id, amount))
+            })
+            .unwrap_err();
+    }
+
+    #[test]
+    fn registry_creates_registered_type() {
+        let mut registry = TransactionRegistry::new();
+        registry
+            .register("bonus", |transaction_id, client_id, amount| {
+                Box::new(Deposit::new(transaction_id, client_id, amount))
+            })
+            .unwrap();
+        let csv_line = CsvLine {
+            t_type: CsvLineType::Custom("bonus".to_owned()),
+            client: 157,
+            tx: 1,
+            amount: 2.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        let transaction = registry.create(csv_line).unwrap();
+        assert_eq!(transaction.requested_client_id(), 157);
+        assert_eq!(transaction.amount(), Some(2.0));
+    }
+
+    #[test]
+    fn registry_unknown_type_errors() {
+        let registry = TransactionRegistry::new();
+        let csv_line = CsvLine {
+            t_type: CsvLineType::Custom("bonus".to_owned()),
+            client: 157,
+            tx: 1,
+            amount: 2.0,
+            reference: None,
+            idempotency_key: None,
+            timestamp: None,
+        };
+        // Loose error handling in place. Just verify an error is returned
+        registry.create(csv_line).err().unwrap();
+    }
 }
@@ -0,0 +1,216 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{transaction::TransactionKind, Amount, ClientID};
+
+/// Largest difference between sums which are still considered equal
+///
+/// Sums of many amounts drift slightly due to floating point rounding.
+const TOLERANCE: Amount = 1e-6;
+
+/// Funds moved by the transactions applied to a ClientStore
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Flows {
+    /// Sum of the changes of the clients' totals by every transaction
+    pub net: Amount,
+    /// Sum of the deposited amounts
+    pub deposits: Amount,
+    /// Sum of the withdrawn amounts
+    pub withdrawals: Amount,
+    /// Sum of the funds taken by chargebacks
+    pub chargebacks: Amount,
+    /// Sum of the changes of the clients' totals by other transactions, e.g. interest or fees
+    pub other: Amount,
+}
+
+impl Flows {
+    /// Count an applied transaction which changed it's client's total by the delta.
+    pub(crate) fn count(&mut self, kind: TransactionKind, amount: Option<Amount>, delta: Amount) {
+        self.net += delta;
+        match kind {
+            TransactionKind::Deposit => self.deposits += amount.unwrap_or_default(),
+            TransactionKind::Withdrawal => self.withdrawals += amount.unwrap_or_default(),
+            TransactionKind::Chargeback => self.chargebacks -= delta,
+            _ => self.other += delta,
+        }
+    }
+
+    /// Get the net flow expected from deposits minus withdrawals minus
+    /// chargebacks, plus the other transactions
+    pub fn expected(&self) -> Amount {
+        self.deposits - self.withdrawals - self.chargebacks + self.other
+    }
+}
+
+/// Broken invariant of a trial balance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// Changes of the totals don't add up to deposits minus withdrawals minus chargebacks
+    UnbalancedFlows {
+        /// Sum of the changes of the totals
+        net: Amount,
+        /// Deposits minus withdrawals minus chargebacks, plus other transactions
+        expected: Amount,
+    },
+    /// Totals of the clients don't add up to the changes of the totals
+    UnbalancedTotals {
+        /// Sum of the clients' totals
+        totals: Amount,
+        /// Sum of the changes of the totals
+        net: Amount,
+    },
+    /// Client's total is not it's available plus held funds
+    InconsistentTotal {
+        /// Client of the total
+        client: ClientID,
+        /// Total funds of the client
+        total: Amount,
+        /// Available plus held funds of the client
+        expected: Amount,
+    },
+    /// Client has negative held funds
+    NegativeHeld {
+        /// Client with the held funds
+        client: ClientID,
+        /// Held funds of the client
+        held: Amount,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::UnbalancedFlows { net, expected } => write!(
+                f,
+                "Net flow of {} is not deposits minus withdrawals minus chargebacks of {}",
+                net, expected
+            ),
+            Self::UnbalancedTotals { totals, net } => write!(
+                f,
+                "Client totals of {} are not the net flow of {}",
+                totals, net
+            ),
+            Self::InconsistentTotal {
+                client,
+                total,
+                expected,
+            } => write!(
+                f,
+                "Total {} of client {} is not available plus held of {}",
+                total, client, expected
+            ),
+            Self::NegativeHeld { client, held } => {
+                write!(f, "Client {} has negative held funds of {}", client, held)
+            }
+        }
+    }
+}
+
+/// Check of the global invariants of a ClientStore
+///
+/// The store is balanced if it's flows add up, the clients' totals add up to
+/// the net flow, every total is available plus held and no held funds are
+/// negative.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrialBalance {
+    /// Funds moved by the applied transactions
+    pub flows: Flows,
+    /// Sum of the clients' totals
+    pub totals: Amount,
+    /// Broken invariants
+    pub violations: Vec<Violation>,
+}
+
+impl TrialBalance {
+    /// Start a trial balance of the flows.
+    pub(crate) fn new(flows: Flows) -> Self {
+        let mut balance = Self {
+            flows,
+            ..Default::default()
+        };
+        if !approx_eq(flows.net, flows.expected()) {
+            balance.violations.push(Violation::UnbalancedFlows {
+                net: flows.net,
+                expected: flows.expected(),
+            });
+        }
+        balance
+    }
+
+    /// Check the balances of a client.
+    pub(crate) fn add(&mut self, client: ClientID, available: Amount, held: Amount, total: Amount) {
+        self.totals += total;
+        if !approx_eq(total, available + held) {
+            self.violations.push(Violation::InconsistentTotal {
+                client,
+                total,
+                expected: available + held,
+            });
+        }
+        if held < -TOLERANCE {
+            self.violations
+                .push(Violation::NegativeHeld { client, held });
+        }
+    }
+
+    /// Check the sum of the clients' totals once every client was added.
+    pub(crate) fn finish(mut self) -> Self {
+        if !approx_eq(self.totals, self.flows.net) {
+            self.violations.push(Violation::UnbalancedTotals {
+                totals: self.totals,
+                net: self.flows.net,
+            });
+        }
+        self
+    }
+
+    /// Check if no invariant is broken
+    pub fn is_balanced(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Check if the amounts are equal within the tolerance, relative to large amounts.
+fn approx_eq(a: Amount, b: Amount) -> bool {
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn violations_found() {
+        let mut flows = Flows::default();
+        flows.count(TransactionKind::Deposit, Some(10.0), 10.0);
+        flows.count(TransactionKind::Withdrawal, Some(3.0), -3.0);
+        flows.count(TransactionKind::Chargeback, None, -2.0);
+        flows.count(TransactionKind::Interest, Some(0.1), 0.1);
+        assert_eq!(flows.expected(), 5.1);
+
+        let mut balance = TrialBalance::new(flows);
+        balance.add(1, 5.1, 0.0, 5.1);
+        assert!(balance.clone().finish().is_balanced());
+        balance.add(2, 1.0, -1.0, 0.0);
+        balance.add(3, Amount::NAN, 0.0, Amount::NAN);
+        let balance = balance.finish();
+        assert_eq!(balance.violations.len(), 3);
+        assert_eq!(
+            balance.violations[0],
+            Violation::NegativeHeld {
+                client: 2,
+                held: -1.0
+            }
+        );
+        assert!(matches!(
+            balance.violations[1],
+            Violation::InconsistentTotal { client: 3, .. }
+        ));
+        assert!(matches!(
+            balance.violations[2],
+            Violation::UnbalancedTotals { .. }
+        ));
+
+        flows.deposits += 1.0;
+        assert!(!TrialBalance::new(flows).finish().is_balanced());
+    }
+}
@@ -0,0 +1,116 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    client::ClientStore,
+    error::TransactionError,
+    handle_transactions_with_registry,
+    output::{JsonFormat, OutputOptions},
+    transaction::TransactionRegistry,
+    Amount, ClientID, TransactionID,
+};
+
+/// Convert an error to a JavaScript `Error`
+fn js_error(err: TransactionError) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// Handle CSV transactions and get the final state of all clients as CSV.
+///
+/// Clients are sorted by their client. Rows which can not be handled are
+/// logged and ignored, like the command line tool.
+#[wasm_bindgen]
+pub fn process_csv(input: &str) -> Result<String, JsError> {
+    let mut engine = Engine::new();
+    engine.process_csv(input);
+    engine.state_csv()
+}
+
+/// Transaction engine holding the state of clients between calls
+#[wasm_bindgen]
+pub struct Engine {
+    store: ClientStore,
+    registry: TransactionRegistry,
+}
+
+#[wasm_bindgen]
+impl Engine {
+    /// Create a new Engine with no clients
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            store: ClientStore::new(),
+            registry: TransactionRegistry::new(),
+        }
+    }
+
+    /// Handle CSV transactions, returning the number of applied transactions.
+    pub fn process_csv(&mut self, input: &str) -> u64 {
+        handle_transactions_with_registry(input.as_bytes(), &self.registry, &mut self.store).applied
+    }
+
+    /// Submit a single transaction.
+    ///
+    /// The type is a type name of the input, e.g. `deposit`. Disputes,
+    /// resolves and chargebacks have no amount. Throws if the transaction
+    /// is rejected.
+    pub fn submit(
+        &mut self,
+        t_type: &str,
+        client: ClientID,
+        tx: TransactionID,
+        amount: Option<Amount>,
+    ) -> Result<(), JsError> {
+        let amount = amount.unwrap_or(0.0);
+        if !amount.is_finite() {
+            return Err(JsError::new("Amount must be a finite number"));
+        }
+        let transaction = self
+            .registry
+            .create_named(t_type, client, tx, amount)
+            .map_err(js_error)?;
+        self.store.execute(transaction.as_ref()).map_err(js_error)
+    }
+
+    /// Get the current state of all clients as CSV, sorted by their client
+    pub fn state_csv(&self) -> Result<String, JsError> {
+        self.store.get_current_state(true).map_err(js_error)
+    }
+
+    /// Get the current state of all clients as a JSON array, sorted by their client
+    pub fn state_json(&self) -> Result<String, JsError> {
+        let mut state = Vec::new();
+        self.store
+            .write_state(
+                &mut JsonFormat::new(&mut state, &OutputOptions::default()),
+                true,
+            )
+            .map_err(js_error)?;
+        String::from_utf8(state).map_err(|err| js_error(err.into()))
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_state_json() {
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.process_csv("t_type,client,tx,amount\ndeposit,2,1,3.0\n"),
+            1
+        );
+        engine.submit("deposit", 1, 2, Some(1.5)).unwrap();
+        engine.submit("dispute", 1, 2, None).unwrap();
+        assert_eq!(
+            engine.state_json().unwrap(),
+            r#"[{"client":1,"available":0.0,"held":1.5,"total":1.5,"locked":false},{"client":2,"available":3.0,"held":0.0,"total":3.0,"locked":false}]"#
+        );
+    }
+}
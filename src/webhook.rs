@@ -0,0 +1,196 @@
+use std::{
+    sync::mpsc::{channel, Sender},
+    thread::{self, JoinHandle},
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::TransactionError,
+    observer::{AccountEvent, TransactionObserver},
+    remote::{backoff, retryable},
+    transaction::TransactionKind,
+    Amount, ClientID, Timestamp, TransactionID,
+};
+
+/// Critical event webhooks are called for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CriticalEvent {
+    /// The client was locked
+    AccountLocked,
+    /// A disputed transaction was charged back
+    ChargebackApplied,
+    /// The available funds of the client went negative
+    NegativeBalance,
+}
+
+/// JSON payload webhooks are called with
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookPayload {
+    /// Critical event which occurred
+    pub event: CriticalEvent,
+    /// Client of the event
+    pub client: ClientID,
+    /// Transaction causing the event
+    pub tx: TransactionID,
+    /// Kind of the transaction
+    pub kind: TransactionKind,
+    /// Funds available to the client after the transaction
+    pub available: Amount,
+    /// Funds held by disputes after the transaction
+    pub held: Amount,
+    /// Time of the transaction, if known
+    pub timestamp: Option<Timestamp>,
+}
+
+impl WebhookPayload {
+    /// Get the payloads of the critical events of a change of a client's state.
+    pub fn from_account_event(event: &AccountEvent) -> Vec<Self> {
+        let payload = |critical| Self {
+            event: critical,
+            client: event.client,
+            tx: event.tx,
+            kind: event.kind,
+            available: event.after.available,
+            held: event.after.held,
+            timestamp: event.timestamp,
+        };
+        let mut payloads = Vec::new();
+        if event.kind == TransactionKind::Chargeback {
+            payloads.push(payload(CriticalEvent::ChargebackApplied));
+        }
+        if event.locked() {
+            payloads.push(payload(CriticalEvent::AccountLocked));
+        }
+        if event.before.available >= 0.0 && event.after.available < 0.0 {
+            payloads.push(payload(CriticalEvent::NegativeBalance));
+        }
+        payloads
+    }
+}
+
+/// Observer calling webhooks on critical events
+///
+/// Accounts getting locked, chargebacks and balances going negative are
+/// posted as a JSON `WebhookPayload` to every URL. Webhooks are called on a
+/// background thread so transactions never wait on them. Failed calls are
+/// retried with an increasing delay, then logged to stderr. Pending calls are
+/// completed when the notifier is dropped with it's store.
+pub struct WebhookNotifier {
+    sender: Option<Sender<WebhookPayload>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl WebhookNotifier {
+    /// Create a new WebhookNotifier calling the URLs, retrying failed calls up to the number of retries
+    pub fn new(urls: Vec<String>, retries: u32) -> Self {
+        let (sender, receiver) = channel::<WebhookPayload>();
+        let worker = thread::spawn(move || {
+            for payload in receiver {
+                let body = match serde_json::to_string(&payload) {
+                    Ok(body) => body,
+                    Err(err) => {
+                        eprintln!("Couldn't serialize webhook payload: {}", err);
+                        continue;
+                    }
+                };
+                for url in &urls {
+                    if let Err(err) = post(url, &body, retries) {
+                        eprintln!("{}", err);
+                    }
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl TransactionObserver for WebhookNotifier {
+    fn on_event(&mut self, event: &AccountEvent) {
+        if let Some(sender) = &self.sender {
+            for payload in WebhookPayload::from_account_event(event) {
+                let _ = sender.send(payload);
+            }
+        }
+    }
+}
+
+impl Drop for WebhookNotifier {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish the pending calls
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Post the body to the URL, retrying failed calls up to the number of retries.
+fn post(url: &str, body: &str, retries: u32) -> Result<(), TransactionError> {
+    let mut attempts = 0;
+    loop {
+        match ureq::post(url)
+            .set("Content-Type", "application/json")
+            .send_string(body)
+        {
+            Ok(_response) => return Ok(()),
+            Err(err) if attempts < retries && retryable(&err) => {
+                thread::sleep(backoff(attempts));
+                attempts += 1;
+            }
+            Err(err) => return Err(format!("Couldn't call webhook {}: {}", url, err).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Balances;
+
+    #[test]
+    fn critical_events_of_chargeback() {
+        let balances = |available, locked| Balances {
+            available,
+            held: 0.0,
+            locked,
+        };
+        let event = AccountEvent {
+            client: 3,
+            tx: 9,
+            kind: TransactionKind::Chargeback,
+            before: balances(2.0, false),
+            after: balances(-1.0, true),
+            timestamp: Some(1_700_000_000),
+        };
+        let events: Vec<CriticalEvent> = WebhookPayload::from_account_event(&event)
+            .iter()
+            .map(|payload| payload.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                CriticalEvent::ChargebackApplied,
+                CriticalEvent::AccountLocked,
+                CriticalEvent::NegativeBalance,
+            ]
+        );
+        let payload = &WebhookPayload::from_account_event(&event)[0];
+        assert_eq!(
+            serde_json::to_string(payload).unwrap(),
+            r#"{"event":"chargeback_applied","client":3,"tx":9,"kind":"chargeback","available":-1.0,"held":0.0,"timestamp":1700000000}"#
+        );
+
+        let deposit = AccountEvent {
+            kind: TransactionKind::Deposit,
+            before: balances(-1.0, false),
+            after: balances(-0.5, false),
+            ..event
+        };
+        assert!(WebhookPayload::from_account_event(&deposit).is_empty());
+    }
+}
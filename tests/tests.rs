@@ -1,121 +1,750 @@
-use transactions::{self, client::ClientStore};
+use transactions::{
+    self,
+    amount::{AmountFormat, PrecisionPolicy, RoundingMode},
+    client::{Client, ClientStore},
+    clock::ManualClock,
+    config::{NegativeAvailablePolicy, StoreConfig},
+    error::{ErrorKind, TransactionError},
+    fixture,
+    observer::{AccountEvent, Balances, EventCallback},
+    output::OutputOptions,
+    report::ProcessingReport,
+    storage::ClientStorage,
+    transaction::{Transaction, TransactionKind, TransactionRegistry},
+    Amount, ClientID, ColumnMapping, CsvOptions, ParseOptions, ParserKind, TransactionID,
+};
 
 #[test]
 fn handle_transations_deposits_one_client() {
-    let csv = include_str!("../data/deposit_one_client.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n";
+    fixture::run_fixture(
+        include_str!("../data/deposit_one_client.csv"),
+        include_str!("../data/deposit_one_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_multi_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_multi_client.csv"),
+        include_str!("../data/deposit_multi_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_and_withdrawals_one_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_and_withdrawal_one_client.csv"),
+        include_str!("../data/deposit_and_withdrawal_one_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_and_withdrawals_multi_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_and_withdrawal_multi_client.csv"),
+        include_str!("../data/deposit_and_withdrawal_multi_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_and_withdrawals_one_client_insufficent_funds() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_and_withdrawal_one_client_insufficent_funds.csv"),
+        include_str!("../data/deposit_and_withdrawal_one_client_insufficent_funds.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_and_withdrawals_multi_client_insufficent_funds() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_and_withdrawal_multi_client_insufficent_funds.csv"),
+        include_str!("../data/deposit_and_withdrawal_multi_client_insufficent_funds.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_and_dispute_one_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_and_dispute_one_client.csv"),
+        include_str!("../data/deposit_withdrawal_and_dispute_one_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_and_dispute_multi_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_and_dispute_multi_client.csv"),
+        include_str!("../data/deposit_withdrawal_and_dispute_multi_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_dispute_and_resolve_one_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_dispute_and_resolve_one_client.csv"),
+        include_str!("../data/deposit_withdrawal_dispute_and_resolve_one_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_dispute_and_resolve_multi_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_dispute_and_resolve_multi_client.csv"),
+        include_str!("../data/deposit_withdrawal_dispute_and_resolve_multi_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_dispute_and_chargeback_one_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_dispute_and_chargeback_one_client.csv"),
+        include_str!("../data/deposit_withdrawal_dispute_and_chargeback_one_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn handle_transations_deposits_withdrawals_dispute_and_chargeback_multi_client() {
+    fixture::run_fixture(
+        include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv"),
+        include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.expected.csv"),
+    )
+    .unwrap();
+}
+
+struct Fee {
+    transaction_id: TransactionID,
+    client_id: ClientID,
+    amount: Amount,
+}
+
+impl Transaction for Fee {
+    fn execute(&self, client: &mut Client) -> Result<(), TransactionError> {
+        client.available -= self.amount;
+        Ok(())
+    }
+
+    fn requested_client_id(&self) -> ClientID {
+        self.client_id
+    }
+
+    fn amount(&self) -> Option<Amount> {
+        Some(self.amount)
+    }
+
+    fn transaction_id(&self) -> TransactionID {
+        self.transaction_id
+    }
+
+    fn kind(&self) -> TransactionKind {
+        TransactionKind::Custom("fee")
+    }
+}
+
+#[test]
+fn handle_transations_deposits_and_custom_fee_one_client() {
+    let csv = include_str!("../data/deposit_and_custom_fee_one_client.csv");
+    // adjustment is not registered and is ignored
+    let expected = "client,available,held,total,locked\n1,3.5,0.0,3.5,false\n";
+    let mut registry = TransactionRegistry::new();
+    registry
+        .register("fee", |transaction_id, client_id, amount| {
+            Box::new(Fee {
+                transaction_id,
+                client_id,
+                amount,
+            })
+        })
+        .unwrap();
     let mut client_store = ClientStore::new();
+    transactions::handle_transactions_with_registry(csv.as_bytes(), &registry, &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(state, expected);
+}
+
+#[test]
+fn handle_transations_with_history_spilled_to_disk() {
+    let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv");
+    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,true\n2,7.5,0.0,7.5,true\n3,10.5,0.0,10.5,true\n";
+    // Budget for a single history entry in memory
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        history_memory_budget: Some(1),
+        ..Default::default()
+    });
     transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_multi_client() {
-    let csv = include_str!("../data/deposit_multi_client.csv");
+fn handle_transations_skip_withdrawal_history() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.5\ndispute,1,2,\ndispute,1,1,\n";
+    // Dispute of the withdrawal is ignored since it was not recorded
+    let expected = "client,available,held,total,locked\n1,-1.5,5.0,3.5,false\n";
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        skip_withdrawal_history: true,
+        ..Default::default()
+    });
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(state, expected);
+    assert_eq!(
+        client_store.clients.get(&1).unwrap().client_history.len(),
+        1
+    );
+}
+
+#[test]
+fn handle_transations_with_dense_client_storage() {
+    let csv = include_str!("../data/deposit_withdrawal_dispute_and_resolve_multi_client.csv");
     let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n2,10.0,0.0,10.0,false\n3,14.0,0.0,14.0,false\n";
-    let mut client_store = ClientStore::new();
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        client_storage: ClientStorage::Dense,
+        ..Default::default()
+    });
     transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(false).unwrap();
+    assert_eq!(state, expected);
+}
+
+#[test]
+fn handle_transations_with_byte_record_parser() {
+    let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv");
+    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,true\n2,7.5,0.0,7.5,true\n3,10.5,0.0,10.5,true\n";
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_with_parser(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        ParserKind::ByteRecord,
+        &mut client_store,
+    );
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_and_withdrawals_one_client() {
-    let csv = include_str!("../data/deposit_and_withdrawal_one_client.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n";
+fn handle_transations_pipelined() {
+    let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv");
+    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,true\n2,7.5,0.0,7.5,true\n3,10.5,0.0,10.5,true\n";
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    transactions::parallel::handle_transactions_pipelined(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &ParseOptions::default(),
+        &mut client_store,
+    );
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_and_withdrawals_multi_client() {
-    let csv = include_str!("../data/deposit_and_withdrawal_multi_client.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n2,10.0,0.0,10.0,false\n3,14.0,0.0,14.0,false\n";
+fn handle_transations_parsed_in_parallel() {
+    let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv");
+    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,true\n2,7.5,0.0,7.5,true\n3,10.5,0.0,10.5,true\n";
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    transactions::parallel::handle_transactions_parallel(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &ParseOptions::default(),
+        4,
+        &mut client_store,
+    )
+    .unwrap();
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_and_withdrawals_one_client_insufficent_funds() {
-    let csv = include_str!("../data/deposit_and_withdrawal_one_client_insufficent_funds.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n";
+fn handle_transations_with_amount_format() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,\"$1,000.50\"\ndeposit,1,2,NaN\ndeposit,1,3,0.00001\nwithdrawal,1,4,$0.5\n";
+    let expected = "client,available,held,total,locked\n1,1000.0,0.0,1000.0,false\n";
+    let options = ParseOptions {
+        amount_format: AmountFormat {
+            max_decimal_places: Some(4),
+            currency_symbols: vec!["$".to_owned()],
+            thousands_separator: Some(','),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    transactions::handle_transactions_with_parse_options(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &options,
+        &mut client_store,
+    );
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_and_withdrawals_multi_client_insufficent_funds() {
-    let csv = include_str!("../data/deposit_and_withdrawal_multi_client_insufficent_funds.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n2,10.0,0.0,10.0,false\n3,14.0,0.0,14.0,false\n";
+fn handle_transations_rounding_precision_recorded_in_report() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,1.00005\nwithdrawal,1,2,5.0\ndeposit,2,3,1.5\n";
+    let expected =
+        "client,available,held,total,locked\n1,1.0001,0.0,1.0001,false\n2,1.5,0.0,1.5,false\n";
+    let options = ParseOptions {
+        amount_format: AmountFormat {
+            max_decimal_places: Some(4),
+            precision_policy: PrecisionPolicy::Round(RoundingMode::HalfUp),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report = transactions::handle_transactions_with_parse_options(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &options,
+        &mut client_store,
+    );
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
+    assert_eq!(
+        report,
+        ProcessingReport {
+            rows_read: 3,
+            applied: 2,
+            rejected: 1,
+            rejected_by_kind: [(ErrorKind::InsufficientFunds, 1)].into_iter().collect(),
+            rounded_amounts: 1,
+            bytes_read: csv.len() as u64,
+            // Timings differ between runs
+            timings: report.timings,
+            files: Vec::new(),
+        }
+    );
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_and_dispute_one_client() {
-    let csv = include_str!("../data/deposit_withdrawal_and_dispute_one_client.csv");
-    let expected = "client,available,held,total,locked\n1,4.5,1.5,6.0,false\n";
+fn handle_transations_directory_in_order() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/data/hourly_shards");
+    let expected = "client,available,held,total,locked\n1,0.0,5.0,5.0,false\n2,3.0,0.0,3.0,false\n";
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report = transactions::handle_transactions_from_directory(
+        directory,
+        &TransactionRegistry::new(),
+        &ParseOptions::default(),
+        &mut client_store,
+    )
+    .unwrap();
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
+    assert_eq!(
+        (report.rows_read, report.applied, report.rejected),
+        (4, 3, 1)
+    );
+    let files: Vec<_> = report
+        .files
+        .iter()
+        .map(|(path, file)| (path.file_name().unwrap().to_owned(), file.rejected))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            ("settlement-00.csv".into(), 0),
+            ("settlement-01.csv".into(), 1)
+        ]
+    );
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_and_dispute_multi_client() {
-    let csv = include_str!("../data/deposit_withdrawal_and_dispute_multi_client.csv");
-    let expected = "client,available,held,total,locked\n1,4.5,1.5,6.0,false\n2,7.5,2.5,10.0,false\n3,10.5,3.5,14.0,false\n";
+fn handle_transations_strict_parsing() {
+    let csv = "t_type,client,tx,amount\r\ndeposit,1,1,1.0\r\ndeposit,1,2\r\ndeposit,1,3,2\"0\r\ndeposit,1,4,1.0,extra\r\ndeposit,1\r,5,1.0\r\ndeposit,1,6,2.0\r\n";
+    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n";
+    let options = ParseOptions {
+        strict: true,
+        ..Default::default()
+    };
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report = transactions::handle_transactions_with_parse_options(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &options,
+        &mut client_store,
+    );
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
+    assert_eq!(report.rejected, 4);
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_dispute_and_resolve_one_client() {
-    let csv = include_str!("../data/deposit_withdrawal_dispute_and_resolve_one_client.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n";
+fn handle_transations_byte_order_marks() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,1.5\n";
+    let expected = "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n";
+
+    let mut utf8 = b"\xEF\xBB\xBF".to_vec();
+    utf8.extend_from_slice(csv.as_bytes());
+    let mut utf16 = vec![0xFF, 0xFE];
+    for unit in csv.encode_utf16() {
+        utf16.extend_from_slice(&unit.to_le_bytes());
+    }
+    for input in [utf8, utf16] {
+        let mut client_store = ClientStore::new();
+        let _ = transactions::handle_transactions_from_reader(input.as_slice(), &mut client_store);
+        let state = client_store.get_current_state(true).unwrap();
+        assert_eq!(state, expected);
+    }
+}
+
+#[test]
+fn handle_transations_mapped_columns() {
+    let csv = include_str!("../data/deposit_and_withdrawal_mapped_columns.csv");
+    let expected = "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n2,2.0,0.0,2.0,false\n";
+    let options = ParseOptions {
+        columns: ColumnMapping {
+            t_type: "txn_type".to_owned(),
+            client: "customer_id".to_owned(),
+            tx: "reference".to_owned(),
+            amount: "value".to_owned(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for parser in [ParserKind::Serde, ParserKind::ByteRecord] {
+        let options = ParseOptions {
+            parser,
+            ..options.clone()
+        };
+        let mut client_store = ClientStore::new();
+        let report = transactions::handle_transactions_with_parse_options(
+            csv.as_bytes(),
+            &TransactionRegistry::new(),
+            &options,
+            &mut client_store,
+        );
+        let state = client_store.get_current_state(true).unwrap();
+        assert_eq!(state, expected);
+        assert_eq!(report.applied, 3);
+    }
+}
+
+#[test]
+fn handle_transations_with_csv_options() {
+    let csv = "deposit;1;1;1.5\ndeposit;2;2;2.0\nwithdrawal;1;3;0.5\n";
+    let expected = "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n2,2.0,0.0,2.0,false\n";
+    let options = CsvOptions::new().has_headers(false).delimiter(b';');
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report =
+        transactions::handle_transactions_with_options(csv.as_bytes(), &options, &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(state, expected);
+    assert_eq!(report.applied, 3);
+
+    let mut client_store = ClientStore::new();
+    transactions::parallel::handle_transactions_parallel(
+        csv.as_bytes(),
+        &TransactionRegistry::new(),
+        &ParseOptions {
+            csv: options,
+            ..Default::default()
+        },
+        2,
+        &mut client_store,
+    )
+    .unwrap();
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_dispute_and_resolve_multi_client() {
-    let csv = include_str!("../data/deposit_withdrawal_dispute_and_resolve_multi_client.csv");
-    let expected = "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n2,10.0,0.0,10.0,false\n3,14.0,0.0,14.0,false\n";
+fn handle_transations_with_csv_options_not_flexible() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1\n";
+    let expected = "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n";
+    let options = CsvOptions::new().flexible(false);
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report =
+        transactions::handle_transactions_with_options(csv.as_bytes(), &options, &mut client_store);
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
+    assert_eq!(report.rejected, 1);
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_dispute_and_chargeback_one_client() {
+fn handle_transations_account_event_stream() {
     let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_one_client.csv");
-    let expected = "client,available,held,total,locked\n1,1.5,0.0,1.5,true\n";
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut client_store = ClientStore::new();
+    client_store.add_observer(sender);
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let events: Vec<AccountEvent> = receiver.try_iter().collect();
+
+    let first = &events[0];
+    assert_eq!(first.kind, TransactionKind::Deposit);
+    assert_eq!(first.before.total(), 0.0);
+    // Every event starts from the state the previous event of the client ended in
+    for pair in events.windows(2) {
+        if pair[0].client == pair[1].client {
+            assert_eq!(pair[0].after, pair[1].before);
+        }
+    }
+    let last = events.last().unwrap();
+    assert_eq!(last.kind, TransactionKind::Chargeback);
+    assert!(last.locked());
+}
+
+#[test]
+fn handle_transations_account_event_callback() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\n";
+    let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut client_store = ClientStore::new();
+    let sink = std::rc::Rc::clone(&events);
+    client_store.add_observer(EventCallback(move |event: &AccountEvent| {
+        sink.borrow_mut().push(event.clone())
+    }));
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let withdrawal = AccountEvent {
+        client: 1,
+        tx: 2,
+        kind: TransactionKind::Withdrawal,
+        before: Balances {
+            available: 2.0,
+            held: 0.0,
+            locked: false,
+        },
+        after: Balances {
+            available: 1.5,
+            held: 0.0,
+            locked: false,
+        },
+        timestamp: None,
+    };
+    assert_eq!(events.borrow().len(), 2);
+    assert_eq!(events.borrow()[1], withdrawal);
+}
+
+#[test]
+fn handle_transations_changed_clients_only() {
+    let csv = include_str!("../data/deposit_multi_client.csv");
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    client_store.clear_changes();
+
+    // Second run over the same store only changes client 2, a failed withdrawal changes nothing
+    let csv = "t_type,client,tx,amount\ndeposit,2,100,1.0\nwithdrawal,3,101,100.0\n";
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let options = OutputOptions {
+        sort: true,
+        changed_only: true,
+        ..Default::default()
+    };
+    let state = client_store
+        .get_current_state_with_options(&options)
+        .unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked,changed\n2,11.0,0.0,11.0,false,true\n"
+    );
+}
+
+#[test]
+fn handle_transations_marks_changed_clients() {
+    let csv = include_str!("../data/deposit_multi_client.csv");
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    client_store.clear_changes();
+
+    // Client 1 is changed back to it's previous state and client 4 is new
+    let csv = "t_type,client,tx,amount\ndeposit,1,100,1.0\nwithdrawal,1,101,1.0\n\
+               deposit,2,102,1.0\ndeposit,4,103,0.0\n";
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let options = OutputOptions {
+        mark_changed: true,
+        ..Default::default()
+    };
+    let state = client_store
+        .get_current_state_with_options(&options)
+        .unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked,changed\n\
+         1,6.0,0.0,6.0,false,false\n\
+         2,11.0,0.0,11.0,false,true\n\
+         3,14.0,0.0,14.0,false,false\n\
+         4,0.0,0.0,0.0,false,true\n"
+    );
+    assert_eq!(client_store.changed_clients().count(), 2);
+}
+
+#[test]
+fn handle_transations_dispute_after_withdrawal() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,1.5\ndispute,1,1,\n";
+
+    // Allowed by default, leaving available funds negative
     let mut client_store = ClientStore::new();
     transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
     let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,-1.5,2.0,0.5,false\n"
+    );
+
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        negative_available: NegativeAvailablePolicy::Reject,
+        ..Default::default()
+    });
+    let report = transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,0.5,0.0,0.5,false\n"
+    );
+    assert_eq!(report.rejected, 1);
+
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        negative_available: NegativeAvailablePolicy::Flag,
+        ..Default::default()
+    });
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let flagged: Vec<ClientID> = client_store
+        .flagged_clients()
+        .map(|client| client.id)
+        .collect();
+    assert_eq!(flagged, vec![1]);
+    assert_eq!(client_store.clients.get(&1).unwrap().available, -1.5);
+}
+
+#[cfg(any(feature = "client-id-u32", feature = "client-id-u64"))]
+#[test]
+fn handle_transations_wide_client_ids() {
+    let csv = "t_type,client,tx,amount\ndeposit,70000,1,1.0\ndeposit,4000000000,2,2.0\n";
+    let expected = "client,available,held,total,locked\n70000,1.0,0.0,1.0,false\n4000000000,2.0,0.0,2.0,false\n";
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        history_memory_budget: Some(1),
+        ..Default::default()
+    });
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
 }
 
 #[test]
-fn handle_transations_deposits_withdrawals_dispute_and_chargeback_multi_client() {
-    let csv = include_str!("../data/deposit_withdrawal_dispute_and_chargeback_multi_client.csv");
-    let expected = "client,available,held,total,locked\n1,3.0,0.0,3.0,true\n2,7.5,0.0,7.5,true\n3,10.5,0.0,10.5,true\n";
+fn handle_transations_by_reference() {
+    let csv = include_str!("../data/deposit_and_dispute_by_reference.csv");
+    let expected = "client,available,held,total,locked\n1,0.0,5.0,5.0,true\n2,4.0,0.0,4.0,false\n";
     let mut client_store = ClientStore::new();
-    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let report = transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
     let state = client_store.get_current_state(true).unwrap();
     assert_eq!(state, expected);
+    assert_eq!(report.rejected, 1);
+    assert_eq!(client_store.references().get("BANK-0003"), Some(3));
+}
+
+#[test]
+fn handle_transations_timestamps() {
+    let csv = "t_type,client,tx,amount,timestamp\ndeposit,1,1,2.0,1700000000\ndeposit,1,2,1.0,\n";
+    let clock = ManualClock::new(1_800_000_000);
+    let mut client_store = ClientStore::new();
+    client_store.set_clock(clock.clone());
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let history = &client_store.clients.get(&1).unwrap().client_history;
+    // Timestamps from the input take precedence over the clock
+    assert_eq!(history.get(&1).unwrap().timestamp, Some(1_700_000_000));
+    assert_eq!(history.get(&2).unwrap().timestamp, Some(1_800_000_000));
+
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let history = &client_store.clients.get(&1).unwrap().client_history;
+    assert_eq!(history.get(&2).unwrap().timestamp, None);
+}
+
+#[test]
+fn handle_transations_holds_expire() {
+    let csv = "t_type,client,tx,amount,timestamp\ndeposit,1,1,10.0,0\nhold,1,2,4.0,100\nhold,1,3,3.0,200\nrelease,1,2,,300\nhold,1,4,100.0,400\n";
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        hold_expiry: Some(3600),
+        ..Default::default()
+    });
+    let report = transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    assert_eq!(report.rejected, 1);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,7.0,3.0,10.0,false\n"
+    );
+
+    // Hold 3 expires at 3800
+    assert_eq!(client_store.expire_holds(3799), 0);
+    assert_eq!(client_store.expire_holds(3800), 1);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,10.0,0.0,10.0,false\n"
+    );
+}
+
+#[test]
+fn handle_transations_chargeback_reversal() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\nchargeback,1,1,\nchargeback_reversal,1,1,\n";
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,2.0,0.0,2.0,true\n"
+    );
+
+    let mut client_store = ClientStore::with_config(StoreConfig {
+        reversal_unlocks: true,
+        ..Default::default()
+    });
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store.get_current_state(true).unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,2.0,0.0,2.0,false\n"
+    );
+}
+
+#[test]
+fn handle_transations_freeze() {
+    let csv = "t_type,client,tx,amount\ndeposit,1,1,5.0\nfreeze,1,0,\ndeposit,1,2,1.0\nwithdrawal,1,3,1.0\ndispute,1,1,\n";
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let options = OutputOptions {
+        sort: true,
+        status: true,
+        ..Default::default()
+    };
+    let state = client_store
+        .get_current_state_with_options(&options)
+        .unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked,status\n1,0.0,5.0,5.0,false,frozen\n"
+    );
+
+    let csv = "t_type,client,tx,amount\nunfreeze,1,0,\nresolve,1,1,\nwithdrawal,1,4,1.0\n";
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store
+        .get_current_state_with_options(&options)
+        .unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked,status\n1,4.0,0.0,4.0,false,active\n"
+    );
+}
+
+#[test]
+fn handle_transations_sorted_by_default() {
+    let csv = "t_type,client,tx,amount\ndeposit,3,1,1.0\ndeposit,1,2,2.0\ndeposit,2,3,3.0\n";
+    let mut client_store = ClientStore::new();
+    transactions::handle_transactions_from_reader(csv.as_bytes(), &mut client_store);
+    let state = client_store
+        .get_current_state_with_options(&OutputOptions::default())
+        .unwrap();
+    assert_eq!(
+        state,
+        "client,available,held,total,locked\n1,2.0,0.0,2.0,false\n2,3.0,0.0,3.0,false\n3,1.0,0.0,1.0,false\n"
+    );
 }